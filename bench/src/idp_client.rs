@@ -3,8 +3,10 @@ use anyhow::{anyhow, ensure, Context, Result};
 use bytes::{BufMut, Bytes, BytesMut};
 use clap::{arg, crate_name, crate_version, Arg, Command};
 use config::{Committee, Import};
+use crypto::KeyPair;
 use futures::stream::{futures_unordered::FuturesUnordered, StreamExt};
 use log::{info, warn};
+use messages::{update::SignedUpdateRequest, ClientToIdPMessage};
 use network::reliable_sender::ReliableSender;
 use tokio::{
     net::TcpStream,
@@ -147,8 +149,9 @@ impl BenchmarkClient {
         let burst = self.rate / PRECISION;
         let mut counter = 0;
 
-        let mut network = ReliableSender::new();
-        let address = self.committee.idp.address;
+        let mut network = ReliableSender::default();
+        let (name, address) = (self.committee.idp.name, self.committee.idp.address);
+        let (_, keypair) = KeyPair::generate_production_keypair();
         let mut key = BytesMut::with_capacity(self.size);
         let value = AkdValue(vec![0; self.size]);
         let mut pending = FuturesUnordered::new();
@@ -169,10 +172,13 @@ impl BenchmarkClient {
                         key.resize(self.size, 0u8);
                         let label = AkdLabel(key.split().freeze().to_vec());
 
+                        // Every label is new, so the client registers as its owner.
                         let update = (label, value.clone());
+                        let request = SignedUpdateRequest::new(update, /* nonce */ 1, &keypair);
+                        let update = ClientToIdPMessage::Update(request);
                         let bytes = Bytes::from(bincode::serialize(&update).unwrap());
 
-                        let handle = network.send(address, bytes).await;
+                        let handle = network.send(name, address, bytes).await;
                         pending.push(handle);
 
                         // NOTE: This log entry is used to compute performance.
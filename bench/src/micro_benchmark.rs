@@ -5,13 +5,13 @@ use config::Committee;
 use crypto::KeyPair;
 use futures::executor::block_on;
 use messages::{
-    publish::{PublishCertificate, PublishNotification, PublishVote},
+    publish::{PublishCertificate, PublishNotification, PublishVote, Votes},
     Root,
 };
 use statistical::{mean, standard_deviation};
 use std::time::Instant;
 use storage::akd_storage::AkdStorage;
-use test_utils::{certificate, committee, keys, notification, votes};
+use test_utils::{bls_committee, certificate, committee, keys, notification, votes};
 use utils::{display_file_sizes, proof, proof_with_storage, publish_with_storage_stats};
 
 use crate::utils::{generate_key_entries, publish_with_storage};
@@ -46,6 +46,7 @@ fn main() {
     verify_vote();
     aggregate_certificate();
     verify_certificate();
+    verify_aggregate_certificate();
     publish_with_different_batch_sizes(true);
     publish_with_different_batch_sizes(false);
     // AKD in-memory storage implementations don't have stats as of now. Disabling this one.
@@ -261,10 +262,13 @@ fn aggregate_certificate() {
         PublishCertificate {
             root: notification.root,
             sequence_number: notification.sequence_number,
-            votes: votes
-                .iter()
-                .map(|x| (x.author, x.signature.clone()))
-                .collect(),
+            committee_change: None,
+            votes: Votes::Individual(
+                votes
+                    .iter()
+                    .map(|x| (x.author, x.signature.clone()))
+                    .collect(),
+            ),
         }
     };
 
@@ -284,7 +288,9 @@ fn verify_certificate() {
     let setup = || {
         let threshold = committee(0).quorum_threshold() as usize;
         let mut certificate = block_on(certificate());
-        certificate.votes.truncate(threshold);
+        if let Votes::Individual(votes) = &mut certificate.votes {
+            votes.truncate(threshold);
+        }
         Data(certificate, committee(0))
     };
 
@@ -301,3 +307,26 @@ fn verify_certificate() {
         DEFAULT_PRECISION,
     );
 }
+
+/// Benchmark the verification of a certificate holding a single aggregate signature.
+fn verify_aggregate_certificate() {
+    struct Data(PublishCertificate, Committee);
+
+    let setup = || {
+        let certificate = block_on(test_utils::aggregate_certificate());
+        Data(certificate, bls_committee(0))
+    };
+
+    let run = |data: &Data| {
+        let Data(certificate, committee) = data;
+        certificate.verify(committee)
+    };
+
+    bench(
+        "verify aggregate certificate",
+        setup,
+        run,
+        DEFAULT_RUNS,
+        DEFAULT_PRECISION,
+    );
+}
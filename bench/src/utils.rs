@@ -14,7 +14,7 @@ use bytes::{BufMut, Bytes, BytesMut};
 use config::Committee;
 use crypto::KeyPair;
 use messages::{
-    publish::{Proof, PublishCertificate, PublishNotification, PublishVote},
+    publish::{Proof, PublishCertificate, PublishNotification, PublishVote, Votes},
     Blake3, IdPToWitnessMessage, Root,
 };
 use std::time::Instant;
@@ -236,11 +236,13 @@ impl CertificateGenerator {
             let certificate = PublishCertificate {
                 root: self.votes[0].root,
                 sequence_number: self.votes[0].sequence_number,
-                votes: self
-                    .votes
-                    .drain(..)
-                    .map(|v| (v.author, v.signature))
-                    .collect(),
+                committee_change: None,
+                votes: Votes::Individual(
+                    self.votes
+                        .drain(..)
+                        .map(|v| (v.author, v.signature))
+                        .collect(),
+                ),
             };
             let message = IdPToWitnessMessage::PublishCertificate(certificate);
             let serialized = bincode::serialize(&message).unwrap();
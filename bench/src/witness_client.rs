@@ -3,7 +3,7 @@ mod utils;
 use anyhow::{anyhow, ensure, Context, Result};
 use clap::{arg, crate_name, crate_version, Arg, Command};
 use config::{Committee, Import, PrivateConfig};
-use crypto::KeyPair;
+use crypto::{KeyPair, PublicKey};
 use futures::stream::{futures_unordered::FuturesUnordered, StreamExt};
 use log::{debug, info, warn};
 use messages::WitnessToIdPMessage;
@@ -99,8 +99,8 @@ pub struct BenchmarkClient {
     faults: usize,
     /// The number of key updates per proof.
     proof_entries: u64,
-    /// The network address of the witnesses.
-    targets: Vec<SocketAddr>,
+    /// The public keys and network addresses of the witnesses.
+    targets: Vec<(PublicKey, SocketAddr)>,
 }
 
 impl BenchmarkClient {
@@ -112,11 +112,7 @@ impl BenchmarkClient {
         faults: usize,
         proof_entries: u64,
     ) -> Self {
-        let targets: Vec<_> = committee
-            .witnesses_addresses()
-            .into_iter()
-            .map(|(_, x)| x)
-            .collect();
+        let targets = committee.witnesses_addresses();
 
         Self {
             idp,
@@ -133,7 +129,7 @@ impl BenchmarkClient {
         // NOTE: These log entries are used to compute performance.
         info!("Batch size: {} proofs/notification", self.proof_entries);
         info!("Transactions rate: {} tx/s", self.rate);
-        for target in &self.targets {
+        for (_, target) in &self.targets {
             info!("Target witness address: {}", target);
         }
     }
@@ -173,8 +169,8 @@ impl BenchmarkClient {
         let burst = self.rate / PRECISION;
         let mut counter = 0; // Identifies sample transactions.
 
-        // Connect to the witnesses.
-        let mut network = ReliableSender::new();
+        // Connect to the witnesses (on behalf of the IdP).
+        let mut network = ReliableSender::new(self.idp.copy());
 
         // Initiate the generator of dumb requests.
         let notification_generator =
@@ -0,0 +1,37 @@
+use config::ConfigError;
+use messages::{
+    error::{IdpError, MessageError, WitnessError},
+    update::UpdateReceipt,
+};
+use thiserror::Error;
+
+/// Convenient result wrapper.
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// Errors triggered by the client.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    ConfigError(#[from] ConfigError),
+
+    #[error(transparent)]
+    MessageError(#[from] MessageError),
+
+    #[error(transparent)]
+    IdpError(#[from] IdpError),
+
+    #[error(transparent)]
+    WitnessError(#[from] WitnessError),
+
+    #[error("Failed to receive reply from the IdP or witness")]
+    FailedToReceiveReply,
+
+    #[error("Received unexpected protocol message")]
+    UnexpectedProtocolMessage,
+
+    #[error("Received a receipt that does not match the update request")]
+    InvalidReceipt,
+
+    #[error("The IdP did not commit the update by sequence number {}: {:?}", .0.deadline, .0)]
+    ReceiptViolation(Box<UpdateReceipt>),
+}
@@ -0,0 +1,262 @@
+mod error;
+
+pub use crate::error::{ClientError, ClientResult};
+use akd::storage::types::{AkdLabel, AkdValue};
+use bytes::Bytes;
+use config::{Committee, Import, PrivateConfig};
+use crypto::{KeyPair, PublicKey};
+use log::debug;
+use messages::{
+    ensure,
+    error::{IdpError, MessageError},
+    history::{KeyHistoryRequest, KeyHistoryResponse},
+    lookup::LookupRequest,
+    reconfiguration::CommitteeHistory,
+    update::{SignedUpdateRequest, UpdateReceipt},
+    ClientToIdPMessage, IdPToClientMessage, IdPToWitnessMessage, SequenceNumber,
+    WitnessToIdPMessage,
+};
+use network::reliable_sender::ReliableSender;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A client of the key directory. It only accepts values whose proofs verify against roots
+/// certified by a quorum of witnesses.
+pub struct Client {
+    /// The committees in charge of certifying each range of sequence numbers (learned from the
+    /// certificates handing over from one committee to the next).
+    committees: CommitteeHistory,
+    /// The keypair authenticating the client's update requests.
+    keypair: KeyPair,
+    /// The nonce of the next update request.
+    nonce: u64,
+    /// A reliable network sender to reach the IdP and the witnesses.
+    network: ReliableSender,
+}
+
+impl Client {
+    /// Create a new client. The committee is the genesis committee; the client learns the later
+    /// committees from the IdP.
+    pub fn new(committee: Committee, keypair: KeyPair) -> Self {
+        // Nonces only need to increase for each label, so starting from the current time (in
+        // microseconds) keeps them fresh across restarts of the client.
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Failed to read the system time")
+            .as_micros() as u64;
+        Self {
+            committees: CommitteeHistory::new(committee),
+            network: ReliableSender::new(keypair.copy()),
+            keypair,
+            nonce,
+        }
+    }
+
+    /// Create a new client from a committee file and a keypair file.
+    pub fn load(committee_file: &str, keypair_file: &str) -> ClientResult<Self> {
+        let committee = Committee::import(committee_file)?;
+        let private_config = PrivateConfig::import(keypair_file)?;
+        Ok(Self::new(committee, private_config.secret))
+    }
+
+    /// Send a message to the IdP and wait for its reply.
+    async fn request(&mut self, message: &ClientToIdPMessage) -> ClientResult<Bytes> {
+        let serialized = bincode::serialize(message).expect("Failed to serialize client message");
+        let idp = &self.committees.current().idp;
+        let handle = self
+            .network
+            .send(idp.name, idp.address, Bytes::from(serialized))
+            .await;
+        handle.await.map_err(|_| ClientError::FailedToReceiveReply)
+    }
+
+    /// Send a message to a witness and wait for its reply.
+    async fn request_witness(
+        &mut self,
+        witness: &PublicKey,
+        message: &IdPToWitnessMessage,
+    ) -> ClientResult<Bytes> {
+        let address = self
+            .committees
+            .witness_address(witness)
+            .ok_or(MessageError::UnknownWitness(*witness))?;
+        let serialized = bincode::serialize(message).expect("Failed to serialize client message");
+        let handle = self
+            .network
+            .send(*witness, address, Bytes::from(serialized))
+            .await;
+        handle.await.map_err(|_| ClientError::FailedToReceiveReply)
+    }
+
+    /// Submit an update request (signed by the client) to the IdP. It returns the receipt signed
+    /// by the IdP, promising to commit the update by the receipt's deadline.
+    pub async fn update(
+        &mut self,
+        label: AkdLabel,
+        value: AkdValue,
+    ) -> ClientResult<UpdateReceipt> {
+        let update = (label, value);
+        self.nonce += 1;
+        let request = SignedUpdateRequest::new(update.clone(), self.nonce, &self.keypair);
+        let message = ClientToIdPMessage::Update(request);
+        let reply = self.request(&message).await?;
+        let receipt = match bincode::deserialize(&reply).map_err(MessageError::from)? {
+            IdPToClientMessage::UpdateReceipt(result) => result?,
+            _ => return Err(ClientError::UnexpectedProtocolMessage),
+        };
+        debug!("Received {:?}", receipt);
+
+        receipt.verify(self.committees.current())?;
+        ensure!(receipt.matches(&update), ClientError::InvalidReceipt);
+        Ok(receipt)
+    }
+
+    /// Check whether the update described by a receipt has been committed. It returns the
+    /// sequence number at which the update was committed, or `None` if its deadline did not
+    /// pass yet. It fails with a `ReceiptViolation` (proving the IdP's misbehaviour) if the
+    /// update was not committed by the deadline.
+    pub async fn commit_status(
+        &mut self,
+        receipt: &UpdateReceipt,
+    ) -> ClientResult<Option<SequenceNumber>> {
+        let violation = || ClientError::ReceiptViolation(Box::new(receipt.clone()));
+        let response = match self.fetch_key_history(&receipt.label).await {
+            Ok(response) => response,
+            // Nothing is committed before the first certificate.
+            Err(ClientError::IdpError(IdpError::NoCertifiedState)) => return Ok(None),
+            // The label has no history before its first commit.
+            Err(ClientError::IdpError(IdpError::LabelNotFound(certificate))) => {
+                if certificate
+                    .verify(self.committees.get(certificate.sequence_number))
+                    .is_err()
+                {
+                    self.update_committees().await?;
+                    certificate.verify(self.committees.get(certificate.sequence_number))?;
+                }
+                ensure!(certificate.sequence_number < receipt.deadline, violation());
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+
+        // The update is committed by the notification whose batch included it (the label may
+        // hold the same value at earlier versions).
+        let committed = response.proof.proofs.iter().any(|x| {
+            x.epoch == receipt.deadline
+                && UpdateReceipt::hash_value(&x.plaintext_value) == receipt.value_hash
+        });
+
+        if committed {
+            Ok(Some(receipt.deadline))
+        } else if response.certificate.sequence_number < receipt.deadline {
+            Ok(None)
+        } else {
+            Err(violation())
+        }
+    }
+
+    /// Look up the value associated with a label. It returns the value and its version only if
+    /// the lookup proof verifies against a certified root.
+    pub async fn lookup(&mut self, label: AkdLabel) -> ClientResult<(AkdValue, u64)> {
+        let message = ClientToIdPMessage::Lookup(LookupRequest {
+            label: label.clone(),
+        });
+        let reply = self.request(&message).await?;
+        let response = match bincode::deserialize(&reply).map_err(MessageError::from)? {
+            IdPToClientMessage::LookupResponse(result) => result?,
+            _ => return Err(ClientError::UnexpectedProtocolMessage),
+        };
+        debug!("Received {:?}", response);
+
+        // The response may be certified by a committee the client does not know yet.
+        if response.verify(&self.committees, &label).is_err() {
+            self.update_committees().await?;
+            response.verify(&self.committees, &label)?;
+        }
+        Ok((response.value().clone(), response.version()))
+    }
+
+    /// Look up the value associated with a label from the replica of a full witness (e.g., when
+    /// the IdP is unavailable). As for `lookup`, the proof must verify against a certified root.
+    pub async fn lookup_from_witness(
+        &mut self,
+        witness: &PublicKey,
+        label: AkdLabel,
+    ) -> ClientResult<(AkdValue, u64)> {
+        let message = IdPToWitnessMessage::Lookup(LookupRequest {
+            label: label.clone(),
+        });
+        let reply = self.request_witness(witness, &message).await?;
+        let response = match bincode::deserialize(&reply).map_err(MessageError::from)? {
+            WitnessToIdPMessage::LookupResponse(result) => result?,
+            _ => return Err(ClientError::UnexpectedProtocolMessage),
+        };
+        debug!("Received {:?}", response);
+
+        // The response may be certified by a committee the client does not know yet.
+        if response.verify(&self.committees, &label).is_err() {
+            self.update_committees().await?;
+            response.verify(&self.committees, &label)?;
+        }
+        Ok((response.value().clone(), response.version()))
+    }
+
+    /// Retrieve every value ever associated with a label. It returns the sequence number at
+    /// which each value was committed, its version, and the value itself only if the history
+    /// proof verifies against the chain of certified roots.
+    pub async fn key_history(
+        &mut self,
+        label: AkdLabel,
+    ) -> ClientResult<Vec<(SequenceNumber, u64, AkdValue)>> {
+        let response = self.fetch_key_history(&label).await?;
+        Ok(response
+            .proof
+            .proofs
+            .into_iter()
+            .map(|x| (x.epoch, x.version, x.plaintext_value))
+            .collect())
+    }
+
+    /// Retrieve the history of a label and verify it against the chain of certified roots.
+    async fn fetch_key_history(&mut self, label: &AkdLabel) -> ClientResult<KeyHistoryResponse> {
+        let message = ClientToIdPMessage::KeyHistory(KeyHistoryRequest {
+            label: label.clone(),
+        });
+        let reply = self.request(&message).await?;
+        let response = match bincode::deserialize(&reply).map_err(MessageError::from)? {
+            IdPToClientMessage::KeyHistoryResponse(result) => result?,
+            _ => return Err(ClientError::UnexpectedProtocolMessage),
+        };
+        debug!("Received {:?}", response);
+
+        // The response may be certified by a committee the client does not know yet.
+        if response.verify(&self.committees, label).is_err() {
+            self.update_committees().await?;
+            response.verify(&self.committees, label)?;
+        }
+        Ok(response)
+    }
+
+    /// Learn from the IdP the committee changes certified since the latest committee known by
+    /// the client. Each change must be certified by the committee it hands over from.
+    pub async fn update_committees(&mut self) -> ClientResult<()> {
+        let activation = self.committees.last_activation();
+        let message = ClientToIdPMessage::CommitteeChangeQuery(activation);
+        let reply = self.request(&message).await?;
+        let certificates = match bincode::deserialize(&reply).map_err(MessageError::from)? {
+            IdPToClientMessage::CommitteeChangeResponse(certificates) => certificates,
+            _ => return Err(ClientError::UnexpectedProtocolMessage),
+        };
+
+        for certificate in certificates {
+            debug!("Received {:?}", certificate);
+            ensure!(
+                certificate.committee_change.is_some()
+                    && certificate.sequence_number >= self.committees.last_activation(),
+                ClientError::UnexpectedProtocolMessage
+            );
+            certificate.verify(self.committees.get(certificate.sequence_number))?;
+            self.committees.update(&certificate);
+        }
+        Ok(())
+    }
+}
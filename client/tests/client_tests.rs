@@ -0,0 +1,301 @@
+use akd::storage::types::{AkdLabel, AkdValue};
+use bytes::Bytes;
+use client::{Client, ClientError};
+use function_name::named;
+use messages::{
+    error::{IdpError, WitnessError},
+    IdPToWitnessMessage, WitnessToIdPMessage,
+};
+use network::transport::TcpTransport;
+use std::sync::Arc;
+use test_utils::{
+    client_keypair, committee, committee_change, delete_storage, idp_sender, keys,
+    spawn_test_full_witness, spawn_test_idp, spawn_test_joining_witness,
+    spawn_test_reconfiguring_idp, spawn_test_witness, spawn_test_witnesses, updates,
+};
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+#[named]
+async fn update_and_lookup() {
+    let base_port = 6_000;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+
+    // Spawn the IdP and 4 witnesses.
+    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_idp(&test_id, committee.clone());
+    tokio::task::yield_now().await;
+
+    // Send enough updates to create a batch.
+    let mut client = Client::new(committee, client_keypair());
+    let mut receipts = Vec::new();
+    for (label, value) in updates() {
+        let receipt = client.update(label, value).await.unwrap();
+        assert_eq!(receipt.deadline, 1);
+        receipts.push(receipt);
+    }
+
+    // Look up the first label until the IdP certified the batch.
+    let (label, value) = updates().into_iter().next().unwrap();
+    let (received, version) = loop {
+        match client.lookup(label.clone()).await {
+            Ok(x) => break x,
+            Err(ClientError::IdpError(IdpError::NoCertifiedState)) => {
+                sleep(Duration::from_millis(100)).await
+            }
+            Err(e) => panic!("Unexpected error: {}", e),
+        }
+    };
+    assert_eq!(received, value);
+    assert_eq!(version, 1);
+
+    // Retrieve the history of the same label.
+    let history = client.key_history(label).await.unwrap();
+    assert_eq!(history, vec![(1, 1, value)]);
+
+    // Ensure the IdP kept its promise.
+    let status = client.commit_status(&receipts[0]).await.unwrap();
+    assert_eq!(status, Some(1));
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn lookup_from_full_witness() {
+    let base_port = 6_100;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+
+    // Spawn the IdP, a full witness, and 3 other witnesses.
+    spawn_test_full_witness(&test_id, &committee, 0);
+    for i in 1..keys().len() {
+        spawn_test_witness(&test_id, &committee, i);
+    }
+    spawn_test_idp(&test_id, committee.clone());
+    tokio::task::yield_now().await;
+
+    // Send enough updates to create a batch.
+    let mut client = Client::new(committee, client_keypair());
+    for (label, value) in updates() {
+        client.update(label, value).await.unwrap();
+    }
+
+    // Look up the first label from the full witness until it replayed the certified batch.
+    let (name, _) = keys().swap_remove(0);
+    let (label, value) = updates().into_iter().next().unwrap();
+    let (received, version) = loop {
+        match client.lookup_from_witness(&name, label.clone()).await {
+            Ok(x) => break x,
+            Err(ClientError::WitnessError(WitnessError::NoReplicatedState)) => {
+                sleep(Duration::from_millis(100)).await
+            }
+            Err(e) => panic!("Unexpected error: {}", e),
+        }
+    };
+    assert_eq!(received, value);
+    assert_eq!(version, 1);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn full_witness_catches_up() {
+    let base_port = 6_500;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+
+    // Spawn the IdP and 3 witnesses (enough to certify batches without the full witness).
+    for i in 1..keys().len() {
+        spawn_test_witness(&test_id, &committee, i);
+    }
+    spawn_test_idp(&test_id, committee.clone());
+    tokio::task::yield_now().await;
+
+    // Commit a first batch before the full witness boots.
+    let mut client = Client::new(committee.clone(), client_keypair());
+    let mut receipts = Vec::new();
+    for (label, value) in updates() {
+        receipts.push(client.update(label, value).await.unwrap());
+    }
+    while client.commit_status(&receipts[0]).await.unwrap().is_none() {
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    // Boot the full witness and commit a second batch.
+    spawn_test_full_witness(&test_id, &committee, 0);
+    let receipt = client
+        .update(AkdLabel(vec![3]), AkdValue(vec![4]))
+        .await
+        .unwrap();
+    while client.commit_status(&receipt).await.unwrap().is_none() {
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    // The full witness pulls the batch it missed from the IdP and serves lookups.
+    let (name, _) = keys().swap_remove(0);
+    let (label, value) = updates().into_iter().next().unwrap();
+    let (received, version) = loop {
+        match client.lookup_from_witness(&name, label.clone()).await {
+            Ok(x) => break x,
+            Err(ClientError::WitnessError(WitnessError::NoReplicatedState)) => {
+                sleep(Duration::from_millis(100)).await
+            }
+            Err(e) => panic!("Unexpected error: {}", e),
+        }
+    };
+    assert_eq!(received, value);
+    assert_eq!(version, 1);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn commit_status() {
+    let base_port = 6_200;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+
+    // Spawn the IdP and 4 witnesses.
+    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_idp(&test_id, committee.clone());
+    tokio::task::yield_now().await;
+
+    // Commit a first batch of updates.
+    let mut client = Client::new(committee, client_keypair());
+    let mut receipts = Vec::new();
+    for (label, value) in updates() {
+        receipts.push(client.update(label, value).await.unwrap());
+    }
+    while client.commit_status(&receipts[0]).await.unwrap().is_none() {
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    // Set a label to the value it already holds, then update a label that is not yet in the
+    // directory (one batch each).
+    let (label, value) = updates().into_iter().next().unwrap();
+    let requests = vec![(label, value), (AkdLabel(vec![3]), AkdValue(vec![4]))];
+
+    // Ensure each update is reported as committed only once its own batch is certified.
+    for (label, value) in requests {
+        let receipt = client.update(label, value).await.unwrap();
+        let status = loop {
+            match client.commit_status(&receipt).await.unwrap() {
+                Some(x) => break x,
+                None => sleep(Duration::from_millis(100)).await,
+            }
+        };
+        assert_eq!(status, receipt.deadline);
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn lookup_after_committee_change() {
+    let base_port = 6_300;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+
+    // Spawn 4 witnesses and an IdP handing over to the first witness with its first notification.
+    let next_committee = committee_change(base_port).committee;
+    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_reconfiguring_idp(
+        &test_id,
+        committee.clone(),
+        Some(next_committee),
+        Arc::new(TcpTransport),
+    );
+    tokio::task::yield_now().await;
+
+    // Commit two batches: the second one is certified by the new committee only.
+    let mut client = Client::new(committee, client_keypair());
+    for (label, value) in updates() {
+        client.update(label, value).await.unwrap();
+    }
+    let receipt = client
+        .update(AkdLabel(vec![3]), AkdValue(vec![4]))
+        .await
+        .unwrap();
+    assert_eq!(receipt.deadline, 2);
+
+    // Ensure the client learns the committee change to verify the second batch.
+    while client.commit_status(&receipt).await.unwrap().is_none() {
+        sleep(Duration::from_millis(100)).await;
+    }
+    let (received, version) = client.lookup(AkdLabel(vec![3])).await.unwrap();
+    assert_eq!(received, AkdValue(vec![4]));
+    assert_eq!(version, 1);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn joining_witness() {
+    let base_port = 6_400;
+    let next_committee = committee(base_port);
+    let test_id = function_name!();
+
+    // The last witness is not in the genesis committee: it joins the next one.
+    let (joining, _) = keys().pop().unwrap();
+    let mut committee = next_committee.clone();
+    committee.witnesses.remove(&joining);
+
+    // Spawn the witnesses and an IdP handing over to the next committee with its first
+    // notification.
+    let transport = Arc::new(TcpTransport);
+    for i in 0..keys().len() - 1 {
+        spawn_test_witness(&test_id, &committee, i);
+    }
+    let index = keys().len() - 1;
+    let next = Some(next_committee.clone());
+    spawn_test_joining_witness(&test_id, &committee, next.clone(), index, transport.clone());
+    spawn_test_reconfiguring_idp(&test_id, committee.clone(), next, transport);
+    tokio::task::yield_now().await;
+
+    // Commit two batches: the second one is addressed to the next committee.
+    let mut client = Client::new(committee, client_keypair());
+    for (label, value) in updates() {
+        client.update(label, value).await.unwrap();
+    }
+    let receipt = client
+        .update(AkdLabel(vec![3]), AkdValue(vec![4]))
+        .await
+        .unwrap();
+    while client.commit_status(&receipt).await.unwrap().is_none() {
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    // Ensure the joining witness caught up with the certificates of both batches.
+    let address = next_committee.witness_address(&joining).unwrap();
+    let serialized = bincode::serialize(&IdPToWitnessMessage::StateQuery).unwrap();
+    let mut network = idp_sender();
+    let mut synced = false;
+    for _ in 0..100 {
+        let handle = network
+            .send(joining, address, Bytes::from(serialized.clone()))
+            .await;
+        match bincode::deserialize(&handle.await.unwrap()).unwrap() {
+            WitnessToIdPMessage::State(Ok(state)) if state.sequence_number == 3 => {
+                synced = true;
+                break;
+            }
+            WitnessToIdPMessage::State(Ok(_)) => sleep(Duration::from_millis(100)).await,
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+    assert!(synced);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
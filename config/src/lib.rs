@@ -1,4 +1,4 @@
-use crypto::{KeyPair, PublicKey};
+use crypto::{BlsKeyPair, BlsPublicKey, BlsSignature, KeyPair, PublicKey};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
     collections::BTreeMap,
@@ -24,10 +24,22 @@ pub trait Import: DeserializeOwned {
             let data = fs::read(path)?;
             Ok(serde_json::from_slice(data.as_slice())?)
         };
-        reader().map_err(|e| ConfigError::ImportError {
+        let config = reader().map_err(|e| ConfigError::ImportError {
             file: path.to_string(),
             message: e.to_string(),
-        })
+        })?;
+        config
+            .validate()
+            .map_err(|message| ConfigError::ImportError {
+                file: path.to_string(),
+                message,
+            })?;
+        Ok(config)
+    }
+
+    /// Check the configuration read from file is well-formed.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
     }
 }
 
@@ -53,31 +65,56 @@ pub trait Export: Serialize {
 pub type VotingPower = u32;
 
 /// The public information of the IdP.
-#[derive(Clone, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Idp {
     /// The public key of the Idp.
     pub name: PublicKey,
+    /// The public key of the VRF of the IdP (to verify the labels of the directory).
+    pub vrf_public_key: PublicKey,
     /// The network addresses to receive client update requests.
     pub address: SocketAddr,
 }
 
 /// The public information of a witness.
-#[derive(Clone, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Witness {
     /// The voting power of this witness.
     pub voting_power: VotingPower,
     /// The network addresses of the witness.
     pub address: SocketAddr,
+    /// The network address to receive gossip from other witnesses.
+    pub gossip_address: SocketAddr,
+    /// The public key verifying the aggregatable (BLS) signatures of the witness.
+    pub bls_public_key: BlsPublicKey,
+    /// The proof that the witness holds the secret key of `bls_public_key`. It prevents rogue-key
+    /// attacks against the aggregate certificates.
+    pub bls_proof_of_possession: BlsSignature,
+}
+
+/// The signature scheme with which the witnesses certify publish notifications.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    /// Certificates hold the ed25519 signature of every witness of the quorum.
+    Ed25519,
+    /// Certificates hold a single BLS signature aggregating the votes of the quorum.
+    Bls,
 }
 
 /// The (public) committee information.
-#[derive(Clone, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Committee {
     pub idp: Idp,
     pub witnesses: BTreeMap<PublicKey, Witness>,
+    /// The signature scheme of the certificates of this committee.
+    pub signature_scheme: SignatureScheme,
 }
 
-impl Import for Committee {}
+impl Import for Committee {
+    fn validate(&self) -> Result<(), String> {
+        self.verify_proofs_of_possession()
+            .map_err(|name| format!("Witness {} does not own its BLS key", name))
+    }
+}
 
 impl Committee {
     /// Return the number of witnesses.
@@ -92,23 +129,52 @@ impl Committee {
             .map_or_else(|| 0, |x| x.voting_power)
     }
 
+    /// Returns the sum of the voting power of all witnesses.
+    pub fn total_voting_power(&self) -> VotingPower {
+        self.witnesses.values().map(|x| x.voting_power).sum()
+    }
+
     /// Returns the stake required to reach a quorum (2f+1).
     pub fn quorum_threshold(&self) -> VotingPower {
         // If N = 3f + 1 + k (0 <= k < 3)
         // then (2 N + 3) / 3 = 2f + 1 + (2k + 2)/3 = 2f + 1 + k = N - f
-        let total_votes: VotingPower = self.witnesses.values().map(|x| x.voting_power).sum();
-        2 * total_votes / 3 + 1
+        2 * self.total_voting_power() / 3 + 1
     }
 
     /// Returns the stake required to reach availability (f+1).
     pub fn validity_threshold(&self) -> VotingPower {
         // If N = 3f + 1 + k (0 <= k < 3)
         // then (N + 2) / 3 = f + 1 + k/3 = f + 1
-        let total_votes: VotingPower = self.witnesses.values().map(|x| x.voting_power).sum();
-        (total_votes + 2) / 3
+        (self.total_voting_power() + 2) / 3
     }
 
-    /// Returns the addresses of all witnesses.
+    /// Returns the BLS public key of a specific witness.
+    pub fn bls_public_key(&self, name: &PublicKey) -> Option<&BlsPublicKey> {
+        self.witnesses
+            .get(name)
+            .map(|witness| &witness.bls_public_key)
+    }
+
+    /// Ensure every witness proves the possession of its BLS key. Returns the name of the first
+    /// witness failing to do so.
+    pub fn verify_proofs_of_possession(&self) -> Result<(), PublicKey> {
+        match self.witnesses.iter().find(|(_, witness)| {
+            witness
+                .bls_proof_of_possession
+                .verify_possession(&witness.bls_public_key)
+                .is_err()
+        }) {
+            Some((name, _)) => Err(*name),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the position of a specific witness in the committee (ordered by public key).
+    pub fn witness_index(&self, name: &PublicKey) -> Option<usize> {
+        self.witnesses.keys().position(|x| x == name)
+    }
+
+    /// Returns the address of a specific witness.
     pub fn witness_address(&self, name: &PublicKey) -> Option<SocketAddr> {
         self.witnesses.get(name).map(|witness| witness.address)
     }
@@ -120,6 +186,22 @@ impl Committee {
             .map(|(name, witness)| (*name, witness.address))
             .collect()
     }
+
+    /// Returns the gossip address of a specific witness.
+    pub fn gossip_address(&self, name: &PublicKey) -> Option<SocketAddr> {
+        self.witnesses
+            .get(name)
+            .map(|witness| witness.gossip_address)
+    }
+
+    /// Returns the gossip addresses of all witnesses except `myself`.
+    pub fn others_gossip_addresses(&self, myself: &PublicKey) -> Vec<(PublicKey, SocketAddr)> {
+        self.witnesses
+            .iter()
+            .filter(|(name, _)| *name != myself)
+            .map(|(name, witness)| (*name, witness.gossip_address))
+            .collect()
+    }
 }
 
 /// The private configuration of the identity provider and witnesses.
@@ -147,3 +229,79 @@ impl PrivateConfig {
 
 impl Import for PrivateConfig {}
 impl Export for PrivateConfig {}
+
+/// The private configuration of a witness.
+#[derive(Serialize, Deserialize)]
+pub struct WitnessPrivateConfig {
+    /// The public key of the witness.
+    pub name: PublicKey,
+    /// The private key of the witness.
+    pub secret: KeyPair,
+    /// The BLS public key of the witness.
+    pub bls_public_key: BlsPublicKey,
+    /// The BLS private key of the witness.
+    pub bls_secret: BlsKeyPair,
+    /// The proof of possession of the BLS private key (to publish in the committee file).
+    pub bls_proof_of_possession: BlsSignature,
+}
+
+impl Default for WitnessPrivateConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WitnessPrivateConfig {
+    /// Creates a new private configuration.
+    pub fn new() -> Self {
+        let (name, secret) = KeyPair::generate_production_keypair();
+        let (bls_public_key, bls_secret) = BlsKeyPair::generate_production_keypair();
+        let bls_proof_of_possession = bls_secret.proof_of_possession();
+        Self {
+            name,
+            secret,
+            bls_public_key,
+            bls_secret,
+            bls_proof_of_possession,
+        }
+    }
+}
+
+impl Import for WitnessPrivateConfig {}
+impl Export for WitnessPrivateConfig {}
+
+/// The private configuration of the identity provider.
+#[derive(Serialize, Deserialize)]
+pub struct IdpPrivateConfig {
+    /// The public key of the IdP.
+    pub name: PublicKey,
+    /// The private key of the IdP.
+    pub secret: KeyPair,
+    /// The public key of the VRF of the IdP.
+    pub vrf_public_key: PublicKey,
+    /// The private key of the VRF of the IdP.
+    pub vrf_secret: KeyPair,
+}
+
+impl Default for IdpPrivateConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdpPrivateConfig {
+    /// Creates a new private configuration.
+    pub fn new() -> Self {
+        let (name, secret) = KeyPair::generate_production_keypair();
+        let (vrf_public_key, vrf_secret) = KeyPair::generate_production_keypair();
+        Self {
+            name,
+            secret,
+            vrf_public_key,
+            vrf_secret,
+        }
+    }
+}
+
+impl Import for IdpPrivateConfig {}
+impl Export for IdpPrivateConfig {}
@@ -0,0 +1,229 @@
+use crate::{CryptoError, Digest};
+use blst::{
+    min_pk::{AggregateSignature, PublicKey, SecretKey, Signature},
+    BLST_ERROR,
+};
+use rand::{rngs::OsRng, CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// The domain separation tag of the BLS signatures (proof-of-possession ciphersuite). Aggregating
+/// signatures under the same message is only safe if every public key comes with a valid proof of
+/// possession (otherwise rogue-key attacks are possible).
+const DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// The domain separation tag of the proofs of possession (signatures over the signer's own public
+/// key). It differs from `DST` so that a proof can never be replayed as a regular signature.
+const POP_DST: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// The length of a (compressed) BLS public key.
+pub const BLS_PUBLIC_KEY_LENGTH: usize = 48;
+
+/// Convert the error codes of `blst` into a crypto error.
+fn check(code: BLST_ERROR) -> Result<(), CryptoError> {
+    match code {
+        BLST_ERROR::BLST_SUCCESS => Ok(()),
+        _ => Err(CryptoError::new()),
+    }
+}
+
+/// Represents a BLS public key (compressed). It verifies signatures that can be aggregated.
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub struct BlsPublicKey(pub [u8; BLS_PUBLIC_KEY_LENGTH]);
+
+impl Serialize for BlsPublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(&self.encode_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsPublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let value = Self::decode_base64(&s).map_err(|e| serde::de::Error::custom(e.to_string()))?;
+        Ok(value)
+    }
+}
+
+impl std::fmt::Debug for BlsPublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.encode_base64())
+    }
+}
+
+impl std::fmt::Display for BlsPublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.encode_base64().get(0..16).unwrap())
+    }
+}
+
+impl BlsPublicKey {
+    /// Encode a public key in base64 (human-readable).
+    pub fn encode_base64(&self) -> String {
+        base64::encode(&self.0[..])
+    }
+
+    /// Decode a base64-encoded public key.
+    pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
+        let bytes = base64::decode(s)?;
+        let array = bytes[..]
+            .try_into()
+            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        Ok(Self(array))
+    }
+
+    /// Decompress the public key and ensure it is a valid point of the curve.
+    fn decompress(&self) -> Result<PublicKey, CryptoError> {
+        PublicKey::key_validate(&self.0).map_err(|_| CryptoError::new())
+    }
+}
+
+/// Represents a BLS public and secret key pair.
+pub struct BlsKeyPair(SecretKey);
+
+impl Serialize for BlsKeyPair {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(&base64::encode(&self.0.to_bytes()))
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsKeyPair {
+    fn deserialize<D>(deserializer: D) -> Result<BlsKeyPair, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let value = base64::decode(&s).map_err(|err| serde::de::Error::custom(err.to_string()))?;
+        let key = SecretKey::from_bytes(&value)
+            .map_err(|err| serde::de::Error::custom(format!("{:?}", err)))?;
+        Ok(BlsKeyPair(key))
+    }
+}
+
+impl BlsKeyPair {
+    /// Returns the public key part of the keypair.
+    pub fn public(&self) -> BlsPublicKey {
+        BlsPublicKey(self.0.sk_to_pk().compress())
+    }
+
+    /// Duplicate the keypair. Use with care, every copy leaves the secret in a new memory location.
+    pub fn copy(&self) -> Self {
+        let bytes = self.0.to_bytes();
+        BlsKeyPair(SecretKey::from_bytes(&bytes).expect("Failed to copy keypair"))
+    }
+
+    /// Prove the possession of the secret key by signing the public key.
+    pub fn proof_of_possession(&self) -> BlsSignature {
+        BlsSignature(self.0.sign(&self.public().0, POP_DST, &[]))
+    }
+
+    /// Generate a new keypair.
+    pub fn generate_production_keypair() -> (BlsPublicKey, BlsKeyPair) {
+        Self::generate_keypair(&mut OsRng)
+    }
+
+    /// Generate a keypair from the specified RNG (useful for testing).
+    pub fn generate_keypair<R>(csprng: &mut R) -> (BlsPublicKey, BlsKeyPair)
+    where
+        R: CryptoRng + RngCore,
+    {
+        let mut ikm = [0u8; 32];
+        csprng.fill_bytes(&mut ikm);
+        let secret = SecretKey::key_gen(&ikm, &[]).expect("Failed to generate BLS keypair");
+        let keypair = BlsKeyPair(secret);
+        (keypair.public(), keypair)
+    }
+}
+
+/// A BLS signature over a digest. Signatures over the same digest can be aggregated into a single
+/// signature verified with a single pairing check.
+#[derive(Clone, PartialEq)]
+pub struct BlsSignature(Signature);
+
+impl Serialize for BlsSignature {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_bytes(&self.0.compress())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsSignature {
+    fn deserialize<D>(deserializer: D) -> Result<BlsSignature, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let signature = Signature::uncompress(&bytes)
+            .map_err(|err| serde::de::Error::custom(format!("{:?}", err)))?;
+        Ok(BlsSignature(signature))
+    }
+}
+
+impl std::fmt::Debug for BlsSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", base64::encode(&self.0.compress()))
+    }
+}
+
+impl BlsSignature {
+    /// Sign a digest with the specified private key.
+    pub fn new(value: &Digest, secret: &BlsKeyPair) -> Self {
+        BlsSignature(secret.0.sign(value.as_ref(), DST, &[]))
+    }
+
+    /// Verify a (single) signature over a digest.
+    pub fn verify(&self, value: &Digest, author: &BlsPublicKey) -> Result<(), CryptoError> {
+        let public_key = author.decompress()?;
+        check(
+            self.0
+                .verify(true, value.as_ref(), DST, &[], &public_key, false),
+        )
+    }
+
+    /// Verify a proof of possession of the secret key matching the specified public key.
+    pub fn verify_possession(&self, author: &BlsPublicKey) -> Result<(), CryptoError> {
+        let public_key = author.decompress()?;
+        check(
+            self.0
+                .verify(true, &author.0, POP_DST, &[], &public_key, false),
+        )
+    }
+
+    /// Aggregate many signatures over the same digest into a single signature.
+    pub fn aggregate<'a, I>(signatures: I) -> Result<Self, CryptoError>
+    where
+        I: IntoIterator<Item = &'a BlsSignature>,
+    {
+        let signatures: Vec<_> = signatures.into_iter().map(|x| &x.0).collect();
+        let aggregate =
+            AggregateSignature::aggregate(&signatures, true).map_err(|_| CryptoError::new())?;
+        Ok(BlsSignature(aggregate.to_signature()))
+    }
+
+    /// Verify an aggregate signature over a digest against the public keys of all its signers.
+    pub fn verify_aggregate<'a, I>(&self, value: &Digest, authors: I) -> Result<(), CryptoError>
+    where
+        I: IntoIterator<Item = &'a BlsPublicKey>,
+    {
+        let public_keys = authors
+            .into_iter()
+            .map(|x| x.decompress())
+            .collect::<Result<Vec<_>, _>>()?;
+        let public_keys: Vec<_> = public_keys.iter().collect();
+        check(
+            self.0
+                .fast_aggregate_verify(true, value.as_ref(), DST, &public_keys),
+        )
+    }
+}
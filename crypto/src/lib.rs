@@ -7,6 +7,9 @@ use std::{
     convert::{TryFrom, TryInto},
 };
 
+mod bls;
+pub use bls::{BlsKeyPair, BlsPublicKey, BlsSignature, BLS_PUBLIC_KEY_LENGTH};
+
 #[cfg(test)]
 #[path = "tests/crypto_tests.rs"]
 pub mod crypto_tests;
@@ -145,6 +148,17 @@ impl KeyPair {
         PublicKey(self.0.public.to_bytes())
     }
 
+    /// Duplicate the keypair. Use with care, every copy leaves the secret in a new memory location.
+    pub fn copy(&self) -> Self {
+        let bytes = self.0.to_bytes();
+        KeyPair(dalek::Keypair::from_bytes(&bytes).expect("Failed to copy keypair"))
+    }
+
+    /// Returns the secret part of the keypair. Use with care, the secret leaves the keypair.
+    pub fn secret_bytes(&self) -> [u8; dalek::SECRET_KEY_LENGTH] {
+        self.0.secret.to_bytes()
+    }
+
     /// Generate a new keypair.
     pub fn generate_production_keypair() -> (PublicKey, KeyPair) {
         Self::generate_keypair(&mut OsRng)
@@ -98,3 +98,127 @@ fn verify_invalid_batch() {
     // Verify the batch.
     assert!(Signature::verify_batch(&message.digest(), &signatures).is_err());
 }
+
+pub fn bls_keys() -> Vec<(BlsPublicKey, BlsKeyPair)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..4)
+        .map(|_| BlsKeyPair::generate_keypair(&mut rng))
+        .collect()
+}
+
+#[test]
+fn verify_valid_bls_signature() {
+    // Get a keypair.
+    let (public_key, keypair) = bls_keys().pop().unwrap();
+
+    // Make signature.
+    let message = Message {
+        content: "Hello, world!".to_string(),
+    };
+    let signature = BlsSignature::new(&message.digest(), &keypair);
+
+    // Verify the signature.
+    assert!(signature.verify(&message.digest(), &public_key).is_ok());
+}
+
+#[test]
+fn verify_valid_aggregate() {
+    // Make signatures.
+    let message = Message {
+        content: "Hello, world!".to_string(),
+    };
+    let (public_keys, signatures): (Vec<_>, Vec<_>) = bls_keys()
+        .into_iter()
+        .take(3)
+        .map(|(public_key, secret_key)| {
+            let signature = BlsSignature::new(&message.digest(), &secret_key);
+            (public_key, signature)
+        })
+        .unzip();
+
+    // Aggregate and verify the signatures.
+    let aggregate = BlsSignature::aggregate(&signatures).unwrap();
+    assert!(aggregate
+        .verify_aggregate(&message.digest(), &public_keys)
+        .is_ok());
+}
+
+#[test]
+fn verify_invalid_aggregate() {
+    // Make signatures.
+    let message = Message {
+        content: "Hello, world!".to_string(),
+    };
+    let (mut public_keys, signatures): (Vec<_>, Vec<_>) = bls_keys()
+        .into_iter()
+        .take(3)
+        .map(|(public_key, secret_key)| {
+            let signature = BlsSignature::new(&message.digest(), &secret_key);
+            (public_key, signature)
+        })
+        .unzip();
+
+    // Aggregate the signatures.
+    let aggregate = BlsSignature::aggregate(&signatures).unwrap();
+
+    // Verify the aggregate against the wrong set of signers.
+    let (public_key, _) = bls_keys().pop().unwrap();
+    public_keys[0] = public_key;
+    assert!(aggregate
+        .verify_aggregate(&message.digest(), &public_keys)
+        .is_err());
+}
+
+#[test]
+fn verify_valid_proof_of_possession() {
+    let (public_key, keypair) = bls_keys().pop().unwrap();
+    let proof = keypair.proof_of_possession();
+    assert!(proof.verify_possession(&public_key).is_ok());
+
+    // The proof does not hold for any other key.
+    let (other, _) = bls_keys().swap_remove(0);
+    assert!(proof.verify_possession(&other).is_err());
+}
+
+// Make a rogue key `pk - target` so that the aggregate of `target` and the rogue key is `pk`.
+fn rogue_key(public_key: &BlsPublicKey, target: &BlsPublicKey) -> BlsPublicKey {
+    use blst::{
+        blst_p1, blst_p1_add_or_double, blst_p1_affine, blst_p1_cneg, blst_p1_compress,
+        blst_p1_from_affine, blst_p1_uncompress,
+    };
+    let mut bytes = [0u8; BLS_PUBLIC_KEY_LENGTH];
+    unsafe {
+        let (mut a, mut b) = (blst_p1_affine::default(), blst_p1_affine::default());
+        blst_p1_uncompress(&mut a, public_key.0.as_ptr());
+        blst_p1_uncompress(&mut b, target.0.as_ptr());
+        let (mut p, mut q, mut rogue) =
+            (blst_p1::default(), blst_p1::default(), blst_p1::default());
+        blst_p1_from_affine(&mut p, &a);
+        blst_p1_from_affine(&mut q, &b);
+        blst_p1_cneg(&mut q, true);
+        blst_p1_add_or_double(&mut rogue, &p, &q);
+        blst_p1_compress(bytes.as_mut_ptr(), &rogue);
+    }
+    BlsPublicKey(bytes)
+}
+
+#[test]
+fn reject_rogue_key() {
+    let message = Message {
+        content: "Hello, world!".to_string(),
+    };
+    let mut keys = bls_keys();
+    let (attacker_key, attacker) = keys.pop().unwrap();
+    let (honest_key, _) = keys.pop().unwrap();
+
+    // The attacker alone forges an aggregate 'signed' by the honest key and its rogue key.
+    let rogue = rogue_key(&attacker_key, &honest_key);
+    let forgery = BlsSignature::new(&message.digest(), &attacker);
+    assert!(forgery
+        .verify_aggregate(&message.digest(), &[honest_key, rogue])
+        .is_ok());
+
+    // But the attacker cannot prove the possession of the rogue key.
+    let proof = attacker.proof_of_possession();
+    assert!(proof.verify_possession(&rogue).is_err());
+}
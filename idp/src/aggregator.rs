@@ -1,9 +1,10 @@
-use config::{Committee, VotingPower};
-use crypto::{PublicKey, Signature};
+use config::{Committee, SignatureScheme, VotingPower};
+use crypto::{BlsSignature, PublicKey, Signature};
 use messages::{
     ensure,
     error::{IdpError, IdpResult, MessageError},
-    publish::{PublishCertificate, PublishVote},
+    publish::{PublishCertificate, PublishVote, SignerBitmap, Votes},
+    reconfiguration::CommitteeChange,
     Root,
 };
 use std::collections::HashSet;
@@ -14,32 +15,42 @@ pub struct Aggregator {
     committee: Committee,
     /// The root to certify.
     root: Root,
+    /// The committee change to certify along with the root (if any).
+    committee_change: Option<CommitteeChange>,
     /// The current voting power accumulated for this root.
     weight: VotingPower,
     /// The list of votes' signatures.
     votes: Vec<(PublicKey, Signature)>,
+    /// The list of votes' aggregatable signatures (if the committee aggregates votes).
+    bls_signatures: Vec<BlsSignature>,
+    /// The position in the committee of the authors of the aggregatable signatures.
+    signers: SignerBitmap,
     /// The set of witness that already voted.
     used: HashSet<PublicKey>,
 }
 
 impl Aggregator {
     /// Initialize a new aggregator.
-    pub fn new(committee: Committee, root: Root) -> Self {
+    pub fn new(
+        committee: Committee,
+        root: Root,
+        committee_change: Option<CommitteeChange>,
+    ) -> Self {
         Self {
             committee,
             root,
+            committee_change,
             weight: VotingPower::default(),
             votes: Vec::new(),
+            bls_signatures: Vec::new(),
+            signers: SignerBitmap::default(),
             used: HashSet::new(),
         }
     }
 
-    /// Reset the aggregator.
-    pub fn reset(&mut self, root: Root) {
-        self.root = root;
-        self.weight = 0;
-        self.votes.clear();
-        self.used.clear();
+    /// Check whether the aggregator holds a (valid) vote from a witness.
+    pub fn voted(&self, author: &PublicKey) -> bool {
+        self.votes.iter().any(|(x, _)| x == author)
     }
 
     /// Append a vote to the aggregator.
@@ -56,6 +67,12 @@ impl Aggregator {
             }
         );
 
+        // Ensure the vote is for the correct committee change.
+        ensure!(
+            self.committee_change.as_ref().map(|x| x.digest()) == vote.committee_change,
+            IdpError::UnexpectedCommitteeChange
+        );
+
         // Ensure the witness is in the committee.
         ensure!(
             voting_power > 0,
@@ -73,13 +90,30 @@ impl Aggregator {
 
         // Check if we have a quorum.
         self.votes.push((author, vote.signature));
+        if let Some(signature) = vote.bls_signature {
+            let index = self
+                .committee
+                .witness_index(&author)
+                .expect("Witnesses with voting power are in the committee");
+            self.bls_signatures.push(signature);
+            self.signers.insert(index);
+        }
         self.weight += voting_power;
         if self.weight >= self.committee.quorum_threshold() {
             self.weight = 0; // Ensures quorum is only reached once.
+            let votes = match self.committee.signature_scheme {
+                SignatureScheme::Ed25519 => Votes::Individual(self.votes.clone()),
+                SignatureScheme::Bls => {
+                    let signature = BlsSignature::aggregate(&self.bls_signatures)
+                        .map_err(MessageError::from)?;
+                    Votes::Aggregate(self.signers.clone(), signature)
+                }
+            };
             return Ok(Some(PublishCertificate {
                 root: vote.root,
                 sequence_number: vote.sequence_number,
-                votes: self.votes.clone(),
+                committee_change: self.committee_change.clone(),
+                votes,
             }));
         }
         Ok(None)
@@ -89,14 +123,14 @@ impl Aggregator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use test_utils::{committee, votes};
+    use test_utils::{bls_committee, bls_votes, committee, votes};
 
     #[tokio::test]
     async fn make_certificate() {
         let mut votes = votes().await;
         let root = votes[0].root;
         let sequence_number = votes[0].sequence_number;
-        let mut aggregator = Aggregator::new(committee(0), root);
+        let mut aggregator = Aggregator::new(committee(0), root, None);
 
         // Add a quorum of votes.
         let vote_0 = votes.pop().unwrap();
@@ -119,4 +153,25 @@ mod tests {
         assert_eq!(certificate.root, root);
         assert_eq!(certificate.sequence_number, sequence_number);
     }
+
+    #[tokio::test]
+    async fn make_aggregate_certificate() {
+        let mut votes = bls_votes().await;
+        let root = votes[0].root;
+        let mut aggregator = Aggregator::new(bls_committee(0), root, None);
+
+        // Add a quorum of votes.
+        let vote_0 = votes.pop().unwrap();
+        assert!(aggregator.append(vote_0).unwrap().is_none());
+        let vote_1 = votes.pop().unwrap();
+        assert!(aggregator.append(vote_1).unwrap().is_none());
+        let vote_2 = votes.pop().unwrap();
+        let result = aggregator.append(vote_2);
+        assert!(result.is_ok());
+
+        // Verify the resulting certificate holds a single aggregate signature.
+        let certificate = result.unwrap().unwrap();
+        assert!(certificate.verify(&bls_committee(0)).is_ok());
+        assert!(matches!(certificate.votes, Votes::Aggregate(..)));
+    }
 }
@@ -1,6 +1,12 @@
-use bytes::Bytes;
-use log::{debug, warn};
-use messages::update::Batch;
+use crate::{registry::Registry, UpdateReplier};
+use crypto::KeyPair;
+use log::debug;
+use messages::{
+    error::IdpError,
+    update::{SignedUpdateRequest, UpdateReceipt},
+    SequenceNumber,
+};
+use std::collections::HashSet;
 use tokio::{
     sync::mpsc::{Receiver, Sender},
     task::JoinHandle,
@@ -9,27 +15,38 @@ use tokio::{
 
 /// Assemble clients requests into batches.
 pub struct Batcher {
+    /// The private key material of the IdP (to sign receipts).
+    keypair: KeyPair,
+    /// The sequence number of the notification that will include the current batch.
+    sequence_number: SequenceNumber,
+    /// The owners of the labels (to authenticate requests).
+    registry: Registry,
     /// The preferred batch size (in bytes).
     batch_size: usize,
     /// The maximum delay after which to seal the batch (in ms).
     max_batch_delay: u64,
     /// Channel to receive requests from the network.
-    rx_request: Receiver<Bytes>,
+    rx_request: Receiver<(SignedUpdateRequest, UpdateReplier)>,
     /// Output channel to deliver sealed batches to the `NotificationMaker`.
-    tx_batch: Sender<Batch>,
+    tx_batch: Sender<Vec<SignedUpdateRequest>>,
     /// Holds the current batch.
-    current_batch: Batch,
+    current_batch: Vec<SignedUpdateRequest>,
     /// Holds the size of the current batch (in bytes).
     current_batch_size: usize,
+    /// Holds the labels updated by the current batch.
+    current_labels: HashSet<Vec<u8>>,
 }
 
 impl Batcher {
     /// Spawn a new `Batcher` task.
     pub fn spawn(
+        keypair: KeyPair,
+        sequence_number: SequenceNumber,
+        registry: Registry,
         batch_size: usize,
         max_batch_delay: u64,
-        rx_request: Receiver<Bytes>,
-        tx_batch: Sender<Batch>,
+        rx_request: Receiver<(SignedUpdateRequest, UpdateReplier)>,
+        tx_batch: Sender<Vec<SignedUpdateRequest>>,
     ) -> JoinHandle<()> {
         #[cfg(feature = "benchmark")]
         // NOTE: These log entries are used to compute performance.
@@ -37,12 +54,16 @@ impl Batcher {
 
         tokio::spawn(async move {
             Self {
+                keypair,
+                sequence_number,
+                registry,
                 batch_size,
                 max_batch_delay,
                 rx_request,
                 tx_batch,
                 current_batch: Vec::with_capacity(2 * batch_size),
                 current_batch_size: 0,
+                current_labels: HashSet::new(),
             }
             .run()
             .await
@@ -57,17 +78,32 @@ impl Batcher {
         loop {
             tokio::select! {
                 // Assemble client requests into batches of preset size.
-                Some(bytes) = self.rx_request.recv() => {
-                    let update = match bincode::deserialize(&bytes) {
-                        Ok(x) => x,
-                        Err(e) => {
-                            warn!("Failed to deserialize request: {}", e);
-                            continue;
-                        }
-                    };
+                Some((request, replier)) = self.rx_request.recv() => {
+                    // Drop requests that are not signed by the owner of the label.
+                    if let Err(e) = self.registry.authenticate(&request) {
+                        debug!("Rejected {:?}: {}", request, e);
+                        let _ = replier.send(Err(e));
+                        continue;
+                    }
+
+                    // Drop requests updating a label that the current batch already updates: the
+                    // directory publishes a single value per label and epoch.
+                    let (label, _) = &request.update;
+                    if self.current_labels.contains(&label.0) {
+                        debug!("Rejected {:?}: label already in the current batch", request);
+                        let _ = replier.send(Err(IdpError::PendingUpdate(self.sequence_number)));
+                        continue;
+                    }
+                    self.current_labels.insert(label.0.clone());
+                    self.registry.register(&request);
+
+                    // Promise the client to include the update in the current batch.
+                    let receipt =
+                        UpdateReceipt::new(&request.update, self.sequence_number, &self.keypair);
+                    let _ = replier.send(Ok(receipt));
 
                     self.current_batch_size += 1;
-                    self.current_batch.push(update);
+                    self.current_batch.push(request);
                     if self.current_batch_size >= self.batch_size {
                         self.seal().await;
                         timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
@@ -80,7 +116,7 @@ impl Batcher {
                         debug!("Timer triggered, sealing batch early");
                         #[cfg(feature = "benchmark")]
                         // NOTE: These log entries are used to compute performance.
-                        warn!("Timer triggered, sealing batch early");
+                        log::warn!("Timer triggered, sealing batch early");
 
                         self.seal().await;
                     }
@@ -95,8 +131,10 @@ impl Batcher {
 
     /// Seal the current batch.
     async fn seal(&mut self) {
+        self.sequence_number += 1;
         self.current_batch_size = 0;
-        let batch: Batch = self.current_batch.drain(..).collect();
+        self.current_labels.clear();
+        let batch: Vec<_> = self.current_batch.drain(..).collect();
         self.tx_batch
             .send(batch)
             .await
@@ -1,86 +1,235 @@
 mod aggregator;
 mod batcher;
+mod migration;
 mod prover;
 mod publisher;
+mod registry;
 mod synchronizer;
 
 use async_trait::async_trait;
 use batcher::Batcher;
 use bytes::Bytes;
 use config::Committee;
-use crypto::KeyPair;
-use futures::{future::join_all, SinkExt};
+use crypto::{KeyPair, PublicKey};
+use futures::future::join_all;
 use log::info;
-use network::receiver::{MessageHandler, Receiver as NetworkReceiver, Writer};
+use messages::{
+    error::{IdpResult, MessageError},
+    history::{KeyHistoryRequest, KeyHistoryResponse},
+    lookup::{LookupRequest, LookupResponse},
+    publish::PublishCertificate,
+    reconfiguration::CommitteeHistory,
+    update::{ReplicaBatch, SignedUpdateRequest, UpdateReceipt},
+    vrf::IdpVrf,
+    ClientToIdPMessage, IdPToClientMessage, SequenceNumber,
+};
+pub use migration::migrate_storage;
+use network::{
+    receiver::{MessageHandler, Receiver as NetworkReceiver, Writer},
+    transport::{TcpTransport, Transport},
+};
 use prover::Prover;
 use publisher::Publisher;
-use std::error::Error;
-use storage::Storage;
-use synchronizer::Synchronizer;
-use tokio::sync::mpsc::{channel, Sender};
+use registry::Registry;
+use std::{error::Error, sync::Arc};
+use storage::{Column, Storage};
+use synchronizer::{CertificateQuery, Synchronizer};
+use tokio::sync::{
+    mpsc::{channel, Sender},
+    oneshot,
+};
 
-/// Storage address of the sequence number.
-pub(crate) const STORE_LAST_NOTIFICATION_ADDR: [u8; 32] = [255; 32];
+/// Storage key (in the state column) of the last notification created by the IdP.
+pub(crate) const STORE_LAST_NOTIFICATION_KEY: &str = "last_notification";
+
+/// Storage key (in the state column) of the committees in charge of each range of sequence numbers.
+pub(crate) const STORE_COMMITTEES_KEY: &str = "committees";
+
+/// Storage key (in the state column) of the last committee the IdP handed over to (to hand over
+/// only once).
+pub(crate) const STORE_NEXT_COMMITTEE_KEY: &str = "next_committee";
+
+/// Storage key (in the state column) of the notifications waiting for a certificate.
+pub(crate) const STORE_PENDING_NOTIFICATIONS_KEY: &str = "pending_notifications";
 
 /// The default size of inter-tasks channels.
 pub(crate) const DEFAULT_CHANNEL_SIZE: usize = 1_000;
 
+/// One-shot channel to reply to a client update request.
+pub(crate) type UpdateReplier = oneshot::Sender<IdpResult<UpdateReceipt>>;
+
+/// One-shot channel to reply to a client lookup request.
+pub(crate) type LookupReplier = oneshot::Sender<IdpResult<LookupResponse>>;
+
+/// One-shot channel to reply to a client key history request.
+pub(crate) type KeyHistoryReplier = oneshot::Sender<IdpResult<KeyHistoryResponse>>;
+
+/// A client query served by the `Prover`.
+pub(crate) enum ClientQuery {
+    Lookup(LookupRequest, LookupReplier),
+    KeyHistory(KeyHistoryRequest, KeyHistoryReplier),
+}
+
+impl std::fmt::Debug for ClientQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Self::Lookup(request, _) => write!(f, "{:?}", request),
+            Self::KeyHistory(request, _) => write!(f, "{:?}", request),
+        }
+    }
+}
+
 /// Spawn a new IdP.
+#[allow(clippy::too_many_arguments)]
 pub async fn spawn_idp<AkdStorage>(
     // The keypair of the IdP.
     keypair: KeyPair,
-    // The committee information.
+    // The keypair of the VRF of the IdP.
+    vrf_keypair: KeyPair,
+    // The (genesis) committee information.
     committee: Committee,
-    // The secure storage containing the last publish notification.
-    secure_storage: Storage,
-    // The storage containing all past certificates.
-    sync_storage: Storage,
+    // The committee to hand over the directory to with the next notification (if any).
+    next_committee: Option<Committee>,
+    // The storage containing the last publish notification, all past certificates, and the owner
+    // of each label.
+    storage: Storage,
     // The big storage containing all key-values.
     akd_storage: AkdStorage,
     // The number of updates to batch into a single proof.
     batch_size: usize,
     // The maximum delay before sealing a batch of requests.
     max_batch_delay: u64,
+    // The maximum number of notifications waiting for a certificate at the same time.
+    pipeline_depth: usize,
+    // Whether to ship the batches of updates to the witnesses (only useful to full witnesses).
+    ship_batches: bool,
 ) where
     AkdStorage: akd::storage::Storage + Sync + Send + 'static,
 {
+    spawn_idp_with_transport(
+        keypair,
+        vrf_keypair,
+        committee,
+        next_committee,
+        storage,
+        akd_storage,
+        batch_size,
+        max_batch_delay,
+        pipeline_depth,
+        ship_batches,
+        Arc::new(TcpTransport),
+    )
+    .await;
+}
+
+/// Spawn a new IdP talking to the witnesses and clients over a specific transport (e.g., an
+/// in-memory network simulating a whole committee within a single process).
+#[allow(clippy::too_many_arguments)]
+pub async fn spawn_idp_with_transport<AkdStorage>(
+    // The keypair of the IdP.
+    keypair: KeyPair,
+    // The keypair of the VRF of the IdP.
+    vrf_keypair: KeyPair,
+    // The (genesis) committee information.
+    committee: Committee,
+    // The committee to hand over the directory to with the next notification (if any).
+    next_committee: Option<Committee>,
+    // The storage containing the last publish notification, all past certificates, and the owner
+    // of each label.
+    storage: Storage,
+    // The big storage containing all key-values.
+    akd_storage: AkdStorage,
+    // The number of updates to batch into a single proof.
+    batch_size: usize,
+    // The maximum delay before sealing a batch of requests.
+    max_batch_delay: u64,
+    // The maximum number of notifications waiting for a certificate at the same time.
+    pipeline_depth: usize,
+    // Whether to ship the batches of updates to the witnesses (only useful to full witnesses).
+    ship_batches: bool,
+    // The transport carrying the messages of the IdP.
+    transport: Arc<dyn Transport>,
+) where
+    AkdStorage: akd::storage::Storage + Sync + Send + 'static,
+{
+    // The publisher needs room for at least one notification waiting for its certificate.
+    assert!(pipeline_depth > 0, "The pipeline depth must be positive");
+
     let (tx_request, rx_request) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_query, rx_query) = channel(DEFAULT_CHANNEL_SIZE);
     let (tx_batch, rx_batch) = channel(DEFAULT_CHANNEL_SIZE);
     let (tx_notification, rx_notification) = channel(DEFAULT_CHANNEL_SIZE);
     let (tx_trigger, rx_trigger) = channel(DEFAULT_CHANNEL_SIZE);
     let (tx_certificate, rx_certificate) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_certificate_query, rx_certificate_query) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_committed_certificate, rx_committed_certificate) = channel(DEFAULT_CHANNEL_SIZE);
 
-    // The `Batcher` receives clients update requests and batch them together.
-    let batcher_handle = Batcher::spawn(batch_size, max_batch_delay, rx_request, tx_batch);
+    // The `Batcher` authenticates clients update requests and batch them together. Every batch
+    // is included in the notification following the last one created by the IdP.
+    let sequence_number = prover::load_last_notification(&storage)
+        .map_or_else(SequenceNumber::default, |x| x.sequence_number);
+    let registry = Registry::new(storage.clone());
+    let batcher_handle = Batcher::spawn(
+        keypair.copy(),
+        sequence_number + 1,
+        registry.clone(),
+        batch_size,
+        max_batch_delay,
+        rx_request,
+        tx_batch,
+    );
 
-    // The `Prover` persists batches of updates and generate a commit (audit) proof.
+    // The `Prover` persists batches of updates and generate a commit (audit) proof. It also
+    // answers clients' queries against the latest certified state.
     let prover_handle = Prover::spawn(
-        keypair,
-        &secure_storage,
+        keypair.copy(),
+        IdpVrf::new(&vrf_keypair),
+        next_committee,
+        &storage,
         akd_storage,
+        registry,
+        ship_batches,
         rx_batch,
+        rx_query,
+        rx_committed_certificate,
         tx_notification,
     );
 
     // The `Publisher` broadcasts publish notifications to the witnesses.
     let publisher_handle = Publisher::spawn(
+        keypair.copy(),
         committee.clone(),
-        secure_storage,
+        storage.clone(),
         rx_notification,
         tx_trigger,
         tx_certificate,
+        tx_committed_certificate,
+        pipeline_depth,
+        transport.clone(),
     );
 
     // The `Synchronizer` helps the witnesses to remain up to date.
-    let synchronizer_handle =
-        Synchronizer::spawn(committee.clone(), sync_storage, rx_trigger, rx_certificate);
+    let synchronizer_handle = Synchronizer::spawn(
+        keypair.copy(),
+        storage.clone(),
+        rx_trigger,
+        rx_certificate,
+        rx_certificate_query,
+        transport.clone(),
+    );
 
     // Spawn a network receiver.
     let name = committee.idp.name;
     let mut address = committee.idp.address;
     address.set_ip("0.0.0.0".parse().unwrap());
-    let handler = IdpHandler { tx_request };
-    NetworkReceiver::spawn(address, handler);
+    let handler = IdpHandler {
+        committee: committee.clone(),
+        storage,
+        tx_request,
+        tx_query,
+        tx_certificate_query,
+    };
+    NetworkReceiver::spawn_with_transport(address, keypair, handler, transport);
 
     // Prevent the function from returning.
     info!(
@@ -100,20 +249,120 @@ pub async fn spawn_idp<AkdStorage>(
 /// Defines how the network receiver handles incoming messages.
 #[derive(Clone)]
 struct IdpHandler {
-    tx_request: Sender<Bytes>,
+    /// The genesis committee (later committees are loaded from the storage).
+    committee: Committee,
+    /// The storage holding the committees handed over to and the batches shipped to the witnesses.
+    storage: Storage,
+    tx_request: Sender<(SignedUpdateRequest, UpdateReplier)>,
+    tx_query: Sender<ClientQuery>,
+    tx_certificate_query: Sender<CertificateQuery>,
+}
+
+impl IdpHandler {
+    /// Load certificates from storage (through the `Synchronizer`).
+    async fn certificates(&self, sequence_numbers: Vec<SequenceNumber>) -> Vec<PublishCertificate> {
+        let (sender, receiver) = oneshot::channel();
+        let query = CertificateQuery {
+            sequence_numbers,
+            replier: sender,
+        };
+        self.tx_certificate_query
+            .send(query)
+            .await
+            .expect("Failed to deliver certificate query");
+        receiver.await.expect("Failed to receive certificates")
+    }
+
+    /// Load the certificates handing over to the committees activated after the specified
+    /// sequence number (to let clients learn the committee changes they missed).
+    async fn committee_changes(&self, activation: SequenceNumber) -> Vec<PublishCertificate> {
+        let committees: CommitteeHistory = self
+            .storage
+            .get(Column::State, STORE_COMMITTEES_KEY)
+            .expect("Failed to load committees from storage")
+            .unwrap_or_else(|| CommitteeHistory::new(self.committee.clone()));
+        self.certificates(committees.changes_after(activation))
+            .await
+    }
+
+    /// Get a key history proof from the `Prover` and complete it with the certificates (loaded
+    /// by the `Synchronizer`) required to verify it.
+    async fn key_history(&self, request: KeyHistoryRequest) -> IdpResult<KeyHistoryResponse> {
+        let (sender, receiver) = oneshot::channel();
+        self.tx_query
+            .send(ClientQuery::KeyHistory(request, sender))
+            .await
+            .expect("Failed to deliver key history request");
+        let mut response = receiver
+            .await
+            .expect("Failed to receive key history reply")?;
+
+        let sequence_numbers = response.required_sequence_numbers().into_iter().collect();
+        response.certificates = self.certificates(sequence_numbers).await;
+        Ok(response)
+    }
 }
 
 #[async_trait]
 impl MessageHandler for IdpHandler {
-    async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        // Reply with an ACK.
-        let _ = writer.send(Bytes::from("Ack")).await;
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        _peer: &PublicKey,
+        serialized: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        // Deserialize and parse the message.
+        match bincode::deserialize(&serialized).map_err(MessageError::from)? {
+            ClientToIdPMessage::Update(request) => {
+                // Forward the request to the `Batcher`.
+                let (sender, receiver) = oneshot::channel();
+                self.tx_request
+                    .send((request, sender))
+                    .await
+                    .expect("Failed to deliver request");
 
-        // Forward the request to the `Batcher`.
-        self.tx_request
-            .send(serialized)
-            .await
-            .expect("Failed to deliver request");
+                // Reply to the client with a receipt.
+                let reply = receiver.await.expect("Failed to receive update receipt");
+                let message = IdPToClientMessage::UpdateReceipt(reply);
+                let bytes = bincode::serialize(&message).expect("Failed to serialize reply");
+                writer.send(Bytes::from(bytes)).await?;
+            }
+            ClientToIdPMessage::Lookup(request) => {
+                // Forward the request to the `Prover`.
+                let (sender, receiver) = oneshot::channel();
+                self.tx_query
+                    .send(ClientQuery::Lookup(request, sender))
+                    .await
+                    .expect("Failed to deliver lookup request");
+
+                // Reply to the client.
+                let reply = receiver.await.expect("Failed to receive lookup reply");
+                let message = IdPToClientMessage::LookupResponse(reply);
+                let bytes = bincode::serialize(&message).expect("Failed to serialize reply");
+                writer.send(Bytes::from(bytes)).await?;
+            }
+            ClientToIdPMessage::KeyHistory(request) => {
+                let reply = self.key_history(request).await;
+                let message = IdPToClientMessage::KeyHistoryResponse(reply);
+                let bytes = bincode::serialize(&message).expect("Failed to serialize reply");
+                writer.send(Bytes::from(bytes)).await?;
+            }
+            ClientToIdPMessage::CommitteeChangeQuery(activation) => {
+                let certificates = self.committee_changes(activation).await;
+                let message = IdPToClientMessage::CommitteeChangeResponse(certificates);
+                let bytes = bincode::serialize(&message).expect("Failed to serialize reply");
+                writer.send(Bytes::from(bytes)).await?;
+            }
+            ClientToIdPMessage::BatchQuery(sequence_number) => {
+                let batch: Option<ReplicaBatch> = self
+                    .storage
+                    .get(Column::Batches, &sequence_number)
+                    .expect("Failed to load batch from storage");
+                let message = IdPToClientMessage::BatchResponse(batch);
+                let bytes = bincode::serialize(&message).expect("Failed to serialize reply");
+                writer.send(Bytes::from(bytes)).await?;
+            }
+        }
         Ok(())
     }
 }
@@ -1,7 +1,8 @@
 use anyhow::{Context, Result};
-use clap::{arg, crate_name, crate_version, Arg, Command};
-use config::{Committee, Import, PrivateConfig};
-use idp::spawn_idp;
+use clap::{arg, crate_name, crate_version, Arg, ArgMatches, Command};
+use config::{Committee, Export, IdpPrivateConfig, Import};
+use idp::{migrate_storage, spawn_idp};
+use messages::DEFAULT_PIPELINE_DEPTH;
 use storage::{akd_storage::AkdStorage, Storage};
 
 /// The default maximum delay before sealing a batch (in ms).
@@ -14,15 +15,33 @@ async fn main() -> Result<()> {
         .version(crate_version!())
         .about("The Key Transparency IdP.")
         .arg(Arg::new("verbose").multiple_occurrences(true).short('v'))
-        .args(&[
-            arg!(--keypair <FILE> "The path to the witness keypair"),
+        .subcommand(
+            Command::new("generate")
+                .about("Print a fresh key pair and VRF key pair to file")
+                .arg(arg!(--filename <FILE> "The path to the IdP keypair")),
+        )
+        .subcommand(Command::new("run").about("Run the IdP").args(&[
+            arg!(--keypair <FILE> "The path to the IdP keypair"),
             arg!(--committee <FILE> "The path to the committee file"),
-            arg!(--secure_storage <FILE> "The directory to hold the secure storage"),
-            arg!(--sync_storage <FILE> "The directory to hold the sync storage"),
-            arg!(--akd_storage <FILE> "The directory to hold the big akd database"),
+            arg!(--next_committee [FILE] "The path to the file of the committee to hand over to"),
+            arg!(--storage <FILE> "The directory to hold the database of the IdP"),
             arg!(--batch_size <INT> "The number of client update requests to batch into a proof"),
             arg!(--max_batch_delay [INT] "The maximum delay (ms) before sealing a batch"),
-        ])
+            arg!(--pipeline_depth [INT] "The maximum number of uncertified notifications"),
+            arg!(--ship_batches "Ship the batches of updates to full witnesses"),
+        ]))
+        .subcommand(
+            Command::new("migrate")
+                .about("Move the legacy databases of the IdP into a single database")
+                .args(&[
+                    arg!(--keypair <FILE> "The path to the IdP keypair"),
+                    arg!(--storage <FILE> "The directory to hold the database of the IdP"),
+                    arg!(--secure_storage <FILE> "The directory of the legacy secure storage"),
+                    arg!(--sync_storage <FILE> "The directory of the legacy sync storage"),
+                    arg!(--registry_storage <FILE> "The directory of the legacy registry"),
+                    arg!(--akd_storage <FILE> "The directory of the legacy akd database"),
+                ]),
+        )
         .arg_required_else_help(true)
         .get_matches();
 
@@ -40,23 +59,37 @@ async fn main() -> Result<()> {
         .filter_module("network", log_level)
         .init();
 
-    // Parse the parameters.
+    // Parse the input parameters.
+    match matches.subcommand() {
+        Some(("generate", sub_matches)) => IdpPrivateConfig::new()
+            .export(sub_matches.value_of("filename").unwrap())
+            .context("Failed to generate key pair")?,
+        Some(("run", sub_matches)) => spawn(sub_matches).await.context("Failed to spawn IdP")?,
+        Some(("migrate", sub_matches)) => {
+            migrate(sub_matches).context("Failed to migrate storage")?
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Spawn the IdP.
+async fn spawn(matches: &ArgMatches) -> Result<()> {
     let private_config_file = matches.value_of("keypair").unwrap();
     let private_config =
-        PrivateConfig::import(private_config_file).context("Failed to load keypair")?;
+        IdpPrivateConfig::import(private_config_file).context("Failed to load keypair")?;
 
     let committee_file = matches.value_of("committee").unwrap();
     let committee = Committee::import(committee_file).context("Failed to load committee")?;
 
-    let secure_storage_file = matches.value_of("secure_storage").unwrap();
-    let secure_storage =
-        Storage::new(secure_storage_file).context("Failed to create secure storage")?;
-
-    let sync_storage_file = matches.value_of("sync_storage").unwrap();
-    let sync_storage = Storage::new(sync_storage_file).context("Failed to create sync storage")?;
+    let next_committee = match matches.value_of("next_committee") {
+        Some(x) => Some(Committee::import(x).context("Failed to load next committee")?),
+        None => None,
+    };
 
-    let akd_storage_file = matches.value_of("akd_storage").unwrap();
-    let akd_storage = AkdStorage::new(akd_storage_file);
+    let storage_file = matches.value_of("storage").unwrap();
+    let storage = Storage::new(storage_file).context("Failed to create storage")?;
+    let akd_storage = AkdStorage::with_storage(storage.clone());
 
     let batch_size = matches
         .value_of("batch_size")
@@ -71,18 +104,57 @@ async fn main() -> Result<()> {
         None => DEFAULT_MAX_BATCH_DELAY,
     };
 
+    let pipeline_depth = match matches.value_of("pipeline_depth") {
+        Some(x) => x
+            .parse::<usize>()
+            .context("The pipeline depth must be a positive integer")?,
+        None => DEFAULT_PIPELINE_DEPTH,
+    };
+    anyhow::ensure!(
+        pipeline_depth > 0,
+        "The pipeline depth must be a positive integer"
+    );
+
     // Spawn the IdP.
     spawn_idp(
         /* keypair */ private_config.secret,
+        /* vrf_keypair */ private_config.vrf_secret,
         committee,
-        secure_storage,
-        sync_storage,
+        next_committee,
+        storage,
         akd_storage,
         batch_size,
         max_batch_delay,
+        pipeline_depth,
+        /* ship_batches */ matches.is_present("ship_batches"),
     )
     .await;
 
     // If the following statement is reached, all IdP tasks go out of scope.
     unreachable!();
 }
+
+/// Move the legacy databases of the IdP into a single database (only once).
+fn migrate(matches: &ArgMatches) -> Result<()> {
+    let private_config_file = matches.value_of("keypair").unwrap();
+    let private_config =
+        IdpPrivateConfig::import(private_config_file).context("Failed to load keypair")?;
+
+    let storage_file = matches.value_of("storage").unwrap();
+    let storage = Storage::new(storage_file).context("Failed to create storage")?;
+    anyhow::ensure!(
+        !storage.is_migrated()?,
+        "The legacy databases are already migrated"
+    );
+
+    migrate_storage(
+        &storage,
+        /* keypair */ &private_config.secret,
+        matches.value_of("secure_storage").unwrap(),
+        matches.value_of("sync_storage").unwrap(),
+        matches.value_of("registry_storage").unwrap(),
+        matches.value_of("akd_storage").unwrap(),
+    )?;
+    storage.set_migrated()?;
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use crate::STORE_LAST_NOTIFICATION_KEY;
+use crypto::KeyPair;
+use log::info;
+use messages::{
+    legacy::LegacyIdPToWitnessMessage, publish::PublishCertificate, IdPToWitnessMessage,
+};
+use std::convert::TryInto;
+use storage::{akd_storage::AkdStorage, Column, Key, Storage, StoreResult};
+
+/// Legacy address (in the secure storage) of the last notification. It is the only record of the
+/// secure storage: the records added since (e.g., the committees or the pending notifications) are
+/// named keys of the state column.
+const LEGACY_LAST_NOTIFICATION_ADDR: [u8; 32] = [255; 32];
+
+/// Move the records of the legacy layout of the IdP (one database per directory) into the column
+/// families of a single database, translating them into their current format. The IdP signs its
+/// last notification again (the format of its id changed). Returns the number of migrated records.
+pub fn migrate_storage(
+    storage: &Storage,
+    keypair: &KeyPair,
+    secure_storage: &str,
+    sync_storage: &str,
+    registry_storage: &str,
+    akd_storage: &str,
+) -> StoreResult<usize> {
+    let mut migrated = 0;
+
+    // The secure storage holds the last notification of the IdP at a fixed address.
+    migrated += storage.migrate(secure_storage, |key, value| {
+        if key != LEGACY_LAST_NOTIFICATION_ADDR {
+            return Ok(None);
+        }
+
+        // The legacy layout persisted the notification wrapped into its network message.
+        let notification = match bincode::deserialize(value)? {
+            LegacyIdPToWitnessMessage::PublishNotification(notification) => notification,
+            _ => return Ok(None),
+        };
+        let serialized = bincode::serialize(&notification.upgrade(keypair))?;
+        Ok(Some((
+            Column::State,
+            STORE_LAST_NOTIFICATION_KEY.encode(),
+            serialized,
+        )))
+    })?;
+
+    // The sync storage indexes certificates by little-endian sequence numbers. They are persisted
+    // as serialized network messages.
+    migrated += storage.migrate(sync_storage, |key, value| {
+        let sequence_number = match key.try_into() {
+            Ok(bytes) => u64::from_le_bytes(bytes),
+            Err(_) => return Ok(None),
+        };
+        let certificate = match bincode::deserialize(value)? {
+            LegacyIdPToWitnessMessage::PublishCertificate(certificate) => certificate,
+            _ => return Ok(None),
+        };
+        let message =
+            IdPToWitnessMessage::PublishCertificate(PublishCertificate::from(certificate));
+        let serialized = bincode::serialize(&message)?;
+        Ok(Some((
+            Column::Certificates,
+            sequence_number.encode(),
+            serialized,
+        )))
+    })?;
+
+    // The keys of the registry and of the akd records are unchanged, but value states are now
+    // indexed by label.
+    migrated += storage.migrate(registry_storage, |key, value| {
+        Ok(Some((Column::Registry, key.to_vec(), value.to_vec())))
+    })?;
+    migrated += storage.migrate(akd_storage, |key, value| {
+        let entries = AkdStorage::legacy_entries(key, value)?;
+        Ok(entries
+            .into_iter()
+            .map(|(key, value)| (Column::Akd, key, value)))
+    })?;
+
+    info!("Migrated {} records", migrated);
+    Ok(migrated)
+}
@@ -1,30 +1,78 @@
-use crate::STORE_LAST_NOTIFICATION_ADDR;
-use akd::{directory::Directory, ecvrf::HardCodedAkdVRF};
+use crate::{
+    registry::Registry, ClientQuery, STORE_LAST_NOTIFICATION_KEY, STORE_NEXT_COMMITTEE_KEY,
+    STORE_PENDING_NOTIFICATIONS_KEY,
+};
+use akd::{
+    directory::Directory,
+    storage::{types::ValueStateRetrievalFlag, Storage as _},
+};
+use config::Committee;
 use crypto::KeyPair;
 use futures::executor::block_on;
+use log::{debug, info, warn};
 use messages::{
-    publish::{Proof, PublishNotification},
-    update::Batch,
+    error::{IdpError, IdpResult},
+    history::{KeyHistoryRequest, KeyHistoryResponse},
+    lookup::{LookupRequest, LookupResponse},
+    publish::{Proof, PublishCertificate, PublishNotification},
+    reconfiguration::CommitteeChange,
+    update::{Batch, ReplicaBatch, SignedUpdateRequest},
+    vrf::IdpVrf,
     Blake3, Root, SequenceNumber,
 };
-use storage::Storage;
+use storage::{Column, Storage};
 use tokio::{
     sync::mpsc::{Receiver, Sender},
     task::JoinHandle,
 };
 
+/// Load from storage the last notification created by the IdP (if any).
+pub fn load_last_notification(storage: &Storage) -> Option<PublishNotification> {
+    storage
+        .get(Column::State, STORE_LAST_NOTIFICATION_KEY)
+        .expect("Failed to load last notification from storage")
+}
+
+/// Load from storage the notifications broadcast by the IdP but not yet certified.
+pub fn load_pending_notifications(storage: &Storage) -> Vec<PublishNotification> {
+    storage
+        .get(Column::State, STORE_PENDING_NOTIFICATIONS_KEY)
+        .expect("Failed to load pending notifications from storage")
+        .unwrap_or_default()
+}
+
 /// Create publish notifications from client requests.
 pub struct Prover<AkdStorage> {
     /// The private key material of the IdP.
     keypair: KeyPair,
+    /// The committee to hand over the directory to with the next notification (if any).
+    next_committee: Option<Committee>,
+    /// The persistent storage.
+    storage: Storage,
+    /// The owners of the labels (registered once their updates commit).
+    registry: Registry,
     /// Receive batches of clients' requests.
-    rx_batch: Receiver<Batch>,
+    rx_batch: Receiver<Vec<SignedUpdateRequest>>,
+    /// Receive clients' queries.
+    rx_query: Receiver<ClientQuery>,
+    /// Receive the certificates over the notifications created by this prover.
+    rx_committed_certificate: Receiver<PublishCertificate>,
     /// Outputs handles waiting to receive witnesses' votes.
     tx_notification: Sender<PublishNotification>,
     /// The sequence number of the last notification created by the IdP.
     sequence_number: SequenceNumber,
     /// The `akd` key directory.
-    akd: Directory<AkdStorage, HardCodedAkdVRF>,
+    akd: Directory<AkdStorage, IdpVrf>,
+    /// The storage of the `akd` key directory (to look up the current versions of the labels).
+    akd_storage: AkdStorage,
+    /// The VRF of the IdP (to prove the labels of the batches shipped to full witnesses).
+    vrf: IdpVrf,
+    /// Whether to ship the batches of updates along with the notifications.
+    ship_batches: bool,
+    /// The latest certificate received from the publisher (over the last certified epoch).
+    certificate: Option<PublishCertificate>,
+    /// Queries waiting for a certified state of the directory.
+    pending_queries: Vec<ClientQuery>,
 }
 
 impl<AkdStorage> Prover<AkdStorage>
@@ -32,32 +80,60 @@ where
     AkdStorage: akd::storage::Storage + Sync + Send + 'static,
 {
     /// Spawn a new `Prover`.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         keypair: KeyPair,
-        secure_storage: &Storage,
+        vrf: IdpVrf,
+        next_committee: Option<Committee>,
+        storage: &Storage,
         akd_storage: AkdStorage,
-        rx_batch: Receiver<Batch>,
+        registry: Registry,
+        ship_batches: bool,
+        rx_batch: Receiver<Vec<SignedUpdateRequest>>,
+        rx_query: Receiver<ClientQuery>,
+        rx_committed_certificate: Receiver<PublishCertificate>,
         tx_notification: Sender<PublishNotification>,
     ) -> JoinHandle<()> {
         // Load the last sequence number and perform initialization steps.
-        let sequence_number = block_on(Self::initialize(secure_storage, &tx_notification));
+        let sequence_number = block_on(Self::initialize(storage, &tx_notification));
+
+        // Do not hand over twice to the same committee (e.g., after a restart).
+        let applied: Option<Committee> = storage
+            .get(Column::State, STORE_NEXT_COMMITTEE_KEY)
+            .expect("Failed to load next committee from storage");
+        let next_committee = next_committee.filter(|committee| {
+            let fresh = applied.as_ref() != Some(committee);
+            if !fresh {
+                info!("Already handed over to the next committee");
+            }
+            fresh
+        });
+        let storage = storage.clone();
 
         // Run the prover in a new task.
         tokio::spawn(async move {
             // Make or load the akd directory.
-            let db = akd_storage;
-            let vrf = HardCodedAkdVRF {};
-            let akd = Directory::new::<Blake3>(&db, &vrf, false)
+            let akd = Directory::new::<Blake3>(&akd_storage, &vrf, false)
                 .await
                 .expect("Failed to create akd");
 
             // Run a new `NotificationMaker`.
             Self {
                 keypair,
+                next_committee,
+                storage,
+                registry,
                 rx_batch,
+                rx_query,
+                rx_committed_certificate,
                 tx_notification,
                 sequence_number,
                 akd,
+                akd_storage,
+                vrf,
+                ship_batches,
+                certificate: None,
+                pending_queries: Vec::new(),
             }
             .run()
             .await;
@@ -69,32 +145,33 @@ where
         storage: &Storage,
         tx_notification: &Sender<PublishNotification>,
     ) -> SequenceNumber {
-        match storage
-            .read(&STORE_LAST_NOTIFICATION_ADDR)
-            .expect("Failed to load last notification from storage")
+        let last = match load_last_notification(storage) {
+            Some(notification) => notification,
+            None => return SequenceNumber::default(),
+        };
+        let sequence_number = last.sequence_number;
+
+        // Try to re-broadcast all the notifications that are not yet certified. This is useful in
+        // case the IdP crashes after updating its last notification but before successfully
+        // broadcasting it. Otherwise it will have no effect (witnesses are idempotent).
+        let mut notifications = load_pending_notifications(storage);
+        if notifications
+            .last()
+            .map_or(true, |x| x.sequence_number < sequence_number)
         {
-            Some(serialized) => {
-                // Deserialize the notification and extract its sequence number.
-                let notification: PublishNotification =
-                    bincode::deserialize(&serialized).expect("Failed to deserialize notification");
-                let sequence_number = notification.sequence_number;
-
-                // Try to re-broadcast it. This is useful in case the IdP crashes after updating its
-                // last notification but before successfully broadcasting it. Otherwise it will have
-                // no effect (witnesses are idempotent).
-                tx_notification
-                    .send(notification)
-                    .await
-                    .expect("Failed to deliver serialized notification");
-
-                sequence_number
-            }
-            None => SequenceNumber::default(),
+            notifications.push(last);
+        }
+        for notification in notifications {
+            tx_notification
+                .send(notification)
+                .await
+                .expect("Failed to deliver serialized notification");
         }
+        sequence_number
     }
 
     /// Compute an audit proof from a batch of requests.
-    async fn make_proof(&mut self, batch: Batch) -> (Root, Proof) {
+    async fn make_proof(&mut self, batch: Batch) -> IdpResult<(Root, Proof)> {
         let current = self.sequence_number;
         let next = current + 1;
 
@@ -102,48 +179,245 @@ where
         self.akd
             .publish::<Blake3>(batch)
             .await
-            .expect("Failed to persist publish request");
+            .map_err(|e| IdpError::PublishFailed(e.to_string()))?;
 
         // Extract the latest root.
-        let current_azks = self.akd.retrieve_current_azks().await.unwrap();
+        let current_azks = self
+            .akd
+            .retrieve_current_azks()
+            .await
+            .map_err(|e| IdpError::ProofGenerationFailed(e.to_string()))?;
         let root = self
             .akd
             .get_root_hash_at_epoch::<Blake3>(&current_azks, next)
             .await
-            .unwrap();
+            .map_err(|e| IdpError::ProofGenerationFailed(e.to_string()))?;
 
         // Generate the audit proof.
         let proof = self
             .akd
             .audit::<Blake3>(current, next)
             .await
-            .expect("Failed to create audit proof");
+            .map_err(|e| IdpError::ProofGenerationFailed(e.to_string()))?;
 
         // Output the latest root hash and the audit proof.
-        (root, proof)
+        Ok((root, proof))
+    }
+
+    /// Assemble the batch shipped to the full witnesses: the updates along with the VRF proofs of
+    /// their labels (computed before the updates are published to the directory).
+    async fn replica_batch(&self, batch: &Batch) -> ReplicaBatch {
+        let labels: Vec<_> = batch.iter().map(|(label, _)| label.clone()).collect();
+        let versions = self
+            .akd_storage
+            .get_user_state_versions(&labels, ValueStateRetrievalFlag::MaxEpoch)
+            .await
+            .expect("Failed to load the versions of the labels");
+
+        let mut proofs = Vec::new();
+        for label in &labels {
+            let version = versions.get(label).map_or(1, |(version, _)| version + 1);
+            let label_proofs = self
+                .vrf
+                .label_proofs::<Blake3>(label, version)
+                .await
+                .expect("Failed to compute VRF proofs");
+            proofs.extend(label_proofs);
+        }
+        ReplicaBatch {
+            updates: batch.clone(),
+            proofs,
+        }
+    }
+
+    /// Check whether the current state of the directory is certified.
+    fn is_certified(&self) -> bool {
+        self.certificate
+            .as_ref()
+            .map_or(false, |x| x.sequence_number == self.sequence_number)
+    }
+
+    /// Check whether a query can be answered. Lookups are proven against the last certified epoch
+    /// (the directory may run ahead of it), key histories against the current epoch.
+    fn can_serve(&self, query: &ClientQuery) -> bool {
+        match query {
+            ClientQuery::Lookup(..) => self.certificate.is_some(),
+            ClientQuery::KeyHistory(..) => self.is_certified(),
+        }
+    }
+
+    /// Compute a lookup proof against the last certified state of the directory.
+    async fn lookup(&self, request: LookupRequest) -> IdpResult<LookupResponse> {
+        let certificate = self.certificate.clone().ok_or(IdpError::NoCertifiedState)?;
+        let epoch = certificate.sequence_number;
+        let current_azks = self
+            .akd
+            .retrieve_current_azks()
+            .await
+            .map_err(|e| IdpError::ProofGenerationFailed(e.to_string()))?;
+        let info = self
+            .akd
+            .get_lookup_info::<Blake3>(request.label.clone(), epoch)
+            .await
+            .map_err(|e| IdpError::ProofGenerationFailed(e.to_string()))?;
+        let proof = self
+            .akd
+            .lookup_with_info::<Blake3>(request.label, &current_azks, epoch, info)
+            .await
+            .map_err(|e| IdpError::ProofGenerationFailed(e.to_string()))?;
+        Ok(LookupResponse { proof, certificate })
+    }
+
+    /// Compute a key history proof against the current (certified) state of the directory. The
+    /// certificates of the epochs in which the label was updated are added by the caller.
+    async fn key_history(&self, request: KeyHistoryRequest) -> IdpResult<KeyHistoryResponse> {
+        let certificate = self.certificate.clone().ok_or(IdpError::NoCertifiedState)?;
+        let proof = match self.akd.key_history::<Blake3>(&request.label).await {
+            Ok(proof) => proof,
+            // The label has no history before its first commit. Clients check the certificate to
+            // learn whether the label was due to be committed.
+            Err(e) => {
+                debug!("Failed to compute history of {:?}: {}", request, e);
+                return Err(IdpError::LabelNotFound(Box::new(certificate)));
+            }
+        };
+        Ok(KeyHistoryResponse {
+            proof,
+            certificate,
+            certificates: Vec::new(),
+        })
+    }
+
+    /// Check whether key history queries are waiting for the current state to be certified.
+    fn pending_histories(&self) -> bool {
+        self.pending_queries
+            .iter()
+            .any(|x| matches!(x, ClientQuery::KeyHistory(..)))
+    }
+
+    /// Serve a client query.
+    async fn serve(&self, query: ClientQuery) {
+        match query {
+            ClientQuery::Lookup(request, replier) => {
+                let _ = replier.send(self.lookup(request).await);
+            }
+            ClientQuery::KeyHistory(request, replier) => {
+                let _ = replier.send(self.key_history(request).await);
+            }
+        }
+    }
+
+    /// Reject a client query.
+    fn reject(query: ClientQuery, error: IdpError) {
+        match query {
+            ClientQuery::Lookup(_, replier) => {
+                let _ = replier.send(Err(error));
+            }
+            ClientQuery::KeyHistory(_, replier) => {
+                let _ = replier.send(Err(error));
+            }
+        }
     }
 
     /// Main loop receiving batches of client requests.
     async fn run(&mut self) {
-        while let Some(batch) = self.rx_batch.recv().await {
-            #[cfg(feature = "benchmark")]
-            Self::link_requests_and_notifications(self.sequence_number + 1, &batch);
+        loop {
+            tokio::select! {
+                // Stop processing new batches while key history queries are waiting for the current
+                // state to be certified. This ensures the directory does not run ahead of their
+                // certificate (lookups do not need to wait).
+                Some(requests) = self.rx_batch.recv(), if !self.pending_histories() => {
+                    let batch: Batch = requests.iter().map(|x| x.update.clone()).collect();
+                    #[cfg(feature = "benchmark")]
+                    Self::link_requests_and_notifications(self.sequence_number + 1, &batch);
 
-            // Compute the audit proof (CPU-intensive).
-            let (root, proof) = self.make_proof(batch).await;
+                    // Prove the labels of the batch for the full witnesses (if any).
+                    let replica_batch = if self.ship_batches {
+                        Some(self.replica_batch(&batch).await)
+                    } else {
+                        None
+                    };
 
-            // Increment the sequence number.
-            self.sequence_number += 1;
+                    // Compute the audit proof (CPU-intensive). A batch that fails to publish is
+                    // dropped (its clients do not find their updates in the promised batch).
+                    let (root, proof) = match self.make_proof(batch).await {
+                        Ok(x) => x,
+                        Err(e) => {
+                            warn!("{}", e);
+                            self.registry.abort(&requests);
+                            continue;
+                        }
+                    };
 
-            // Make a new publish notification.
-            let notification =
-                PublishNotification::new(root, proof, self.sequence_number, &self.keypair);
+                    // The updates are now committed to the directory: register the owners of
+                    // their labels.
+                    self.registry.commit(&requests);
 
-            // Send the notification to the broadcaster.
-            self.tx_notification
-                .send(notification)
-                .await
-                .expect("Failed to deliver serialized notification");
+                    // Increment the sequence number.
+                    self.sequence_number += 1;
+
+                    // Make a new publish notification. The first notification hands over the
+                    // directory to the next committee (if any).
+                    let activation = self.sequence_number + 1;
+                    let committee_change = self.next_committee.take().map(|committee| {
+                        self.storage
+                            .put(Column::State, STORE_NEXT_COMMITTEE_KEY, &committee)
+                            .expect("Failed to persist next committee");
+                        CommitteeChange::new(committee, activation)
+                    });
+                    let mut notification = PublishNotification::with_committee_change(
+                        root,
+                        proof,
+                        self.sequence_number,
+                        committee_change,
+                        &self.keypair,
+                    );
+
+                    // Ship the batch along so that full witnesses can replay it. It is also kept
+                    // to let full witnesses catch up on the batches they miss.
+                    if let Some(batch) = replica_batch {
+                        self.storage
+                            .put(Column::Batches, &self.sequence_number, &batch)
+                            .expect("Failed to persist batch");
+                        notification = notification.with_batch(batch);
+                    }
+
+                    // Send the notification to the broadcaster.
+                    self.tx_notification
+                        .send(notification)
+                        .await
+                        .expect("Failed to deliver serialized notification");
+                },
+
+                // Receive the certificates assembled by the publisher.
+                Some(certificate) = self.rx_committed_certificate.recv() => {
+                    debug!("Prover received {:?}", certificate);
+                    self.certificate = Some(certificate);
+
+                    // Serve the queries that were waiting for this certificate.
+                    let pending: Vec<_> = self.pending_queries.drain(..).collect();
+                    for query in pending {
+                        if self.can_serve(&query) {
+                            self.serve(query).await;
+                        } else {
+                            self.pending_queries.push(query);
+                        }
+                    }
+                },
+
+                // Receive queries from the clients.
+                Some(query) = self.rx_query.recv() => {
+                    debug!("Received {:?}", query);
+                    if self.can_serve(&query) {
+                        self.serve(query).await;
+                    } else if self.sequence_number == SequenceNumber::default() {
+                        // There is nothing to query before the first notification.
+                        Self::reject(query, IdpError::NoCertifiedState);
+                    } else {
+                        self.pending_queries.push(query);
+                    }
+                }
+            }
         }
     }
 
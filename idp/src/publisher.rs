@@ -1,30 +1,54 @@
 use crate::{
     aggregator::Aggregator,
     synchronizer::{NewCertificate, SyncTrigger},
-    STORE_LAST_NOTIFICATION_ADDR,
+    STORE_COMMITTEES_KEY, STORE_LAST_NOTIFICATION_KEY, STORE_PENDING_NOTIFICATIONS_KEY,
 };
 use bytes::Bytes;
 use config::Committee;
-use crypto::PublicKey;
+use crypto::{KeyPair, PublicKey};
 use futures::stream::{futures_unordered::FuturesUnordered, StreamExt};
 use log::{debug, info, warn};
 use messages::{
     error::{IdpError, IdpResult, WitnessError},
-    publish::{PublishNotification, PublishVote},
-    IdPToWitnessMessage, Root, SequenceNumber, WitnessToIdPMessage,
+    publish::{PublishCertificate, PublishNotification, PublishVote},
+    reconfiguration::CommitteeHistory,
+    IdPToWitnessMessage, SequenceNumber, WitnessToIdPMessage,
 };
-use network::reliable_sender::{CancelHandler, ReliableSender};
-use std::net::SocketAddr;
-use storage::Storage;
+use network::{
+    reliable_sender::{BufferLimits, CancelHandler, OverflowPolicy, ReliableSender},
+    transport::Transport,
+};
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+use storage::{Column, Storage};
 use tokio::{
     sync::{
         mpsc::{Receiver, Sender},
         oneshot,
     },
     task::JoinHandle,
+    time::{sleep, Duration, Instant},
 };
 
+/// The delay before re-sending the notifications that failed to gather a quorum of votes (in ms).
+const RETRY_DELAY: u64 = 1_000;
+
+/// A publish notification waiting for a certificate.
+struct PendingNotification {
+    /// The notification.
+    notification: PublishNotification,
+    /// The serialized notification (to re-submit it to outdated witnesses).
+    serialized: Bytes,
+    /// A votes aggregator to assemble a quorum of votes into a certificate.
+    aggregator: Aggregator,
+    /// The witnesses that replied while behind in the pipeline (with the sequence number they
+    /// are at). The notification is re-submitted to them once that sequence number is certified.
+    deferred: Vec<(PublicKey, SequenceNumber)>,
+    /// The last time the notification was sent to the witnesses.
+    sent: Instant,
+}
+
 /// Broadcast publish notifications to the witnesses, gather votes and broadcast certificates.
+/// Up to `pipeline_depth` notifications may wait for their certificate at the same time.
 pub struct Publisher {
     /// The persistent storage.
     storage: Storage,
@@ -34,44 +58,86 @@ pub struct Publisher {
     tx_trigger: Sender<SyncTrigger>,
     /// Deliver newly created certificates.
     tx_certificate: Sender<NewCertificate>,
+    /// Deliver newly created certificates to the prover (once they are safely stored).
+    tx_committed_certificate: Sender<PublishCertificate>,
     /// A reliable network sender.
     network: ReliableSender,
+    /// The committees in charge of certifying each range of sequence numbers.
+    committees: CommitteeHistory,
     /// The public keys of the witnesses (in the same order as the `addresses` field).
     names: Vec<PublicKey>,
     /// The network addresses of the witnesses (in the same order as the `names` field).
     addresses: Vec<SocketAddr>,
-    /// A votes aggregator to assemble a quorum of votes into a certificate.
-    aggregator: Aggregator,
+    /// The maximum number of notifications waiting for a certificate.
+    pipeline_depth: usize,
+    /// The notifications waiting for a certificate (indexed by sequence number).
+    pending: BTreeMap<SequenceNumber, PendingNotification>,
+    /// The certificates waiting for the certificates of the previous notifications.
+    certified: BTreeMap<SequenceNumber, PublishCertificate>,
 }
 
 impl Publisher {
-    /// Spawn a new broadcaster.
+    /// Spawn a new broadcaster. The committee is the genesis committee; any later committee is
+    /// loaded from storage.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
+        keypair: KeyPair,
         committee: Committee,
         storage: Storage,
         rx_notification: Receiver<PublishNotification>,
         tx_trigger: Sender<SyncTrigger>,
         tx_certificate: Sender<NewCertificate>,
+        tx_committed_certificate: Sender<PublishCertificate>,
+        pipeline_depth: usize,
+        transport: Arc<dyn Transport>,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            let (names, addresses) = committee.witnesses_addresses().into_iter().unzip();
+            // Try to load the committees from storage.
+            let committees = storage
+                .get(Column::State, STORE_COMMITTEES_KEY)
+                .expect("Failed to load committees from storage")
+                .unwrap_or_else(|| CommitteeHistory::new(committee));
+
+            let (names, addresses) = committees
+                .current()
+                .witnesses_addresses()
+                .into_iter()
+                .unzip();
+
+            // Only the latest messages matter to a witness that falls behind (it catches up with
+            // the synchronizer), so we drop the oldest ones first.
+            let limits = BufferLimits {
+                policy: OverflowPolicy::DropOldest,
+                ..BufferLimits::default()
+            };
             Self {
                 storage,
                 rx_notification,
                 tx_trigger,
                 tx_certificate,
-                network: ReliableSender::new(),
+                tx_committed_certificate,
+                network: ReliableSender::with_transport(keypair, limits, transport),
+                committees,
                 names,
                 addresses,
-                // The aggregator will be reset with the correct root hash upon receiving the
-                // first publish notification.
-                aggregator: Aggregator::new(committee, Root::default()),
+                pipeline_depth,
+                pending: BTreeMap::new(),
+                certified: BTreeMap::new(),
             }
             .run()
             .await;
         })
     }
 
+    /// Return the public keys and network addresses of the witnesses.
+    fn peers(&self) -> Vec<(PublicKey, SocketAddr)> {
+        self.names
+            .iter()
+            .cloned()
+            .zip(self.addresses.iter().cloned())
+            .collect()
+    }
+
     /// Tell the synchronizer to update a witness and then resubmit the notification.
     async fn sync_and_retry(
         &mut self,
@@ -82,6 +148,10 @@ impl Publisher {
         let (sender, receiver) = oneshot::channel();
         let message = SyncTrigger {
             target,
+            address: self
+                .committees
+                .witness_address(&target)
+                .unwrap_or_else(|| panic!("Tried to update unknown witness {}", target)),
             retry: Some((notification, sender)),
             sequence_number,
         };
@@ -100,126 +170,305 @@ impl Publisher {
         }
     }
 
-    /// Helper function. It waits for a future to complete and then delivers a value.
-    async fn waiter(wait_for: CancelHandler, author: PublicKey) -> (Bytes, PublicKey) {
-        let reply = wait_for
-            .await
-            .expect("Failed to receive response from network");
-        (reply, author)
+    /// Helper function. It waits for a future to complete and then delivers a value. The reply is
+    /// `None` if the network dropped the message (the witness is saturated).
+    async fn waiter<T>(wait_for: CancelHandler, tag: T) -> (Option<Bytes>, T) {
+        (wait_for.await.ok(), tag)
+    }
+
+    /// Hand over to the latest committee.
+    fn reconfigure(&mut self) {
+        let committee = self.committees.current().clone();
+        info!("Handing over to {} witnesses", committee.size());
+
+        // Persist the committees to storage.
+        self.storage
+            .put(Column::State, STORE_COMMITTEES_KEY, &self.committees)
+            .expect("Failed to persist committees");
+
+        // Send the next notifications to the new committee.
+        let (names, addresses) = committee.witnesses_addresses().into_iter().unzip();
+        self.names = names;
+        self.addresses = addresses;
+    }
+
+    /// Check whether the pipeline can accommodate another notification. The publisher does not
+    /// pipeline notifications after a committee change: they are addressed to the new committee.
+    fn ready(&self) -> bool {
+        self.pending.len() < self.pipeline_depth
+            && self
+                .pending
+                .values()
+                .all(|x| x.notification.committee_change.is_none())
+    }
+
+    /// Persist the notifications waiting for a certificate (to re-broadcast them after a crash).
+    fn persist_pending(&self) {
+        let notifications: Vec<_> = self.pending.values().map(|x| &x.notification).collect();
+        self.storage
+            .put(
+                Column::State,
+                STORE_PENDING_NOTIFICATIONS_KEY,
+                &notifications,
+            )
+            .expect("Failed to persist pending notifications");
     }
 
     /// Publish a new update to the witnesses.
     async fn publish(
         &mut self,
         notification: PublishNotification,
-    ) -> Vec<(CancelHandler, PublicKey)> {
+    ) -> Vec<(CancelHandler, (PublicKey, SequenceNumber))> {
         let sequence_number = notification.sequence_number;
 
-        // Reset the aggregator to hold the votes for ths notification.
-        self.aggregator.reset(notification.root);
+        // Make an aggregator to hold the votes for this notification.
+        let aggregator = Aggregator::new(
+            self.committees.current().clone(),
+            notification.root,
+            notification.committee_change.clone(),
+        );
 
         // Serialize the notification.
-        let message = IdPToWitnessMessage::PublishNotification(notification);
+        let message = IdPToWitnessMessage::PublishNotification(notification.clone());
         let serialized_notification =
             bincode::serialize(&message).expect("Failed to serialize notification");
 
         // Persist the last notification to storage.
         self.storage
-            .write(&STORE_LAST_NOTIFICATION_ADDR, &serialized_notification)
+            .put(Column::State, STORE_LAST_NOTIFICATION_KEY, &notification)
             .expect("Failed to persist notification");
 
-        // Broadcast the publish notification to the witnesses.
+        // Add the notification to the pipeline.
         let bytes_notification = Bytes::from(serialized_notification);
-        let addresses = self.addresses.clone();
-        let mut wait_for_quorum: FuturesUnordered<_> = self
-            .network
-            .broadcast(addresses, bytes_notification.clone())
+        let pending = PendingNotification {
+            notification,
+            serialized: bytes_notification.clone(),
+            aggregator,
+            deferred: Vec::new(),
+            sent: Instant::now(),
+        };
+        self.pending.insert(sequence_number, pending);
+        self.persist_pending();
+
+        // Broadcast the publish notification to the witnesses.
+        let peers = self.peers();
+        self.network
+            .broadcast(peers, bytes_notification)
             .await
             .into_iter()
-            .zip(self.names.iter().cloned())
-            .map(|(handle, name)| Self::waiter(handle, name))
-            .collect();
-
-        // Collect the votes and assemble a certificate.
-        while let Some((reply, author)) = wait_for_quorum.next().await {
-            // Deserialize the reply.
-            let message: WitnessToIdPMessage = match bincode::deserialize(&reply) {
-                Ok(x) => x,
-                Err(e) => {
-                    warn!("{:?}", e);
-                    continue;
-                }
-            };
+            .zip(self.names.iter().map(|name| (*name, sequence_number)))
+            .collect()
+    }
 
-            // Check if the witness is out of date. If that is the case, update it.
-            if let Some(status) = message.sequence_number() {
-                if status < sequence_number {
-                    debug!("{} is outdated ({} < {})", author, status, sequence_number);
-                    let last_notification = bytes_notification.clone();
-                    let handle = self.sync_and_retry(author, status, last_notification).await;
-                    wait_for_quorum.push(Self::waiter(handle, author));
-                    continue;
-                }
+    /// Process the reply of a witness to a publish notification. Return a handle to the reply to
+    /// the re-submitted notification if the witness needs to be updated first.
+    async fn process_vote(
+        &mut self,
+        reply: Bytes,
+        author: PublicKey,
+        sequence_number: SequenceNumber,
+    ) -> Option<CancelHandler> {
+        // Ignore replies to notifications that are already certified (or committed).
+        if self.certified.contains_key(&sequence_number) {
+            return None;
+        }
+        let notification = self.pending.get(&sequence_number)?.serialized.clone();
+
+        // Deserialize the reply.
+        let message: WitnessToIdPMessage = match bincode::deserialize(&reply) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("{:?}", e);
+                return None;
             }
+        };
 
-            // Finally parse the publish vote.
-            let vote = match Self::parse_notification_reply(message) {
-                Ok(vote) => {
-                    debug!("Received {:?}", vote);
-                    vote
-                }
-                Err(e) => {
-                    warn!("{:?}", e);
-                    continue;
+        // Check if the witness is out of date. If that is the case, update it.
+        if let Some(status) = message.sequence_number() {
+            if status < sequence_number {
+                // The witness only misses earlier notifications of the pipeline. There is no need
+                // to update it: it catches up with the next certificates, after which we
+                // re-submit the notification.
+                if self.pending.contains_key(&status) {
+                    debug!(
+                        "{} is behind in the pipeline ({} < {})",
+                        author, status, sequence_number
+                    );
+                    if let Some(pending) = self.pending.get_mut(&sequence_number) {
+                        pending.deferred.push((author, status));
+                    }
+                    return None;
                 }
-            };
 
-            // Check if we got enough votes to make a certificate.
-            let potential_certificate = match self.aggregator.append(vote) {
-                Ok(x) => x,
-                Err(e) => {
-                    warn!("{}", e);
-                    continue;
-                }
+                debug!("{} is outdated ({} < {})", author, status, sequence_number);
+                let handle = self.sync_and_retry(author, status, notification).await;
+                return Some(handle);
+            }
+        }
+
+        // Finally parse the publish vote.
+        let vote = match Self::parse_notification_reply(message) {
+            Ok(vote) => {
+                debug!("Received {:?}", vote);
+                vote
+            }
+            Err(e) => {
+                warn!("{:?}", e);
+                return None;
+            }
+        };
+
+        // Check if we got enough votes to make a certificate.
+        let aggregator = &mut self
+            .pending
+            .get_mut(&sequence_number)
+            .expect("Pending notifications are not certified")
+            .aggregator;
+        match aggregator.append(vote) {
+            Ok(Some(certificate)) => {
+                self.certified.insert(sequence_number, certificate);
+            }
+            Ok(None) => (),
+            Err(e) => warn!("{}", e),
+        }
+        None
+    }
+
+    /// Commit the certificates (in the order of their sequence numbers) and broadcast them to the
+    /// witnesses. Return the handles to the witnesses' replies.
+    async fn commit(&mut self) -> Vec<(CancelHandler, PublicKey)> {
+        let mut handles = Vec::new();
+        while let Some(certificate) = self
+            .pending
+            .keys()
+            .next()
+            .and_then(|x| self.certified.remove(x))
+        {
+            let sequence_number = certificate.sequence_number;
+            debug!("Commit {:?}", certificate);
+            // NOTE: This log entry is used to compute performance.
+            info!("Commit {}", certificate);
+
+            // Serialize the certificate.
+            let message = IdPToWitnessMessage::PublishCertificate(certificate.clone());
+            let serialized = bincode::serialize(&message).expect("Failed to serialize certificate");
+
+            // Send it to the synchronizer and ensure it is correctly stored.
+            let (sender, receiver) = oneshot::channel();
+            let message = NewCertificate {
+                sequence_number,
+                certificate: serialized.clone(),
+                ack: sender,
             };
-            if let Some(certificate) = potential_certificate {
-                debug!("Commit {:?}", certificate);
-                // NOTE: This log entry is used to compute performance.
-                info!("Commit {}", certificate);
-
-                // Serialize the certificate.
-                let message = IdPToWitnessMessage::PublishCertificate(certificate);
-                let serialized =
-                    bincode::serialize(&message).expect("Failed to serialize certificate");
-
-                // Send it to the synchronizer and ensure it is correctly stored.
-                let (sender, receiver) = oneshot::channel();
-                let message = NewCertificate {
-                    sequence_number,
-                    certificate: serialized.clone(),
-                    ack: sender,
-                };
-                self.tx_certificate
-                    .send(message)
-                    .await
-                    .expect("Failed to deliver certificate");
-                receiver.await.expect("Failed to ack new certificate");
-
-                // Broadcast the certificate to the witnesses.
-                let bytes = Bytes::from(serialized);
-                let handles = self
-                    .network
-                    .broadcast(self.addresses.clone(), bytes)
+            self.tx_certificate
+                .send(message)
+                .await
+                .expect("Failed to deliver certificate");
+            receiver.await.expect("Failed to ack new certificate");
+
+            // Remove the notification from the pipeline.
+            self.pending.remove(&sequence_number);
+            self.persist_pending();
+
+            // Record the committee change (if any) before handing the certificate over.
+            let reconfigure = self.committees.update(&certificate);
+
+            // Let the prover serve lookups against the newly certified state.
+            self.tx_committed_certificate
+                .send(certificate)
+                .await
+                .expect("Failed to deliver certificate to prover");
+
+            // Broadcast the certificate to the witnesses. We skip the saturated witnesses: they
+            // reply to the next notification they receive as outdated and we then update them.
+            let peers: Vec<_> = self
+                .peers()
+                .into_iter()
+                .filter(|(name, address)| !self.network.is_saturated(name, address))
+                .collect();
+            let names: Vec<_> = peers.iter().map(|(name, _)| *name).collect();
+            let bytes = Bytes::from(serialized);
+            handles.extend(
+                self.network
+                    .broadcast(peers, bytes)
                     .await
                     .into_iter()
-                    .zip(self.names.iter().cloned())
-                    .collect();
+                    .zip(names),
+            );
 
-                // Stop waiting for votes.
-                return handles;
+            // Hand over to the new committee (if the certificate changes it).
+            if reconfigure {
+                self.reconfigure();
             }
         }
-        panic!("Failed to gather quorum of votes");
+        handles
+    }
+
+    /// Re-submit the pipelined notifications to the witnesses that were behind in the pipeline and
+    /// that received since the certificates they were missing. Return the handles to their votes.
+    async fn resubmit_deferred(&mut self) -> Vec<(CancelHandler, (PublicKey, SequenceNumber))> {
+        // The certificates of all the sequence numbers before the first pending notification
+        // are committed (and broadcast).
+        let first = match self.pending.keys().next() {
+            Some(sequence_number) => *sequence_number,
+            None => return Vec::new(),
+        };
+
+        let mut resubmissions = Vec::new();
+        for (sequence_number, pending) in self.pending.iter_mut() {
+            let (ready, deferred): (Vec<_>, Vec<_>) = pending
+                .deferred
+                .drain(..)
+                .partition(|(_, status)| *status < first);
+            pending.deferred = deferred;
+            for (author, _) in ready {
+                resubmissions.push((author, *sequence_number, pending.serialized.clone()));
+            }
+        }
+
+        let mut handles = Vec::new();
+        for (author, sequence_number, notification) in resubmissions {
+            if let Some(address) = self.committees.witness_address(&author) {
+                let handle = self.network.send(author, address, notification).await;
+                handles.push((handle, (author, sequence_number)));
+            }
+        }
+        handles
+    }
+
+    /// Re-send the pipelined notifications that failed to gather a quorum of votes in time (e.g.,
+    /// because the network dropped some of them) to the witnesses whose vote is not counted yet.
+    /// The witnesses deferred behind an earlier notification are re-submitted the notification
+    /// once they catch up. Witnesses are idempotent.
+    async fn rebroadcast(&mut self) -> Vec<(CancelHandler, (PublicKey, SequenceNumber))> {
+        let now = Instant::now();
+        let peers = self.peers();
+        let mut resubmissions = Vec::new();
+        for (sequence_number, pending) in self.pending.iter_mut() {
+            let expired = now.duration_since(pending.sent) >= Duration::from_millis(RETRY_DELAY);
+            if !expired || self.certified.contains_key(sequence_number) {
+                continue;
+            }
+            pending.sent = now;
+            for (name, address) in &peers {
+                let deferred = pending.deferred.iter().any(|(x, _)| x == name);
+                if !deferred && !pending.aggregator.voted(name) {
+                    resubmissions.push((
+                        *name,
+                        *address,
+                        *sequence_number,
+                        pending.serialized.clone(),
+                    ));
+                }
+            }
+        }
+
+        let mut handles = Vec::new();
+        for (name, address, sequence_number, notification) in resubmissions {
+            let handle = self.network.send(name, address, notification).await;
+            handles.push((handle, (name, sequence_number)));
+        }
+        handles
     }
 
     /// Analyses the witnesses response to IdP's publishes certificates.
@@ -240,6 +489,10 @@ impl Publisher {
                 debug!("{} is outdated (latest sequence number: {})", author, s);
                 let message = SyncTrigger {
                     target: author,
+                    address: self
+                        .committees
+                        .witness_address(&author)
+                        .unwrap_or_else(|| panic!("Tried to update unknown witness {}", author)),
                     retry: None,
                     sequence_number: s,
                 };
@@ -255,24 +508,68 @@ impl Publisher {
 
     /// Main loop receiving new notifications to publish.
     async fn run(&mut self) {
-        // Gather certificates handles to receive state ack.
-        // TODO: Make this memory-bound (like the synchronizer). A bad witness can make us run out
-        // of memory by never replying to our certificates.
+        // Gather notifications handles to receive votes.
+        let mut votes = FuturesUnordered::new();
+
+        // Gather certificates handles to receive state ack. A bad witness cannot make us run out of
+        // memory by never replying to our certificates: the network drops them once the
+        // connection holds too many messages.
         let mut state_responses = FuturesUnordered::new();
 
+        // Periodically re-send the notifications that are waiting for votes for too long. The
+        // timer does not depend on the replies still expected: the ones of a crashed witness never
+        // arrive.
+        let timer = sleep(Duration::from_millis(RETRY_DELAY));
+        tokio::pin!(timer);
+
         loop {
             tokio::select! {
-                // Receive serialized publish notifications.
-                Some(notification) = self.rx_notification.recv() => self
+                // Receive serialized publish notifications (as long as the pipeline is not full).
+                Some(notification) = self.rx_notification.recv(), if self.ready() => self
                     .publish(notification)
                     .await
                     .into_iter()
-                    .for_each(|(handle, author)| state_responses.push(Self::waiter(handle, author))),
+                    .for_each(|(handle, tag)| votes.push(Self::waiter(handle, tag))),
+
+                // Receive votes from the witnesses.
+                Some((reply, (author, sequence_number))) = votes.next() => {
+                    let retry = match reply {
+                        Some(reply) => self.process_vote(reply, author, sequence_number).await,
+                        None => None
+                    };
+                    if let Some(handle) = retry {
+                        votes.push(Self::waiter(handle, (author, sequence_number)));
+                    }
+
+                    // Commit and broadcast the certificates assembled so far.
+                    for (handle, author) in self.commit().await {
+                        state_responses.push(Self::waiter(handle, author));
+                    }
+
+                    // Re-submit the pipelined notifications to the witnesses that caught up.
+                    for (handle, tag) in self.resubmit_deferred().await {
+                        votes.push(Self::waiter(handle, tag));
+                    }
+                },
+
+                // Re-send the notifications that failed to gather a quorum of votes.
+                () = &mut timer => {
+                    let handles = self.rebroadcast().await;
+                    if !handles.is_empty() {
+                        warn!("Failed to gather quorum of votes, re-sending notifications");
+                    }
+                    for (handle, tag) in handles {
+                        votes.push(Self::waiter(handle, tag));
+                    }
+                    timer.as_mut().reset(Instant::now() + Duration::from_millis(RETRY_DELAY));
+                },
 
                 // Receive state ack from the witnesses.
-                Some((reply, author)) = state_responses.next() => self
-                    .analyze_state_response(reply,author)
-                    .await,
+                Some((reply, author)) = state_responses.next() => {
+                    if let Some(reply) = reply {
+                        self.analyze_state_response(reply, author).await;
+                    }
+                },
             }
         }
     }
@@ -0,0 +1,113 @@
+use akd::storage::types::AkdLabel;
+use crypto::PublicKey;
+use messages::{
+    ensure,
+    error::{IdpError, IdpResult},
+    update::SignedUpdateRequest,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use storage::{Column, Storage};
+
+/// Keeps track of the owner of each label and of the last nonce they used. They are only persisted
+/// once the update commits: until then, they are held in memory (and forgotten if the IdP crashes
+/// before committing the update).
+#[derive(Clone)]
+pub struct Registry {
+    /// The persistent storage.
+    storage: Storage,
+    /// The owner and nonce of the authenticated updates that are not yet committed (indexed by
+    /// label).
+    pending: Arc<Mutex<HashMap<Vec<u8>, (PublicKey, u64)>>>,
+}
+
+impl Registry {
+    /// Create a new registry backed by the specified storage.
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            storage,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Load the owner of a label and the last nonce they used (if the label is registered).
+    fn load(&self, label: &AkdLabel) -> Option<(PublicKey, u64)> {
+        self.storage
+            .get(Column::Registry, label)
+            .expect("Failed to load label owner")
+    }
+
+    /// Authenticate an update request against the owner of its label. The first correctly
+    /// signed request for a label makes its signer the owner of the label (once the request is
+    /// registered and its update commits).
+    pub fn authenticate(&self, request: &SignedUpdateRequest) -> IdpResult<()> {
+        request.verify()?;
+
+        let (label, _) = &request.update;
+        let pending = self.pending.lock().unwrap();
+        let latest = pending.get(&label.0).cloned().or_else(|| self.load(label));
+        if let Some((owner, last)) = latest {
+            ensure!(
+                owner == request.owner,
+                IdpError::UnauthorizedUpdate {
+                    owner,
+                    got: request.owner
+                }
+            );
+            ensure!(
+                request.nonce > last,
+                IdpError::StaleNonce {
+                    last,
+                    got: request.nonce
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Hold the owner and nonce of an authenticated request until its update commits.
+    pub fn register(&self, request: &SignedUpdateRequest) {
+        let (label, _) = &request.update;
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(label.0.clone(), (request.owner, request.nonce));
+    }
+
+    /// Forget the requests of a batch that failed to publish (later requests for their labels are
+    /// authenticated against the last committed nonce again).
+    pub fn abort(&self, requests: &[SignedUpdateRequest]) {
+        let mut pending = self.pending.lock().unwrap();
+        for request in requests {
+            let (label, _) = &request.update;
+            if pending.get(&label.0) == Some(&(request.owner, request.nonce)) {
+                pending.remove(&label.0);
+            }
+        }
+    }
+
+    /// Persist (atomically) the owner of the labels of a committed batch and the last nonce they
+    /// used.
+    pub fn commit(&self, requests: &[SignedUpdateRequest]) {
+        let mut pending = self.pending.lock().unwrap();
+        let entries: Vec<_> = requests
+            .iter()
+            .map(|request| {
+                let (label, _) = &request.update;
+                let record = (request.owner, request.nonce);
+
+                // Keep holding the label if a later update is pending.
+                if pending.get(&label.0) == Some(&record) {
+                    pending.remove(&label.0);
+                }
+                let value = bincode::serialize(&record).expect("Failed to serialize label owner");
+                (label.0.clone(), value)
+            })
+            .collect();
+        self.storage
+            .write_batch(Column::Registry, entries)
+            .expect("Failed to persist label owners");
+    }
+}
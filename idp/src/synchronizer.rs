@@ -1,12 +1,14 @@
 use bytes::Bytes;
-use config::Committee;
-use crypto::PublicKey;
+use crypto::{KeyPair, PublicKey};
 use futures::stream::{futures_unordered::FuturesUnordered, StreamExt};
-use log::debug;
-use messages::SequenceNumber;
-use network::reliable_sender::{CancelHandler, ReliableSender};
-use std::collections::HashMap;
-use storage::Storage;
+use log::{debug, warn};
+use messages::{publish::PublishCertificate, IdPToWitnessMessage, SequenceNumber};
+use network::{
+    reliable_sender::{BufferLimits, CancelHandler, ReliableSender},
+    transport::Transport,
+};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use storage::{Column, Storage};
 use tokio::{
     sync::{mpsc::Receiver, oneshot},
     task::JoinHandle,
@@ -20,6 +22,8 @@ const MAX_PENDING_UPDATES: usize = 100;
 pub struct SyncTrigger {
     /// The witness to update.
     pub target: PublicKey,
+    /// The network address of the witness.
+    pub address: SocketAddr,
     /// The current sequence number of that witness.
     pub sequence_number: SequenceNumber,
     /// An optional message to resent after the witness is updated.
@@ -37,16 +41,25 @@ pub struct NewCertificate {
     pub ack: oneshot::Sender<()>,
 }
 
+/// Request to load stored certificates.
+#[derive(Debug)]
+pub struct CertificateQuery {
+    /// The sequence numbers of the requested certificates.
+    pub sequence_numbers: Vec<SequenceNumber>,
+    /// A channel to reply with the certificates found in storage.
+    pub replier: oneshot::Sender<Vec<PublishCertificate>>,
+}
+
 /// Updates witness by providing publish certificates.
 pub struct Synchronizer {
-    /// The committee information.
-    committee: Committee,
     /// The persistent storage.
     storage: Storage,
     /// Receive signals to update a witness.
     rx_trigger: Receiver<SyncTrigger>,
     /// Receive newly created IdP's certificates.
     rx_certificate: Receiver<NewCertificate>,
+    /// Receive requests to load certificates from storage.
+    rx_certificate_query: Receiver<CertificateQuery>,
     /// Holds the sequence number of the IdP.
     sequence_number: SequenceNumber,
     /// A reliable network sender.
@@ -59,21 +72,31 @@ pub struct Synchronizer {
 impl Synchronizer {
     /// Spawn a new `Synchronizer` task.
     pub fn spawn(
-        committee: Committee,
+        keypair: KeyPair,
         storage: Storage,
         rx_trigger: Receiver<SyncTrigger>,
         rx_certificate: Receiver<NewCertificate>,
+        rx_certificate_query: Receiver<CertificateQuery>,
+        transport: Arc<dyn Transport>,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
+            // Resume from the last certificate in storage (if any).
+            let sequence_number = storage
+                .last(Column::Certificates)
+                .map(|(sequence_number, _)| sequence_number)
+                .unwrap_or_default();
+
             Self {
-                committee,
                 storage,
                 rx_trigger,
                 rx_certificate,
-                // It is ok to initialize the sequence number to zero. In the worst case, the IdP
-                // will need a bit before becoming able to update the witnesses.
-                sequence_number: SequenceNumber::default(),
-                network: ReliableSender::new(),
+                rx_certificate_query,
+                sequence_number,
+                network: ReliableSender::with_transport(
+                    keypair,
+                    BufferLimits::default(),
+                    transport,
+                ),
                 updates_in_progress: HashMap::new(),
             }
             .run()
@@ -85,48 +108,64 @@ impl Synchronizer {
     async fn update(
         &mut self,
         target: PublicKey,
+        address: SocketAddr,
         witness_sequence_number: SequenceNumber,
     ) -> Vec<CancelHandler> {
         debug!("Updating {}", target);
-        let address = self
-            .committee
-            .witness_address(&target)
-            .unwrap_or_else(|| panic!("Tried to update unknown witness {}", target));
 
-        // Try to send all missing certificates to the witness.
-        let mut handles = Vec::new();
-        for s in witness_sequence_number..=self.sequence_number {
-            // Ensure we didn't already reached the maximum pending updates for this witness.
-            if let Some(counter) = self.updates_in_progress.get_mut(&target) {
-                if *counter >= MAX_PENDING_UPDATES {
-                    break;
-                } else {
-                    *counter += 1;
-                }
+        // Ensure we don't exceed the maximum pending updates for this witness.
+        let counter = self.updates_in_progress.entry(target).or_default();
+        let budget = MAX_PENDING_UPDATES.saturating_sub(*counter);
+
+        // Load all missing certificates in a single scan of the storage. The scan stops at the first
+        // gap: the witness would reject the certificates following it.
+        let mut certificates = Vec::new();
+        let stored = self
+            .storage
+            .iter_from(Column::Certificates, &witness_sequence_number)
+            .take_while(|(s, _)| *s <= self.sequence_number)
+            .take(budget);
+        for (expected, (sequence_number, certificate)) in (witness_sequence_number..).zip(stored) {
+            if sequence_number != expected {
+                warn!("Missing certificate {} in storage", expected);
+                break;
             }
+            certificates.push(Bytes::from(certificate.into_vec()));
+        }
+        *counter += certificates.len();
 
-            // Load the certificate from storage and send it to the witness.
-            let certificate = self
-                .storage
-                .read(&s.to_le_bytes())
-                .expect("Failed to load certificate")
-                .unwrap_or_else(|| panic!("We should have certificate {}", s));
-
-            let bytes = Bytes::from(certificate);
-            let handle = self.network.send(address, bytes).await;
+        // Send them to the witness.
+        let mut handles = Vec::new();
+        for bytes in certificates {
+            let handle = self.network.send(target, address, bytes).await;
             handles.push(handle);
         }
         handles
     }
 
+    /// Load a certificate from storage (if we have it).
+    fn load_certificate(&self, sequence_number: SequenceNumber) -> Option<PublishCertificate> {
+        let serialized = self
+            .storage
+            .read(Column::Certificates, &sequence_number)
+            .expect("Failed to load certificate")?;
+        match bincode::deserialize(&serialized) {
+            Ok(IdPToWitnessMessage::PublishCertificate(certificate)) => Some(certificate),
+            _ => {
+                warn!("Failed to deserialize certificate {}", sequence_number);
+                None
+            }
+        }
+    }
+
     /// Helper function. It waits for a future to complete and then forwards it result through the sender.
+    /// If the network drops the message, dropping the sender notifies the publisher.
     async fn retrial_waiter(wait_for: CancelHandler, sender: oneshot::Sender<Bytes>) {
-        let bytes = wait_for
-            .await
-            .expect("Failed to receive response from network");
-        sender
-            .send(bytes)
-            .expect("Failed to deliver retried message");
+        if let Ok(bytes) = wait_for.await {
+            sender
+                .send(bytes)
+                .expect("Failed to deliver retried message");
+        }
     }
 
     /// Helper function. It waits for a future to complete and then delivers a value.
@@ -144,21 +183,25 @@ impl Synchronizer {
             tokio::select! {
                 // Receives signals to update a specific witness.
                 Some(trigger) = self.rx_trigger.recv() => {
-                    // Update the target node.
+                    // Do not pile up messages for a saturated witness: the publisher triggers
+                    // another update once the witness replies again.
                     let target = trigger.target;
+                    let address = trigger.address;
+                    if self.network.is_saturated(&target, &address) {
+                        debug!("Skipping update of saturated witness {}", target);
+                        continue;
+                    }
+
+                    // Update the target node.
                     let sequence_number = trigger.sequence_number;
-                    let handles = self.update(target, sequence_number).await;
+                    let handles = self.update(target, address, sequence_number).await;
                     for handle in handles {
                         pending_updates.push(Self::updates_waiter(handle, target));
                     }
 
                     // Retry to submit the last message (if any).
                     if let Some((message, sender)) = trigger.retry {
-                        let address = self
-                            .committee
-                            .witness_address(&target)
-                            .unwrap_or_else(|| panic!("Tried to update unknown witness {}", target));
-                        let handle = self.network.send(address, message).await;
+                        let handle = self.network.send(target, address, message).await;
                         pending_retrials.push(Self::retrial_waiter(handle, sender));
                     }
                 },
@@ -170,13 +213,23 @@ impl Synchronizer {
 
                     // Persist the new certificate.
                     self.storage
-                        .write(&self.sequence_number.to_le_bytes(), &message.certificate)
+                        .write(Column::Certificates, &self.sequence_number, &message.certificate)
                         .expect("Failed to persist certificate");
 
                     // Ack that the certificate is correctly stored.
                     message.ack.send(()).expect("Failed to ack receipt of new certificate");
                 },
 
+                // Serve certificates from storage.
+                Some(query) = self.rx_certificate_query.recv() => {
+                    let certificates = query
+                        .sequence_numbers
+                        .iter()
+                        .filter_map(|x| self.load_certificate(*x))
+                        .collect();
+                    let _ = query.replier.send(certificates);
+                },
+
                 // Pulls the futures.
                 Some(name) = pending_updates.next() => {
                     if let Some(counter) = self.updates_in_progress.get_mut(&name) {
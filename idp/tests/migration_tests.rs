@@ -0,0 +1,122 @@
+use akd::directory::Directory;
+use function_name::named;
+use idp::migrate_storage;
+use messages::{
+    legacy::{LegacyIdPToWitnessMessage, LegacyPublishNotification},
+    publish::PublishNotification,
+    vrf::IdpVrf,
+    Blake3, IdPToWitnessMessage,
+};
+use storage::{akd_storage::AkdStorage, Column, Storage};
+use test_utils::{
+    certificate, committee, delete_storage, keys, legacy_certificate, legacy_database,
+    notification, updates, vrf_keypair,
+};
+
+#[tokio::test]
+#[named]
+async fn migrate_legacy_storage() {
+    let base_port = 9_600;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let legacy_path = |name: &str| format!(".test_legacy_{}_storage_{}", name, test_id);
+
+    // Make the legacy secure storage (holding the last notification wrapped into its message).
+    let notification = notification().await;
+    let legacy_notification = LegacyPublishNotification {
+        root: notification.root,
+        proof: notification.proof.clone(),
+        sequence_number: notification.sequence_number,
+        id: notification.id.clone(),
+        signature: notification.signature.clone(),
+    };
+    let message = LegacyIdPToWitnessMessage::PublishNotification(legacy_notification);
+    legacy_database(
+        &legacy_path("secure"),
+        vec![(vec![255; 32], bincode::serialize(&message).unwrap())],
+    );
+
+    // Make the legacy sync storage (indexing certificates by little-endian sequence numbers).
+    let message = LegacyIdPToWitnessMessage::PublishCertificate(legacy_certificate().await);
+    legacy_database(
+        &legacy_path("sync"),
+        vec![(
+            1u64.to_le_bytes().to_vec(),
+            bincode::serialize(&message).unwrap(),
+        )],
+    );
+
+    // Make the legacy registry.
+    legacy_database(
+        &legacy_path("registry"),
+        vec![(b"label".to_vec(), b"owner".to_vec())],
+    );
+
+    // Make the legacy akd database: the records of a directory keyed by their binary id (without
+    // the index of value states, whose keys start with `u8::MAX`).
+    let scratch_path = legacy_path("scratch");
+    let _ = std::fs::remove_dir_all(&scratch_path);
+    let scratch = Storage::new(&scratch_path).unwrap();
+    let vrf = IdpVrf::new(&vrf_keypair().1);
+    let db = AkdStorage::with_storage(scratch.clone());
+    let akd = Directory::new::<Blake3>(&db, &vrf, false).await.unwrap();
+    akd.publish::<Blake3>(updates()).await.unwrap();
+    let records = scratch
+        .read_prefix(Column::Akd, &[])
+        .into_iter()
+        .filter(|(key, _)| key[0] != u8::MAX)
+        .map(|(key, value)| (key.to_vec(), value.to_vec()))
+        .collect();
+    legacy_database(&legacy_path("akd"), records);
+
+    // Migrate the legacy databases.
+    let storage_path = format!(".test_idp_storage_{}", test_id);
+    let _ = std::fs::remove_dir_all(&storage_path);
+    let storage = Storage::new(&storage_path).unwrap();
+    let (_, keypair) = keys().pop().unwrap();
+    migrate_storage(
+        &storage,
+        &keypair,
+        &legacy_path("secure"),
+        &legacy_path("sync"),
+        &legacy_path("registry"),
+        &legacy_path("akd"),
+    )
+    .unwrap();
+
+    // The last notification is signed again under the current format.
+    let received: PublishNotification = storage
+        .get(Column::State, "last_notification")
+        .unwrap()
+        .unwrap();
+    assert!(received.verify_signature(&committee).is_ok());
+    assert_eq!(received, notification);
+
+    // The certificate is translated into the current format (and remains valid).
+    let bytes = storage.read(Column::Certificates, &1u64).unwrap().unwrap();
+    match bincode::deserialize(&bytes).unwrap() {
+        IdPToWitnessMessage::PublishCertificate(received) => {
+            assert!(received.verify(&committee).is_ok());
+            assert_eq!(received, certificate().await);
+        }
+        _ => panic!("Unexpected message"),
+    }
+
+    // The registry is unchanged.
+    let owner = storage.read(Column::Registry, b"label".as_ref()).unwrap();
+    assert_eq!(owner, Some(b"owner".to_vec()));
+
+    // The directory serves lookups (and value states are indexed by label).
+    let db = AkdStorage::with_storage(storage.clone());
+    let akd = Directory::new::<Blake3>(&db, &vrf, false).await.unwrap();
+    for (label, value) in updates() {
+        let proof = akd.lookup::<Blake3>(label).await.unwrap();
+        assert_eq!(proof.plaintext_value, value);
+    }
+
+    // Delete the storage.
+    for name in ["secure", "sync", "registry", "akd", "scratch"] {
+        let _ = std::fs::remove_dir_all(legacy_path(name));
+    }
+    delete_storage(&test_id);
+}
@@ -1,9 +1,8 @@
 use function_name::named;
 use futures::future::try_join_all;
-use network::reliable_sender::ReliableSender;
 use test_utils::{
-    certificate, committee, delete_storage, keys, listener, notification, proof,
-    serialized_updates, spawn_test_idp,
+    certificate, client_sender, committee, delete_storage, keys, listener, memory_transport,
+    notification, proof, serialized_updates, spawn_test_idp_with_transport,
 };
 
 #[tokio::test]
@@ -13,9 +12,10 @@ async fn correct_update() {
     let committee = committee(base_port);
     let address = committee.idp.address;
     let test_id = function_name!();
+    let transport = memory_transport();
 
     // Spawn the IdP.
-    spawn_test_idp(&test_id, committee.clone());
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
     tokio::task::yield_now().await;
 
     // Spawn the listeners acting as witnesses.
@@ -23,14 +23,14 @@ async fn correct_update() {
         .into_iter()
         .map(|(name, key)| {
             let address = committee.witness_address(&name).unwrap();
-            listener(address, key)
+            listener(address, key, transport.clone())
         })
         .collect();
 
     // Send a enough correct updates to create a batch.
-    let mut network = ReliableSender::new();
+    let mut network = client_sender(transport);
     for update in serialized_updates() {
-        let handle = network.send(address, update).await;
+        let handle = network.send(committee.idp.name, address, update).await;
         handle.await.unwrap();
     }
 
@@ -56,9 +56,10 @@ async fn faulty_witness() {
     let committee = committee(base_port);
     let address = committee.idp.address;
     let test_id = function_name!();
+    let transport = memory_transport();
 
     // Spawn the IdP.
-    spawn_test_idp(&test_id, committee.clone());
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
     tokio::task::yield_now().await;
 
     // Spawn the listeners acting as witnesses.
@@ -67,14 +68,14 @@ async fn faulty_witness() {
         .skip(1)
         .map(|(name, key)| {
             let address = committee.witness_address(&name).unwrap();
-            listener(address, key)
+            listener(address, key, transport.clone())
         })
         .collect();
 
     // Send enough correct updates to create a batch.
-    let mut network = ReliableSender::new();
+    let mut network = client_sender(transport);
     for update in serialized_updates() {
-        let handle = network.send(address, update).await;
+        let handle = network.send(committee.idp.name, address, update).await;
         handle.await.unwrap();
     }
 
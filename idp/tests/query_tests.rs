@@ -0,0 +1,161 @@
+use akd::storage::types::AkdValue;
+use bytes::Bytes;
+use function_name::named;
+use futures::{sink::SinkExt, stream::StreamExt};
+use messages::{
+    history::KeyHistoryRequest, lookup::LookupRequest, reconfiguration::CommitteeHistory,
+    update::SignedUpdateRequest, ClientToIdPMessage, IdPToClientMessage,
+};
+use network::envelope::Envelope;
+use test_utils::{
+    client_keypair, client_sender, committee, delete_storage, memory_transport, serialized_updates,
+    spawn_test_idp_with_transport, spawn_test_witnesses_with_transport, updates,
+};
+use tokio::time::{sleep, timeout, Duration};
+
+#[tokio::test]
+#[named]
+async fn lookup() {
+    let base_port = 9_200;
+    let committee = committee(base_port);
+    let address = committee.idp.address;
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn the IdP and 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // Send enough correct updates to create a batch.
+    let mut network = client_sender(transport);
+    for update in serialized_updates() {
+        let handle = network.send(committee.idp.name, address, update).await;
+        handle.await.unwrap();
+    }
+
+    // Look up the first label until the IdP processed the batch.
+    let (label, value) = updates().into_iter().next().unwrap();
+    let request = LookupRequest {
+        label: label.clone(),
+    };
+    let message = ClientToIdPMessage::Lookup(request);
+    let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+    let response = loop {
+        let handle = network
+            .send(committee.idp.name, address, bytes.clone())
+            .await;
+        let reply = handle.await.unwrap();
+        match bincode::deserialize(&reply).unwrap() {
+            IdPToClientMessage::LookupResponse(Ok(response)) => break response,
+            IdPToClientMessage::LookupResponse(Err(_)) => sleep(Duration::from_millis(100)).await,
+            _ => panic!("Unexpected protocol message"),
+        }
+    };
+
+    // Ensure the response is valid and contains the expected value.
+    let committees = CommitteeHistory::new(committee.clone());
+    assert!(response.verify(&committees, &label).is_ok());
+    assert_eq!(response.value(), &value);
+    assert_eq!(response.version(), 1);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn malformed_request() {
+    let base_port = 9_500;
+    let committee = committee(base_port);
+    let address = committee.idp.address;
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn the IdP.
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // Send a request that does not parse as a client message.
+    let (mut writer, mut reader) = loop {
+        match transport
+            .connect(address, &client_keypair(), &committee.idp.name)
+            .await
+        {
+            Ok(channel) => break channel,
+            Err(_) => sleep(Duration::from_millis(100)).await,
+        }
+    };
+    let request = Envelope::new(0, Bytes::from("Malformed request"));
+    writer.send(request.encode()).await.unwrap();
+
+    // Ensure the IdP closes the connection without replying.
+    let frame = timeout(Duration::from_secs(5), reader.next())
+        .await
+        .unwrap();
+    assert!(!matches!(frame, Some(Ok(_))));
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn key_history() {
+    let base_port = 9_300;
+    let committee = committee(base_port);
+    let address = committee.idp.address;
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn the IdP and 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // Send two batches of updates, updating every label twice.
+    let mut network = client_sender(transport);
+    for update in serialized_updates() {
+        let handle = network.send(committee.idp.name, address, update).await;
+        handle.await.unwrap();
+    }
+    for (label, _) in updates() {
+        let update = (label, AkdValue(vec![3]));
+        let request = SignedUpdateRequest::new(update, /* nonce */ 2, &client_keypair());
+        let message = ClientToIdPMessage::Update(request);
+        let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+        let handle = network.send(committee.idp.name, address, bytes).await;
+        handle.await.unwrap();
+    }
+
+    // Request the history of the first label until the IdP processed both batches.
+    let (label, _) = updates().into_iter().next().unwrap();
+    let request = KeyHistoryRequest {
+        label: label.clone(),
+    };
+    let message = ClientToIdPMessage::KeyHistory(request);
+    let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+    let response = loop {
+        let handle = network
+            .send(committee.idp.name, address, bytes.clone())
+            .await;
+        let reply = handle.await.unwrap();
+        match bincode::deserialize(&reply).unwrap() {
+            IdPToClientMessage::KeyHistoryResponse(Ok(response))
+                if response.proof.proofs.len() == 2 =>
+            {
+                break response
+            }
+            IdPToClientMessage::KeyHistoryResponse(_) => sleep(Duration::from_millis(100)).await,
+            _ => panic!("Unexpected protocol message"),
+        }
+    };
+
+    // Ensure the response is valid.
+    let committees = CommitteeHistory::new(committee.clone());
+    assert!(response.verify(&committees, &label).is_ok());
+    assert_eq!(response.certificate.sequence_number, 2);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
@@ -0,0 +1,270 @@
+use akd::storage::types::AkdValue;
+use bytes::Bytes;
+use config::Committee;
+use function_name::named;
+use messages::{
+    lookup::{LookupRequest, LookupResponse},
+    reconfiguration::CommitteeHistory,
+    update::SignedUpdateRequest,
+    ClientToIdPMessage, IdPToClientMessage, IdPToWitnessMessage, WitnessToIdPMessage,
+};
+use network::{
+    memory::MemoryNetwork,
+    reliable_sender::{BufferLimits, ReliableSender},
+};
+use std::sync::Arc;
+use test_utils::{
+    client_keypair, committee, delete_storage, keys, serialized_updates,
+    spawn_test_full_witness_with_transport, spawn_test_idp_with_transport,
+    spawn_test_witness_with_transport, spawn_test_witnesses_with_transport, updates,
+};
+use tokio::time::{sleep, timeout, Duration};
+
+// Look up a label until the IdP certified the state holding it.
+async fn lookup(network: &mut ReliableSender, committee: &Committee) -> LookupResponse {
+    let (label, _) = updates().into_iter().next().unwrap();
+    let message = ClientToIdPMessage::Lookup(LookupRequest { label });
+    let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+    loop {
+        let handle = network
+            .send(committee.idp.name, committee.idp.address, bytes.clone())
+            .await;
+        let reply = handle.await.unwrap();
+        match bincode::deserialize(&reply).unwrap() {
+            IdPToClientMessage::LookupResponse(Ok(response)) => break response,
+            IdPToClientMessage::LookupResponse(Err(_)) => sleep(Duration::from_millis(100)).await,
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+}
+
+#[tokio::test]
+#[named]
+async fn simulated_lookup() {
+    let base_port = 1_000;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+
+    // Spawn the IdP and 4 witnesses on a simulated network (no TCP port is bound).
+    let simulation = MemoryNetwork::default();
+    simulation.set_latency(Duration::from_millis(5));
+    let transport = Arc::new(simulation.clone());
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // Send enough correct updates to create a batch.
+    let mut network =
+        ReliableSender::with_transport(client_keypair(), BufferLimits::default(), transport);
+    for update in serialized_updates() {
+        let handle = network
+            .send(committee.idp.name, committee.idp.address, update)
+            .await;
+        handle.await.unwrap();
+    }
+
+    // Ensure the response is valid and contains the expected value.
+    let response = lookup(&mut network, &committee).await;
+    let (label, value) = updates().into_iter().next().unwrap();
+    let committees = CommitteeHistory::new(committee.clone());
+    assert!(response.verify(&committees, &label).is_ok());
+    assert_eq!(response.value(), &value);
+    assert_eq!(response.version(), 1);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn simulated_partition() {
+    let base_port = 1_100;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+
+    // Spawn the IdP and 4 witnesses on a simulated network.
+    let simulation = MemoryNetwork::default();
+    simulation.set_latency(Duration::from_millis(5));
+    let transport = Arc::new(simulation.clone());
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // Cut off one witness; the others still form a quorum.
+    let (isolated, _) = keys().swap_remove(0);
+    let isolated_address = committee.witness_address(&isolated).unwrap();
+    simulation.partition(&[isolated]);
+
+    // Ensure the IdP certifies a batch of updates despite the partition.
+    let mut network = ReliableSender::with_transport(
+        client_keypair(),
+        BufferLimits::default(),
+        transport.clone(),
+    );
+    for update in serialized_updates() {
+        let handle = network
+            .send(committee.idp.name, committee.idp.address, update)
+            .await;
+        handle.await.unwrap();
+    }
+    let response = lookup(&mut network, &committee).await;
+    assert_eq!(response.version(), 1);
+
+    // Ensure the isolated witness catches up once the network heals (only the IdP and the witnesses
+    // may query its state).
+    simulation.heal();
+    let (_, identity_provider) = keys().pop().unwrap();
+    let mut network =
+        ReliableSender::with_transport(identity_provider, BufferLimits::default(), transport);
+    let mut synced = false;
+    for _ in 0..100 {
+        let serialized = bincode::serialize(&IdPToWitnessMessage::StateQuery).unwrap();
+        let reply = network
+            .send(isolated, isolated_address, Bytes::from(serialized))
+            .await
+            .await
+            .unwrap();
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::State(Ok(state)) if state.sequence_number == 2 => {
+                synced = true;
+                break;
+            }
+            WitnessToIdPMessage::State(Ok(_)) => sleep(Duration::from_millis(100)).await,
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+    assert!(synced);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn simulated_full_witness() {
+    let base_port = 1_200;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+
+    // Spawn the IdP, a full witness, and 3 other witnesses on a simulated network.
+    let simulation = MemoryNetwork::default();
+    simulation.set_latency(Duration::from_millis(5));
+    let transport = Arc::new(simulation.clone());
+    spawn_test_full_witness_with_transport(&test_id, &committee, 0, transport.clone());
+    for i in 1..keys().len() {
+        spawn_test_witness_with_transport(&test_id, &committee, i, transport.clone());
+    }
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // Send enough correct updates to create a batch.
+    let mut network =
+        ReliableSender::with_transport(client_keypair(), BufferLimits::default(), transport);
+    for update in serialized_updates() {
+        let handle = network
+            .send(committee.idp.name, committee.idp.address, update)
+            .await;
+        handle.await.unwrap();
+    }
+
+    // Look up the first label from the full witness until it replayed the certified batch.
+    let (name, _) = keys().swap_remove(0);
+    let address = committee.witness_address(&name).unwrap();
+    let (label, value) = updates().into_iter().next().unwrap();
+    let message = IdPToWitnessMessage::Lookup(LookupRequest {
+        label: label.clone(),
+    });
+    let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+    let response = loop {
+        let reply = network
+            .send(name, address, bytes.clone())
+            .await
+            .await
+            .unwrap();
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::LookupResponse(Ok(response)) => break response,
+            WitnessToIdPMessage::LookupResponse(Err(_)) => sleep(Duration::from_millis(100)).await,
+            _ => panic!("Unexpected protocol message"),
+        }
+    };
+
+    // Ensure the response is valid and contains the expected value.
+    let committees = CommitteeHistory::new(committee.clone());
+    assert!(response.verify(&committees, &label).is_ok());
+    assert_eq!(response.value(), &value);
+    assert_eq!(response.version(), 1);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn lookup_with_full_pipeline() {
+    let base_port = 1_300;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+
+    // Spawn the IdP and 4 witnesses on a simulated network.
+    let simulation = MemoryNetwork::default();
+    simulation.set_latency(Duration::from_millis(5));
+    let transport = Arc::new(simulation.clone());
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // Certify a first batch.
+    let mut network =
+        ReliableSender::with_transport(client_keypair(), BufferLimits::default(), transport);
+    for update in serialized_updates() {
+        let handle = network
+            .send(committee.idp.name, committee.idp.address, update)
+            .await;
+        handle.await.unwrap();
+    }
+    assert_eq!(lookup(&mut network, &committee).await.version(), 1);
+
+    // Cut off the witnesses and fill the pipeline with two more batches.
+    let names: Vec<_> = keys().into_iter().map(|(name, _)| name).collect();
+    simulation.partition(&names);
+    for nonce in 2..=3 {
+        for (label, _) in updates() {
+            let update = (label, AkdValue(vec![nonce as u8]));
+            let request = SignedUpdateRequest::new(update, nonce, &client_keypair());
+            let message = ClientToIdPMessage::Update(request);
+            let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+            let handle = network
+                .send(committee.idp.name, committee.idp.address, bytes)
+                .await;
+            handle.await.unwrap();
+        }
+    }
+
+    // Ensure lookups are still served (against the last certified state) while the pipeline is
+    // full.
+    let (label, _) = updates().into_iter().next().unwrap();
+    let committees = CommitteeHistory::new(committee.clone());
+    for _ in 0..10 {
+        let response = timeout(Duration::from_secs(1), lookup(&mut network, &committee))
+            .await
+            .unwrap();
+        assert!(response.verify(&committees, &label).is_ok());
+        assert_eq!(response.certificate.sequence_number, 1);
+        assert_eq!(response.version(), 1);
+    }
+
+    // Ensure the pipelined batches are certified once the network heals.
+    simulation.heal();
+    let mut version = 0;
+    for _ in 0..100 {
+        version = lookup(&mut network, &committee).await.version();
+        if version == 3 {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(version, 3);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
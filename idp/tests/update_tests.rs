@@ -0,0 +1,106 @@
+use akd::storage::types::AkdValue;
+use bytes::Bytes;
+use config::Committee;
+use function_name::named;
+use messages::{
+    error::{IdpError, IdpResult},
+    update::{SignedUpdateRequest, UpdateReceipt},
+    ClientToIdPMessage, IdPToClientMessage,
+};
+use network::reliable_sender::ReliableSender;
+use test_utils::{
+    client_keypair, client_sender, committee, delete_storage, keys, memory_transport,
+    signed_updates, spawn_test_idp_with_transport, updates,
+};
+
+// Send an update request to the IdP and return its reply.
+async fn send(
+    network: &mut ReliableSender,
+    committee: &Committee,
+    request: SignedUpdateRequest,
+) -> IdpResult<UpdateReceipt> {
+    let message = ClientToIdPMessage::Update(request);
+    let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+    let (name, address) = (committee.idp.name, committee.idp.address);
+    let reply = network.send(name, address, bytes).await.await.unwrap();
+    match bincode::deserialize(&reply).unwrap() {
+        IdPToClientMessage::UpdateReceipt(result) => result,
+        x => panic!("Unexpected reply: {:?}", x),
+    }
+}
+
+#[tokio::test]
+#[named]
+async fn unauthenticated_update() {
+    let base_port = 9_400;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn the IdP.
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // The first request registers the owner of the label.
+    let mut network = client_sender(transport);
+    let request = signed_updates().into_iter().next().unwrap();
+    let result = send(&mut network, &committee, request.clone()).await;
+    assert!(result.is_ok());
+
+    // Replaying the same request fails.
+    match send(&mut network, &committee, request).await {
+        Err(IdpError::StaleNonce { last: 1, got: 1 }) => (),
+        x => panic!("Unexpected reply: {:?}", x),
+    }
+
+    // Another user cannot update the label.
+    let (_, keypair) = keys().pop().unwrap();
+    let update = updates().into_iter().next().unwrap();
+    let request = SignedUpdateRequest::new(update, /* nonce */ 2, &keypair);
+    match send(&mut network, &committee, request).await {
+        Err(IdpError::UnauthorizedUpdate { .. }) => (),
+        x => panic!("Unexpected reply: {:?}", x),
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn duplicate_label_in_batch() {
+    let base_port = 9_700;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn the IdP.
+    spawn_test_idp_with_transport(&test_id, committee.clone(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // The first request opens the first batch.
+    let mut network = client_sender(transport);
+    let mut requests = signed_updates().into_iter();
+    let first = requests.next().unwrap();
+    let receipt = send(&mut network, &committee, first).await.unwrap();
+    assert_eq!(receipt.deadline, 1);
+
+    // A second update to the same label cannot join the same batch.
+    let (label, _) = updates().into_iter().next().unwrap();
+    let update = (label, AkdValue(vec![3]));
+    let request = SignedUpdateRequest::new(update, /* nonce */ 2, &client_keypair());
+    match send(&mut network, &committee, request.clone()).await {
+        Err(IdpError::PendingUpdate(1)) => (),
+        x => panic!("Unexpected reply: {:?}", x),
+    }
+
+    // Once the batch is sealed, the update goes into the next batch.
+    let second = requests.next().unwrap();
+    let receipt = send(&mut network, &committee, second).await.unwrap();
+    assert_eq!(receipt.deadline, 1);
+    let receipt = send(&mut network, &committee, request).await.unwrap();
+    assert_eq!(receipt.deadline, 2);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
@@ -0,0 +1,114 @@
+use crate::{
+    ensure,
+    error::{MessageError, MessageResult},
+    publish::{PublishCertificate, PublishMessage},
+    reconfiguration::CommitteeHistory,
+    Root, SequenceNumber,
+};
+use config::Committee;
+use crypto::Digest;
+use ed25519_dalek::{Digest as _, Sha512};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// A certified snapshot of the state of the directory. A fresh witness can adopt it (instead of
+/// replaying all the certificates that precede it) after verifying the quorum of its certificate.
+/// It carries the certificates of the committee changes preceding the snapshot, so that a witness
+/// only knowing the genesis committee learns the committee that certified the snapshot.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Checkpoint {
+    /// The certificate over the root and sequence number of the snapshot.
+    pub certificate: PublishCertificate,
+    /// The certificates handing over to new committees before the snapshot (sorted by sequence
+    /// number).
+    pub committee_changes: Vec<PublishCertificate>,
+    /// The committee in charge of the sequence numbers following the snapshot.
+    pub committee: Committee,
+}
+
+impl std::fmt::Debug for Checkpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "K{}({:?}, {})",
+            self.sequence_number(),
+            self.certificate,
+            self.committee_digest()
+        )
+    }
+}
+
+impl Checkpoint {
+    /// Create a new checkpoint of a (committed) certificate from the committees that certified
+    /// the sequence numbers up to it.
+    pub fn new(certificate: PublishCertificate, committees: &CommitteeHistory) -> Self {
+        let sequence_number = certificate.sequence_number();
+        let committee_changes = committees
+            .changes()
+            .iter()
+            .filter(|x| x.sequence_number() < sequence_number)
+            .cloned()
+            .collect();
+        let committee = committees.get(sequence_number + 1).clone();
+        Self {
+            certificate,
+            committee_changes,
+            committee,
+        }
+    }
+
+    /// Return the certified root of the snapshot.
+    pub fn root(&self) -> &Root {
+        self.certificate.root()
+    }
+
+    /// Return the sequence number of the snapshot.
+    pub fn sequence_number(&self) -> SequenceNumber {
+        self.certificate.sequence_number()
+    }
+
+    /// Compute the hash of the committee in charge after the snapshot.
+    pub fn committee_digest(&self) -> Digest {
+        digest(&self.committee)
+    }
+
+    /// Verify the checkpoint against the committees known by the verifier. The committee changes
+    /// unknown to the verifier are verified in order (each under the committee it hands over
+    /// from) before the certificate of the snapshot. Returns the committees known once the
+    /// checkpoint is adopted.
+    pub fn verify(&self, committees: &CommitteeHistory) -> MessageResult<CommitteeHistory> {
+        let mut committees = committees.clone();
+        for certificate in &self.committee_changes {
+            // Skip the committee changes already known.
+            let sequence_number = certificate.sequence_number();
+            if sequence_number < committees.last_activation() {
+                continue;
+            }
+            certificate.verify(committees.get(sequence_number))?;
+            committees.update(certificate);
+        }
+
+        self.certificate
+            .verify(committees.get(self.sequence_number()))?;
+        committees.update(&self.certificate);
+
+        // The committee in charge after the snapshot is the latest certified one.
+        let expected = digest(committees.current());
+        ensure!(
+            self.committee_digest() == expected,
+            MessageError::UnexpectedCommittee {
+                expected,
+                got: self.committee_digest()
+            }
+        );
+        Ok(committees)
+    }
+}
+
+/// Compute the hash of a committee.
+fn digest(committee: &Committee) -> Digest {
+    let serialized = bincode::serialize(committee).expect("Failed to serialize committee");
+    let mut hasher = Sha512::new();
+    hasher.update(&serialized);
+    Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+}
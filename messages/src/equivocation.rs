@@ -0,0 +1,82 @@
+use crate::{
+    ensure,
+    error::{MessageError, MessageResult},
+    publish::{PublishMessage, PublishNotification},
+    SequenceNumber,
+};
+use config::Committee;
+use serde::{Deserialize, Serialize};
+
+/// Self-contained evidence that the IdP signed two different roots for the same sequence number.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EquivocationProof {
+    /// The first notification signed by the IdP.
+    pub first: PublishNotification,
+    /// A conflicting notification signed by the IdP.
+    pub second: PublishNotification,
+}
+
+impl std::fmt::Debug for EquivocationProof {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "E{}({:?}, {:?})",
+            self.sequence_number(),
+            self.first,
+            self.second
+        )
+    }
+}
+
+// Useful for tests.
+impl PartialEq for EquivocationProof {
+    fn eq(&self, other: &Self) -> bool {
+        self.first == other.first && self.second == other.second
+    }
+}
+
+impl EquivocationProof {
+    /// Create a new equivocation proof from two notifications.
+    pub fn new(first: PublishNotification, second: PublishNotification) -> Self {
+        Self { first, second }
+    }
+
+    /// Return the sequence number at which the IdP equivocated.
+    pub fn sequence_number(&self) -> SequenceNumber {
+        self.first.sequence_number()
+    }
+
+    /// Verify that the proof is a valid evidence of equivocation. There is no need to check the
+    /// state-transition proofs of the notifications: the IdP signature alone binds it to both roots.
+    pub fn verify(&self, committee: &Committee) -> MessageResult<()> {
+        self.first.verify_signature(committee)?;
+        self.second.verify_signature(committee)?;
+        check_conflict(&self.first, &self.second)
+    }
+}
+
+/// Check that two messages carry different roots for the same sequence number.
+pub(crate) fn check_conflict<M: PublishMessage>(a: &M, b: &M) -> MessageResult<()> {
+    ensure!(
+        a.sequence_number() == b.sequence_number(),
+        MessageError::SequenceNumberMismatch(a.sequence_number(), b.sequence_number())
+    );
+    ensure!(
+        a.root() != b.root(),
+        MessageError::NoEquivocation(a.sequence_number())
+    );
+    Ok(())
+}
+
+/// Request of an equivocation proof.
+#[derive(Serialize, Deserialize)]
+pub struct EquivocationProofQuery {
+    /// The sequence number at which the IdP may have equivocated.
+    pub sequence_number: SequenceNumber,
+}
+
+impl std::fmt::Debug for EquivocationProofQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "EquivocationRequest({})", self.sequence_number)
+    }
+}
@@ -1,5 +1,6 @@
-use crate::{deserialize_root, serialize_root, Root, SequenceNumber};
-use akd::errors::AkdError;
+use crate::{deserialize_root, publish::PublishCertificate, serialize_root, Root, SequenceNumber};
+use akd::{ecvrf::VrfError, errors::AkdError};
+use config::SignatureScheme;
 use crypto::{CryptoError, Digest, PublicKey};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -51,6 +52,48 @@ pub enum MessageError {
 
     #[error("The update request is too short (min 2 bytes)")]
     UpdateRequestTooShort,
+
+    #[error("Missing certificate for sequence number {0}")]
+    MissingCertificate(SequenceNumber),
+
+    #[error("Messages for sequence numbers {0} and {1} cannot conflict")]
+    SequenceNumberMismatch(SequenceNumber, SequenceNumber),
+
+    #[error("Messages for sequence number {0} do not conflict")]
+    NoEquivocation(SequenceNumber),
+
+    #[error("Proof computed at unexpected epoch, expected {expected} but got {got}")]
+    UnexpectedProofEpoch {
+        expected: SequenceNumber,
+        got: SequenceNumber,
+    },
+
+    #[error("Unexpected committee change activation, expected {expected} but got {got}")]
+    UnexpectedActivation {
+        expected: SequenceNumber,
+        got: SequenceNumber,
+    },
+
+    #[error("A committee change cannot replace the IdP")]
+    IdpChange,
+
+    #[error("The new committee has no voting power")]
+    EmptyCommittee,
+
+    #[error("Missing aggregatable signature from witness {0}")]
+    MissingBlsSignature(PublicKey),
+
+    #[error("The signers of the aggregate signature are not in the committee")]
+    MalformedSignerBitmap,
+
+    #[error("Checkpoint hands over to unexpected committee, expected {expected} but got {got}")]
+    UnexpectedCommittee { expected: Digest, got: Digest },
+
+    #[error("Witness {0} does not prove possession of its BLS key")]
+    InvalidProofOfPossession(PublicKey),
+
+    #[error("The certificate does not use the signature scheme of the committee ({0:?})")]
+    UnexpectedSignatureScheme(SignatureScheme),
 }
 
 impl From<CryptoError> for MessageError {
@@ -71,6 +114,12 @@ impl From<AkdError> for MessageError {
     }
 }
 
+impl From<VrfError> for MessageError {
+    fn from(error: VrfError) -> Self {
+        MessageError::PoofVerificationFailed(error.to_string())
+    }
+}
+
 /// Errors triggered by the witness when processing IdP's messages.
 #[derive(Debug, Error, Serialize, Deserialize)]
 pub enum WitnessError {
@@ -95,6 +144,37 @@ pub enum WitnessError {
 
     #[error("Missing earlier certificates, current sequence number at {0}")]
     MissingEarlierCertificates(SequenceNumber),
+
+    #[error("The witness does not hold a replica of the directory")]
+    NotFullWitness,
+
+    #[error("The replica of the directory does not match a certified state")]
+    NoReplicatedState,
+
+    #[error("Missing batch of updates for sequence number {0}")]
+    MissingBatch(SequenceNumber),
+
+    #[error("The replica diverged from the directory: {expected:?} != {got:?}")]
+    ReplicaDiverged {
+        #[serde(serialize_with = "serialize_root")]
+        #[serde(deserialize_with = "deserialize_root")]
+        expected: Root,
+        #[serde(serialize_with = "serialize_root")]
+        #[serde(deserialize_with = "deserialize_root")]
+        got: Root,
+    },
+
+    #[error("Failed to replicate the directory: {0}")]
+    ReplicationFailed(String),
+
+    #[error("Only the IdP can publish new states, received message from {0}")]
+    UnauthorizedSender(PublicKey),
+
+    #[error("The witness is not in the committee in charge of sequence number {0}")]
+    NotInCommittee(SequenceNumber),
+
+    #[error("Peer {0} is not a member of the committees known by the witness")]
+    UnauthorizedPeer(PublicKey),
 }
 
 /// Errors triggered by the IdP.
@@ -109,6 +189,24 @@ pub enum IdpError {
     #[error("Received unexpected protocol message")]
     UnexpectedProtocolMessage,
 
+    #[error("The directory does not have a certified state yet")]
+    NoCertifiedState,
+
+    #[error("The label is not in the directory certified by {0:?}")]
+    LabelNotFound(Box<PublishCertificate>),
+
+    #[error("Failed to generate proof: {0}")]
+    ProofGenerationFailed(String),
+
+    #[error("Update request signed by {got} but the label is owned by {owner}")]
+    UnauthorizedUpdate { owner: PublicKey, got: PublicKey },
+
+    #[error("Received stale nonce {got}, last nonce for the label is {last}")]
+    StaleNonce { last: u64, got: u64 },
+
+    #[error("Received vote for an unexpected committee change")]
+    UnexpectedCommitteeChange,
+
     #[error("Received unexpected vote: {expected:?} != {received:?}")]
     UnexpectedVote {
         #[serde(serialize_with = "serialize_root")]
@@ -118,4 +216,10 @@ pub enum IdpError {
         #[serde(deserialize_with = "deserialize_root")]
         received: Root,
     },
+
+    #[error("The label already has an update in batch {0} (retry once it is sealed)")]
+    PendingUpdate(SequenceNumber),
+
+    #[error("Failed to publish batch: {0}")]
+    PublishFailed(String),
 }
@@ -0,0 +1,71 @@
+use crate::{
+    equivocation::{check_conflict, EquivocationProof},
+    error::MessageResult,
+    publish::{PublishCertificate, PublishMessage, PublishNotification},
+    sync::State,
+    SequenceNumber,
+};
+use config::Committee;
+use crypto::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Messages exchanged between witnesses.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum WitnessToWitnessMessage {
+    Gossip(Gossip),
+    EquivocationReport(EquivocationReport),
+}
+
+/// The latest information observed by a witness.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Gossip {
+    /// The witness sending the gossip.
+    pub author: PublicKey,
+    /// The latest state of the witness.
+    pub state: State,
+    /// The certificate over the latest root committed by the witness (if any).
+    pub certificate: Option<PublishCertificate>,
+    /// The latest notification the witness voted for (if any).
+    pub notification: Option<PublishNotification>,
+}
+
+impl std::fmt::Debug for Gossip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "Gossip({}, {:?}, {:?}, {:?})",
+            self.author, self.state, self.certificate, self.notification
+        )
+    }
+}
+
+/// Evidence that the IdP showed different roots for the same sequence number.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum EquivocationReport {
+    /// Two notifications signed by the IdP.
+    ConflictingNotifications(EquivocationProof),
+    /// Two certificates formed by a quorum of witnesses.
+    ConflictingCertificates(PublishCertificate, PublishCertificate),
+}
+
+impl EquivocationReport {
+    /// Return the sequence number at which the IdP equivocated.
+    pub fn sequence_number(&self) -> SequenceNumber {
+        match self {
+            Self::ConflictingNotifications(proof) => proof.sequence_number(),
+            Self::ConflictingCertificates(x, _) => x.sequence_number(),
+        }
+    }
+
+    /// Verify that the report is a valid evidence of equivocation.
+    pub fn verify(&self, committee: &Committee) -> MessageResult<()> {
+        match self {
+            Self::ConflictingNotifications(proof) => proof.verify(committee),
+            Self::ConflictingCertificates(a, b) => {
+                a.verify(committee)?;
+                b.verify(committee)?;
+                check_conflict(a, b)
+            }
+        }
+    }
+}
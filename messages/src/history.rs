@@ -0,0 +1,116 @@
+use crate::{
+    ensure,
+    error::{MessageError, MessageResult},
+    publish::PublishCertificate,
+    reconfiguration::CommitteeHistory,
+    vrf, Blake3, SequenceNumber,
+};
+use akd::storage::types::AkdLabel;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// Represents a key history proof.
+pub type HistoryProof = akd::proof_structs::HistoryProof<Blake3>;
+
+/// A client request to retrieve all the values ever associated with a label.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeyHistoryRequest {
+    /// The label whose history to retrieve.
+    pub label: AkdLabel,
+}
+
+impl std::fmt::Debug for KeyHistoryRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "KeyHistory({})", base64::encode(&self.label.0))
+    }
+}
+
+/// The IdP's reply to a key history request.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeyHistoryResponse {
+    /// The history proof (containing one update proof per value of the label).
+    pub proof: HistoryProof,
+    /// The certificate over the latest root, against which the history is computed.
+    pub certificate: PublishCertificate,
+    /// The certificates over the roots of the epochs in which the label was updated.
+    pub certificates: Vec<PublishCertificate>,
+}
+
+impl std::fmt::Debug for KeyHistoryResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "KeyHistoryResponse({} updates, {:?})",
+            self.proof.proofs.len(),
+            self.certificate
+        )
+    }
+}
+
+impl KeyHistoryResponse {
+    /// Return the sequence numbers of the certificates (other than the latest) required to
+    /// verify the history proof.
+    pub fn required_sequence_numbers(&self) -> BTreeSet<SequenceNumber> {
+        self.proof
+            .proofs
+            .iter()
+            .flat_map(|update| [Some(update.epoch), update.epoch.checked_sub(1)])
+            .flatten()
+            .filter(|x| *x > 0 && *x != self.certificate.sequence_number)
+            .collect()
+    }
+
+    /// Verify the history proof against the chain of certified roots. Each certificate is
+    /// verified under the committee in charge of its sequence number.
+    pub fn verify(&self, committees: &CommitteeHistory, label: &AkdLabel) -> MessageResult<()> {
+        // Verify all certificates and index their roots by sequence number.
+        let latest = self.certificate.sequence_number;
+        self.certificate.verify(committees.get(latest))?;
+
+        let mut roots = HashMap::new();
+        roots.insert(latest, self.certificate.root);
+        for certificate in &self.certificates {
+            certificate.verify(committees.get(certificate.sequence_number))?;
+            roots.insert(certificate.sequence_number, certificate.root);
+        }
+
+        // Gather the roots against which each update proof is verified.
+        let mut root_hashes = Vec::new();
+        let mut previous_root_hashes = Vec::new();
+        for update in &self.proof.proofs {
+            ensure!(
+                update.epoch <= latest,
+                MessageError::UnexpectedProofEpoch {
+                    expected: latest,
+                    got: update.epoch
+                }
+            );
+            let root = roots
+                .get(&update.epoch)
+                .ok_or(MessageError::MissingCertificate(update.epoch))?;
+            root_hashes.push(*root);
+
+            let previous_root = match update.epoch {
+                x if x > 1 => Some(
+                    *roots
+                        .get(&(x - 1))
+                        .ok_or(MessageError::MissingCertificate(x - 1))?,
+                ),
+                _ => None,
+            };
+            previous_root_hashes.push(previous_root);
+        }
+
+        // Verify the history proof.
+        let vrf_public_key = vrf::vrf_public_key(committees.current())?;
+        akd::client::key_history_verify::<Blake3>(
+            &vrf_public_key,
+            root_hashes,
+            previous_root_hashes,
+            label.clone(),
+            self.proof.clone(),
+            /* allow_tombstones */ false,
+        )?;
+        Ok(())
+    }
+}
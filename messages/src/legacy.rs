@@ -0,0 +1,122 @@
+use crate::{
+    deserialize_root,
+    publish::{Proof, PublishCertificate, PublishNotification, PublishVote, Votes},
+    serialize_root,
+    sync::State,
+    Root, SequenceNumber,
+};
+use crypto::{Digest, KeyPair, PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+
+/// The layout of the publish notifications persisted by the legacy databases (before committee
+/// changes and domain-separated ids).
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LegacyPublishNotification {
+    /// The root committing to the new state.
+    #[serde(serialize_with = "serialize_root")]
+    #[serde(deserialize_with = "deserialize_root")]
+    pub root: Root,
+    /// The state-transition proof ensuring the published state is valid.
+    pub proof: Proof,
+    /// The sequence number unique to this publish notification.
+    pub sequence_number: SequenceNumber,
+    /// The hash of the previous fields of this publish.
+    pub id: Digest,
+    /// A signature from the IdP authenticating the publish.
+    pub signature: Signature,
+}
+
+impl LegacyPublishNotification {
+    /// Translate the notification into the current layout. Its id is not domain-separated, so the
+    /// IdP signs it again.
+    pub fn upgrade(self, keypair: &KeyPair) -> PublishNotification {
+        PublishNotification::new(self.root, self.proof, self.sequence_number, keypair)
+    }
+}
+
+/// The layout of the votes persisted by the legacy databases (before committee changes and BLS
+/// signatures).
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LegacyPublishVote {
+    /// The root commitment of the publish notification.
+    #[serde(serialize_with = "serialize_root")]
+    #[serde(deserialize_with = "deserialize_root")]
+    pub root: Root,
+    /// The sequence number of the publish notification.
+    pub sequence_number: SequenceNumber,
+    /// The witness creating the vote.
+    pub author: PublicKey,
+    /// A signature authenticating the vote.
+    pub signature: Signature,
+}
+
+/// The digest of a vote without committee change is unchanged: the signature remains valid.
+impl From<LegacyPublishVote> for PublishVote {
+    fn from(vote: LegacyPublishVote) -> Self {
+        Self {
+            root: vote.root,
+            sequence_number: vote.sequence_number,
+            committee_change: None,
+            author: vote.author,
+            signature: vote.signature,
+            bls_signature: None,
+        }
+    }
+}
+
+/// The layout of the certificates persisted by the legacy databases (before committee changes and
+/// aggregate signatures).
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LegacyPublishCertificate {
+    /// The root commitment of the certified notification.
+    #[serde(serialize_with = "serialize_root")]
+    #[serde(deserialize_with = "deserialize_root")]
+    pub root: Root,
+    /// The sequence number of the publish notification.
+    pub sequence_number: SequenceNumber,
+    /// The quorum of votes making the certificate.
+    pub votes: Vec<(PublicKey, Signature)>,
+}
+
+/// The digest of a certificate without committee change is unchanged: the votes remain valid.
+impl From<LegacyPublishCertificate> for PublishCertificate {
+    fn from(certificate: LegacyPublishCertificate) -> Self {
+        Self {
+            root: certificate.root,
+            sequence_number: certificate.sequence_number,
+            committee_change: None,
+            votes: Votes::Individual(certificate.votes),
+        }
+    }
+}
+
+/// The layout of the state of a witness persisted by the legacy databases.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LegacyState {
+    /// The latest root commitment.
+    #[serde(serialize_with = "serialize_root")]
+    #[serde(deserialize_with = "deserialize_root")]
+    pub root: Root,
+    /// The current sequence number.
+    pub sequence_number: SequenceNumber,
+    /// The notification on which this entity is locked.
+    pub lock: Option<LegacyPublishVote>,
+}
+
+impl From<LegacyState> for State {
+    fn from(state: LegacyState) -> Self {
+        Self {
+            root: state.root,
+            sequence_number: state.sequence_number,
+            lock: state.lock.map(PublishVote::from),
+        }
+    }
+}
+
+/// The messages of the IdP persisted by the legacy databases (the legacy databases only hold the
+/// first two variants of the legacy `IdPToWitnessMessage`).
+#[derive(Serialize, Deserialize)]
+pub enum LegacyIdPToWitnessMessage {
+    PublishNotification(LegacyPublishNotification),
+    PublishCertificate(LegacyPublishCertificate),
+}
@@ -1,12 +1,25 @@
+pub mod checkpoint;
+pub mod equivocation;
 pub mod error;
+pub mod gossip;
+pub mod history;
+pub mod legacy;
+pub mod lookup;
 pub mod publish;
+pub mod reconfiguration;
 pub mod sync;
 pub mod update;
+pub mod vrf;
 
-use error::{WitnessError, WitnessResult};
+use checkpoint::Checkpoint;
+use equivocation::{EquivocationProof, EquivocationProofQuery};
+use error::{IdpResult, WitnessError, WitnessResult};
+use history::{KeyHistoryRequest, KeyHistoryResponse};
+use lookup::{LookupRequest, LookupResponse};
 use publish::{PublishCertificate, PublishNotification, PublishVote};
 use serde::{Deserialize, Serialize};
-use sync::{PublishCertificateQuery, State};
+use sync::{PublishCertificateRangeQuery, PublishCertificateRangeResponse, State};
+use update::{ReplicaBatch, SignedUpdateRequest, UpdateReceipt};
 use winter_crypto::{hashers::Blake3_256, Digest as _, Hasher};
 use winter_math::fields::f128::BaseElement;
 use winter_utils::{Deserializable, SliceReader};
@@ -20,7 +33,10 @@ pub enum IdPToWitnessMessage {
     PublishNotification(PublishNotification),
     PublishCertificate(PublishCertificate),
     StateQuery,
-    PublishCertificateQuery(PublishCertificateQuery),
+    PublishCertificateRangeQuery(PublishCertificateRangeQuery),
+    EquivocationProofQuery(EquivocationProofQuery),
+    Lookup(LookupRequest),
+    CheckpointQuery,
 }
 
 /// Replies sent by the witnesses to the IdP.
@@ -28,7 +44,10 @@ pub enum IdPToWitnessMessage {
 pub enum WitnessToIdPMessage {
     PublishVote(WitnessResult<PublishVote>),
     State(WitnessResult<State>),
-    PublishCertificateResponse(SerializedPublishCertificateMessage),
+    PublishCertificateRangeResponse(PublishCertificateRangeResponse),
+    EquivocationProofResponse(Option<EquivocationProof>),
+    LookupResponse(WitnessResult<LookupResponse>),
+    CheckpointResponse(Option<Checkpoint>),
 }
 
 impl WitnessToIdPMessage {
@@ -50,9 +69,37 @@ impl WitnessToIdPMessage {
     }
 }
 
+/// Messages sent by the clients to the IdP.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ClientToIdPMessage {
+    Update(SignedUpdateRequest),
+    Lookup(LookupRequest),
+    KeyHistory(KeyHistoryRequest),
+    /// Request the certificates handing over to the committees activated after the specified
+    /// sequence number.
+    CommitteeChangeQuery(SequenceNumber),
+    /// Request the batch of updates (and VRF proofs) of the specified sequence number, to let full
+    /// witnesses catch up on the batches they missed.
+    BatchQuery(SequenceNumber),
+}
+
+/// Replies sent by the IdP to the clients.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum IdPToClientMessage {
+    UpdateReceipt(IdpResult<UpdateReceipt>),
+    LookupResponse(IdpResult<LookupResponse>),
+    KeyHistoryResponse(IdpResult<KeyHistoryResponse>),
+    CommitteeChangeResponse(Vec<PublishCertificate>),
+    BatchResponse(Option<ReplicaBatch>),
+}
+
 /// The sequence number of consistent (or reliable) broadcast.
 pub type SequenceNumber = u64;
 
+/// The default maximum number of uncertified notifications. The witnesses only vote for that many
+/// notifications ahead of their state, so they must use the same pipeline depth as the IdP.
+pub const DEFAULT_PIPELINE_DEPTH: usize = 1;
+
 // The hasher for the state tree.
 pub type Blake3 = Blake3_256<BaseElement>;
 
@@ -0,0 +1,83 @@
+use crate::{
+    ensure,
+    error::{MessageError, MessageResult},
+    publish::PublishCertificate,
+    reconfiguration::CommitteeHistory,
+    vrf, Blake3,
+};
+use akd::storage::types::{AkdLabel, AkdValue};
+use serde::{Deserialize, Serialize};
+
+/// Represents a lookup proof.
+pub type LookupProof = akd::proof_structs::LookupProof<Blake3>;
+
+/// A client request to retrieve the value associated with a label.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LookupRequest {
+    /// The label to look up.
+    pub label: AkdLabel,
+}
+
+impl std::fmt::Debug for LookupRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "Lookup({})", base64::encode(&self.label.0))
+    }
+}
+
+/// The IdP's reply to a lookup request.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LookupResponse {
+    /// The lookup proof (containing the value and its version).
+    pub proof: LookupProof,
+    /// The certificate over the root against which the proof is computed.
+    pub certificate: PublishCertificate,
+}
+
+impl std::fmt::Debug for LookupResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "LookupResponse(v{}, {:?})",
+            self.proof.version, self.certificate
+        )
+    }
+}
+
+impl LookupResponse {
+    /// Return the value associated with the label.
+    pub fn value(&self) -> &AkdValue {
+        &self.proof.plaintext_value
+    }
+
+    /// Return the version of the value.
+    pub fn version(&self) -> u64 {
+        self.proof.version
+    }
+
+    /// Verify the lookup proof against the certified root. The certificate is verified under
+    /// the committee in charge of its sequence number.
+    pub fn verify(&self, committees: &CommitteeHistory, label: &AkdLabel) -> MessageResult<()> {
+        // Verify the certificate.
+        let committee = committees.get(self.certificate.sequence_number);
+        self.certificate.verify(committee)?;
+
+        // Ensure the proof is computed against the certified root.
+        ensure!(
+            self.proof.epoch == self.certificate.sequence_number,
+            MessageError::UnexpectedProofEpoch {
+                expected: self.certificate.sequence_number,
+                got: self.proof.epoch
+            }
+        );
+
+        // Verify the lookup proof.
+        let vrf_public_key = vrf::vrf_public_key(committee)?;
+        akd::client::lookup_verify::<Blake3>(
+            &vrf_public_key,
+            self.certificate.root,
+            label.clone(),
+            self.proof.clone(),
+        )?;
+        Ok(())
+    }
+}
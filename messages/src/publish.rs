@@ -1,16 +1,22 @@
 use crate::{
     deserialize_root, ensure,
     error::{MessageError, MessageResult},
-    serialize_root, Blake3, Root, SequenceNumber,
+    reconfiguration::CommitteeChange,
+    serialize_root,
+    update::ReplicaBatch,
+    Blake3, Root, SequenceNumber,
 };
 use akd::proof_structs::AppendOnlyProof;
-use config::Committee;
-use crypto::{Digest, KeyPair, PublicKey, Signature};
+use config::{Committee, SignatureScheme};
+use crypto::{BlsKeyPair, BlsSignature, Digest, KeyPair, PublicKey, Signature};
 use ed25519_dalek::{Digest as _, Sha512};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashSet, convert::TryInto};
 use winter_crypto::Digest as _;
 
+/// Domain separator of the notifications (the IdP signs other messages with the same key).
+const NOTIFICATION_DOMAIN: &[u8] = b"BananaTree_PublishNotification";
+
 /// Represents a state proof.
 pub type Proof = AppendOnlyProof<Blake3>;
 
@@ -22,11 +28,17 @@ pub trait PublishMessage {
     /// Return the sequence number of the message.
     fn sequence_number(&self) -> SequenceNumber;
 
+    /// Return the hash of the committee change carried by the message (if any).
+    fn committee_change_digest(&self) -> Option<Digest>;
+
     /// Compute the hash of the message.
     fn digest(&self) -> Digest {
         let mut hasher = Sha512::new();
         hasher.update(&self.root().as_bytes());
         hasher.update(self.sequence_number().to_le_bytes());
+        if let Some(digest) = self.committee_change_digest() {
+            hasher.update(&digest);
+        }
         Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
     }
 }
@@ -42,10 +54,16 @@ pub struct PublishNotification {
     pub proof: Proof,
     /// The sequence number unique to this publish notification.
     pub sequence_number: SequenceNumber,
-    /// The hash of the previous fields of this publish.
+    /// A change of the committee of witnesses taking effect after this notification (if any).
+    pub committee_change: Option<CommitteeChange>,
+    /// The (domain-separated) hash of the previous fields of this publish.
     pub id: Digest,
     /// A signature from the IdP authenticating the publish.
     pub signature: Signature,
+    /// The batch of updates committed by the notification (if the IdP ships it to full witnesses).
+    /// It is not authenticated by the IdP: full witnesses check it by replaying it up to the same
+    /// root.
+    pub batch: Option<ReplicaBatch>,
 }
 
 impl std::fmt::Debug for PublishNotification {
@@ -75,6 +93,10 @@ impl PublishMessage for PublishNotification {
     fn sequence_number(&self) -> SequenceNumber {
         self.sequence_number
     }
+
+    fn committee_change_digest(&self) -> Option<Digest> {
+        self.committee_change.as_ref().map(|x| x.digest())
+    }
 }
 
 impl PublishNotification {
@@ -84,15 +106,29 @@ impl PublishNotification {
         proof: Proof,
         sequence_number: SequenceNumber,
         keypair: &KeyPair,
+    ) -> Self {
+        Self::with_committee_change(root, proof, sequence_number, None, keypair)
+    }
+
+    /// Create a new PublishNotification signed by the IdP and handing over the directory to a new
+    /// committee (if any).
+    pub fn with_committee_change(
+        root: Root,
+        proof: Proof,
+        sequence_number: SequenceNumber,
+        committee_change: Option<CommitteeChange>,
+        keypair: &KeyPair,
     ) -> Self {
         let notification = Self {
             root,
             proof,
             sequence_number,
+            committee_change,
             id: Digest::default(),
             signature: Signature::default(),
+            batch: None,
         };
-        let id = notification.digest();
+        let id = notification.compute_id();
         let signature = Signature::new(&id, keypair);
         Self {
             id,
@@ -101,16 +137,46 @@ impl PublishNotification {
         }
     }
 
-    /// Verify a publish notification (very CPU-intensive).
-    pub async fn verify(&self, committee: &Committee, previous_root: &Root) -> MessageResult<()> {
+    /// Compute the id of the notification, signed by the IdP.
+    fn compute_id(&self) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(NOTIFICATION_DOMAIN);
+        hasher.update(&self.digest());
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+
+    /// Ship the batch of updates committed by the notification along with it.
+    pub fn with_batch(self, batch: ReplicaBatch) -> Self {
+        Self {
+            batch: Some(batch),
+            ..self
+        }
+    }
+
+    /// Verify that the publish notification is well-formed and signed by the IdP (without
+    /// checking its state-transition proof).
+    pub fn verify_signature(&self, committee: &Committee) -> MessageResult<()> {
         // Ensure the id is well formed.
         ensure!(
-            self.digest() == self.id,
+            self.compute_id() == self.id,
             MessageError::MalformedNotificationId(self.id.clone())
         );
 
         // Verify the signature on the publish notification
-        self.signature.verify(&self.id, &committee.idp.name)?;
+        self.signature
+            .verify(&self.id, &committee.idp.name)
+            .map_err(MessageError::from)
+    }
+
+    /// Verify a publish notification (very CPU-intensive).
+    pub async fn verify(&self, committee: &Committee, previous_root: &Root) -> MessageResult<()> {
+        // Verify the id and signature of the notification.
+        self.verify_signature(committee)?;
+
+        // Verify the committee change (if any).
+        if let Some(change) = &self.committee_change {
+            change.verify(committee, self.sequence_number)?;
+        }
 
         // Verify the commit proof.
         let hashes = vec![*previous_root, self.root];
@@ -129,10 +195,15 @@ pub struct PublishVote {
     pub root: Root,
     /// The sequence number of the publish notification.
     pub sequence_number: SequenceNumber,
+    /// The hash of the committee change of the publish notification (if any).
+    pub committee_change: Option<Digest>,
     /// The witness creating the vote.
     pub author: PublicKey,
     /// A signature authenticating the vote.
     pub signature: Signature,
+    /// A signature that can be aggregated with the votes of the other witnesses (if the committee
+    /// aggregates votes into compact certificates).
+    pub bls_signature: Option<BlsSignature>,
 }
 
 impl std::fmt::Debug for PublishVote {
@@ -153,6 +224,7 @@ impl PartialEq for PublishVote {
     fn eq(&self, other: &Self) -> bool {
         self.root == other.root
             && self.sequence_number == other.sequence_number
+            && self.committee_change == other.committee_change
             && self.author == other.author
     }
 }
@@ -165,19 +237,37 @@ impl PublishMessage for PublishVote {
     fn sequence_number(&self) -> SequenceNumber {
         self.sequence_number
     }
+
+    fn committee_change_digest(&self) -> Option<Digest> {
+        self.committee_change.clone()
+    }
 }
 
 impl PublishVote {
     /// Create a new vote for a publish notification (signed by a witness).
     pub fn new(notification: &PublishNotification, keypair: &KeyPair) -> Self {
+        Self::with_bls_signature(notification, keypair, None)
+    }
+
+    /// Create a new vote for a publish notification signed by a witness and, if a BLS keypair is
+    /// specified, with a signature that can be aggregated into a compact certificate.
+    pub fn with_bls_signature(
+        notification: &PublishNotification,
+        keypair: &KeyPair,
+        bls_keypair: Option<&BlsKeyPair>,
+    ) -> Self {
         let vote = Self {
             root: notification.root,
             sequence_number: notification.sequence_number,
+            committee_change: notification.committee_change_digest(),
             author: keypair.public(),
             signature: Signature::default(),
+            bls_signature: None,
         };
+        let digest = vote.digest();
         Self {
-            signature: Signature::new(&vote.digest(), keypair),
+            signature: Signature::new(&digest, keypair),
+            bls_signature: bls_keypair.map(|x| BlsSignature::new(&digest, x)),
             ..vote
         }
     }
@@ -191,12 +281,59 @@ impl PublishVote {
         );
 
         // Check the signature.
-        self.signature
-            .verify(&self.digest(), &self.author)
-            .map_err(MessageError::from)
+        self.signature.verify(&self.digest(), &self.author)?;
+
+        // Check the aggregatable signature (if the committee aggregates votes).
+        if committee.signature_scheme == SignatureScheme::Bls {
+            let bls_signature = self
+                .bls_signature
+                .as_ref()
+                .ok_or(MessageError::MissingBlsSignature(self.author))?;
+            let bls_public_key = committee
+                .bls_public_key(&self.author)
+                .expect("Witnesses with voting power are in the committee");
+            bls_signature.verify(&self.digest(), bls_public_key)?;
+        }
+        Ok(())
+    }
+}
+
+/// A set of witnesses represented by their position in the committee (ordered by public key).
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct SignerBitmap(Vec<u8>);
+
+impl SignerBitmap {
+    /// Add the witness at the specified position to the set.
+    pub fn insert(&mut self, index: usize) {
+        let byte = index / 8;
+        if self.0.len() <= byte {
+            self.0.resize(byte + 1, 0);
+        }
+        self.0[byte] |= 1 << (index % 8);
+    }
+
+    /// Check whether the witness at the specified position is in the set.
+    pub fn contains(&self, index: usize) -> bool {
+        self.0
+            .get(index / 8)
+            .map_or(false, |byte| byte & (1 << (index % 8)) != 0)
+    }
+
+    /// Return the number of witnesses in the set.
+    pub fn count(&self) -> usize {
+        self.0.iter().map(|byte| byte.count_ones() as usize).sum()
     }
 }
 
+/// The votes of a quorum of witnesses.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Votes {
+    /// The (ed25519) signature of every witness of the quorum.
+    Individual(Vec<(PublicKey, Signature)>),
+    /// A single (BLS) signature aggregating the votes of the witnesses of the bitmap.
+    Aggregate(SignerBitmap, BlsSignature),
+}
+
 /// A certificate over a publish notification.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct PublishCertificate {
@@ -206,8 +343,10 @@ pub struct PublishCertificate {
     pub root: Root,
     /// The sequence number of the publish notification.
     pub sequence_number: SequenceNumber,
+    /// The committee change certified along with the root (if any).
+    pub committee_change: Option<CommitteeChange>,
     /// The quorum of votes making the certificate.
-    pub votes: Vec<(PublicKey, Signature)>,
+    pub votes: Votes,
 }
 
 impl std::fmt::Debug for PublishCertificate {
@@ -243,15 +382,43 @@ impl PublishMessage for PublishCertificate {
     fn sequence_number(&self) -> SequenceNumber {
         self.sequence_number
     }
+
+    fn committee_change_digest(&self) -> Option<Digest> {
+        self.committee_change.as_ref().map(|x| x.digest())
+    }
 }
 
 impl PublishCertificate {
-    /// Verify that certificate.
+    /// Verify that certificate. The committee must be the one in charge of certifying the
+    /// sequence number of the certificate.
     pub fn verify(&self, committee: &Committee) -> MessageResult<()> {
+        // Verify the committee change (if any).
+        if let Some(change) = &self.committee_change {
+            change.verify(committee, self.sequence_number)?;
+        }
+
+        // The votes must follow the signature scheme of the committee.
+        match (&self.votes, committee.signature_scheme) {
+            (Votes::Individual(votes), SignatureScheme::Ed25519) => {
+                self.verify_individual_votes(committee, votes)
+            }
+            (Votes::Aggregate(signers, signature), SignatureScheme::Bls) => {
+                self.verify_aggregate_votes(committee, signers, signature)
+            }
+            (_, scheme) => Err(MessageError::UnexpectedSignatureScheme(scheme)),
+        }
+    }
+
+    /// Verify a quorum of individual votes (one signature verification per vote).
+    fn verify_individual_votes(
+        &self,
+        committee: &Committee,
+        votes: &[(PublicKey, Signature)],
+    ) -> MessageResult<()> {
         // Ensure the certificate has a quorum.
         let mut weight = 0;
         let mut used = HashSet::new();
-        for (name, _) in self.votes.iter() {
+        for (name, _) in votes.iter() {
             ensure!(!used.contains(name), MessageError::WitnessReuse(*name));
             let voting_power = committee.voting_power(name);
             ensure!(voting_power > 0, MessageError::UnknownWitness(*name));
@@ -264,6 +431,42 @@ impl PublishCertificate {
         );
 
         // Check the signatures.
-        Signature::verify_batch(&self.digest(), &self.votes).map_err(MessageError::from)
+        Signature::verify_batch(&self.digest(), votes).map_err(MessageError::from)
+    }
+
+    /// Verify an aggregate of a quorum of votes (a single pairing check regardless of the size
+    /// of the committee).
+    fn verify_aggregate_votes(
+        &self,
+        committee: &Committee,
+        signers: &SignerBitmap,
+        signature: &BlsSignature,
+    ) -> MessageResult<()> {
+        // Ensure the certificate has a quorum.
+        let mut weight = 0;
+        let mut public_keys = Vec::new();
+        for (index, (name, witness)) in committee.witnesses.iter().enumerate() {
+            if signers.contains(index) {
+                ensure!(
+                    witness.voting_power > 0,
+                    MessageError::UnknownWitness(*name)
+                );
+                public_keys.push(&witness.bls_public_key);
+                weight += witness.voting_power;
+            }
+        }
+        ensure!(
+            public_keys.len() == signers.count(),
+            MessageError::MalformedSignerBitmap
+        );
+        ensure!(
+            weight >= committee.quorum_threshold(),
+            MessageError::CertificateRequiresQuorum
+        );
+
+        // Check the aggregate signature.
+        signature
+            .verify_aggregate(&self.digest(), public_keys)
+            .map_err(MessageError::from)
     }
 }
@@ -0,0 +1,184 @@
+use crate::{
+    ensure,
+    error::{MessageError, MessageResult},
+    publish::PublishCertificate,
+    SequenceNumber,
+};
+use config::Committee;
+use crypto::{Digest, PublicKey};
+use ed25519_dalek::{Digest as _, Sha512};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, convert::TryInto, net::SocketAddr};
+
+/// A handover of the directory to a new committee of witnesses. The IdP signs it as part of a
+/// publish notification and the current committee certifies it as part of the publish certificate.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CommitteeChange {
+    /// The committee taking over.
+    pub committee: Committee,
+    /// The first sequence number certified by the new committee.
+    pub activation: SequenceNumber,
+}
+
+impl std::fmt::Debug for CommitteeChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}: R{}({} witnesses)",
+            self.digest(),
+            self.activation,
+            self.committee.size()
+        )
+    }
+}
+
+impl CommitteeChange {
+    /// Create a new committee change.
+    pub fn new(committee: Committee, activation: SequenceNumber) -> Self {
+        Self {
+            committee,
+            activation,
+        }
+    }
+
+    /// Compute the hash of the committee change.
+    pub fn digest(&self) -> Digest {
+        let serialized = bincode::serialize(self).expect("Failed to serialize committee change");
+        let mut hasher = Sha512::new();
+        hasher.update(&serialized);
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+
+    /// Verify that the committee change can be certified by the specified committee in the
+    /// message carrying the specified sequence number.
+    pub fn verify(
+        &self,
+        committee: &Committee,
+        sequence_number: SequenceNumber,
+    ) -> MessageResult<()> {
+        // The new committee takes over right after the change is certified.
+        ensure!(
+            self.activation == sequence_number + 1,
+            MessageError::UnexpectedActivation {
+                expected: sequence_number + 1,
+                got: self.activation
+            }
+        );
+
+        // Only the witnesses can change.
+        ensure!(
+            self.committee.idp.name == committee.idp.name,
+            MessageError::IdpChange
+        );
+
+        // Ensure the new committee can certify messages.
+        ensure!(
+            self.committee.total_voting_power() > 0,
+            MessageError::EmptyCommittee
+        );
+
+        // Ensure no witness registers a rogue BLS key.
+        self.committee
+            .verify_proofs_of_possession()
+            .map_err(MessageError::InvalidProofOfPossession)?;
+        Ok(())
+    }
+}
+
+/// The committees in charge of certifying each range of sequence numbers. It allows to verify
+/// historical certificates under the committee that signed them.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CommitteeHistory {
+    /// The committees indexed by the first sequence number they certify.
+    committees: BTreeMap<SequenceNumber, Committee>,
+    /// The certificates handing over to the committees following the genesis one (sorted by
+    /// sequence number).
+    changes: Vec<PublishCertificate>,
+}
+
+impl CommitteeHistory {
+    /// Create a new history starting with the genesis committee.
+    pub fn new(genesis: Committee) -> Self {
+        Self {
+            committees: BTreeMap::from([(SequenceNumber::default(), genesis)]),
+            changes: Vec::new(),
+        }
+    }
+
+    /// Return the committee in charge of certifying the specified sequence number.
+    pub fn get(&self, sequence_number: SequenceNumber) -> &Committee {
+        self.committees
+            .range(..=sequence_number)
+            .next_back()
+            .map(|(_, committee)| committee)
+            .expect("The history always contains the genesis committee")
+    }
+
+    /// Return the latest committee.
+    pub fn current(&self) -> &Committee {
+        self.committees
+            .values()
+            .next_back()
+            .expect("The history always contains the genesis committee")
+    }
+
+    /// Return the first sequence number certified by the latest committee.
+    pub fn last_activation(&self) -> SequenceNumber {
+        *self
+            .committees
+            .keys()
+            .next_back()
+            .expect("The history always contains the genesis committee")
+    }
+
+    /// Return the sequence numbers of the certificates handing over to the committees activated
+    /// after the specified sequence number.
+    pub fn changes_after(&self, activation: SequenceNumber) -> Vec<SequenceNumber> {
+        self.committees
+            .range(activation + 1..)
+            .map(|(activation, _)| activation - 1)
+            .collect()
+    }
+
+    /// Return all the committees (starting with the genesis one).
+    pub fn iter(&self) -> impl Iterator<Item = &Committee> {
+        self.committees.values()
+    }
+
+    /// Return the certificates handing over to the committees following the genesis one.
+    pub fn changes(&self) -> &[PublishCertificate] {
+        &self.changes
+    }
+
+    /// Return the latest committee including the specified witness (if any).
+    pub fn latest_including(&self, name: &PublicKey) -> Option<&Committee> {
+        self.committees
+            .values()
+            .rev()
+            .find(|committee| committee.witnesses.contains_key(name))
+    }
+
+    /// Return the latest known address of a witness (even if it is no longer in the committee).
+    pub fn witness_address(&self, name: &PublicKey) -> Option<SocketAddr> {
+        self.latest_including(name)
+            .and_then(|committee| committee.witness_address(name))
+    }
+
+    /// Record the committee change carried by a (verified) certificate. Return whether the
+    /// certificate changed the committee.
+    pub fn update(&mut self, certificate: &PublishCertificate) -> bool {
+        match &certificate.committee_change {
+            Some(change) => {
+                let known = self
+                    .committees
+                    .insert(change.activation, change.committee.clone())
+                    .is_some();
+                if !known {
+                    self.changes.push(certificate.clone());
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     deserialize_root,
     publish::{PublishMessage, PublishVote},
-    serialize_root, Blake3, Root, SequenceNumber,
+    serialize_root, Blake3, Root, SequenceNumber, SerializedPublishCertificateMessage,
 };
 use akd::{directory::Directory, ecvrf::HardCodedAkdVRF, storage::memory::AsyncInMemoryDatabase};
 use futures::executor::block_on;
@@ -22,6 +22,7 @@ pub struct State {
 
 impl Default for State {
     fn default() -> Self {
+        // The root of an empty directory does not depend on the VRF key.
         let db = AsyncInMemoryDatabase::new();
         let vrf = HardCodedAkdVRF {};
         let akd = block_on(Directory::new::<Blake3>(&db, &vrf, false))
@@ -59,15 +60,43 @@ impl PartialEq for State {
     }
 }
 
-/// Request of a publish certificate request.
+/// Request of a range of publish certificates.
 #[derive(Serialize, Deserialize)]
-pub struct PublishCertificateQuery {
-    /// The sequence number of the requested certificate.
-    pub sequence_number: SequenceNumber,
+pub struct PublishCertificateRangeQuery {
+    /// The sequence number of the first requested certificate.
+    pub from: SequenceNumber,
+    /// The sequence number of the last requested certificate (inclusive).
+    pub to: SequenceNumber,
+    /// The maximum size (in bytes) of the certificates of the response. The response always
+    /// includes the first certificate (if found) to ensure progress.
+    pub max_bytes: usize,
+}
+
+impl std::fmt::Debug for PublishCertificateRangeQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "CertRequest({}..={}, {}B)",
+            self.from, self.to, self.max_bytes
+        )
+    }
+}
+
+/// Reply to a request of a range of publish certificates.
+#[derive(Serialize, Deserialize)]
+pub enum PublishCertificateRangeResponse {
+    /// The (serialized) certificates in the order of their sequence numbers and starting with the
+    /// first requested certificate. They may stop short of the range to respect the size limit.
+    Found(Vec<SerializedPublishCertificateMessage>),
+    /// The responder does not hold the first requested certificate.
+    NotFound(SequenceNumber),
 }
 
-impl std::fmt::Debug for PublishCertificateQuery {
+impl std::fmt::Debug for PublishCertificateRangeResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(f, "CertRequest({})", self.sequence_number)
+        match self {
+            Self::Found(certificates) => write!(f, "CertResponse({} certs)", certificates.len()),
+            Self::NotFound(sequence_number) => write!(f, "CertResponse(!{})", sequence_number),
+        }
     }
 }
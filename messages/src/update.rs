@@ -1,7 +1,159 @@
+use crate::{error::MessageResult, vrf::LabelProof, SequenceNumber};
 use akd::storage::types::{AkdLabel, AkdValue};
+use config::Committee;
+use crypto::{Digest, KeyPair, PublicKey, Signature};
+use ed25519_dalek::{Digest as _, Sha512};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// Domain separator of the receipts (the IdP signs other messages with the same key).
+const RECEIPT_DOMAIN: &[u8] = b"BananaTree_UpdateReceipt";
 
 /// A client request in a format understandable by `akd`.
 pub type UpdateRequest = (AkdLabel, AkdValue);
 
 /// A batch of requests.
 pub type Batch = Vec<UpdateRequest>;
+
+/// A batch of requests shipped to the full witnesses, along with the VRF proofs of the labels it
+/// updates (so that full witnesses replay it without the VRF secret key of the IdP).
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ReplicaBatch {
+    /// The updates of the batch.
+    pub updates: Batch,
+    /// The VRF proofs of the labels of the updates.
+    pub proofs: Vec<LabelProof>,
+}
+
+/// An update request signed by the owner of the label.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignedUpdateRequest {
+    /// The update request.
+    pub update: UpdateRequest,
+    /// The public key of the owner of the label.
+    pub owner: PublicKey,
+    /// A number used once to prevent replays (strictly increasing for each label).
+    pub nonce: u64,
+    /// A signature from the owner authenticating the request.
+    pub signature: Signature,
+}
+
+impl std::fmt::Debug for SignedUpdateRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}: U{}({}, {})",
+            self.digest(),
+            self.nonce,
+            base64::encode(&self.update.0 .0),
+            self.owner
+        )
+    }
+}
+
+impl SignedUpdateRequest {
+    /// Create a new update request signed by the owner of the label.
+    pub fn new(update: UpdateRequest, nonce: u64, keypair: &KeyPair) -> Self {
+        let request = Self {
+            update,
+            owner: keypair.public(),
+            nonce,
+            signature: Signature::default(),
+        };
+        Self {
+            signature: Signature::new(&request.digest(), keypair),
+            ..request
+        }
+    }
+
+    /// Compute the hash of the request.
+    pub fn digest(&self) -> Digest {
+        let (label, value) = &self.update;
+        let mut hasher = Sha512::new();
+        hasher.update(&label.0);
+        hasher.update(UpdateReceipt::hash_value(value));
+        hasher.update(&self.owner);
+        hasher.update(self.nonce.to_le_bytes());
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+
+    /// Verify that the request is correctly signed by its (claimed) owner.
+    pub fn verify(&self) -> MessageResult<()> {
+        self.signature
+            .verify(&self.digest(), &self.owner)
+            .map_err(Into::into)
+    }
+}
+
+/// A promise signed by the IdP to commit an update request by a deadline.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UpdateReceipt {
+    /// The label of the update request.
+    pub label: AkdLabel,
+    /// The hash of the value of the update request.
+    pub value_hash: Digest,
+    /// The sequence number by which the update will be committed.
+    pub deadline: SequenceNumber,
+    /// A signature from the IdP authenticating the receipt.
+    pub signature: Signature,
+}
+
+impl std::fmt::Debug for UpdateReceipt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}: R{}({}, {})",
+            self.digest(),
+            self.deadline,
+            base64::encode(&self.label.0),
+            self.value_hash
+        )
+    }
+}
+
+impl UpdateReceipt {
+    /// Create a new receipt signed by the IdP.
+    pub fn new(update: &UpdateRequest, deadline: SequenceNumber, keypair: &KeyPair) -> Self {
+        let (label, value) = update;
+        let receipt = Self {
+            label: label.clone(),
+            value_hash: Self::hash_value(value),
+            deadline,
+            signature: Signature::default(),
+        };
+        Self {
+            signature: Signature::new(&receipt.digest(), keypair),
+            ..receipt
+        }
+    }
+
+    /// Compute the hash of a value.
+    pub fn hash_value(value: &AkdValue) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(&value.0);
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+
+    /// Compute the hash of the receipt.
+    pub fn digest(&self) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(RECEIPT_DOMAIN);
+        hasher.update(&self.label.0);
+        hasher.update(&self.value_hash);
+        hasher.update(self.deadline.to_le_bytes());
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+
+    /// Check whether the receipt refers to the specified update request.
+    pub fn matches(&self, update: &UpdateRequest) -> bool {
+        let (label, value) = update;
+        self.label == *label && self.value_hash == Self::hash_value(value)
+    }
+
+    /// Verify that the receipt is correctly signed by the IdP.
+    pub fn verify(&self, committee: &Committee) -> MessageResult<()> {
+        self.signature
+            .verify(&self.digest(), &committee.idp.name)
+            .map_err(Into::into)
+    }
+}
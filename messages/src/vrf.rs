@@ -0,0 +1,82 @@
+use crate::error::MessageResult;
+use akd::{
+    ecvrf::{VRFKeyStorage, VRFPublicKey, VrfError},
+    storage::types::AkdLabel,
+};
+use async_trait::async_trait;
+use config::Committee;
+use crypto::KeyPair;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use winter_crypto::Hasher;
+
+/// The VRF proof of a version of a label. The IdP ships them to the full witnesses so that they
+/// derive the labels of the directory without ever holding the VRF secret key.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LabelProof {
+    /// The label of the directory.
+    pub label: AkdLabel,
+    /// Whether the proof derives the label marking the version as stale.
+    pub stale: bool,
+    /// The version of the label.
+    pub version: u64,
+    /// The serialized VRF proof.
+    pub proof: Vec<u8>,
+}
+
+/// The VRF private key of the IdP, in a format understandable by `akd`.
+#[derive(Clone)]
+pub struct IdpVrf {
+    /// The secret bytes of the VRF key.
+    secret: Vec<u8>,
+}
+
+impl IdpVrf {
+    /// Create a new VRF from the IdP's VRF keypair.
+    pub fn new(keypair: &KeyPair) -> Self {
+        Self {
+            secret: keypair.secret_bytes().to_vec(),
+        }
+    }
+
+    /// Compute the VRF proofs required to publish a new version of a label and to prove lookups
+    /// of it: the label of the new version and of its freshness, the label marking the previous
+    /// version as stale, and the label of the marker version (the largest power of two not
+    /// greater than the version).
+    pub async fn label_proofs<H: Hasher>(
+        &self,
+        label: &AkdLabel,
+        version: u64,
+    ) -> Result<Vec<LabelProof>, VrfError> {
+        let marker = 1u64 << (63 - version.leading_zeros());
+        let mut inputs = vec![(false, version), (true, version), (false, marker)];
+        if version > 1 {
+            inputs.push((true, version - 1));
+        }
+
+        let mut proofs = Vec::with_capacity(inputs.len());
+        for (stale, version) in inputs {
+            let proof = self.get_label_proof::<H>(label, stale, version).await?;
+            proofs.push(LabelProof {
+                label: label.clone(),
+                stale,
+                version,
+                proof: proof.to_bytes().to_vec(),
+            });
+        }
+        Ok(proofs)
+    }
+}
+
+#[async_trait]
+impl VRFKeyStorage for IdpVrf {
+    async fn retrieve(&self) -> Result<Vec<u8>, VrfError> {
+        Ok(self.secret.clone())
+    }
+}
+
+/// Load the VRF public key of the IdP from the committee.
+pub fn vrf_public_key(committee: &Committee) -> MessageResult<VRFPublicKey> {
+    let bytes = committee.idp.vrf_public_key.as_ref();
+    VRFPublicKey::try_from(bytes).map_err(Into::into)
+}
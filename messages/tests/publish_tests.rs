@@ -1,4 +1,17 @@
-use test_utils::{certificate, committee, notification, proof, votes};
+use config::SignatureScheme;
+use crypto::BlsSignature;
+use messages::{
+    checkpoint::Checkpoint,
+    equivocation::EquivocationProof,
+    error::MessageError,
+    publish::{PublishCertificate, PublishMessage as _, PublishVote, SignerBitmap, Votes},
+    reconfiguration::{CommitteeChange, CommitteeHistory},
+};
+use test_utils::{
+    aggregate_certificate, bls_committee, bls_keys, bls_votes, certificate, committee,
+    committee_change_certificate, forked_notification, keys, notification, pipelined_notification,
+    proof, rogue_bls_key, votes,
+};
 
 #[tokio::test]
 async fn verify_notification() {
@@ -25,3 +38,192 @@ async fn verify_certificate() {
     let certificate = certificate().await;
     assert!(certificate.verify(&committee(0)).is_ok());
 }
+
+#[tokio::test]
+async fn verify_bls_vote() {
+    let vote = bls_votes().await.pop().unwrap();
+    assert!(vote.verify(&bls_committee(0)).is_ok());
+
+    // Committees aggregating votes require an aggregatable signature.
+    let vote = votes().await.pop().unwrap();
+    assert!(vote.verify(&bls_committee(0)).is_err());
+}
+
+#[tokio::test]
+async fn verify_aggregate_certificate() {
+    let certificate = aggregate_certificate().await;
+    assert!(certificate.verify(&bls_committee(0)).is_ok());
+}
+
+#[tokio::test]
+async fn reject_individual_votes_under_bls() {
+    // Committees aggregating votes only accept aggregate certificates.
+    let certificate = certificate().await;
+    match certificate.verify(&bls_committee(0)) {
+        Err(MessageError::UnexpectedSignatureScheme(SignatureScheme::Bls)) => (),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn reject_aggregate_under_ed25519() {
+    // Committees not aggregating votes only accept certificates of individual votes.
+    let certificate = aggregate_certificate().await;
+    match certificate.verify(&committee(0)) {
+        Err(MessageError::UnexpectedSignatureScheme(SignatureScheme::Ed25519)) => (),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn verify_bad_aggregate_certificate() {
+    // The bitmap does not match the signers of the aggregate signature.
+    let mut certificate = aggregate_certificate().await;
+    if let Votes::Aggregate(signers, _) = &mut certificate.votes {
+        *signers = SignerBitmap::default();
+        (0..3).for_each(|i| signers.insert(i));
+    }
+    assert!(certificate.verify(&bls_committee(0)).is_err());
+
+    // The bitmap includes witnesses that are not in the committee.
+    let mut certificate = aggregate_certificate().await;
+    if let Votes::Aggregate(signers, _) = &mut certificate.votes {
+        signers.insert(4);
+    }
+    assert!(certificate.verify(&bls_committee(0)).is_err());
+}
+
+#[tokio::test]
+async fn reject_rogue_key_aggregate() {
+    // The first witness registers a rogue key cancelling out the keys of the other witnesses.
+    let mut committee = bls_committee(0);
+    let (attacker, _) = keys().swap_remove(0);
+    let (attacker_key, attacker_secret) = bls_keys().swap_remove(0);
+    let others: Vec<_> = bls_keys().into_iter().skip(1).map(|(x, _)| x).collect();
+    let witness = committee.witnesses.get_mut(&attacker).unwrap();
+    witness.bls_public_key = rogue_bls_key(&attacker_key, &others);
+    witness.bls_proof_of_possession = attacker_secret.proof_of_possession();
+
+    // The attacker alone forges an aggregate certificate 'signed' by the whole committee.
+    let mut certificate = aggregate_certificate().await;
+    let forgery = BlsSignature::new(&certificate.digest(), &attacker_secret);
+    if let Votes::Aggregate(_, signature) = &mut certificate.votes {
+        *signature = forgery;
+    }
+    assert!(certificate.verify(&committee).is_ok());
+
+    // The rogue key has no valid proof of possession, so the committee is never accepted.
+    assert_eq!(committee.verify_proofs_of_possession(), Err(attacker));
+    let committee_change = CommitteeChange::new(committee, /* activation */ 2);
+    assert!(committee_change.verify(&bls_committee(0), 1).is_err());
+}
+
+#[tokio::test]
+async fn verify_committee_change() {
+    let certificate = committee_change_certificate(0).await;
+    assert!(certificate.verify(&committee(0)).is_ok());
+
+    // The new committee is in charge right after the certificate.
+    let mut committees = CommitteeHistory::new(committee(0));
+    assert!(committees.update(&certificate));
+    assert_eq!(committees.get(certificate.sequence_number).size(), 4);
+    assert_eq!(committees.get(certificate.sequence_number + 1).size(), 1);
+    assert_eq!(committees.current().size(), 1);
+
+    // Clients missing the change learn it from the certificate at the sequence number before.
+    assert_eq!(committees.last_activation(), 2);
+    assert_eq!(committees.changes_after(0), vec![1]);
+    assert!(committees.changes_after(2).is_empty());
+}
+
+#[tokio::test]
+async fn verify_bad_committee_change() {
+    // The votes do not certify the committee change.
+    let mut certificate = certificate().await;
+    certificate.committee_change = committee_change_certificate(0).await.committee_change;
+    assert!(certificate.verify(&committee(0)).is_err());
+}
+
+#[tokio::test]
+async fn verify_checkpoint() {
+    let committees = CommitteeHistory::new(committee(0));
+    let checkpoint = Checkpoint::new(certificate().await, &committees);
+    assert!(checkpoint.verify(&committees).is_ok());
+
+    // The checkpoint of a committee change carries the new committee.
+    let certificate = committee_change_certificate(0).await;
+    let mut history = committees.clone();
+    history.update(&certificate);
+    let checkpoint = Checkpoint::new(certificate, &history);
+    let verified = checkpoint.verify(&committees).unwrap();
+    assert_eq!(verified.current().size(), 1);
+}
+
+#[tokio::test]
+async fn verify_checkpoint_after_committee_change() {
+    // A certificate of the committee that took over after `committee_change_certificate()`.
+    let notification = pipelined_notification().await;
+    let (_, keypair) = keys().swap_remove(0);
+    let vote = PublishVote::new(&notification, &keypair);
+    let certificate = PublishCertificate {
+        root: notification.root,
+        sequence_number: notification.sequence_number,
+        committee_change: None,
+        votes: Votes::Individual(vec![(vote.author, vote.signature)]),
+    };
+
+    // A witness only knowing the genesis committee learns the change from the checkpoint.
+    let genesis = CommitteeHistory::new(committee(0));
+    let mut committees = genesis.clone();
+    committees.update(&committee_change_certificate(0).await);
+    let checkpoint = Checkpoint::new(certificate, &committees);
+    assert_eq!(checkpoint.committee_changes.len(), 1);
+    let verified = checkpoint.verify(&genesis).unwrap();
+    assert_eq!(verified.last_activation(), 2);
+    assert_eq!(verified.changes().len(), 1);
+
+    // A witness already knowing the change verifies the checkpoint as well.
+    assert!(checkpoint.verify(&committees).is_ok());
+}
+
+#[tokio::test]
+async fn verify_bad_checkpoint() {
+    // The checkpoint does not carry the committee in charge after the certificate.
+    let committees = CommitteeHistory::new(committee(0));
+    let checkpoint = Checkpoint {
+        certificate: committee_change_certificate(0).await,
+        committee_changes: Vec::new(),
+        committee: committee(0),
+    };
+    assert!(checkpoint.verify(&committees).is_err());
+
+    // The checkpoint does not carry the committee changes preceding the certificate.
+    let notification = pipelined_notification().await;
+    let (_, keypair) = keys().swap_remove(0);
+    let vote = PublishVote::new(&notification, &keypair);
+    let mut history = committees.clone();
+    history.update(&committee_change_certificate(0).await);
+    let checkpoint = Checkpoint {
+        certificate: PublishCertificate {
+            root: notification.root,
+            sequence_number: notification.sequence_number,
+            committee_change: None,
+            votes: Votes::Individual(vec![(vote.author, vote.signature)]),
+        },
+        committee_changes: Vec::new(),
+        committee: history.current().clone(),
+    };
+    assert!(checkpoint.verify(&committees).is_err());
+}
+
+#[tokio::test]
+async fn verify_equivocation_proof() {
+    let proof = EquivocationProof::new(notification().await, forked_notification().await);
+    assert!(proof.verify(&committee(0)).is_ok());
+}
+
+#[tokio::test]
+async fn verify_bad_equivocation_proof() {
+    let proof = EquivocationProof::new(notification().await, notification().await);
+    assert!(proof.verify(&committee(0)).is_err());
+}
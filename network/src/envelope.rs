@@ -0,0 +1,48 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::convert::TryInto;
+
+/// Identifies a request on a connection (and the reply to it).
+pub type RequestId = u64;
+
+/// The size (in bytes) of the header of an envelope.
+const HEADER_SIZE: usize = std::mem::size_of::<RequestId>();
+
+/// Wraps every message sent over a connection. Replies carry the id of the request they answer so
+/// that the sender can match them even if the peer answers out of order (or not at all).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// The id of the request (or of the request answered by this reply).
+    pub id: RequestId,
+    /// The message itself.
+    pub payload: Bytes,
+}
+
+impl Envelope {
+    /// Create a new envelope.
+    pub fn new(id: RequestId, payload: Bytes) -> Self {
+        Self { id, payload }
+    }
+
+    /// Make the reply to this request.
+    pub fn reply(&self, payload: Bytes) -> Self {
+        Self::new(self.id, payload)
+    }
+
+    /// Serialize the envelope into a frame.
+    pub fn encode(&self) -> Bytes {
+        let mut frame = BytesMut::with_capacity(HEADER_SIZE + self.payload.len());
+        frame.put_u64(self.id);
+        frame.put_slice(&self.payload);
+        frame.freeze()
+    }
+
+    /// Parse a frame into an envelope. Returns `None` if the frame is too short to hold a header.
+    pub fn decode(mut frame: BytesMut) -> Option<Self> {
+        if frame.len() < HEADER_SIZE {
+            return None;
+        }
+        let header = frame.split_to(HEADER_SIZE);
+        let id = RequestId::from_be_bytes(header[..].try_into().ok()?);
+        Some(Self::new(id, frame.freeze()))
+    }
+}
@@ -1,3 +1,5 @@
+use crate::envelope::RequestId;
+use crypto::PublicKey;
 use std::{fmt::Debug, net::SocketAddr};
 use thiserror::Error;
 
@@ -18,6 +20,21 @@ pub enum NetworkError {
     #[error("Failed to receive ACK from {0}")]
     FailedToReceiveAck(SocketAddr),
 
-    #[error("Receive unexpected ACK from {0}")]
-    UnexpectedAck(SocketAddr),
+    #[error("Receive unexpected ACK {1} from {0}")]
+    UnexpectedAck(SocketAddr, RequestId),
+
+    #[error("Dropped message to {0}: the connection holds too many messages")]
+    BufferOverflow(SocketAddr),
+
+    #[error("Received malformed envelope from {0}")]
+    MalformedEnvelope(SocketAddr),
+
+    #[error("Failed to authenticate {0}: {1}")]
+    FailedHandshake(SocketAddr, String),
+
+    #[error("Unexpected identity of {0}, expected {1} but got {2}")]
+    UnexpectedIdentity(SocketAddr, PublicKey, PublicKey),
+
+    #[error("Rejected connection from {0}: {1} is not allowed to connect")]
+    UnauthorizedPeer(SocketAddr, PublicKey),
 }
@@ -0,0 +1,323 @@
+use crate::error::NetworkError;
+use bytes::{Bytes, BytesMut};
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use crypto::{Digest, KeyPair, PublicKey, Signature};
+use ed25519_dalek::{Digest as _, Sha512};
+use futures::{sink::SinkExt, stream::StreamExt};
+use rand::rngs::OsRng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    convert::TryInto,
+    io::{Error, ErrorKind},
+    net::SocketAddr,
+};
+use tokio::net::TcpStream;
+use tokio_util::codec::{Decoder, Encoder, Framed, FramedParts, LengthDelimitedCodec};
+use x25519_dalek as x25519;
+
+#[cfg(test)]
+#[path = "tests/handshake_tests.rs"]
+pub mod handshake_tests;
+
+/// Domain separator of the handshake (bound into every signature and session key).
+const PROTOCOL_NAME: &[u8] = b"BananaTree_X25519_ChaChaPoly_SHA512_Ed25519";
+
+/// Convenient alias for a TCP channel encrypted and authenticated with the session keys.
+pub type SecureTransport = Framed<TcpStream, SecureCodec>;
+
+/// The first handshake message, sent by the initiator of the connection.
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    /// The ephemeral Diffie-Hellman key of the initiator.
+    ephemeral: [u8; 32],
+}
+
+/// The reply of the responder, proving its identity.
+#[derive(Serialize, Deserialize)]
+struct Reply {
+    /// The ephemeral Diffie-Hellman key of the responder.
+    ephemeral: [u8; 32],
+    /// The identity of the responder.
+    name: PublicKey,
+    /// The signature of the responder over the handshake transcript.
+    signature: Signature,
+}
+
+/// The last handshake message, proving the identity of the initiator.
+#[derive(Serialize, Deserialize)]
+struct Finish {
+    /// The identity of the initiator.
+    name: PublicKey,
+    /// The signature of the initiator over the handshake transcript.
+    signature: Signature,
+}
+
+/// The hash of the ephemeral keys exchanged by the two ends of a connection. Both ends sign it
+/// (along with their role and the identities they know of) so that the session keys are bound to
+/// their identities.
+struct Transcript([u8; 64]);
+
+impl Transcript {
+    /// Hash the ephemeral keys of the initiator and of the responder.
+    fn new(initiator: &[u8; 32], responder: &[u8; 32]) -> Self {
+        let mut hasher = Sha512::new();
+        hasher.update(PROTOCOL_NAME);
+        hasher.update(initiator);
+        hasher.update(responder);
+        let mut hash = [0u8; 64];
+        hash.copy_from_slice(&hasher.finalize());
+        Self(hash)
+    }
+
+    /// Compute the digest signed by one end of the connection. The responder only signs its own
+    /// identity (it does not know the initiator yet) while the initiator signs both identities, so
+    /// that its signature cannot be replayed to another responder.
+    fn challenge(&self, role: &[u8], names: &[&PublicKey]) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(self.0);
+        hasher.update(role);
+        for name in names {
+            hasher.update(name);
+        }
+        Digest(hasher.finalize()[..32].try_into().unwrap())
+    }
+
+    /// Derive the session keys from the Diffie-Hellman shared secret. Returns the key encrypting
+    /// the messages of the initiator followed by the key encrypting the messages of the responder.
+    fn session_keys(&self, shared_secret: &x25519::SharedSecret) -> ([u8; 32], [u8; 32]) {
+        let mut hasher = Sha512::new();
+        hasher.update(PROTOCOL_NAME);
+        hasher.update(shared_secret.as_bytes());
+        hasher.update(self.0);
+        let keys = hasher.finalize();
+        (
+            keys[..32].try_into().unwrap(),
+            keys[32..].try_into().unwrap(),
+        )
+    }
+}
+
+/// Run the handshake as the initiator of the connection. It authenticates both ends and ensures
+/// the responder is the peer `expected`.
+pub async fn connect(
+    stream: TcpStream,
+    address: SocketAddr,
+    keypair: &KeyPair,
+    expected: &PublicKey,
+) -> Result<SecureTransport, NetworkError> {
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+
+    // Send our ephemeral key.
+    let secret = x25519::EphemeralSecret::new(OsRng);
+    let ephemeral = x25519::PublicKey::from(&secret).to_bytes();
+    send(&mut transport, address, &Hello { ephemeral }).await?;
+
+    // Authenticate the responder.
+    let reply: Reply = receive(&mut transport, address).await?;
+    if &reply.name != expected {
+        return Err(NetworkError::UnexpectedIdentity(
+            address, *expected, reply.name,
+        ));
+    }
+    let transcript = Transcript::new(&ephemeral, &reply.ephemeral);
+    reply
+        .signature
+        .verify(
+            &transcript.challenge(b"responder", &[&reply.name]),
+            &reply.name,
+        )
+        .map_err(|e| NetworkError::FailedHandshake(address, e.to_string()))?;
+
+    // Prove our identity.
+    let name = keypair.public();
+    let challenge = transcript.challenge(b"initiator", &[&name, &reply.name]);
+    let signature = Signature::new(&challenge, keypair);
+    send(&mut transport, address, &Finish { name, signature }).await?;
+
+    let shared_secret = agree(secret, &reply.ephemeral, address)?;
+    let (sending, receiving) = transcript.session_keys(&shared_secret);
+    Ok(SecureCodec::upgrade(transport, &sending, &receiving))
+}
+
+/// Run the handshake as the responder of the connection. It authenticates both ends and returns
+/// the identity of the initiator.
+pub async fn accept(
+    stream: TcpStream,
+    address: SocketAddr,
+    keypair: &KeyPair,
+) -> Result<(SecureTransport, PublicKey), NetworkError> {
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+
+    // Prove our identity.
+    let hello: Hello = receive(&mut transport, address).await?;
+    let secret = x25519::EphemeralSecret::new(OsRng);
+    let ephemeral = x25519::PublicKey::from(&secret).to_bytes();
+    let transcript = Transcript::new(&hello.ephemeral, &ephemeral);
+    let name = keypair.public();
+    let signature = Signature::new(&transcript.challenge(b"responder", &[&name]), keypair);
+    let reply = Reply {
+        ephemeral,
+        name,
+        signature,
+    };
+    send(&mut transport, address, &reply).await?;
+
+    // Authenticate the initiator.
+    let finish: Finish = receive(&mut transport, address).await?;
+    finish
+        .signature
+        .verify(
+            &transcript.challenge(b"initiator", &[&finish.name, &name]),
+            &finish.name,
+        )
+        .map_err(|e| NetworkError::FailedHandshake(address, e.to_string()))?;
+
+    let shared_secret = agree(secret, &hello.ephemeral, address)?;
+    let (receiving, sending) = transcript.session_keys(&shared_secret);
+    Ok((
+        SecureCodec::upgrade(transport, &sending, &receiving),
+        finish.name,
+    ))
+}
+
+/// Compute the Diffie-Hellman shared secret. Rejects the low-order keys of the peer that would
+/// let it force the session keys.
+fn agree(
+    secret: x25519::EphemeralSecret,
+    peer: &[u8; 32],
+    address: SocketAddr,
+) -> Result<x25519::SharedSecret, NetworkError> {
+    let shared_secret = secret.diffie_hellman(&x25519::PublicKey::from(*peer));
+    if shared_secret.as_bytes() == &[0; 32] {
+        let message = "Non-contributory ephemeral key".to_string();
+        return Err(NetworkError::FailedHandshake(address, message));
+    }
+    Ok(shared_secret)
+}
+
+/// Helper function sending a handshake message.
+async fn send<T: Serialize>(
+    transport: &mut Framed<TcpStream, LengthDelimitedCodec>,
+    address: SocketAddr,
+    message: &T,
+) -> Result<(), NetworkError> {
+    let bytes = bincode::serialize(message).expect("Failed to serialize handshake message");
+    transport
+        .send(Bytes::from(bytes))
+        .await
+        .map_err(|e| NetworkError::FailedToSendMessage(address, e))
+}
+
+/// Helper function receiving a handshake message.
+async fn receive<T: DeserializeOwned>(
+    transport: &mut Framed<TcpStream, LengthDelimitedCodec>,
+    address: SocketAddr,
+) -> Result<T, NetworkError> {
+    let bytes = match transport.next().await {
+        Some(frame) => frame.map_err(|e| NetworkError::FailedToReceiveMessage(address, e))?,
+        None => {
+            let error = Error::from(ErrorKind::UnexpectedEof);
+            return Err(NetworkError::FailedToReceiveMessage(address, error));
+        }
+    };
+    bincode::deserialize(&bytes).map_err(|e| NetworkError::FailedHandshake(address, e.to_string()))
+}
+
+/// One direction of an encrypted channel. Each message is sealed under a fresh nonce (a counter),
+/// so that the peer detects replayed, reordered, or dropped frames.
+struct Cipher {
+    /// The session key of this direction.
+    aead: ChaCha20Poly1305,
+    /// The number of messages sealed (or opened) so far.
+    counter: u64,
+}
+
+impl Cipher {
+    fn new(key: &[u8; 32]) -> Self {
+        Self {
+            aead: ChaCha20Poly1305::new(&Key::from(*key)),
+            counter: 0,
+        }
+    }
+
+    /// Return the nonce of the next message.
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter += 1;
+        nonce
+    }
+
+    fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let nonce = self.next_nonce();
+        self.aead
+            .encrypt(&Nonce::from(nonce), plaintext)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Failed to encrypt message"))
+    }
+
+    fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        let nonce = self.next_nonce();
+        self.aead
+            .decrypt(&Nonce::from(nonce), ciphertext)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Failed to authenticate message"))
+    }
+}
+
+/// A length-delimited codec encrypting and authenticating every frame with the session keys
+/// derived by the handshake.
+pub struct SecureCodec {
+    /// The underlying codec delimiting the frames.
+    frames: LengthDelimitedCodec,
+    /// Encrypts outgoing frames.
+    sending: Cipher,
+    /// Decrypts incoming frames.
+    receiving: Cipher,
+}
+
+impl SecureCodec {
+    /// Switch a transport to the session keys once the handshake is over (keeping the bytes
+    /// already buffered by the transport).
+    fn upgrade(
+        transport: Framed<TcpStream, LengthDelimitedCodec>,
+        sending: &[u8; 32],
+        receiving: &[u8; 32],
+    ) -> SecureTransport {
+        let parts = transport.into_parts();
+        let codec = Self {
+            frames: parts.codec,
+            sending: Cipher::new(sending),
+            receiving: Cipher::new(receiving),
+        };
+        let mut secure_parts = FramedParts::new::<Bytes>(parts.io, codec);
+        secure_parts.read_buf = parts.read_buf;
+        secure_parts.write_buf = parts.write_buf;
+        Framed::from_parts(secure_parts)
+    }
+}
+
+impl Encoder<Bytes> for SecureCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let ciphertext = self.sending.seal(&item)?;
+        self.frames.encode(Bytes::from(ciphertext), dst)
+    }
+}
+
+impl Decoder for SecureCodec {
+    type Item = BytesMut;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.frames.decode(src)? {
+            Some(frame) => {
+                let plaintext = self.receiving.open(&frame)?;
+                Ok(Some(BytesMut::from(&plaintext[..])))
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -1,3 +1,7 @@
-mod error;
+pub mod envelope;
+pub mod error;
+pub mod handshake;
+pub mod memory;
 pub mod receiver;
 pub mod reliable_sender;
+pub mod transport;
@@ -0,0 +1,269 @@
+use crate::{
+    error::NetworkError,
+    transport::{FrameSink, FrameStream, Incoming, Listener, Transport},
+};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use crypto::{KeyPair, PublicKey};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    sink::Sink,
+    stream::StreamExt,
+};
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::{
+    sync::mpsc,
+    time::{sleep_until, Duration, Instant},
+};
+
+#[cfg(test)]
+#[path = "tests/memory_tests.rs"]
+pub mod memory_tests;
+
+/// A connection handed over to a listener: the address and identity of the peer and the
+/// listener's ends of the channel.
+type Connection = (SocketAddr, PublicKey, FrameSink, FrameStream);
+
+/// The state of the simulated network.
+struct State {
+    /// The listening nodes (indexed by address) with the channels delivering their connections.
+    listeners: HashMap<SocketAddr, (PublicKey, mpsc::UnboundedSender<Connection>)>,
+    /// The delay to deliver a frame.
+    latency: Duration,
+    /// The probability to lose a frame (which breaks its connection).
+    drop_rate: f64,
+    /// The nodes cut off from the rest of the network.
+    partition: HashSet<PublicKey>,
+    /// The source of randomness deciding which frames are lost.
+    rng: StdRng,
+    /// The port given to the next outgoing connection (to identify connections in the logs).
+    next_port: u16,
+}
+
+impl State {
+    /// Find the listener of an address (possibly listening on all interfaces).
+    fn listener(
+        &self,
+        address: &SocketAddr,
+    ) -> Option<&(PublicKey, mpsc::UnboundedSender<Connection>)> {
+        let any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), address.port());
+        self.listeners
+            .get(address)
+            .or_else(|| self.listeners.get(&any))
+    }
+
+    /// Check whether a partition separates two nodes.
+    fn separated(&self, a: &PublicKey, b: &PublicKey) -> bool {
+        self.partition.contains(a) != self.partition.contains(b)
+    }
+}
+
+/// An in-process network connecting the nodes of a test through channels. It simulates latency,
+/// frame losses, and partitions, and makes the losses reproducible from a seed. As with TCP, a
+/// lost frame breaks its connection (the `ReliableSender` then re-transmits the messages that
+/// did not get a reply).
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    state: Arc<Mutex<State>>,
+}
+
+impl std::default::Default for MemoryNetwork {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl MemoryNetwork {
+    /// Make a network without latency, losses, or partitions.
+    pub fn new(seed: u64) -> Self {
+        let state = State {
+            listeners: HashMap::new(),
+            latency: Duration::default(),
+            drop_rate: 0.0,
+            partition: HashSet::new(),
+            rng: StdRng::seed_from_u64(seed),
+            next_port: 0,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Delay the delivery of every frame.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Lose frames with the given probability.
+    pub fn set_drop_rate(&self, drop_rate: f64) {
+        assert!((0.0..=1.0).contains(&drop_rate), "Invalid drop rate");
+        self.state.lock().unwrap().drop_rate = drop_rate;
+    }
+
+    /// Cut off the specified nodes from the rest of the network (until the network heals).
+    pub fn partition(&self, nodes: &[PublicKey]) {
+        self.state.lock().unwrap().partition = nodes.iter().cloned().collect();
+    }
+
+    /// Reconnect all the nodes.
+    pub fn heal(&self) {
+        self.state.lock().unwrap().partition.clear();
+    }
+
+    /// Decide whether a frame reaches its destination.
+    fn deliver(&self, from: &PublicKey, to: &PublicKey) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let drop_rate = state.drop_rate;
+        !state.separated(from, to) && !state.rng.gen_bool(drop_rate)
+    }
+
+    /// Make one direction of a channel. Frames are delivered in order (after the latency).
+    fn direction(&self, from: PublicKey, to: PublicKey) -> (FrameSink, FrameStream) {
+        let (tx_sent, rx_sent) = unbounded();
+        let (tx_delivered, rx_delivered) = unbounded();
+        tokio::spawn(self.clone().forward(from, to, rx_sent, tx_delivered));
+
+        let sink = MemorySink {
+            network: self.clone(),
+            sender: tx_sent,
+        };
+        let stream = rx_delivered.map(|frame: Bytes| Ok(BytesMut::from(&frame[..])));
+        (Box::pin(sink), Box::pin(stream))
+    }
+
+    /// Deliver the frames of one direction of a channel. Stops (and thus breaks the connection) at
+    /// the first frame that does not reach its destination.
+    async fn forward(
+        self,
+        from: PublicKey,
+        to: PublicKey,
+        mut rx_sent: UnboundedReceiver<(Instant, Bytes)>,
+        tx_delivered: UnboundedSender<Bytes>,
+    ) {
+        while let Some((deadline, frame)) = rx_sent.next().await {
+            sleep_until(deadline).await;
+            if !self.deliver(&from, &to) || tx_delivered.unbounded_send(frame).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryNetwork {
+    async fn connect(
+        &self,
+        address: SocketAddr,
+        keypair: &KeyPair,
+        expected: &PublicKey,
+    ) -> Result<(FrameSink, FrameStream), NetworkError> {
+        let refused = || {
+            let error = io::Error::from(ErrorKind::ConnectionRefused);
+            NetworkError::FailedToConnect(address, 0, error)
+        };
+
+        let name = keypair.public();
+        let (peer, tx_connection, local) = {
+            let mut state = self.state.lock().unwrap();
+            let (peer, tx_connection) = state.listener(&address).cloned().ok_or_else(refused)?;
+            if state.separated(&name, &peer) {
+                return Err(refused());
+            }
+            state.next_port = state.next_port.wrapping_add(1);
+            let local = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), state.next_port);
+            (peer, tx_connection, local)
+        };
+        if &peer != expected {
+            return Err(NetworkError::UnexpectedIdentity(address, *expected, peer));
+        }
+
+        // Make both directions of the channel and hand over the listener's ends.
+        let (sink, their_stream) = self.direction(name, peer);
+        let (their_sink, stream) = self.direction(peer, name);
+        tx_connection
+            .send((local, name, their_sink, their_stream))
+            .map_err(|_| refused())?;
+        Ok((sink, stream))
+    }
+
+    async fn listen(
+        &self,
+        address: SocketAddr,
+        keypair: Arc<KeyPair>,
+    ) -> Result<Box<dyn Listener>, io::Error> {
+        let (tx_connection, rx_connection) = mpsc::unbounded_channel();
+        let mut state = self.state.lock().unwrap();
+        if state.listeners.contains_key(&address) {
+            return Err(io::Error::from(ErrorKind::AddrInUse));
+        }
+        state
+            .listeners
+            .insert(address, (keypair.public(), tx_connection));
+        Ok(Box::new(MemoryListener { rx_connection }))
+    }
+}
+
+/// Receives the connections to a node of the simulated network.
+struct MemoryListener {
+    rx_connection: mpsc::UnboundedReceiver<Connection>,
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    async fn accept(&mut self) -> Result<(SocketAddr, Incoming), io::Error> {
+        let (peer, name, sink, stream) = self
+            .rx_connection
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(ErrorKind::NotConnected))?;
+
+        // The simulated network already authenticated the peer.
+        let incoming: Incoming = Box::pin(async move { Ok((sink, stream, name)) });
+        Ok((peer, incoming))
+    }
+}
+
+/// The writer end of one direction of a simulated channel. It schedules the delivery of each
+/// frame when it is sent.
+struct MemorySink {
+    /// The simulated network.
+    network: MemoryNetwork,
+    /// Outputs the frames (along with their delivery time) to the task delivering them.
+    sender: UnboundedSender<(Instant, Bytes)>,
+}
+
+impl Sink<Bytes> for MemorySink {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.sender.is_closed() {
+            Poll::Ready(Err(io::Error::from(ErrorKind::BrokenPipe)))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: Bytes) -> Result<(), Self::Error> {
+        let deadline = Instant::now() + self.network.state.lock().unwrap().latency;
+        self.sender
+            .unbounded_send((deadline, frame))
+            .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sender.close_channel();
+        Poll::Ready(Ok(()))
+    }
+}
@@ -1,18 +1,78 @@
-use crate::error::NetworkError;
+use crate::{
+    envelope::{Envelope, RequestId},
+    error::NetworkError,
+    transport::{FrameSink, Incoming, TcpTransport, Transport},
+};
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::stream::{SplitSink, StreamExt};
+use crypto::{KeyPair, PublicKey};
+use futures::{sink::SinkExt, stream::StreamExt};
 use log::{debug, info, warn};
-use std::{error::Error, net::SocketAddr};
-use tokio::net::{TcpListener, TcpStream};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::{Arc, Mutex, Weak},
+};
+use tokio::{
+    sync::{mpsc, OwnedSemaphorePermit, Semaphore},
+    time::{timeout, Duration},
+};
 
 #[cfg(test)]
 #[path = "tests/receiver_tests.rs"]
 pub mod receiver_tests;
 
-/// Convenient alias for the writer end of the TCP channel.
-pub type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
+/// The delay after which a peer that did not complete the handshake is disconnected (in ms).
+const HANDSHAKE_TIMEOUT: u64 = 5_000;
+
+/// The size of the channels between the runner of a connection and its tasks.
+const CHANNEL_CAPACITY: usize = 1_000;
+
+/// The maximum number of messages of a peer being handled at the same time (across all its
+/// connections). A message counts against this limit until all the handles to reply to it are
+/// dropped. The runners stop reading from the peer once it reaches this limit.
+const MAX_CONCURRENT_TASKS: usize = 100;
+
+/// The semaphores bounding the concurrent tasks of each peer. They are dropped once the peer has no
+/// connection nor task left.
+type TaskLimits = Arc<Mutex<HashMap<PublicKey, Weak<Semaphore>>>>;
+
+/// The reply handle of a request. It tags the replies with the id of the request they answer and
+/// hands them over to the task writing to the TCP channel. It can be cloned to reply from another
+/// task (the request keeps counting against the task limit of the peer until all clones are
+/// dropped).
+#[derive(Clone)]
+pub struct Writer {
+    /// Outputs the replies to the writer end of the TCP channel.
+    sender: mpsc::Sender<Bytes>,
+    /// The id of the request being handled.
+    request: RequestId,
+    /// The slot of the request in the task limit of the peer.
+    _permit: Arc<OwnedSemaphorePermit>,
+}
+
+impl Writer {
+    /// Reply to the request being handled.
+    pub async fn send(&mut self, data: Bytes) -> Result<(), io::Error> {
+        let envelope = Envelope::new(self.request, data);
+        self.sender
+            .send(envelope.encode())
+            .await
+            .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))
+    }
+}
+
+/// Declares how the receiver dispatches a message with respect to the other messages received on
+/// the same connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ordering {
+    /// Dispatch the message once the previous sequential messages of the connection are handled.
+    Sequential,
+    /// Dispatch the message right away, concurrently with the other messages of the connection.
+    Concurrent,
+}
 
 #[async_trait]
 pub trait MessageHandler: Clone + Send + Sync + 'static {
@@ -20,7 +80,26 @@ pub trait MessageHandler: Clone + Send + Sync + 'static {
     /// number of `Sender<T>` channels. Then implement `dispatch` to deserialize incoming messages and
     /// forward them through the appropriate delivery channel. Then `writer` can be used to send back
     /// responses or acknowledgements to the sender machine (see unit tests for examples).
-    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>>;
+    /// The `peer` is the identity authenticated by the handshake of the connection.
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        peer: &PublicKey,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Declares whether a message may be dispatched concurrently with the other messages of its
+    /// connection. By default, the messages of a connection are dispatched one at the time, in the
+    /// order they were received.
+    fn ordering(&self, _peer: &PublicKey, _message: &Bytes) -> Ordering {
+        Ordering::Sequential
+    }
+
+    /// Declares whether to accept the connection of a peer (once authenticated by the handshake).
+    /// By default, any peer completing the handshake is accepted.
+    fn authorize(&self, _peer: &PublicKey) -> bool {
+        true
+    }
 }
 
 /// For each incoming request, we spawn a new runner responsible to receive messages and forward them
@@ -28,27 +107,54 @@ pub trait MessageHandler: Clone + Send + Sync + 'static {
 pub struct Receiver<Handler: MessageHandler> {
     /// Address to listen to.
     address: SocketAddr,
+    /// The keypair authenticating this end of the connections.
+    keypair: Arc<KeyPair>,
     /// Struct responsible to define how to handle received messages.
     handler: Handler,
+    /// The transport carrying the connections.
+    transport: Arc<dyn Transport>,
+    /// Bounds the concurrent tasks of each peer.
+    limits: TaskLimits,
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
-    /// Spawn a new network receiver handling connections from any incoming peer.
-    pub fn spawn(address: SocketAddr, handler: Handler) {
+    /// Spawn a new network receiver handling connections from the incoming peers that complete the
+    /// handshake (and that the handler authorizes).
+    pub fn spawn(address: SocketAddr, keypair: KeyPair, handler: Handler) {
+        Self::spawn_with_transport(address, keypair, handler, Arc::new(TcpTransport));
+    }
+
+    /// Spawn a new network receiver accepting connections over a specific transport.
+    pub fn spawn_with_transport(
+        address: SocketAddr,
+        keypair: KeyPair,
+        handler: Handler,
+        transport: Arc<dyn Transport>,
+    ) {
         tokio::spawn(async move {
-            Self { address, handler }.run().await;
+            Self {
+                address,
+                keypair: Arc::new(keypair),
+                handler,
+                transport,
+                limits: TaskLimits::default(),
+            }
+            .run()
+            .await;
         });
     }
 
     /// Main loop responsible to accept incoming connections and spawn a new runner to handle it.
     async fn run(&self) {
-        let listener = TcpListener::bind(&self.address)
+        let mut listener = self
+            .transport
+            .listen(self.address, self.keypair.clone())
             .await
-            .expect("Failed to bind TCP port");
+            .expect("Failed to bind address");
 
         debug!("Listening on {}", self.address);
         loop {
-            let (socket, peer) = match listener.accept().await {
+            let (peer, incoming) = match listener.accept().await {
                 Ok(value) => value,
                 Err(e) => {
                     warn!("{}", NetworkError::FailedToListen(e));
@@ -56,34 +162,169 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                 }
             };
 
-            // TODO: Accept only authenticated connections (DoS attack).
-
             info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone()).await;
+            Self::spawn_runner(incoming, peer, self.handler.clone(), self.limits.clone()).await;
         }
     }
 
-    /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
-    /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) {
+    /// Spawn a new runner to handle a specific TCP connection. It authenticates the peer (dropping
+    /// the connection if the handler does not authorize it), then receives messages and process
+    /// them using the provided handler. Sequential messages are handed over to a dedicated task
+    /// (so that they do not prevent the runner from reading the next messages) while concurrent
+    /// messages are each dispatched in their own task. Either way, the peer may not have more than
+    /// `MAX_CONCURRENT_TASKS` messages in flight.
+    async fn spawn_runner(
+        incoming: Incoming,
+        peer: SocketAddr,
+        handler: Handler,
+        limits: TaskLimits,
+    ) {
         tokio::spawn(async move {
-            let transport = Framed::new(socket, LengthDelimitedCodec::new());
-            let (mut writer, mut reader) = transport.split();
-            while let Some(frame) = reader.next().await {
-                match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
-                    Ok(message) => {
-                        if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
-                            warn!("{}", e);
-                            return;
-                        }
-                    }
-                    Err(e) => {
+            // Drop the peers that do not complete the handshake in time.
+            let (sink, mut reader, name) =
+                match timeout(Duration::from_millis(HANDSHAKE_TIMEOUT), incoming).await {
+                    Ok(Ok(value)) => value,
+                    Ok(Err(e)) => {
                         warn!("{}", e);
                         return;
                     }
+                    Err(_) => {
+                        let error = "Handshake timed out".to_string();
+                        warn!("{}", NetworkError::FailedHandshake(peer, error));
+                        return;
+                    }
+                };
+            debug!("Authenticated {} as {}", peer, name);
+            if !handler.authorize(&name) {
+                warn!("{}", NetworkError::UnauthorizedPeer(peer, name));
+                return;
+            }
+            let semaphore = Self::task_limit(&limits, name);
+
+            let (tx_reply, rx_reply) = mpsc::channel(CHANNEL_CAPACITY);
+            tokio::spawn(Self::write_replies(sink, rx_reply, peer));
+
+            // The dispatch tasks signal the failures of the handler to close the connection.
+            let (tx_failure, mut rx_failure) = mpsc::channel(1);
+            let (tx_sequential, rx_sequential) = mpsc::channel(CHANNEL_CAPACITY);
+            tokio::spawn(Self::dispatch_sequential(
+                handler.clone(),
+                name,
+                rx_sequential,
+                tx_failure.clone(),
+            ));
+
+            loop {
+                tokio::select! {
+                    frame = reader.next() => {
+                        let frame = match frame {
+                            Some(Ok(frame)) => frame,
+                            Some(Err(e)) => {
+                                warn!("{}", NetworkError::FailedToReceiveMessage(peer, e));
+                                return;
+                            }
+                            None => break
+                        };
+                        let envelope = match Envelope::decode(frame) {
+                            Some(envelope) => envelope,
+                            None => {
+                                warn!("{}", NetworkError::MalformedEnvelope(peer));
+                                return;
+                            }
+                        };
+
+                        // Stop reading from the peer while it has too many messages in flight.
+                        let permit = match semaphore.clone().acquire_owned().await {
+                            Ok(permit) => permit,
+                            Err(_) => return
+                        };
+                        let writer = Writer {
+                            sender: tx_reply.clone(),
+                            request: envelope.id,
+                            _permit: Arc::new(permit),
+                        };
+                        match handler.ordering(&name, &envelope.payload) {
+                            Ordering::Sequential => {
+                                if tx_sequential.send((writer, envelope.payload)).await.is_err() {
+                                    return;
+                                }
+                            },
+                            Ordering::Concurrent => {
+                                let handler = handler.clone();
+                                let tx_failure = tx_failure.clone();
+                                tokio::spawn(async move {
+                                    let message = envelope.payload;
+                                    Self::dispatch(
+                                        &handler,
+                                        writer,
+                                        &name,
+                                        message,
+                                        &tx_failure
+                                    ).await;
+                                });
+                            }
+                        }
+                    },
+                    Some(()) = rx_failure.recv() => return
                 }
             }
             info!("Connection closed by peer {}", peer);
         });
     }
+
+    /// Return the semaphore bounding the concurrent tasks of a peer (shared by its connections).
+    fn task_limit(limits: &TaskLimits, name: PublicKey) -> Arc<Semaphore> {
+        let mut limits = limits.lock().unwrap();
+        if let Some(semaphore) = limits.get(&name).and_then(Weak::upgrade) {
+            return semaphore;
+        }
+
+        // Forget the peers without connection nor task left.
+        limits.retain(|_, semaphore| semaphore.strong_count() > 0);
+        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_TASKS));
+        limits.insert(name, Arc::downgrade(&semaphore));
+        semaphore
+    }
+
+    /// Dispatch a message to the handler. Signals the runner to close the connection if the
+    /// handler fails.
+    async fn dispatch(
+        handler: &Handler,
+        mut writer: Writer,
+        peer: &PublicKey,
+        message: Bytes,
+        tx_failure: &mpsc::Sender<()>,
+    ) {
+        if let Err(e) = handler.dispatch(&mut writer, peer, message).await {
+            warn!("{}", e);
+            let _ = tx_failure.try_send(());
+        }
+    }
+
+    /// Dispatch the sequential messages of a connection one at the time (in the order they were
+    /// received).
+    async fn dispatch_sequential(
+        handler: Handler,
+        peer: PublicKey,
+        mut rx_sequential: mpsc::Receiver<(Writer, Bytes)>,
+        tx_failure: mpsc::Sender<()>,
+    ) {
+        while let Some((writer, message)) = rx_sequential.recv().await {
+            Self::dispatch(&handler, writer, &peer, message, &tx_failure).await;
+        }
+    }
+
+    /// Write the replies of the handler to the TCP channel (in the order they are produced).
+    async fn write_replies(
+        mut sink: FrameSink,
+        mut rx_reply: mpsc::Receiver<Bytes>,
+        peer: SocketAddr,
+    ) {
+        while let Some(frame) = rx_reply.recv().await {
+            if let Err(e) = sink.send(frame).await {
+                warn!("{}", NetworkError::FailedToSendMessage(peer, e));
+                return;
+            }
+        }
+    }
 }
@@ -1,84 +1,246 @@
-use crate::error::NetworkError;
+use crate::{
+    envelope::{Envelope, RequestId},
+    error::NetworkError,
+    transport::{FrameSink, FrameStream, TcpTransport, Transport},
+};
 use bytes::Bytes;
+use crypto::{KeyPair, PublicKey};
 use futures::{sink::SinkExt, stream::StreamExt};
 use log::{info, warn};
 use std::{
     cmp::min,
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
     fmt::Debug,
+    future::Future,
     net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
 };
 use tokio::{
-    net::TcpStream,
     sync::{
         mpsc::{channel, Receiver, Sender},
         oneshot,
     },
     time::{sleep, Duration},
 };
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[cfg(test)]
 #[path = "tests/reliable_sender_tests.rs"]
 pub mod reliable_sender_tests;
 
-/// Convenient alias for cancel handlers returned to the caller task.
-pub type CancelHandler = oneshot::Receiver<Bytes>;
+/// The handler of a message returned to the caller task. Awaiting it returns the reply of the peer,
+/// or `NetworkError::BufferOverflow` if the connection dropped the message to enforce its limits.
+/// Dropping it cancels the transmission of the message.
+pub struct CancelHandler {
+    /// Receives the outcome of the transmission.
+    receiver: oneshot::Receiver<Result<Bytes, NetworkError>>,
+    /// The address of the peer.
+    address: SocketAddr,
+}
+
+impl Future for CancelHandler {
+    type Output = Result<Bytes, NetworkError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let address = self.address;
+        Pin::new(&mut self.receiver)
+            .poll(cx)
+            .map(|result| result.unwrap_or(Err(NetworkError::FailedToReceiveAck(address))))
+    }
+}
+
+/// Convenient alias for the reply channel of a message held by a connection.
+type Replier = oneshot::Sender<Result<Bytes, NetworkError>>;
+
+/// What a connection does with a new message once it holds as many messages as it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the new message.
+    DropNewest,
+    /// Drop the oldest messages (sent or not) to make room for the new message.
+    DropOldest,
+}
+
+/// The limits of the messages held by each connection, either waiting to be sent or waiting for
+/// a reply. They bound the memory used for a peer that is down or does not keep up.
+#[derive(Debug, Clone, Copy)]
+pub struct BufferLimits {
+    /// The maximum number of messages held by a connection.
+    pub max_messages: usize,
+    /// The maximum total size (in bytes) of the messages held by a connection.
+    pub max_bytes: usize,
+    /// What to do with a new message once the limits are reached.
+    pub policy: OverflowPolicy,
+}
+
+impl std::default::Default for BufferLimits {
+    fn default() -> Self {
+        Self {
+            max_messages: 10_000,
+            max_bytes: 256 * 1024 * 1024,
+            policy: OverflowPolicy::DropNewest,
+        }
+    }
+}
+
+impl BufferLimits {
+    /// Check whether a connection is saturated, that is, it holds more than half of its limits.
+    pub fn is_saturated(&self, metrics: &ConnectionMetrics) -> bool {
+        metrics.messages > self.max_messages / 2 || metrics.bytes > self.max_bytes / 2
+    }
+}
+
+/// A snapshot of the metrics of a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionMetrics {
+    /// Whether the connection with the peer is currently established.
+    pub connected: bool,
+    /// The number of messages waiting to be sent or waiting for a reply.
+    pub messages: usize,
+    /// The total size (in bytes) of the messages waiting to be sent or waiting for a reply.
+    pub bytes: usize,
+    /// The number of messages dropped to enforce the limits of the connection.
+    pub dropped: u64,
+}
+
+/// The metrics of a connection, updated by the connection and read by the `ReliableSender`.
+#[derive(Default)]
+struct SharedMetrics {
+    connected: AtomicBool,
+    messages: AtomicUsize,
+    bytes: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+impl SharedMetrics {
+    fn snapshot(&self) -> ConnectionMetrics {
+        ConnectionMetrics {
+            connected: self.connected.load(Ordering::Relaxed),
+            messages: self.messages.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
 
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 /// This sender is 'reliable' in the sense that it keeps trying to re-transmit messages for which it didn't
-/// receive an ACK back (until they succeed or are canceled).
+/// receive an ACK back (until they succeed, are canceled, or are dropped to enforce the limits of
+/// the connection). Every connection authenticates both ends and only talks to the peer with the
+/// expected identity.
 pub struct ReliableSender {
-    /// A map holding the channels to our connections.
-    connections: HashMap<SocketAddr, Sender<InnerMessage>>,
+    /// The keypair authenticating this end of the connections.
+    keypair: Arc<KeyPair>,
+    /// The limits of the messages held by each connection.
+    limits: BufferLimits,
+    /// The transport carrying the connections.
+    transport: Arc<dyn Transport>,
+    /// A map holding the channels to our connections (and their metrics).
+    connections: HashMap<(PublicKey, SocketAddr), (Sender<InnerMessage>, Arc<SharedMetrics>)>,
 }
 
 impl std::default::Default for ReliableSender {
+    /// Make a sender with a fresh (anonymous) identity.
     fn default() -> Self {
-        Self::new()
+        let (_, keypair) = KeyPair::generate_production_keypair();
+        Self::new(keypair)
     }
 }
 
 impl ReliableSender {
-    pub fn new() -> Self {
+    pub fn new(keypair: KeyPair) -> Self {
+        Self::with_limits(keypair, BufferLimits::default())
+    }
+
+    /// Make a sender whose connections hold messages up to the specified limits.
+    pub fn with_limits(keypair: KeyPair, limits: BufferLimits) -> Self {
+        Self::with_transport(keypair, limits, Arc::new(TcpTransport))
+    }
+
+    /// Make a sender whose connections run over a specific transport.
+    pub fn with_transport(
+        keypair: KeyPair,
+        limits: BufferLimits,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
         Self {
+            keypair: Arc::new(keypair),
+            limits,
+            transport,
             connections: HashMap::new(),
         }
     }
 
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
+    fn spawn_connection(
+        &self,
+        name: PublicKey,
+        address: SocketAddr,
+    ) -> (Sender<InnerMessage>, Arc<SharedMetrics>) {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
-        tx
+        let metrics = Arc::new(SharedMetrics::default());
+        Connection::spawn(
+            name,
+            address,
+            self.keypair.clone(),
+            self.transport.clone(),
+            self.limits,
+            metrics.clone(),
+            rx,
+        );
+        (tx, metrics)
     }
 
-    /// Reliably send a message to a specific address.
-    pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
-        let (sender, receiver) = oneshot::channel();
+    /// Return the metrics of the connection with a peer (if we ever sent it a message).
+    pub fn metrics(&self, name: &PublicKey, address: &SocketAddr) -> Option<ConnectionMetrics> {
         self.connections
-            .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
+            .get(&(*name, *address))
+            .map(|(_, metrics)| metrics.snapshot())
+    }
+
+    /// Check whether the connection with a peer is saturated (the peer is down or falls behind).
+    pub fn is_saturated(&self, name: &PublicKey, address: &SocketAddr) -> bool {
+        matches!(self.metrics(name, address), Some(metrics) if self.limits.is_saturated(&metrics))
+    }
+
+    /// Reliably send a message to the peer `name` at a specific address.
+    pub async fn send(
+        &mut self,
+        name: PublicKey,
+        address: SocketAddr,
+        data: Bytes,
+    ) -> CancelHandler {
+        let (sender, receiver) = oneshot::channel();
+        if !self.connections.contains_key(&(name, address)) {
+            let connection = self.spawn_connection(name, address);
+            self.connections.insert((name, address), connection);
+        }
+        let (connection, _) = &self.connections[&(name, address)];
+        connection
             .send(InnerMessage {
                 data,
                 cancel_handler: sender,
             })
             .await
             .expect("Failed to send internal message");
-        receiver
+        CancelHandler { receiver, address }
     }
 
-    /// Broadcast the message to all specified addresses in a reliable manner. It returns a vector of
-    /// cancel handlers ordered as the input `addresses` vector.
+    /// Broadcast the message to all specified peers in a reliable manner. It returns a vector of
+    /// cancel handlers ordered as the input `peers` vector.
     pub async fn broadcast(
         &mut self,
-        addresses: Vec<SocketAddr>,
+        peers: Vec<(PublicKey, SocketAddr)>,
         data: Bytes,
     ) -> Vec<CancelHandler> {
         let mut handlers = Vec::new();
-        for address in addresses {
-            let handler = self.send(address, data.clone()).await;
+        for (name, address) in peers {
+            let handler = self.send(name, address, data.clone()).await;
             handlers.push(handler);
         }
         handlers
@@ -92,43 +254,176 @@ struct InnerMessage {
     data: Bytes,
     /// The cancel handler allowing the caller task to cancel the transmission of this message
     /// and to be notified of its successfully transmission.
-    cancel_handler: oneshot::Sender<Bytes>,
+    cancel_handler: Replier,
 }
 
 /// A connection is responsible to reliably establish (and keep alive) a connection with a single peer.
 struct Connection {
+    /// The identity of the peer.
+    name: PublicKey,
     /// The destination address.
     address: SocketAddr,
+    /// The keypair authenticating this end of the connection.
+    keypair: Arc<KeyPair>,
+    /// The transport carrying the connection.
+    transport: Arc<dyn Transport>,
+    /// The limits of the messages held by the connection.
+    limits: BufferLimits,
+    /// The metrics of the connection (shared with the `ReliableSender`).
+    metrics: Arc<SharedMetrics>,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<InnerMessage>,
     /// The initial delay to wait before re-attempting a connection (in ms).
     retry_delay: u64,
     /// Buffer keeping all messages that need to be re-transmitted.
-    buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
+    buffer: VecDeque<(Bytes, Replier)>,
+    /// The messages that we have successfully transmitted but for which we are still waiting to
+    /// receive an ACK (indexed by request id).
+    pending_replies: BTreeMap<RequestId, (Bytes, Replier)>,
+    /// The id of the next request sent over the connection.
+    next_id: RequestId,
+    /// The number of messages held by the connection (in the buffer or waiting for a reply).
+    held_messages: usize,
+    /// The total size (in bytes) of the messages held by the connection.
+    held_bytes: usize,
 }
 
 impl Connection {
     /// Spawn a new connection with the given address.
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    fn spawn(
+        name: PublicKey,
+        address: SocketAddr,
+        keypair: Arc<KeyPair>,
+        transport: Arc<dyn Transport>,
+        limits: BufferLimits,
+        metrics: Arc<SharedMetrics>,
+        receiver: Receiver<InnerMessage>,
+    ) {
         tokio::spawn(async move {
             Self {
+                name,
                 address,
+                keypair,
+                transport,
+                limits,
+                metrics,
                 receiver,
                 retry_delay: 200,
                 buffer: VecDeque::new(),
+                pending_replies: BTreeMap::new(),
+                next_id: 0,
+                held_messages: 0,
+                held_bytes: 0,
             }
             .run()
             .await;
         });
     }
 
+    /// Connect to the peer and authenticate it.
+    async fn connect(&self, retry: u16) -> Result<(FrameSink, FrameStream), NetworkError> {
+        self.transport
+            .connect(self.address, &self.keypair, &self.name)
+            .await
+            .map_err(|e| match e {
+                // Record how many times we tried to connect to the peer.
+                NetworkError::FailedToConnect(address, _, e) => {
+                    NetworkError::FailedToConnect(address, retry, e)
+                }
+                e => e,
+            })
+    }
+
+    /// Add a new message to the buffer, unless the connection cannot hold it. The handler of a
+    /// dropped message reports the overflow to the caller task.
+    fn enqueue(&mut self, data: Bytes, handler: Replier) {
+        // Forget the messages cancelled by the caller task. They are only looked up once the
+        // connection is full (so that enqueuing a message does not depend on the size of the
+        // buffer).
+        if self.overflows(&data) {
+            self.forget_cancelled();
+        }
+
+        // Make room for the new message by dropping the oldest messages (the ones waiting for a
+        // reply were sent before the ones waiting to be sent).
+        if self.limits.policy == OverflowPolicy::DropOldest {
+            while self.overflows(&data) {
+                let oldest = match self.pending_replies.keys().next().cloned() {
+                    Some(id) => self.pending_replies.remove(&id),
+                    None => self.buffer.pop_front(),
+                };
+                match oldest {
+                    Some((dropped, handler)) => {
+                        self.release(&dropped);
+                        self.drop_message(handler);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        if self.overflows(&data) {
+            self.drop_message(handler);
+        } else {
+            self.held_messages += 1;
+            self.held_bytes += data.len();
+            self.buffer.push_back((data, handler));
+        }
+        self.update_metrics();
+    }
+
+    /// Check whether the connection cannot hold a new message.
+    fn overflows(&self, data: &Bytes) -> bool {
+        self.held_messages >= self.limits.max_messages
+            || self.held_bytes + data.len() > self.limits.max_bytes
+    }
+
+    /// Forget the messages cancelled by the caller task.
+    fn forget_cancelled(&mut self) {
+        let (mut messages, mut bytes) = (0, 0);
+        let mut held = |data: &Bytes, handler: &Replier| {
+            let keep = !handler.is_closed();
+            if keep {
+                messages += 1;
+                bytes += data.len();
+            }
+            keep
+        };
+        self.buffer.retain(|(data, handler)| held(data, handler));
+        self.pending_replies
+            .retain(|_, (data, handler)| held(data, handler));
+        self.held_messages = messages;
+        self.held_bytes = bytes;
+    }
+
+    /// Record that the connection no longer holds a message.
+    fn release(&mut self, data: &Bytes) {
+        self.held_messages -= 1;
+        self.held_bytes -= data.len();
+    }
+
+    /// Drop a message to enforce the limits of the connection and report it to the caller task.
+    fn drop_message(&self, handler: Replier) {
+        warn!("{}", NetworkError::BufferOverflow(self.address));
+        self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+        let _ = handler.send(Err(NetworkError::BufferOverflow(self.address)));
+    }
+
+    /// Publish the number and size of the messages held by the connection.
+    fn update_metrics(&self) {
+        self.metrics
+            .messages
+            .store(self.held_messages, Ordering::Relaxed);
+        self.metrics.bytes.store(self.held_bytes, Ordering::Relaxed);
+    }
+
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
         let mut delay = self.retry_delay;
         let mut retry = 0;
         loop {
-            match TcpStream::connect(self.address).await {
-                Ok(stream) => {
+            match self.connect(retry).await {
+                Ok(transport) => {
                     info!("Outgoing connection established with {}", self.address);
 
                     // Reset the delay.
@@ -137,11 +432,13 @@ impl Connection {
 
                     // Try to transmit all messages in the buffer and keep transmitting incoming messages.
                     // The following function only returns if there is an error.
-                    let error = self.keep_alive(stream).await;
+                    self.metrics.connected.store(true, Ordering::Relaxed);
+                    let error = self.keep_alive(transport).await;
+                    self.metrics.connected.store(false, Ordering::Relaxed);
                     warn!("{}", error);
                 }
                 Err(e) => {
-                    warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
+                    warn!("{}", e);
                     let timer = sleep(Duration::from_millis(delay));
                     tokio::pin!(timer);
 
@@ -155,10 +452,10 @@ impl Connection {
                             },
 
                             // Drain the channel into the buffer to not saturate the channel and block the caller task.
-                            // The caller is responsible to cleanup the buffer through the cancel handlers.
+                            // The caller is responsible to cleanup the buffer through the cancel handlers
+                            // (otherwise, the limits of the connection bound the buffer).
                             Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                                self.buffer.push_back((data, cancel_handler));
-                                self.buffer.retain(|(_, handler)| !handler.is_closed());
+                                self.enqueue(data, cancel_handler);
                             }
                         }
                     }
@@ -168,26 +465,29 @@ impl Connection {
     }
 
     /// Transmit messages once we have established a connection.
-    async fn keep_alive(&mut self, stream: TcpStream) -> NetworkError {
-        // This buffer keeps all messages and handlers that we have successfully transmitted but for
-        // which we are still waiting to receive an ACK.
-        let mut pending_replies = VecDeque::new();
-
-        let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+    async fn keep_alive(
+        &mut self,
+        (mut writer, mut reader): (FrameSink, FrameStream),
+    ) -> NetworkError {
         let error = 'connection: loop {
             // Try to send all messages of the buffer.
             while let Some((data, handler)) = self.buffer.pop_front() {
                 // Skip messages that have been cancelled.
                 if handler.is_closed() {
+                    self.release(&data);
+                    self.update_metrics();
                     continue;
                 }
 
                 // Try to send the message.
-                match writer.send(data.clone()).await {
+                let id = self.next_id;
+                let envelope = Envelope::new(id, data.clone());
+                match writer.send(envelope.encode()).await {
                     Ok(()) => {
                         // The message has been sent, we remove it from the buffer and add it to
                         // `pending_replies` while we wait for an ACK.
-                        pending_replies.push_back((data, handler));
+                        self.next_id += 1;
+                        self.pending_replies.insert(id, (data, handler));
                     }
                     Err(e) => {
                         // We failed to send the message, we put it back into the buffer.
@@ -200,33 +500,39 @@ impl Connection {
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
                 Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                    // Add the message to the buffer of messages to send.
-                    self.buffer.push_back((data, cancel_handler));
+                    // Add the message to the buffer of messages to send (within our limits).
+                    self.enqueue(data, cancel_handler);
                 },
                 response = reader.next() => {
-                    let (data, handler) = match pending_replies.pop_front() {
-                        Some(message) => message,
-                        None => break 'connection NetworkError::UnexpectedAck(self.address)
+                    let envelope = match response {
+                        Some(Ok(frame)) => match Envelope::decode(frame) {
+                            Some(envelope) => envelope,
+                            None => {
+                                warn!("{}", NetworkError::MalformedEnvelope(self.address));
+                                continue 'connection;
+                            }
+                        },
+                        // Something has gone wrong (either the channel dropped or we failed to read from it).
+                        _ => break 'connection NetworkError::FailedToReceiveAck(self.address)
                     };
-                    match response {
-                        Some(Ok(bytes)) => {
-                            // Notify the handler that the message has been successfully sent.
-                            let _ = handler.send(bytes.freeze());
+                    match self.pending_replies.remove(&envelope.id) {
+                        // Notify the handler that the message has been successfully sent.
+                        Some((data, handler)) => {
+                            let _ = handler.send(Ok(envelope.payload));
+                            self.release(&data);
+                            self.update_metrics();
                         },
-                        _ => {
-                            // Something has gone wrong (either the channel dropped or we failed to read from it).
-                            // Put the message back in the buffer, we will try to send it again.
-                            pending_replies.push_front((data, handler));
-                            break 'connection NetworkError::FailedToReceiveAck(self.address);
-                        }
+                        None => warn!("{}", NetworkError::UnexpectedAck(self.address, envelope.id))
                     }
                 },
             }
         };
 
         // If we reach this code, it means something went wrong. Put the messages for which we didn't receive an ACK
-        // back into the sending buffer, we will try to send them again once we manage to establish a new connection.
-        while let Some(message) = pending_replies.pop_back() {
+        // back into the sending buffer (in their original order), we will try to send them again once we manage to
+        // establish a new connection.
+        let pending_replies = std::mem::take(&mut self.pending_replies);
+        for (_, message) in pending_replies.into_iter().rev() {
             self.buffer.push_front(message);
         }
         error
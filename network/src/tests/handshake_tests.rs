@@ -0,0 +1,78 @@
+use super::*;
+use rand::{rngs::StdRng, SeedableRng as _};
+use tokio::net::TcpListener;
+
+// Test cryptographic keys.
+fn keys() -> Vec<(PublicKey, KeyPair)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..2)
+        .map(|_| KeyPair::generate_keypair(&mut rng))
+        .collect()
+}
+
+#[tokio::test]
+async fn authenticate_both_ends() {
+    let (client_name, client_keypair) = keys().swap_remove(0);
+    let (server_name, server_keypair) = keys().swap_remove(1);
+
+    // Run a TCP server echoing the first message.
+    let address = "127.0.0.1:4100".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(&address).await.unwrap();
+    let handle = tokio::spawn(async move {
+        let (socket, peer) = listener.accept().await.unwrap();
+        let (mut transport, name) = accept(socket, peer, &server_keypair).await.unwrap();
+        let message = transport.next().await.unwrap().unwrap();
+        transport.send(message.freeze()).await.unwrap();
+        name
+    });
+
+    // Connect to the server and send a message.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = connect(stream, address, &client_keypair, &server_name)
+        .await
+        .unwrap();
+    let message = Bytes::from("Hello, world!");
+    transport.send(message.clone()).await.unwrap();
+
+    // Ensure the message goes through the encrypted channel.
+    let received = transport.next().await.unwrap().unwrap();
+    assert_eq!(received, message);
+
+    // Ensure the server authenticated the client.
+    assert_eq!(handle.await.unwrap(), client_name);
+}
+
+#[tokio::test]
+async fn reject_unexpected_identity() {
+    let (_, client_keypair) = keys().swap_remove(0);
+    let (server_name, _) = keys().swap_remove(1);
+
+    // Run a TCP server impersonating another peer.
+    let address = "127.0.0.1:4200".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(&address).await.unwrap();
+    tokio::spawn(async move {
+        let (_, impostor_keypair) = keys().swap_remove(0);
+        let (socket, peer) = listener.accept().await.unwrap();
+        let _ = accept(socket, peer, &impostor_keypair).await;
+    });
+
+    // Ensure the client detects the impostor.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let result = connect(stream, address, &client_keypair, &server_name).await;
+    assert!(matches!(
+        result,
+        Err(NetworkError::UnexpectedIdentity(_, expected, _)) if expected == server_name
+    ));
+}
+
+#[test]
+fn bind_initiator_to_responder() {
+    let (initiator, _) = keys().swap_remove(0);
+    let (responder, _) = keys().swap_remove(1);
+
+    // Ensure the signature of the initiator is only valid for the responder it talks to.
+    let transcript = Transcript::new(&[0; 32], &[1; 32]);
+    let challenge = transcript.challenge(b"initiator", &[&initiator, &responder]);
+    let other = transcript.challenge(b"initiator", &[&initiator, &initiator]);
+    assert_ne!(challenge, other);
+}
@@ -0,0 +1,115 @@
+use super::*;
+use crate::{
+    receiver::{MessageHandler, Receiver, Writer},
+    reliable_sender::{BufferLimits, ReliableSender},
+};
+use futures::future::try_join_all;
+use std::error::Error;
+use tokio::time::timeout;
+
+// Test cryptographic keys.
+fn keys() -> Vec<(PublicKey, KeyPair)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..2)
+        .map(|_| KeyPair::generate_keypair(&mut rng))
+        .collect()
+}
+
+#[derive(Clone)]
+struct EchoHandler;
+
+#[async_trait]
+impl MessageHandler for EchoHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        _peer: &PublicKey,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(message).await;
+        Ok(())
+    }
+}
+
+// Spawn an echo server on the simulated network and return a sender connected to it.
+fn spawn_echo_server(network: &MemoryNetwork, address: SocketAddr) -> (PublicKey, ReliableSender) {
+    let (name, keypair) = keys().pop().unwrap();
+    let transport = Arc::new(network.clone());
+    Receiver::spawn_with_transport(address, keypair, EchoHandler, transport.clone());
+
+    let (_, client_keypair) = keys().swap_remove(0);
+    let sender = ReliableSender::with_transport(client_keypair, BufferLimits::default(), transport);
+    (name, sender)
+}
+
+#[tokio::test]
+async fn send() {
+    let network = MemoryNetwork::default();
+    network.set_latency(Duration::from_millis(10));
+    let address = "127.0.0.1:1000".parse::<SocketAddr>().unwrap();
+    let (name, mut sender) = spawn_echo_server(&network, address);
+    tokio::task::yield_now().await;
+
+    // Ensure we get back the echo of the message.
+    let message = Bytes::from("Hello, world!");
+    let reply = sender.send(name, address, message.clone()).await.await;
+    assert_eq!(reply.unwrap(), message);
+}
+
+#[tokio::test]
+async fn partition() {
+    let network = MemoryNetwork::default();
+    let address = "127.0.0.1:1000".parse::<SocketAddr>().unwrap();
+    let (name, mut sender) = spawn_echo_server(&network, address);
+    tokio::task::yield_now().await;
+
+    // Ensure the message does not cross the partition.
+    network.partition(&[name]);
+    let message = Bytes::from("Hello, world!");
+    let mut handle = sender.send(name, address, message.clone()).await;
+    let result = timeout(Duration::from_millis(100), &mut handle).await;
+    assert!(result.is_err());
+
+    // Ensure the message is delivered once the network heals.
+    network.heal();
+    assert_eq!(handle.await.unwrap(), message);
+}
+
+#[tokio::test]
+async fn lossy_network() {
+    let network = MemoryNetwork::new(1);
+    network.set_drop_rate(0.2);
+    let address = "127.0.0.1:1000".parse::<SocketAddr>().unwrap();
+    let (name, mut sender) = spawn_echo_server(&network, address);
+    tokio::task::yield_now().await;
+
+    // Ensure all messages eventually get a reply (they are re-transmitted over new connections).
+    let mut handles = Vec::new();
+    for i in 0..10 {
+        let message = Bytes::from(format!("Message {}", i));
+        handles.push(sender.send(name, address, message).await);
+    }
+    let replies = timeout(Duration::from_secs(10), try_join_all(handles))
+        .await
+        .unwrap()
+        .unwrap();
+    for (i, reply) in replies.into_iter().enumerate() {
+        assert_eq!(reply, Bytes::from(format!("Message {}", i)));
+    }
+}
+
+#[tokio::test]
+async fn reject_unexpected_identity() {
+    let network = MemoryNetwork::default();
+    let address = "127.0.0.1:1000".parse::<SocketAddr>().unwrap();
+    let (_, keypair) = keys().pop().unwrap();
+    let _listener = network.listen(address, Arc::new(keypair)).await.unwrap();
+
+    // Ensure the client detects the impostor.
+    let (name, client_keypair) = keys().swap_remove(0);
+    let result = network.connect(address, &client_keypair, &name).await;
+    assert!(matches!(
+        result,
+        Err(NetworkError::UnexpectedIdentity(_, expected, _)) if expected == name
+    ));
+}
@@ -1,15 +1,24 @@
 use super::*;
-use futures::sink::SinkExt as _;
-use tokio::sync::mpsc::{channel, Sender};
+use crate::handshake;
+use rand::{rngs::StdRng, SeedableRng as _};
+use tokio::{
+    net::TcpStream,
+    sync::mpsc::{channel, Sender},
+};
 
 #[derive(Clone)]
 struct TestHandler {
-    deliver: Sender<String>,
+    deliver: Sender<(PublicKey, String)>,
 }
 
 #[async_trait]
 impl MessageHandler for TestHandler {
-    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        peer: &PublicKey,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
         // Reply with an ACK.
         let _ = writer.send(Bytes::from("Ack")).await;
 
@@ -17,29 +26,280 @@ impl MessageHandler for TestHandler {
         let message = bincode::deserialize(&message).unwrap();
 
         // Deliver the message to the application.
-        self.deliver.send(message).await.unwrap();
+        self.deliver.send((*peer, message)).await.unwrap();
         Ok(())
     }
 }
 
 #[tokio::test]
 async fn receive() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, keypair) = KeyPair::generate_keypair(&mut rng);
+    let (client_name, client_keypair) = KeyPair::generate_keypair(&mut rng);
+
     // Make the network receiver.
     let address = "127.0.0.1:4000".parse::<SocketAddr>().unwrap();
     let (tx, mut rx) = channel(1);
-    Receiver::spawn(address, TestHandler { deliver: tx });
+    Receiver::spawn(address, keypair, TestHandler { deliver: tx });
     tokio::task::yield_now().await;
 
     // Send a message.
     let sent = "Hello, world!";
     let bytes = Bytes::from(bincode::serialize(sent).unwrap());
     let stream = TcpStream::connect(address).await.unwrap();
-    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
-    transport.send(bytes.clone()).await.unwrap();
+    let mut transport = handshake::connect(stream, address, &client_keypair, &name)
+        .await
+        .unwrap();
+    let envelope = Envelope::new(0, bytes.clone());
+    transport.send(envelope.encode()).await.unwrap();
 
-    // Ensure the message gets passed to the channel.
+    // Ensure the message gets passed to the channel (along with the identity of the sender).
     let message = rx.recv().await;
     assert!(message.is_some());
-    let received = message.unwrap();
+    let (peer, received) = message.unwrap();
+    assert_eq!(peer, client_name);
     assert_eq!(received, sent);
 }
+
+#[derive(Clone)]
+struct SlowHandler {
+    ordering: Ordering,
+}
+
+#[async_trait]
+impl MessageHandler for SlowHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        _peer: &PublicKey,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        // Take a while to handle the first message, then echo it back.
+        if message == "slow" {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        let _ = writer.send(message).await;
+        Ok(())
+    }
+
+    fn ordering(&self, _peer: &PublicKey, _message: &Bytes) -> Ordering {
+        self.ordering
+    }
+}
+
+// Send a slow message followed by a fast one and return the ids of the replies (in the order
+// they are received).
+async fn slow_then_fast(address: SocketAddr, ordering: Ordering) -> Vec<RequestId> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, keypair) = KeyPair::generate_keypair(&mut rng);
+    let (_, client_keypair) = KeyPair::generate_keypair(&mut rng);
+
+    // Make the network receiver.
+    Receiver::spawn(address, keypair, SlowHandler { ordering });
+    tokio::task::yield_now().await;
+
+    // Send the two messages on the same connection.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = handshake::connect(stream, address, &client_keypair, &name)
+        .await
+        .unwrap();
+    for (id, message) in [(0, "slow"), (1, "fast")] {
+        let envelope = Envelope::new(id, Bytes::from(message));
+        transport.send(envelope.encode()).await.unwrap();
+    }
+
+    // Collect the replies.
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let frame = transport.next().await.unwrap().unwrap();
+        ids.push(Envelope::decode(frame).unwrap().id);
+    }
+    ids
+}
+
+#[tokio::test]
+async fn sequential_dispatch() {
+    let address = "127.0.0.1:4300".parse::<SocketAddr>().unwrap();
+    let ids = slow_then_fast(address, Ordering::Sequential).await;
+
+    // Ensure the fast message waits for the slow one.
+    assert_eq!(ids, vec![0, 1]);
+}
+
+#[tokio::test]
+async fn concurrent_dispatch() {
+    let address = "127.0.0.1:4400".parse::<SocketAddr>().unwrap();
+    let ids = slow_then_fast(address, Ordering::Concurrent).await;
+
+    // Ensure the fast message is not blocked behind the slow one.
+    assert_eq!(ids, vec![1, 0]);
+}
+
+#[derive(Clone)]
+struct RestrictedHandler {
+    allowed: PublicKey,
+}
+
+#[async_trait]
+impl MessageHandler for RestrictedHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        _peer: &PublicKey,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(message).await;
+        Ok(())
+    }
+
+    fn authorize(&self, peer: &PublicKey) -> bool {
+        peer == &self.allowed
+    }
+}
+
+#[tokio::test]
+async fn reject_unauthorized_peer() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, keypair) = KeyPair::generate_keypair(&mut rng);
+    let (allowed, _) = KeyPair::generate_keypair(&mut rng);
+    let (_, client_keypair) = KeyPair::generate_keypair(&mut rng);
+
+    // Make a network receiver only accepting connections from another peer.
+    let address = "127.0.0.1:4500".parse::<SocketAddr>().unwrap();
+    Receiver::spawn(address, keypair, RestrictedHandler { allowed });
+    tokio::task::yield_now().await;
+
+    // Complete the handshake and send a message.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = handshake::connect(stream, address, &client_keypair, &name)
+        .await
+        .unwrap();
+    let envelope = Envelope::new(0, Bytes::from("Hello, world!"));
+    let _ = transport.send(envelope.encode()).await;
+
+    // Ensure the receiver closes the connection without replying.
+    assert!(!matches!(transport.next().await, Some(Ok(_))));
+}
+
+#[derive(Clone)]
+struct BlockingHandler;
+
+#[async_trait]
+impl MessageHandler for BlockingHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        _peer: &PublicKey,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        // Never complete the blocking messages, echo back the others.
+        if message == "block" {
+            futures::future::pending::<()>().await;
+        }
+        let _ = writer.send(message).await;
+        Ok(())
+    }
+
+    fn ordering(&self, _peer: &PublicKey, _message: &Bytes) -> Ordering {
+        Ordering::Concurrent
+    }
+}
+
+#[tokio::test]
+async fn bound_concurrent_tasks() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, keypair) = KeyPair::generate_keypair(&mut rng);
+    let (_, client_keypair) = KeyPair::generate_keypair(&mut rng);
+
+    // Make the network receiver.
+    let address = "127.0.0.1:4600".parse::<SocketAddr>().unwrap();
+    Receiver::spawn(address, keypair, BlockingHandler);
+    tokio::task::yield_now().await;
+
+    // Exhaust the tasks of the peer with messages that never complete.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = handshake::connect(stream, address, &client_keypair, &name)
+        .await
+        .unwrap();
+    for id in 0..MAX_CONCURRENT_TASKS as RequestId {
+        let envelope = Envelope::new(id, Bytes::from("block"));
+        transport.send(envelope.encode()).await.unwrap();
+    }
+
+    // Ensure the peer cannot get more tasks, even on another connection.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut other = handshake::connect(stream, address, &client_keypair, &name)
+        .await
+        .unwrap();
+    let envelope = Envelope::new(0, Bytes::from("fast"));
+    other.send(envelope.encode()).await.unwrap();
+    let reply = timeout(Duration::from_millis(500), other.next()).await;
+    assert!(reply.is_err());
+
+    // Ensure another peer is not affected.
+    let (_, keypair) = KeyPair::generate_keypair(&mut rng);
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = handshake::connect(stream, address, &keypair, &name)
+        .await
+        .unwrap();
+    transport.send(envelope.encode()).await.unwrap();
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(Envelope::decode(frame).unwrap().payload, "fast");
+}
+
+#[derive(Clone)]
+struct DetachedHandler;
+
+#[async_trait]
+impl MessageHandler for DetachedHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        _peer: &PublicKey,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        // Hand the blocking messages over to a task that never replies, echo back the others.
+        if message == "block" {
+            let writer = writer.clone();
+            tokio::spawn(async move {
+                let _writer = writer;
+                futures::future::pending::<()>().await;
+            });
+            return Ok(());
+        }
+        let _ = writer.send(message).await;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn bound_detached_replies() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, keypair) = KeyPair::generate_keypair(&mut rng);
+    let (_, client_keypair) = KeyPair::generate_keypair(&mut rng);
+
+    // Make the network receiver.
+    let address = "127.0.0.1:4700".parse::<SocketAddr>().unwrap();
+    Receiver::spawn(address, keypair, DetachedHandler);
+    tokio::task::yield_now().await;
+
+    // Exhaust the tasks of the peer with sequential messages whose replies never complete.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = handshake::connect(stream, address, &client_keypair, &name)
+        .await
+        .unwrap();
+    for id in 0..MAX_CONCURRENT_TASKS as RequestId {
+        let envelope = Envelope::new(id, Bytes::from("block"));
+        transport.send(envelope.encode()).await.unwrap();
+    }
+
+    // Ensure the peer cannot get more messages handled, even on another connection.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut other = handshake::connect(stream, address, &client_keypair, &name)
+        .await
+        .unwrap();
+    let envelope = Envelope::new(0, Bytes::from("fast"));
+    other.send(envelope.encode()).await.unwrap();
+    let reply = timeout(Duration::from_millis(500), other.next()).await;
+    assert!(reply.is_err());
+}
@@ -1,17 +1,29 @@
 use super::*;
+use crate::handshake;
 use futures::future::try_join_all;
+use rand::{rngs::StdRng, SeedableRng as _};
 use tokio::{net::TcpListener, task::JoinHandle};
 
-pub fn listener(address: SocketAddr, expected: String) -> JoinHandle<()> {
+// Test cryptographic keys.
+pub fn keys() -> Vec<(PublicKey, KeyPair)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..4)
+        .map(|_| KeyPair::generate_keypair(&mut rng))
+        .collect()
+}
+
+pub fn listener(address: SocketAddr, keypair: KeyPair, expected: String) -> JoinHandle<()> {
     tokio::spawn(async move {
         let listener = TcpListener::bind(&address).await.unwrap();
-        let (socket, _) = listener.accept().await.unwrap();
-        let transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let (socket, peer) = listener.accept().await.unwrap();
+        let (transport, _) = handshake::accept(socket, peer, &keypair).await.unwrap();
         let (mut writer, mut reader) = transport.split();
         match reader.next().await {
             Some(Ok(received)) => {
-                assert_eq!(received, expected);
-                writer.send(Bytes::from("Ack")).await.unwrap()
+                let envelope = Envelope::decode(received).unwrap();
+                assert_eq!(envelope.payload, expected);
+                let reply = envelope.reply(Bytes::from("Ack"));
+                writer.send(reply.encode()).await.unwrap()
             }
             _ => panic!("Failed to receive network message"),
         }
@@ -21,13 +33,14 @@ pub fn listener(address: SocketAddr, expected: String) -> JoinHandle<()> {
 #[tokio::test]
 async fn send() {
     // Run a TCP server.
+    let (name, keypair) = keys().pop().unwrap();
     let address = "127.0.0.1:5000".parse::<SocketAddr>().unwrap();
     let message = "Hello, world!";
-    let handle = listener(address, message.to_string());
+    let handle = listener(address, keypair, message.to_string());
 
     // Make the network sender and send the message.
-    let mut sender = ReliableSender::new();
-    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+    let mut sender = ReliableSender::default();
+    let cancel_handler = sender.send(name, address, Bytes::from(message)).await;
 
     // Ensure we get back an acknowledgement.
     assert!(cancel_handler.await.is_ok());
@@ -40,20 +53,26 @@ async fn send() {
 async fn broadcast() {
     // Run 3 TCP servers.
     let message = "Hello, world!";
-    let (handles, addresses): (Vec<_>, Vec<_>) = (0..3)
-        .map(|x| {
+    let (handles, peers): (Vec<_>, Vec<_>) = keys()
+        .into_iter()
+        .take(3)
+        .enumerate()
+        .map(|(x, (name, keypair))| {
             let address = format!("127.0.0.1:{}", 5_200 + x)
                 .parse::<SocketAddr>()
                 .unwrap();
-            (listener(address, message.to_string()), address)
+            (
+                listener(address, keypair, message.to_string()),
+                (name, address),
+            )
         })
         .collect::<Vec<_>>()
         .into_iter()
         .unzip();
 
     // Make the network sender and send the message.
-    let mut sender = ReliableSender::new();
-    let cancel_handlers = sender.broadcast(addresses, Bytes::from(message)).await;
+    let mut sender = ReliableSender::default();
+    let cancel_handlers = sender.broadcast(peers, Bytes::from(message)).await;
 
     // Ensure we get back an acknowledgement for each message.
     assert!(try_join_all(cancel_handlers).await.is_ok());
@@ -65,14 +84,15 @@ async fn broadcast() {
 #[tokio::test]
 async fn retry() {
     // Make the network sender and send the message  (no listeners are running).
+    let (name, keypair) = keys().pop().unwrap();
     let address = "127.0.0.1:5300".parse::<SocketAddr>().unwrap();
     let message = "Hello, world!";
-    let mut sender = ReliableSender::new();
-    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+    let mut sender = ReliableSender::default();
+    let cancel_handler = sender.send(name, address, Bytes::from(message)).await;
 
     // Run a TCP server.
     sleep(Duration::from_millis(50)).await;
-    let handle = listener(address, message.to_string());
+    let handle = listener(address, keypair, message.to_string());
 
     // Ensure we get back an acknowledgement.
     assert!(cancel_handler.await.is_ok());
@@ -80,3 +100,178 @@ async fn retry() {
     // Ensure the server received the message (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn reject_unexpected_identity() {
+    // Run a TCP server impersonating another peer.
+    let (name, _) = keys().pop().unwrap();
+    let (_, keypair) = keys().swap_remove(0);
+    let address = "127.0.0.1:5400".parse::<SocketAddr>().unwrap();
+    let message = "Hello, world!";
+    let handle = listener(address, keypair, message.to_string());
+
+    // Make the network sender and send the message.
+    let mut sender = ReliableSender::default();
+    let cancel_handler = sender.send(name, address, Bytes::from(message)).await;
+
+    // Ensure the message is never delivered to the impostor.
+    let result = tokio::time::timeout(Duration::from_millis(500), cancel_handler).await;
+    assert!(result.is_err());
+    assert!(handle.await.is_err());
+}
+
+#[tokio::test]
+async fn out_of_order_replies() {
+    // Run a TCP server replying to two requests in reverse order, and ignoring the third one.
+    let (name, keypair) = keys().pop().unwrap();
+    let address = "127.0.0.1:5500".parse::<SocketAddr>().unwrap();
+    let handle = tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, peer) = listener.accept().await.unwrap();
+        let (mut transport, _) = handshake::accept(socket, peer, &keypair).await.unwrap();
+        let mut requests = Vec::new();
+        for _ in 0..3 {
+            let frame = transport.next().await.unwrap().unwrap();
+            requests.push(Envelope::decode(frame).unwrap());
+        }
+        for request in requests.iter().take(2).rev() {
+            let reply = request.reply(request.payload.clone());
+            transport.send(reply.encode()).await.unwrap();
+        }
+
+        // Send a reply to no request.
+        let reply = Envelope::new(1_000, Bytes::from("Unexpected"));
+        transport.send(reply.encode()).await.unwrap();
+        transport
+    });
+
+    // Make the network sender and send the messages.
+    let mut sender = ReliableSender::default();
+    let mut cancel_handlers = Vec::new();
+    for message in ["First", "Second", "Third"] {
+        cancel_handlers.push(sender.send(name, address, Bytes::from(message)).await);
+    }
+    let ignored = cancel_handlers.pop().unwrap();
+
+    // Ensure each reply is matched with its request.
+    let replies = try_join_all(cancel_handlers).await.unwrap();
+    assert_eq!(replies, vec![Bytes::from("First"), Bytes::from("Second")]);
+
+    // Ensure the connection survives the unexpected reply.
+    let _transport = handle.await.unwrap();
+    let result = tokio::time::timeout(Duration::from_millis(100), ignored).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn drop_newest() {
+    // Make a network sender holding at most two messages per connection and send three messages
+    // (no listeners are running).
+    let (name, _) = keys().pop().unwrap();
+    let address = "127.0.0.1:5600".parse::<SocketAddr>().unwrap();
+    let limits = BufferLimits {
+        max_messages: 2,
+        policy: OverflowPolicy::DropNewest,
+        ..BufferLimits::default()
+    };
+    let mut sender = ReliableSender::with_limits(keys().swap_remove(0).1, limits);
+    let mut cancel_handlers = Vec::new();
+    for message in ["First", "Second", "Third"] {
+        cancel_handlers.push(sender.send(name, address, Bytes::from(message)).await);
+    }
+
+    // Ensure the last message is dropped.
+    let dropped = cancel_handlers.pop().unwrap();
+    let result = tokio::time::timeout(Duration::from_millis(500), dropped).await;
+    assert!(matches!(result, Ok(Err(NetworkError::BufferOverflow(_)))));
+
+    // Ensure the metrics report the saturated connection.
+    let metrics = sender.metrics(&name, &address).unwrap();
+    assert!(!metrics.connected);
+    assert_eq!(metrics.messages, 2);
+    assert_eq!(metrics.dropped, 1);
+    assert!(sender.is_saturated(&name, &address));
+}
+
+#[tokio::test]
+async fn drop_oldest() {
+    // Make a network sender holding at most two messages per connection and send three messages
+    // (no listeners are running).
+    let (name, keypair) = keys().pop().unwrap();
+    let address = "127.0.0.1:5700".parse::<SocketAddr>().unwrap();
+    let limits = BufferLimits {
+        max_messages: 2,
+        policy: OverflowPolicy::DropOldest,
+        ..BufferLimits::default()
+    };
+    let mut sender = ReliableSender::with_limits(keys().swap_remove(0).1, limits);
+    let mut cancel_handlers = Vec::new();
+    for message in ["First", "Second", "Third"] {
+        cancel_handlers.push(sender.send(name, address, Bytes::from(message)).await);
+    }
+
+    // Ensure the first message is dropped.
+    let dropped = cancel_handlers.remove(0);
+    let result = tokio::time::timeout(Duration::from_millis(500), dropped).await;
+    assert!(matches!(result, Ok(Err(NetworkError::BufferOverflow(_)))));
+
+    // Run a TCP server and ensure the remaining messages are delivered.
+    let handle = tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, peer) = listener.accept().await.unwrap();
+        let (mut transport, _) = handshake::accept(socket, peer, &keypair).await.unwrap();
+        for _ in 0..2 {
+            let frame = transport.next().await.unwrap().unwrap();
+            let request = Envelope::decode(frame).unwrap();
+            let reply = request.reply(request.payload.clone());
+            transport.send(reply.encode()).await.unwrap();
+        }
+    });
+    let replies = try_join_all(cancel_handlers).await.unwrap();
+    assert_eq!(replies, vec![Bytes::from("Second"), Bytes::from("Third")]);
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn forget_cancelled() {
+    // Make a network sender holding at most two messages per connection (no listeners are
+    // running).
+    let (name, keypair) = keys().pop().unwrap();
+    let address = "127.0.0.1:5800".parse::<SocketAddr>().unwrap();
+    let limits = BufferLimits {
+        max_messages: 2,
+        policy: OverflowPolicy::DropNewest,
+        ..BufferLimits::default()
+    };
+    let mut sender = ReliableSender::with_limits(keys().swap_remove(0).1, limits);
+    let cancelled = sender.send(name, address, Bytes::from("First")).await;
+    let delivered = sender.send(name, address, Bytes::from("Second")).await;
+
+    // Ensure a cancelled message makes room for a new one.
+    drop(cancelled);
+    let message = "Third";
+    let handler = sender.send(name, address, Bytes::from(message)).await;
+    sleep(Duration::from_millis(50)).await;
+    let metrics = sender.metrics(&name, &address).unwrap();
+    assert_eq!(metrics.messages, 2);
+    assert_eq!(metrics.bytes, "Second".len() + message.len());
+    assert_eq!(metrics.dropped, 0);
+
+    // Run a TCP server and ensure the connection no longer holds the delivered messages.
+    let handle = tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, peer) = listener.accept().await.unwrap();
+        let (mut transport, _) = handshake::accept(socket, peer, &keypair).await.unwrap();
+        for _ in 0..2 {
+            let frame = transport.next().await.unwrap().unwrap();
+            let request = Envelope::decode(frame).unwrap();
+            let reply = request.reply(request.payload.clone());
+            transport.send(reply.encode()).await.unwrap();
+        }
+        transport
+    });
+    assert!(try_join_all(vec![delivered, handler]).await.is_ok());
+    let metrics = sender.metrics(&name, &address).unwrap();
+    assert_eq!((metrics.messages, metrics.bytes), (0, 0));
+    let _transport = handle.await.unwrap();
+}
@@ -0,0 +1,104 @@
+use crate::{error::NetworkError, handshake};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use crypto::{KeyPair, PublicKey};
+use futures::{
+    future::BoxFuture,
+    sink::Sink,
+    stream::{Stream, StreamExt},
+};
+use std::{io, net::SocketAddr, pin::Pin, sync::Arc};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The writer end of an authenticated channel with a peer.
+pub type FrameSink = Pin<Box<dyn Sink<Bytes, Error = io::Error> + Send>>;
+
+/// The reader end of an authenticated channel with a peer.
+pub type FrameStream = Pin<Box<dyn Stream<Item = Result<BytesMut, io::Error>> + Send>>;
+
+/// An incoming connection. It completes once the peer is authenticated and outputs both ends of
+/// the channel with the peer along with its identity.
+pub type Incoming = BoxFuture<'static, Result<(FrameSink, FrameStream, PublicKey), NetworkError>>;
+
+/// Accepts the connections of the peers.
+#[async_trait]
+pub trait Listener: Send {
+    /// Wait for the next incoming connection. Returns the address of the peer and the (pending)
+    /// authentication of the connection.
+    async fn accept(&mut self) -> Result<(SocketAddr, Incoming), io::Error>;
+}
+
+/// Opens channels between nodes, authenticated with their identities. The network `Receiver` and
+/// `ReliableSender` run over any transport (TCP by default).
+#[async_trait]
+pub trait Transport: Send + Sync + 'static {
+    /// Open a channel with the peer `expected`, listening on `address`.
+    async fn connect(
+        &self,
+        address: SocketAddr,
+        keypair: &KeyPair,
+        expected: &PublicKey,
+    ) -> Result<(FrameSink, FrameStream), NetworkError>;
+
+    /// Listen to the connections of the peers on `address`.
+    async fn listen(
+        &self,
+        address: SocketAddr,
+        keypair: Arc<KeyPair>,
+    ) -> Result<Box<dyn Listener>, io::Error>;
+}
+
+/// The transport over TCP. Every connection runs the handshake to authenticate both ends and to
+/// encrypt the channel.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn connect(
+        &self,
+        address: SocketAddr,
+        keypair: &KeyPair,
+        expected: &PublicKey,
+    ) -> Result<(FrameSink, FrameStream), NetworkError> {
+        let stream = TcpStream::connect(address)
+            .await
+            .map_err(|e| NetworkError::FailedToConnect(address, 0, e))?;
+        let transport = handshake::connect(stream, address, keypair, expected).await?;
+        let (sink, stream) = transport.split();
+        Ok((Box::pin(sink), Box::pin(stream)))
+    }
+
+    async fn listen(
+        &self,
+        address: SocketAddr,
+        keypair: Arc<KeyPair>,
+    ) -> Result<Box<dyn Listener>, io::Error> {
+        let listener = TcpListener::bind(&address).await?;
+        Ok(Box::new(TcpAcceptor { listener, keypair }))
+    }
+}
+
+/// Accepts TCP connections and runs the handshake with each peer.
+struct TcpAcceptor {
+    /// The TCP listener.
+    listener: TcpListener,
+    /// The keypair authenticating this end of the connections.
+    keypair: Arc<KeyPair>,
+}
+
+#[async_trait]
+impl Listener for TcpAcceptor {
+    async fn accept(&mut self) -> Result<(SocketAddr, Incoming), io::Error> {
+        let (socket, peer) = self.listener.accept().await?;
+        let keypair = self.keypair.clone();
+        let incoming: Incoming = Box::pin(async move {
+            let (transport, name) = handshake::accept(socket, peer, &keypair).await?;
+            let (sink, stream) = transport.split();
+            let sink: FrameSink = Box::pin(sink);
+            let stream: FrameStream = Box::pin(stream);
+            Ok((sink, stream, name))
+        });
+        Ok((peer, incoming))
+    }
+}
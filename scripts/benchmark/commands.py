@@ -27,32 +27,33 @@ class CommandMaker:
         return f'./witness generate --filename {key_file}'
 
     @staticmethod
-    def run_witness(keypair, committee, secure_store, audit_storage, debug=False):
+    def generate_idp_key(key_file):
+        assert isinstance(key_file, str)
+        return f'./idp generate --filename {key_file}'
+
+    @staticmethod
+    def run_witness(keypair, committee, storage, debug=False):
         assert isinstance(keypair, str)
         assert isinstance(committee, str)
-        assert isinstance(secure_store, str)
-        assert isinstance(audit_storage, str)
+        assert isinstance(storage, str)
         assert isinstance(debug, bool)
         v = '-vvv' if debug else '-vv'
         return (
             f'./witness {v} run --keypair {keypair} --committee {committee} '
-            f'--secure_storage {secure_store} --audit_storage {audit_storage}'
+            f'--storage {storage}'
         )
 
     @staticmethod
-    def run_idp(keypair, committee, secure_store, sync_storage, akd_storage, batch_size, debug=False):
+    def run_idp(keypair, committee, storage, batch_size, debug=False):
         assert isinstance(keypair, str)
         assert isinstance(committee, str)
-        assert isinstance(secure_store, str)
-        assert isinstance(sync_storage, str)
-        assert isinstance(akd_storage, str)
+        assert isinstance(storage, str)
         assert isinstance(batch_size, int)
         assert isinstance(debug, bool)
         v = '-vvv' if debug else '-vv'
         return (
-            f'./idp {v} --keypair {keypair} --committee {committee} '
-            f'--secure_storage {secure_store} --sync_storage {sync_storage} '
-            f'--akd_storage {akd_storage} --batch_size {batch_size}'
+            f'./idp {v} run --keypair {keypair} --committee {committee} '
+            f'--storage {storage} --batch_size {batch_size}'
         )
 
     @staticmethod
@@ -81,15 +82,15 @@ class CommandMaker:
         assert isinstance(origin, str)
         assert isinstance(witness_only, bool)
         node = join(origin, 'witness')
+        idp = join(origin, 'idp')
         if witness_only:
             client = join(origin, 'witness_client')
             return (
-                'rm witness ; rm witness_client'
-                f'; ln -s {node} . ; ln -s {client} .'
+                'rm witness ; rm witness_client ; rm idp'
+                f'; ln -s {node} . ; ln -s {client} . ; ln -s {idp} .'
             )
         else:
             client = join(origin, 'idp_client')
-            idp = join(origin, 'idp')
             return (
                 f'rm witness ; rm idp_client ; rm idp'
                 f'; ln -s {node} . ; ln -s {client} . ; ln -s {idp} .'
@@ -19,6 +19,40 @@ class Key:
         return cls(data['name'], data['secret'])
 
 
+class WitnessKey(Key):
+    def __init__(self, name, secret, bls_public_key, bls_secret, bls_proof_of_possession):
+        super().__init__(name, secret)
+        self.bls_public_key = bls_public_key
+        self.bls_secret = bls_secret
+        self.bls_proof_of_possession = bls_proof_of_possession
+
+    @classmethod
+    def from_file(cls, filename):
+        assert isinstance(filename, str)
+        with open(filename, 'r') as f:
+            data = load(f)
+        return cls(
+            data['name'], data['secret'], data['bls_public_key'], data['bls_secret'],
+            data['bls_proof_of_possession']
+        )
+
+
+class IdpKey(Key):
+    def __init__(self, name, secret, vrf_public_key, vrf_secret):
+        super().__init__(name, secret)
+        self.vrf_public_key = vrf_public_key
+        self.vrf_secret = vrf_secret
+
+    @classmethod
+    def from_file(cls, filename):
+        assert isinstance(filename, str)
+        with open(filename, 'r') as f:
+            data = load(f)
+        return cls(
+            data['name'], data['secret'], data['vrf_public_key'], data['vrf_secret']
+        )
+
+
 class Committee:
     ''' The committee looks as follows:
         "authorities": {
@@ -32,35 +66,49 @@ class Committee:
         }
     '''
 
-    def __init__(self, idp, idp_address, witnesses_addresses, base_port):
+    def __init__(self, idp, idp_vrf, idp_address, witnesses_addresses, bls_keys, base_port,
+                 signature_scheme='Ed25519'):
         ''' The `witnesses_addresses` field looks as follows:
             { 
                 "name": "host",
                 ...
             }
+            and the `bls_keys` field maps each name to the BLS public key of the witness and its
+            proof of possession.
         '''
         assert isinstance(idp, str)
+        assert isinstance(idp_vrf, str)
         assert isinstance(idp_address, str)
         assert isinstance(witnesses_addresses, OrderedDict)
         assert all(isinstance(x, str) for x in witnesses_addresses.keys())
         assert all(isinstance(x, str) for x in witnesses_addresses.values())
+        assert isinstance(bls_keys, dict)
+        assert all(x in bls_keys for x in witnesses_addresses.keys())
         assert isinstance(base_port, int) and base_port > 1024
+        assert signature_scheme in ['Ed25519', 'Bls']
 
         self.json = {
             'idp': {
                 'name': idp,
+                'vrf_public_key': idp_vrf,
                 'address': f'{idp_address}:{base_port}'
             },
-            'witnesses': OrderedDict()
+            'witnesses': OrderedDict(),
+            'signature_scheme': signature_scheme
         }
 
         port = base_port + 1
+        gossip_port = port + len(witnesses_addresses)
         for name, host in witnesses_addresses.items():
             self.json['witnesses'][name] = {
                 'voting_power': 1,
-                'address': f'{host}:{port}'
+                'address': f'{host}:{port}',
+                'gossip_address': f'{host}:{gossip_port}',
+                'bls_public_key': bls_keys[name][0],
+                'bls_proof_of_possession': bls_keys[name][1]
             }
             port += 1
+            gossip_port += 1
 
     def addresses(self, faults=0):
         ''' Returns an ordered list of list of shards' addresses. '''
@@ -112,14 +160,17 @@ class Committee:
 
 
 class LocalCommittee(Committee):
-    def __init__(self, idp, names, port):
+    def __init__(self, idp, idp_vrf, names, bls_keys, port, signature_scheme='Ed25519'):
         assert isinstance(idp, str)
+        assert isinstance(idp_vrf, str)
         assert isinstance(names, list)
         assert all(isinstance(x, str) for x in names)
         assert isinstance(port, int)
         idp_address = '127.0.0.1'
         witnesses_addresses = OrderedDict((x, '127.0.0.1') for x in names)
-        super().__init__(idp, idp_address, witnesses_addresses, port)
+        super().__init__(
+            idp, idp_vrf, idp_address, witnesses_addresses, bls_keys, port, signature_scheme
+        )
 
 
 class BenchParameters:
@@ -156,6 +207,13 @@ class BenchParameters:
                 self.witness_only = False
 
             self.runs = int(json['runs']) if 'runs' in json else 1
+
+            if 'signature_scheme' in json:
+                self.signature_scheme = str(json['signature_scheme'])
+            else:
+                self.signature_scheme = 'Ed25519'
+            if self.signature_scheme not in ['Ed25519', 'Bls']:
+                raise ConfigError('Unknown signature scheme')
         except KeyError as e:
             raise ConfigError(f'Malformed bench parameters: missing key {e}')
 
@@ -4,7 +4,7 @@ from os.path import basename, splitext
 from time import sleep
 
 from benchmark.commands import CommandMaker
-from benchmark.config import BenchParameters, ConfigError, LocalCommittee, Key
+from benchmark.config import BenchParameters, ConfigError, LocalCommittee, WitnessKey, IdpKey
 from benchmark.logs import LogParser, ParseError
 from benchmark.utils import Print, BenchError, PathMaker
 
@@ -67,13 +67,18 @@ class LocalBench:
 
             # Generate key file for the IdP.
             idp_key_file = PathMaker.idp_key_file()
-            cmd = CommandMaker.generate_key(idp_key_file)
+            cmd = CommandMaker.generate_idp_key(idp_key_file)
             subprocess.run(cmd.split(), check=True)
 
             # Generate the committee file.
-            idp = Key.from_file(idp_key_file).name
-            names = [Key.from_file(x).name for x in key_files]
-            committee = LocalCommittee(idp, names, self.BASE_PORT)
+            idp = IdpKey.from_file(idp_key_file)
+            keys = [WitnessKey.from_file(x) for x in key_files]
+            names = [x.name for x in keys]
+            bls_keys = {x.name: (x.bls_public_key, x.bls_proof_of_possession) for x in keys}
+            committee = LocalCommittee(
+                idp.name, idp.vrf_public_key, names, bls_keys, self.BASE_PORT,
+                self.signature_scheme
+            )
             committee.print(PathMaker.committee_file())
 
             # Run the client (it will wait for the witnesses to be ready).
@@ -94,9 +99,7 @@ class LocalBench:
                 cmd = CommandMaker.run_idp(
                     idp_key_file,
                     PathMaker.committee_file(),
-                    PathMaker.idp_secure_db_path(),
-                    PathMaker.sync_db_path(),
-                    PathMaker.akd_db_path(),
+                    PathMaker.idp_db_path(),
                     self.batch_size,
                     debug=debug
                 )
@@ -108,8 +111,7 @@ class LocalBench:
                 cmd = CommandMaker.run_witness(
                     PathMaker.key_file(i),
                     PathMaker.committee_file(),
-                    PathMaker.secure_db_path(i, 0),
-                    PathMaker.audit_db_path(i, 0),
+                    PathMaker.db_path(i, 0),
                     debug=debug
                 )
                 log_file = PathMaker.shard_log_file(i, 0)
@@ -9,7 +9,7 @@ from math import ceil
 from copy import deepcopy
 import subprocess
 
-from benchmark.config import Committee, Key, BenchParameters, ConfigError
+from benchmark.config import Committee, WitnessKey, IdpKey, BenchParameters, ConfigError
 from benchmark.utils import BenchError, Print, PathMaker, progress_bar
 from benchmark.commands import CommandMaker
 from benchmark.logs import LogParser, ParseError
@@ -165,16 +165,19 @@ class Bench:
 
         # Generate key file for the IdP.
         idp_key_file = PathMaker.idp_key_file()
-        cmd = CommandMaker.generate_key(idp_key_file)
+        cmd = CommandMaker.generate_idp_key(idp_key_file)
         subprocess.run(cmd.split(), check=True)
 
         # Generate the committee file.
-        idp = Key.from_file(idp_key_file).name
-        names = [Key.from_file(x).name for x in key_files]
+        idp = IdpKey.from_file(idp_key_file)
+        keys = [WitnessKey.from_file(x) for x in key_files]
+        names = [x.name for x in keys]
+        bls_keys = {x.name: (x.bls_public_key, x.bls_proof_of_possession) for x in keys}
         idp_address = hosts.pop()
         addresses = OrderedDict((x, y) for x, y in zip(names, hosts))
         committee = Committee(
-            idp, idp_address, addresses, self.settings.base_port
+            idp.name, idp.vrf_public_key, idp_address, addresses, bls_keys,
+            self.settings.base_port, bench_parameters.signature_scheme
         )
         committee.print(PathMaker.committee_file())
 
@@ -225,9 +228,7 @@ class Bench:
             cmd = CommandMaker.run_idp(
                 PathMaker.idp_key_file(),
                 PathMaker.committee_file(),
-                PathMaker.idp_secure_db_path(),
-                PathMaker.sync_db_path(),
-                PathMaker.akd_db_path(),
+                PathMaker.idp_db_path(),
                 bench_parameters.batch_size,
                 debug=debug
             )
@@ -242,8 +243,7 @@ class Bench:
             cmd = CommandMaker.run_witness(
                 PathMaker.key_file(i),
                 PathMaker.committee_file(),
-                PathMaker.secure_db_path(i, 0),
-                PathMaker.audit_db_path(i, 0),
+                PathMaker.db_path(i, 0),
                 debug=debug
             )
             log_file = PathMaker.shard_log_file(i, 0)
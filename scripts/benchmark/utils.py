@@ -40,30 +40,15 @@ class PathMaker:
         return f'.idp.json'
 
     @staticmethod
-    def secure_db_path(i, j=None):
+    def db_path(i, j=None):
         assert isinstance(i, int) and i >= 0
         assert (isinstance(j, int) and i >= 0) or j is None
         worker_id = f'-{j}' if j is not None else ''
-        return f'.secure-db-{i}{worker_id}'
+        return f'.witness-db-{i}{worker_id}'
 
     @staticmethod
-    def audit_db_path(i, j=None):
-        assert isinstance(i, int) and i >= 0
-        assert (isinstance(j, int) and i >= 0) or j is None
-        worker_id = f'-{j}' if j is not None else ''
-        return f'.audit-db-{i}{worker_id}'
-
-    @staticmethod
-    def idp_secure_db_path():
-        return f'.idp-secure-db'
-
-    @staticmethod
-    def sync_db_path():
-        return f'.sync-db'
-
-    @staticmethod
-    def akd_db_path():
-        return f'.akd-db'
+    def idp_db_path():
+        return f'.idp-db'
 
     @staticmethod
     def logs_path():
@@ -1,4 +1,4 @@
-use crate::Storage;
+use crate::{Column, Storage, StoreResult};
 use akd::{
     errors::StorageError as AkdStorageError,
     storage::{
@@ -14,6 +14,10 @@ use async_trait::async_trait;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::RwLock;
 
+/// The first byte of the keys of the index of value states (by label). It differs from the first
+/// byte of the keys of `akd` records (their storage type).
+const USER_INDEX_PREFIX: u8 = u8::MAX;
+
 pub struct AkdStorage {
     database: Arc<RwLock<Storage>>,
     transaction: Transaction,
@@ -22,11 +26,59 @@ pub struct AkdStorage {
 impl AkdStorage {
     pub fn new(path: &str) -> Self {
         let storage = Storage::new(path).expect("Failed to initialize inner storage");
+        Self::with_storage(storage)
+    }
+
+    /// Keep the records of the directory in the `akd` column of an existing storage.
+    pub fn with_storage(storage: Storage) -> Self {
         Self {
             database: Arc::new(RwLock::new(storage)),
             transaction: Transaction::new(),
         }
     }
+
+    /// Return the prefix of the index entries of all the value states of a label. The label is
+    /// length-prefixed so that no label prefix matches the entries of another label.
+    fn user_index_prefix(username: &AkdLabel) -> Vec<u8> {
+        let mut prefix = vec![USER_INDEX_PREFIX];
+        prefix.extend_from_slice(&(username.0.len() as u64).to_be_bytes());
+        prefix.extend_from_slice(&username.0);
+        prefix
+    }
+
+    /// Return the key of the entry of a value state in the index of its label. Index entries are
+    /// sorted by epoch.
+    fn user_index_key(state: &ValueState) -> Vec<u8> {
+        let mut key = Self::user_index_prefix(&state.username);
+        key.extend_from_slice(&state.epoch.to_be_bytes());
+        key
+    }
+
+    /// Return the storage entries of a record: the record itself and, for value states, an entry
+    /// in the index of their label pointing to the record.
+    fn entries(record: &DbRecord) -> Result<Vec<(Vec<u8>, Vec<u8>)>, AkdStorageError> {
+        let serialized = bincode::serialize(record)
+            .map_err(|e| AkdStorageError::Other(format!("Serialization error: {}", e)))?;
+        let binary_id = record.get_full_binary_id();
+
+        let mut entries = Vec::with_capacity(2);
+        if let DbRecord::ValueState(state) = record {
+            entries.push((Self::user_index_key(state), binary_id.clone()));
+        }
+        entries.push((binary_id, serialized));
+        Ok(entries)
+    }
+
+    /// Return the storage entries of a record of a legacy database (keyed by the binary id of the
+    /// record, without index). The index entries of value states are rebuilt.
+    pub fn legacy_entries(key: &[u8], value: &[u8]) -> StoreResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = Vec::with_capacity(2);
+        if let DbRecord::ValueState(state) = bincode::deserialize(value)? {
+            entries.push((Self::user_index_key(&state), key.to_vec()));
+        }
+        entries.push((key.to_vec(), value.to_vec()));
+        Ok(entries)
+    }
 }
 
 impl Clone for AkdStorage {
@@ -41,7 +93,7 @@ impl Clone for AkdStorage {
 #[async_trait]
 impl akd::storage::Storage for AkdStorage {
     async fn log_metrics(&self, _level: log::Level) {
-       self.database.read().await.log_metrics();
+        self.database.read().await.log_metrics();
     }
 
     async fn begin_transaction(&self) -> bool {
@@ -67,25 +119,33 @@ impl akd::storage::Storage for AkdStorage {
             return Ok(());
         }
 
-        let serialized = match bincode::serialize(&record) {
-            Ok(x) => x,
-            Err(e) => {
-                let error = format!("Serialization error: {}", e);
-                return Err(AkdStorageError::Other(error));
-            }
-        };
+        // Persist the record together with its index entry (if any).
+        let entries = Self::entries(&record)?;
         let guard = self.database.write().await;
         guard
-            .write(&record.get_full_binary_id(), &serialized)
+            .write_batch(Column::Akd, entries)
             .map_err(|e| AkdStorageError::Other(format!("Failed to persist record: {}", e)))
     }
 
     async fn batch_set(&self, records: Vec<DbRecord>) -> Result<(), AkdStorageError> {
-        // TODO: This is really bad, we may end up with partial writes in case of failure.
-        for record in records {
-            self.set(record).await?;
+        if self.is_transaction_active().await {
+            for record in &records {
+                self.transaction.set(record).await;
+            }
+            return Ok(());
+        }
+
+        // Serialize all records (and their index entries) before writing any of them.
+        let mut entries = Vec::with_capacity(records.len());
+        for record in &records {
+            entries.extend(Self::entries(record)?);
         }
-        Ok(())
+
+        // Persist all records atomically (either all of them or none).
+        let guard = self.database.write().await;
+        guard
+            .write_batch(Column::Akd, entries)
+            .map_err(|e| AkdStorageError::Other(format!("Failed to persist records: {}", e)))
     }
 
     async fn get<St: AkdStorable>(&self, id: &St::Key) -> Result<DbRecord, AkdStorageError> {
@@ -97,7 +157,7 @@ impl akd::storage::Storage for AkdStorage {
 
         let binary_id = St::get_full_binary_key_id(id);
         let guard = self.database.read().await;
-        match (*guard).read(&binary_id) {
+        match (*guard).read(Column::Akd, &binary_id[..]) {
             Ok(Some(bytes)) => bincode::deserialize(&bytes)
                 .map_err(|e| AkdStorageError::Other(format!("Serialization error: {}", e))),
             Ok(None) => Err(AkdStorageError::NotFound("Not found".to_string())),
@@ -111,8 +171,10 @@ impl akd::storage::Storage for AkdStorage {
     ) -> Result<Vec<DbRecord>, AkdStorageError> {
         let mut map = Vec::new();
         for key in ids.iter() {
-            if let Ok(result) = self.get::<St>(key).await {
-                map.push(result);
+            match self.get::<St>(key).await {
+                Ok(result) => map.push(result),
+                Err(AkdStorageError::NotFound(_)) => (),
+                Err(e) => return Err(e),
             }
         }
         Ok(map)
@@ -126,27 +188,104 @@ impl akd::storage::Storage for AkdStorage {
         self.database.read().await.flush_cache();
     }
 
-    async fn get_user_data(&self, _username: &AkdLabel) -> Result<KeyData, AkdStorageError> {
-        unimplemented!()
+    async fn get_user_data(&self, username: &AkdLabel) -> Result<KeyData, AkdStorageError> {
+        // Load all the persisted value states of the label through the index (sorted by epoch).
+        let guard = self.database.read().await;
+        let mut states = Vec::new();
+        for (_, binary_id) in guard.read_prefix(Column::Akd, &Self::user_index_prefix(username)) {
+            let bytes = guard
+                .read(Column::Akd, &binary_id[..])
+                .map_err(|e| AkdStorageError::Other(format!("{}", e)))?
+                .ok_or_else(|| AkdStorageError::Other("Dangling value state index".into()))?;
+            let record = bincode::deserialize(&bytes)
+                .map_err(|e| AkdStorageError::Other(format!("Serialization error: {}", e)))?;
+            match record {
+                DbRecord::ValueState(state) => states.push(state),
+                _ => return Err(AkdStorageError::Other("Malformed value state index".into())),
+            }
+        }
+        drop(guard);
+
+        // The value states set by the active transaction (if any) override the persisted ones.
+        if self.is_transaction_active().await {
+            let pending = self.transaction.get_users_data(&[username.clone()]).await;
+            for state in pending.get(username).into_iter().flatten() {
+                states.retain(|x| x.epoch != state.epoch);
+                states.push(state.clone());
+            }
+            states.sort_by_key(|x| x.epoch);
+        }
+
+        if states.is_empty() {
+            let error = format!("No value state for label {:?}", username);
+            return Err(AkdStorageError::NotFound(error));
+        }
+        Ok(KeyData { states })
     }
 
     async fn get_user_state(
         &self,
-        _username: &AkdLabel,
-        _flag: ValueStateRetrievalFlag,
+        username: &AkdLabel,
+        flag: ValueStateRetrievalFlag,
     ) -> Result<ValueState, AkdStorageError> {
-        unimplemented!()
+        let states = self.get_user_data(username).await?.states;
+        let state = match flag {
+            ValueStateRetrievalFlag::MaxEpoch => states.last(),
+            ValueStateRetrievalFlag::MinEpoch => states.first(),
+            ValueStateRetrievalFlag::SpecificVersion(version) => {
+                states.iter().find(|x| x.version == version)
+            }
+            ValueStateRetrievalFlag::SpecificEpoch(epoch) => {
+                states.iter().find(|x| x.epoch == epoch)
+            }
+            ValueStateRetrievalFlag::LeqEpoch(epoch) => {
+                states.iter().rev().find(|x| x.epoch <= epoch)
+            }
+        };
+        state.cloned().ok_or_else(|| {
+            let error = format!(
+                "No value state for label {:?} matching {:?}",
+                username, flag
+            );
+            AkdStorageError::NotFound(error)
+        })
     }
 
     async fn get_user_state_versions(
         &self,
-        _usernames: &[AkdLabel],
-        _flag: ValueStateRetrievalFlag,
+        usernames: &[AkdLabel],
+        flag: ValueStateRetrievalFlag,
     ) -> Result<HashMap<AkdLabel, (u64, AkdValue)>, AkdStorageError> {
-        Ok(HashMap::new())
+        let mut versions = HashMap::new();
+        for username in usernames {
+            match self.get_user_state(username, flag).await {
+                Ok(state) => {
+                    versions.insert(username.clone(), (state.version, state.plaintext_val));
+                }
+                Err(AkdStorageError::NotFound(_)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(versions)
     }
 
-    async fn tombstone_value_states(&self, _keys: &[ValueStateKey]) -> Result<(), AkdStorageError> {
-        unimplemented!()
+    async fn tombstone_value_states(&self, keys: &[ValueStateKey]) -> Result<(), AkdStorageError> {
+        let tombstones: Vec<_> = self
+            .batch_get::<ValueState>(keys)
+            .await?
+            .into_iter()
+            .filter_map(|record| match record {
+                DbRecord::ValueState(state) => Some(DbRecord::ValueState(ValueState {
+                    plaintext_val: AkdValue(akd::TOMBSTONE.to_vec()),
+                    ..state
+                })),
+                _ => None,
+            })
+            .collect();
+
+        if tombstones.is_empty() {
+            return Ok(());
+        }
+        self.batch_set(tombstones).await
     }
 }
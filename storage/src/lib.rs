@@ -1,29 +1,298 @@
 pub mod akd_storage;
 
-/// Convenient name for rocksdb's error.
-pub type StoreError = rocksdb::Error;
-type StoreResult<T> = Result<T, StoreError>;
+use akd::storage::types::AkdLabel;
+use rocksdb::{perf, ColumnFamily, Direction, IteratorMode, Options, WriteBatch, DB};
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
 
-use rocksdb::perf;
+/// Convenient result wrapper.
+pub type StoreResult<T> = Result<T, StoreError>;
 
-/// Wrapper around rocksdb.
-pub struct Storage(rocksdb::DB);
+/// Errors triggered when accessing the storage.
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error(transparent)]
+    DatabaseError(#[from] rocksdb::Error),
+
+    #[error("Failed to (de)serialize value: {0}")]
+    SerializationError(#[from] Box<bincode::ErrorKind>),
+}
+
+/// The column families of the database. Each holds a different kind of records so that their keys
+/// never collide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    /// The safety-critical state (e.g., the last notification of the IdP, the locks of a witness).
+    State,
+    /// The publish certificates (indexed by sequence number).
+    Certificates,
+    /// The evidence of misbehaviour of the IdP (indexed by sequence number).
+    Evidence,
+    /// The owner of each label (and the last nonce they used).
+    Registry,
+    /// The records of the `akd` key directory.
+    Akd,
+    /// The batches of updates shipped to the full witnesses (indexed by sequence number).
+    Batches,
+    /// The VRF proofs of the labels of the replica of a full witness.
+    VrfProofs,
+}
+
+impl Column {
+    /// All the column families of the database.
+    pub const ALL: [Column; 7] = [
+        Column::State,
+        Column::Certificates,
+        Column::Evidence,
+        Column::Registry,
+        Column::Akd,
+        Column::Batches,
+        Column::VrfProofs,
+    ];
+
+    /// The name of the column family.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::State => "state",
+            Column::Certificates => "certificates",
+            Column::Evidence => "evidence",
+            Column::Registry => "registry",
+            Column::Akd => "akd",
+            Column::Batches => "batches",
+            Column::VrfProofs => "vrf_proofs",
+        }
+    }
+}
+
+/// A key of the database.
+pub trait Key {
+    /// Encode the key into bytes.
+    fn encode(&self) -> Vec<u8>;
+}
+
+impl Key for [u8] {
+    fn encode(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl Key for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl Key for str {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+/// A key that can be recovered from its encoding (to iterate over the typed keys of a column).
+pub trait DecodeKey: Key + Sized {
+    /// Decode a key from bytes (if they encode a key of this type).
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+/// Sequence numbers are big-endian so that certificates are sorted by sequence number.
+impl Key for u64 {
+    fn encode(&self) -> Vec<u8> {
+        self.to_be_bytes().to_vec()
+    }
+}
+
+impl DecodeKey for u64 {
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok().map(u64::from_be_bytes)
+    }
+}
+
+impl Key for AkdLabel {
+    fn encode(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+/// The key (in the state column) marking that the legacy databases were migrated.
+const MIGRATED_KEY: &str = "migrated";
+
+/// The maximum number of records written at once when migrating a legacy database.
+const MIGRATION_CHUNK_SIZE: usize = 10_000;
+
+/// Wrapper around rocksdb. All the clones of a storage share the same database.
+#[derive(Clone)]
+pub struct Storage(Arc<DB>);
 
 impl Storage {
-    /// Create a new persistent storage.
+    /// Create a new persistent storage (or open an existing one).
     pub fn new(path: &str) -> StoreResult<Self> {
-        let db = rocksdb::DB::open_default(path)?;
-        Ok(Self(db))
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let names = Column::ALL.iter().map(|column| column.name());
+        let db = DB::open_cf(&options, path, names)?;
+        Ok(Self(Arc::new(db)))
+    }
+
+    /// Return the handle of a column family.
+    fn handle(&self, column: Column) -> &ColumnFamily {
+        self.0
+            .cf_handle(column.name())
+            .expect("The database always holds all column families")
     }
 
     /// Read a value from storage.
-    pub fn read(&self, key: &[u8]) -> StoreResult<Option<Vec<u8>>> {
-        self.0.get(&key)
+    pub fn read<K: Key + ?Sized>(&self, column: Column, key: &K) -> StoreResult<Option<Vec<u8>>> {
+        self.0
+            .get_cf(self.handle(column), key.encode())
+            .map_err(StoreError::from)
     }
 
     /// Write a value to storage.
-    pub fn write(&self, key: &[u8], value: &[u8]) -> StoreResult<()> {
-        self.0.put(key, value)
+    pub fn write<K: Key + ?Sized>(&self, column: Column, key: &K, value: &[u8]) -> StoreResult<()> {
+        self.0
+            .put_cf(self.handle(column), key.encode(), value)
+            .map_err(StoreError::from)
+    }
+
+    /// Read and deserialize a value from storage.
+    pub fn get<K, V>(&self, column: Column, key: &K) -> StoreResult<Option<V>>
+    where
+        K: Key + ?Sized,
+        V: DeserializeOwned,
+    {
+        match self.read(column, key)? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Serialize and write a value to storage.
+    pub fn put<K, V>(&self, column: Column, key: &K, value: &V) -> StoreResult<()>
+    where
+        K: Key + ?Sized,
+        V: Serialize + ?Sized,
+    {
+        let bytes = bincode::serialize(value)?;
+        self.write(column, key, &bytes)
+    }
+
+    /// Read all the key-values whose key starts with the specified prefix (sorted by key).
+    pub fn read_prefix(&self, column: Column, prefix: &[u8]) -> Vec<(Box<[u8]>, Box<[u8]>)> {
+        self.0
+            .iterator_cf(
+                self.handle(column),
+                IteratorMode::From(prefix, Direction::Forward),
+            )
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .collect()
+    }
+
+    /// Iterate (in key order) over the key-values of a column starting from the specified key. The
+    /// entries whose key is not of the expected type are skipped.
+    pub fn iter_from<K: DecodeKey>(
+        &self,
+        column: Column,
+        from: &K,
+    ) -> impl Iterator<Item = (K, Box<[u8]>)> + '_ {
+        let from = from.encode();
+        self.0
+            .iterator_cf(
+                self.handle(column),
+                IteratorMode::From(&from, Direction::Forward),
+            )
+            .filter_map(|(key, value)| K::decode(&key).map(|key| (key, value)))
+    }
+
+    /// Return the key-value with the greatest key of a column (if the column is not empty).
+    pub fn last<K: DecodeKey>(&self, column: Column) -> Option<(K, Box<[u8]>)> {
+        self.0
+            .iterator_cf(self.handle(column), IteratorMode::End)
+            .find_map(|(key, value)| K::decode(&key).map(|key| (key, value)))
+    }
+
+    /// Atomically write multiple values to storage: either all of them are persisted or none.
+    pub fn write_batch<K, V, I>(&self, column: Column, entries: I) -> StoreResult<()>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let handle = self.handle(column);
+        let mut batch = WriteBatch::default();
+        for (key, value) in entries {
+            batch.put_cf(handle, key, value);
+        }
+        self.0.write(batch).map_err(StoreError::from)
+    }
+
+    /// Atomically delete all the key-values of a column whose key is lower than the specified key.
+    pub fn delete_before<K: Key + ?Sized>(&self, column: Column, key: &K) -> StoreResult<()> {
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(self.handle(column), Vec::new(), key.encode());
+        self.0.write(batch).map_err(StoreError::from)
+    }
+
+    /// Atomically delete all the key-values of a column whose key is within the specified range
+    /// (from `from` included to `to` excluded).
+    pub fn delete_range<K: Key + ?Sized>(
+        &self,
+        column: Column,
+        from: &K,
+        to: &K,
+    ) -> StoreResult<()> {
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(self.handle(column), from.encode(), to.encode());
+        self.0.write(batch).map_err(StoreError::from)
+    }
+
+    /// Atomically delete all the key-values of a column.
+    pub fn clear(&self, column: Column) -> StoreResult<()> {
+        let handle = self.handle(column);
+        let mut batch = WriteBatch::default();
+        if let Some((last, _)) = self.0.iterator_cf(handle, IteratorMode::End).next() {
+            batch.delete_range_cf(handle, Vec::new(), last.to_vec());
+            batch.delete_cf(handle, last);
+        }
+        self.0.write(batch).map_err(StoreError::from)
+    }
+
+    /// Copy all the records of a legacy database (a single directory without column families) into
+    /// this database. The function `route` translates each legacy key-value into the records
+    /// (column, key, and value) it becomes in this database (possibly none). Records are written in
+    /// bounded chunks, so the migration is not atomic: it is re-run from scratch until marked as
+    /// done (see `set_migrated`). Returns the number of migrated records.
+    pub fn migrate<F, I>(&self, legacy_path: &str, route: F) -> StoreResult<usize>
+    where
+        F: Fn(&[u8], &[u8]) -> StoreResult<I>,
+        I: IntoIterator<Item = (Column, Vec<u8>, Vec<u8>)>,
+    {
+        let legacy = DB::open_for_read_only(&Options::default(), legacy_path, false)?;
+        let mut migrated = 0;
+        let mut batch = WriteBatch::default();
+        for (key, value) in legacy.iterator(IteratorMode::Start) {
+            for (column, key, value) in route(&key, &value)? {
+                batch.put_cf(self.handle(column), key, value);
+            }
+            if batch.len() >= MIGRATION_CHUNK_SIZE {
+                migrated += batch.len();
+                self.0.write(std::mem::take(&mut batch))?;
+            }
+        }
+        migrated += batch.len();
+        self.0.write(batch)?;
+        Ok(migrated)
+    }
+
+    /// Check whether the legacy databases were already migrated into this database.
+    pub fn is_migrated(&self) -> StoreResult<bool> {
+        Ok(self.read(Column::State, MIGRATED_KEY)?.is_some())
+    }
+
+    /// Record that the legacy databases are migrated (so that the migration only runs once).
+    pub fn set_migrated(&self) -> StoreResult<()> {
+        self.write(Column::State, MIGRATED_KEY, &[])
     }
 
     pub fn log_metrics(&self) {
@@ -42,7 +311,7 @@ impl Storage {
         }
 
         // Print memory stats.
-        if let Ok(mem_stats) = perf::get_memory_usage_stats(Some(&[&self.0]), None) {
+        if let Ok(mem_stats) = perf::get_memory_usage_stats(Some(&[&*self.0]), None) {
             println!("Memory usage stats: Mem table total: {}, Mem table unflushed: {}, Mem table readers total: {}, Cache total: {}",
             mem_stats.mem_table_total, mem_stats.mem_table_unflushed, mem_stats.mem_table_readers_total, mem_stats.cache_total);
         } else {
@@ -0,0 +1,281 @@
+use akd::{
+    directory::Directory,
+    ecvrf::HardCodedAkdVRF,
+    storage::{
+        memory::AsyncInMemoryDatabase,
+        types::{AkdLabel, AkdValue, DbRecord, ValueStateKey, ValueStateRetrievalFlag},
+        Storage as _,
+    },
+};
+use storage::akd_storage::AkdStorage;
+use winter_crypto::{hashers::Blake3_256, Digest as _};
+use winter_math::fields::f128::BaseElement;
+
+type Blake3 = Blake3_256<BaseElement>;
+
+// Everything a storage backend exposes to `akd` during a test scenario.
+#[derive(Debug, PartialEq)]
+struct Observations {
+    // The root of the directory after each epoch.
+    roots: Vec<Vec<u8>>,
+    // The (epoch, version, value) of each value state of the first label.
+    states: Vec<(u64, u64, Vec<u8>)>,
+    // The (epoch, version) of the value state of the first label returned for each flag.
+    queries: Vec<Option<(u64, u64)>>,
+    // The latest (label, version, value) of each known label.
+    versions: Vec<(Vec<u8>, u64, Vec<u8>)>,
+    // The value returned by a lookup of the first label.
+    lookup: Vec<u8>,
+    // The value of the first state of the first label once tombstoned.
+    tombstone: Vec<u8>,
+}
+
+// Publish a few batches of updates (overwriting some labels) and query the value states.
+async fn scenario<S>(db: S) -> Observations
+where
+    S: akd::storage::Storage + Sync + Send + 'static,
+{
+    let first = AkdLabel(b"first".to_vec());
+    let second = AkdLabel(b"second".to_vec());
+    // A label whose bytes start with those of the first one.
+    let extended = AkdLabel(b"first-extended".to_vec());
+    let unknown = AkdLabel(b"unknown".to_vec());
+
+    let vrf = HardCodedAkdVRF {};
+    let akd = Directory::new::<Blake3>(&db, &vrf, false).await.unwrap();
+    let batches = vec![
+        vec![
+            (first.clone(), AkdValue(b"1".to_vec())),
+            (second.clone(), AkdValue(b"2".to_vec())),
+        ],
+        vec![
+            (first.clone(), AkdValue(b"3".to_vec())),
+            (extended.clone(), AkdValue(b"4".to_vec())),
+        ],
+        vec![(first.clone(), AkdValue(b"5".to_vec()))],
+    ];
+    let mut roots = Vec::new();
+    for (i, batch) in batches.into_iter().enumerate() {
+        akd.publish::<Blake3>(batch).await.unwrap();
+        let current_azks = akd.retrieve_current_azks().await.unwrap();
+        let root = akd
+            .get_root_hash_at_epoch::<Blake3>(&current_azks, i as u64 + 1)
+            .await
+            .unwrap();
+        roots.push(root.as_bytes().to_vec());
+    }
+
+    let states = db
+        .get_user_data(&first)
+        .await
+        .unwrap()
+        .states
+        .into_iter()
+        .map(|x| (x.epoch, x.version, x.plaintext_val.0))
+        .collect();
+
+    let flags = [
+        ValueStateRetrievalFlag::MaxEpoch,
+        ValueStateRetrievalFlag::MinEpoch,
+        ValueStateRetrievalFlag::SpecificVersion(2),
+        ValueStateRetrievalFlag::SpecificVersion(4),
+        ValueStateRetrievalFlag::SpecificEpoch(3),
+        ValueStateRetrievalFlag::SpecificEpoch(4),
+        ValueStateRetrievalFlag::LeqEpoch(2),
+        ValueStateRetrievalFlag::LeqEpoch(10),
+        ValueStateRetrievalFlag::LeqEpoch(0),
+    ];
+    let mut queries = Vec::new();
+    for flag in flags {
+        let state = db.get_user_state(&first, flag).await.ok();
+        queries.push(state.map(|x| (x.epoch, x.version)));
+    }
+
+    let labels = [first.clone(), second, extended, unknown];
+    let mut versions: Vec<_> = db
+        .get_user_state_versions(&labels, ValueStateRetrievalFlag::MaxEpoch)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(label, (version, value))| (label.0, version, value.0))
+        .collect();
+    versions.sort();
+
+    let lookup = akd
+        .lookup::<Blake3>(first.clone())
+        .await
+        .unwrap()
+        .plaintext_value
+        .0;
+
+    db.tombstone_value_states(&[ValueStateKey(first.0.clone(), 1)])
+        .await
+        .unwrap();
+    let tombstone = db
+        .get_user_state(&first, ValueStateRetrievalFlag::MinEpoch)
+        .await
+        .unwrap()
+        .plaintext_val
+        .0;
+
+    Observations {
+        roots,
+        states,
+        queries,
+        versions,
+        lookup,
+        tombstone,
+    }
+}
+
+#[tokio::test]
+async fn parity_with_memory_storage() {
+    let path = ".test_akd_storage_parity_with_memory_storage";
+    let _ = std::fs::remove_dir_all(path);
+
+    let expected = scenario(AsyncInMemoryDatabase::new()).await;
+    let observed = scenario(AkdStorage::new(path)).await;
+    assert_eq!(observed, expected);
+
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[tokio::test]
+async fn value_states_by_label() {
+    let path = ".test_akd_storage_value_states_by_label";
+    let _ = std::fs::remove_dir_all(path);
+
+    let observed = scenario(AkdStorage::new(path)).await;
+
+    // The states of the first label are sorted by epoch and do not include the extended label.
+    let expected = vec![
+        (1, 1, b"1".to_vec()),
+        (2, 2, b"3".to_vec()),
+        (3, 3, b"5".to_vec()),
+    ];
+    assert_eq!(observed.states, expected);
+
+    let expected = vec![
+        Some((3, 3)),
+        Some((1, 1)),
+        Some((2, 2)),
+        None,
+        Some((3, 3)),
+        None,
+        Some((2, 2)),
+        Some((3, 3)),
+        None,
+    ];
+    assert_eq!(observed.queries, expected);
+
+    let expected = vec![
+        (b"first".to_vec(), 3, b"5".to_vec()),
+        (b"first-extended".to_vec(), 1, b"4".to_vec()),
+        (b"second".to_vec(), 1, b"2".to_vec()),
+    ];
+    assert_eq!(observed.versions, expected);
+    assert_eq!(observed.lookup, b"5".to_vec());
+    assert_eq!(observed.tombstone, akd::TOMBSTONE.to_vec());
+
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[tokio::test]
+async fn atomic_transaction_commit() {
+    let path = ".test_akd_storage_atomic_transaction_commit";
+    let _ = std::fs::remove_dir_all(path);
+
+    // Build the value states of an epoch touching several labels.
+    let labels: Vec<_> = (0..10u8).map(|i| AkdLabel(vec![i])).collect();
+    let memory = AsyncInMemoryDatabase::new();
+    let vrf = HardCodedAkdVRF {};
+    let akd = Directory::new::<Blake3>(&memory, &vrf, false)
+        .await
+        .unwrap();
+    let batch = labels
+        .iter()
+        .map(|label| (label.clone(), AkdValue(label.0.clone())))
+        .collect();
+    akd.publish::<Blake3>(batch).await.unwrap();
+    let mut records = Vec::new();
+    for label in &labels {
+        let states = memory.get_user_data(label).await.unwrap().states;
+        records.extend(states.into_iter().map(DbRecord::ValueState));
+    }
+
+    // A clone does not share the transaction: it reads the persisted records only.
+    let db = AkdStorage::new(path);
+    let reader = db.clone();
+    let (first, rest) = records.split_at(records.len() / 2);
+
+    // Records of a rolled back transaction are never persisted.
+    assert!(db.begin_transaction().await);
+    db.batch_set(first.to_vec()).await.unwrap();
+    db.rollback_transaction().await.unwrap();
+    for label in &labels {
+        assert!(reader.get_user_data(label).await.is_err());
+    }
+
+    // None of the records of the epoch are persisted before the commit.
+    assert!(db.begin_transaction().await);
+    db.batch_set(first.to_vec()).await.unwrap();
+    db.batch_set(rest.to_vec()).await.unwrap();
+    for label in &labels {
+        assert!(reader.get_user_data(label).await.is_err());
+    }
+
+    // All of them are persisted by the commit.
+    db.commit_transaction().await.unwrap();
+    for label in &labels {
+        let states = reader.get_user_data(label).await.unwrap().states;
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].plaintext_val, AkdValue(label.0.clone()));
+    }
+
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[tokio::test]
+async fn read_through_transaction() {
+    let path = ".test_akd_storage_read_through_transaction";
+    let _ = std::fs::remove_dir_all(path);
+
+    // Publish a first version of a label.
+    let label = AkdLabel(b"label".to_vec());
+    let db = AkdStorage::new(path);
+    let vrf = HardCodedAkdVRF {};
+    let akd = Directory::new::<Blake3>(&db, &vrf, false).await.unwrap();
+    let batch = vec![(label.clone(), AkdValue(b"1".to_vec()))];
+    akd.publish::<Blake3>(batch).await.unwrap();
+
+    // Set a second version within a transaction.
+    let mut state = db.get_user_data(&label).await.unwrap().states.remove(0);
+    state.epoch = 2;
+    state.version = 2;
+    state.plaintext_val = AkdValue(b"2".to_vec());
+    assert!(db.begin_transaction().await);
+    db.set(DbRecord::ValueState(state)).await.unwrap();
+
+    // The storage reads the records of the transaction on top of the persisted ones.
+    let states: Vec<_> = db
+        .get_user_data(&label)
+        .await
+        .unwrap()
+        .states
+        .into_iter()
+        .map(|x| (x.epoch, x.version, x.plaintext_val.0))
+        .collect();
+    assert_eq!(states, vec![(1, 1, b"1".to_vec()), (2, 2, b"2".to_vec())]);
+    let versions = db
+        .get_user_state_versions(&[label.clone()], ValueStateRetrievalFlag::MaxEpoch)
+        .await
+        .unwrap();
+    assert_eq!(versions[&label], (2, AkdValue(b"2".to_vec())));
+
+    // Other handles on the storage only read the persisted records.
+    let states = db.clone().get_user_data(&label).await.unwrap().states;
+    assert_eq!(states.len(), 1);
+
+    db.rollback_transaction().await.unwrap();
+    let _ = std::fs::remove_dir_all(path);
+}
@@ -0,0 +1,218 @@
+use rocksdb::DB;
+use storage::{Column, Key, Storage};
+
+#[test]
+fn columns_do_not_collide() {
+    let path = ".test_storage_columns_do_not_collide";
+    let _ = std::fs::remove_dir_all(path);
+
+    let storage = Storage::new(path).unwrap();
+    storage.put(Column::State, "key", &1u64).unwrap();
+    storage.put(Column::Certificates, "key", &2u64).unwrap();
+
+    assert_eq!(storage.get(Column::State, "key").unwrap(), Some(1u64));
+    assert_eq!(
+        storage.get(Column::Certificates, "key").unwrap(),
+        Some(2u64)
+    );
+    assert_eq!(
+        storage.get::<_, u64>(Column::Evidence, "key").unwrap(),
+        None
+    );
+
+    // The records survive a restart.
+    drop(storage);
+    let storage = Storage::new(path).unwrap();
+    assert_eq!(storage.get(Column::State, "key").unwrap(), Some(1u64));
+
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn migrate_legacy_database() {
+    let legacy_path = ".test_storage_migrate_legacy_database_legacy";
+    let path = ".test_storage_migrate_legacy_database";
+    let _ = std::fs::remove_dir_all(legacy_path);
+    let _ = std::fs::remove_dir_all(path);
+
+    // Make a legacy database indexing records by little-endian sequence numbers.
+    {
+        let legacy = DB::open_default(legacy_path).unwrap();
+        for i in 1..=3u64 {
+            legacy.put(i.to_le_bytes(), [i as u8]).unwrap();
+        }
+        legacy.put(b"unknown", b"dropped").unwrap();
+    }
+
+    let storage = Storage::new(path).unwrap();
+    assert!(!storage.is_migrated().unwrap());
+    let migrated = storage
+        .migrate(legacy_path, |key, value| {
+            let record = key.try_into().ok().map(|key| {
+                let sequence_number = u64::from_le_bytes(key);
+                (
+                    Column::Certificates,
+                    sequence_number.encode(),
+                    value.to_vec(),
+                )
+            });
+            Ok(record)
+        })
+        .unwrap();
+    storage.set_migrated().unwrap();
+    assert_eq!(migrated, 3);
+    assert!(storage.is_migrated().unwrap());
+
+    // The records are now indexed by big-endian sequence numbers (and thus sorted).
+    for i in 1..=3u64 {
+        let value = storage.read(Column::Certificates, &i).unwrap();
+        assert_eq!(value, Some(vec![i as u8]));
+    }
+    let keys: Vec<_> = storage
+        .read_prefix(Column::Certificates, &[])
+        .into_iter()
+        .map(|(key, _)| key.to_vec())
+        .collect();
+    let expected: Vec<_> = (1..=3u64).map(|i| i.encode()).collect();
+    assert_eq!(keys, expected);
+
+    let _ = std::fs::remove_dir_all(legacy_path);
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn migrate_in_chunks() {
+    let legacy_path = ".test_storage_migrate_in_chunks_legacy";
+    let path = ".test_storage_migrate_in_chunks";
+    let _ = std::fs::remove_dir_all(legacy_path);
+    let _ = std::fs::remove_dir_all(path);
+
+    // Make a legacy database holding more records than a single migration chunk.
+    let count = 25_000u64;
+    {
+        let legacy = DB::open_default(legacy_path).unwrap();
+        for i in 0..count {
+            legacy.put(i.to_be_bytes(), i.to_le_bytes()).unwrap();
+        }
+    }
+
+    // Each legacy record may become several records.
+    let storage = Storage::new(path).unwrap();
+    let migrated = storage
+        .migrate(legacy_path, |key, value| {
+            Ok(vec![
+                (Column::Certificates, key.to_vec(), value.to_vec()),
+                (Column::Evidence, key.to_vec(), value.to_vec()),
+            ])
+        })
+        .unwrap();
+    assert_eq!(migrated, 2 * count as usize);
+    for i in [0, count / 2, count - 1] {
+        let value = storage.read(Column::Certificates, &i).unwrap();
+        assert_eq!(value, Some(i.to_le_bytes().to_vec()));
+        let value = storage.read(Column::Evidence, &i).unwrap();
+        assert_eq!(value, Some(i.to_le_bytes().to_vec()));
+    }
+
+    let _ = std::fs::remove_dir_all(legacy_path);
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn iterate_over_sequence_numbers() {
+    let path = ".test_storage_iterate_over_sequence_numbers";
+    let _ = std::fs::remove_dir_all(path);
+
+    let storage = Storage::new(path).unwrap();
+    assert!(storage.last::<u64>(Column::Certificates).is_none());
+
+    // Sequence numbers are encoded big-endian so that their keys are sorted numerically.
+    for i in [1u64, 2, 10, 255, 256] {
+        storage.write(Column::Certificates, &i, &[i as u8]).unwrap();
+    }
+
+    let keys: Vec<u64> = storage
+        .iter_from(Column::Certificates, &2u64)
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(keys, vec![2, 10, 255, 256]);
+
+    let (key, value) = storage.last::<u64>(Column::Certificates).unwrap();
+    assert_eq!(key, 256);
+    assert_eq!(&*value, &[0u8]);
+
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn delete_records_before_key() {
+    let path = ".test_storage_delete_records_before_key";
+    let _ = std::fs::remove_dir_all(path);
+
+    let storage = Storage::new(path).unwrap();
+    for i in 1..=5u64 {
+        storage.write(Column::Certificates, &i, &[i as u8]).unwrap();
+    }
+    storage.write(Column::State, &1u64, &[1]).unwrap();
+
+    storage.delete_before(Column::Certificates, &3u64).unwrap();
+    let keys: Vec<u64> = storage
+        .iter_from(Column::Certificates, &0u64)
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(keys, vec![3, 4, 5]);
+
+    // The other columns are untouched.
+    assert_eq!(storage.read(Column::State, &1u64).unwrap(), Some(vec![1]));
+
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn delete_records_in_range() {
+    let path = ".test_storage_delete_records_in_range";
+    let _ = std::fs::remove_dir_all(path);
+
+    let storage = Storage::new(path).unwrap();
+    for i in 1..=5u64 {
+        let key = [b"lock/".to_vec(), i.to_be_bytes().to_vec()].concat();
+        storage.write(Column::State, &key, &[i as u8]).unwrap();
+    }
+    storage.write(Column::State, "committees", &[0]).unwrap();
+    storage.write(Column::State, "state", &[0]).unwrap();
+
+    // Only the keys of the range are deleted, not the other keys of the column.
+    let from = [b"lock/".to_vec(), 0u64.to_be_bytes().to_vec()].concat();
+    let to = [b"lock/".to_vec(), 3u64.to_be_bytes().to_vec()].concat();
+    storage.delete_range(Column::State, &from, &to).unwrap();
+    assert_eq!(storage.read_prefix(Column::State, b"lock/").len(), 3);
+    assert_eq!(
+        storage.read(Column::State, "committees").unwrap(),
+        Some(vec![0])
+    );
+    assert_eq!(storage.read(Column::State, "state").unwrap(), Some(vec![0]));
+
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn clear_column() {
+    let path = ".test_storage_clear_column";
+    let _ = std::fs::remove_dir_all(path);
+
+    let storage = Storage::new(path).unwrap();
+    for i in 1..=5u64 {
+        storage.write(Column::Akd, &i, &[i as u8]).unwrap();
+    }
+    storage
+        .write(Column::Akd, &[u8::MAX; 16][..], &[0])
+        .unwrap();
+    storage.write(Column::State, "state", &[0]).unwrap();
+
+    // All the keys of the column are deleted (including the greatest one), not those of the others.
+    storage.clear(Column::Akd).unwrap();
+    assert!(storage.read_prefix(Column::Akd, &[]).is_empty());
+    assert_eq!(storage.read(Column::State, "state").unwrap(), Some(vec![0]));
+
+    let _ = std::fs::remove_dir_all(path);
+}
@@ -1,24 +1,35 @@
-use akd::{
-    directory::Directory, ecvrf::HardCodedAkdVRF, storage::memory::AsyncInMemoryDatabase, AkdLabel,
-    AkdValue,
-};
+use akd::{directory::Directory, storage::memory::AsyncInMemoryDatabase, AkdLabel, AkdValue};
 use bytes::Bytes;
-use config::{Committee, Idp, Witness};
-use crypto::{KeyPair, PublicKey};
+use config::{Committee, Idp, SignatureScheme, Witness};
+use crypto::{BlsKeyPair, BlsPublicKey, BlsSignature, KeyPair, PublicKey, BLS_PUBLIC_KEY_LENGTH};
 use futures::{stream::StreamExt, SinkExt};
-use idp::spawn_idp;
+use idp::spawn_idp_with_transport;
 use messages::{
-    publish::{Proof, PublishCertificate, PublishNotification, PublishVote},
-    update::UpdateRequest,
-    Blake3, IdPToWitnessMessage, Root, WitnessToIdPMessage,
+    gossip::{EquivocationReport, WitnessToWitnessMessage},
+    legacy::LegacyPublishCertificate,
+    publish::{Proof, PublishCertificate, PublishNotification, PublishVote, SignerBitmap, Votes},
+    reconfiguration::CommitteeChange,
+    update::{SignedUpdateRequest, UpdateRequest},
+    vrf::IdpVrf,
+    Blake3, ClientToIdPMessage, IdPToWitnessMessage, Root, SequenceNumber, WitnessToIdPMessage,
+};
+use network::{
+    envelope::Envelope,
+    memory::MemoryNetwork,
+    reliable_sender::{BufferLimits, CancelHandler, ReliableSender},
+    transport::{TcpTransport, Transport},
 };
-use network::reliable_sender::{CancelHandler, ReliableSender};
 use rand::{rngs::StdRng, SeedableRng};
-use std::net::SocketAddr;
+use rocksdb::DB;
+use std::{net::SocketAddr, sync::Arc};
 use storage::Storage;
-use tokio::{net::TcpListener, task::JoinHandle};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
-use witness::spawn_witness;
+use tokio::{sync::mpsc::channel, task::JoinHandle};
+use witness::{
+    spawn_full_witness_with_transport, spawn_witness_with_transport, DEFAULT_CHECKPOINT_INTERVAL,
+};
+
+// The pipeline depth of the test IdP and witnesses.
+pub const PIPELINE_DEPTH: usize = 2;
 
 // Test cryptographic keys.
 pub fn keys() -> Vec<(PublicKey, KeyPair)> {
@@ -28,17 +39,33 @@ pub fn keys() -> Vec<(PublicKey, KeyPair)> {
         .collect()
 }
 
+// Test BLS keys of the witnesses (in the same order as `keys()`).
+pub fn bls_keys() -> Vec<(BlsPublicKey, BlsKeyPair)> {
+    let mut rng = StdRng::from_seed([3; 32]);
+    (0..4)
+        .map(|_| BlsKeyPair::generate_keypair(&mut rng))
+        .collect()
+}
+
+// Test VRF keys of the IdP.
+pub fn vrf_keypair() -> (PublicKey, KeyPair) {
+    let mut rng = StdRng::from_seed([2; 32]);
+    KeyPair::generate_keypair(&mut rng)
+}
+
 // Test committee.
 pub fn committee(base_port: u16) -> Committee {
     Committee {
         idp: Idp {
             name: keys().pop().unwrap().0,
+            vrf_public_key: vrf_keypair().0,
             address: format!("127.0.0.1:{}", base_port).parse().unwrap(),
         },
         witnesses: keys()
             .into_iter()
+            .zip(bls_keys().into_iter())
             .enumerate()
-            .map(|(i, (name, _))| {
+            .map(|(i, ((name, _), (bls_public_key, bls_secret)))| {
                 (
                     name,
                     Witness {
@@ -46,10 +73,24 @@ pub fn committee(base_port: u16) -> Committee {
                         address: format!("127.0.0.1:{}", base_port + 1 + i as u16)
                             .parse()
                             .unwrap(),
+                        gossip_address: format!("127.0.0.1:{}", base_port + 51 + i as u16)
+                            .parse()
+                            .unwrap(),
+                        bls_public_key,
+                        bls_proof_of_possession: bls_secret.proof_of_possession(),
                     },
                 )
             })
             .collect(),
+        signature_scheme: SignatureScheme::Ed25519,
+    }
+}
+
+// Test committee aggregating votes into compact certificates.
+pub fn bls_committee(base_port: u16) -> Committee {
+    Committee {
+        signature_scheme: SignatureScheme::Bls,
+        ..committee(base_port)
     }
 }
 
@@ -64,11 +105,29 @@ pub fn updates() -> Vec<UpdateRequest> {
         .collect()
 }
 
+// Test keypair of the owner of the test labels.
+pub fn client_keypair() -> KeyPair {
+    let mut rng = StdRng::from_seed([1; 32]);
+    KeyPair::generate_keypair(&mut rng).1
+}
+
+// Test update requests signed by the owner of the labels.
+pub fn signed_updates() -> Vec<SignedUpdateRequest> {
+    let keypair = client_keypair();
+    updates()
+        .into_iter()
+        .map(|update| SignedUpdateRequest::new(update, /* nonce */ 1, &keypair))
+        .collect()
+}
+
 // Serialized test update requests.
 pub fn serialized_updates() -> Vec<Bytes> {
-    updates()
-        .iter()
-        .map(|update| Bytes::from(bincode::serialize(&update).unwrap()))
+    signed_updates()
+        .into_iter()
+        .map(|request| {
+            let message = ClientToIdPMessage::Update(request);
+            Bytes::from(bincode::serialize(&message).unwrap())
+        })
         .collect()
 }
 
@@ -79,7 +138,7 @@ pub async fn proof() -> (Root, Root, Proof) {
 
     // Create a test tree with dumb key-values.
     let db = AsyncInMemoryDatabase::new();
-    let vrf = HardCodedAkdVRF {};
+    let vrf = IdpVrf::new(&vrf_keypair().1);
     let akd = Directory::new::<Blake3>(&db, &vrf, false).await.unwrap();
 
     // Compute the start root (at sequence 0) and end root (at sequence 1).
@@ -116,6 +175,71 @@ pub async fn notification() -> PublishNotification {
     )
 }
 
+// Test publish notification pipelined after `notification()` (building on its root).
+pub async fn pipelined_notification() -> PublishNotification {
+    chained_notification(/* sequence_number */ 2).await
+}
+
+// Test publish notification of the specified sequence number in the chain starting with
+// `notification()` (each later notification updates a single label).
+pub async fn chained_notification(sequence_number: SequenceNumber) -> PublishNotification {
+    // Make a proof of update on top of the state of the previous notification.
+    let db = AsyncInMemoryDatabase::new();
+    let vrf = IdpVrf::new(&vrf_keypair().1);
+    let akd = Directory::new::<Blake3>(&db, &vrf, false).await.unwrap();
+    akd.publish::<Blake3>(updates()).await.unwrap();
+    for i in 2..=sequence_number {
+        let value = AkdValue(vec![3, 4, i as u8 + 4]);
+        akd.publish::<Blake3>(vec![(AkdLabel(vec![1, 2, 3]), value)])
+            .await
+            .unwrap();
+    }
+    let current_azks = akd.retrieve_current_azks().await.unwrap();
+    let root = akd
+        .get_root_hash_at_epoch::<Blake3>(&current_azks, sequence_number)
+        .await
+        .unwrap();
+
+    // Generate the audit proof.
+    let proof = akd
+        .audit::<Blake3>(sequence_number - 1, sequence_number)
+        .await
+        .unwrap();
+
+    // Make the notification.
+    let (_, identity_provider) = keys().pop().unwrap();
+    PublishNotification::new(root, proof, sequence_number, &identity_provider)
+}
+
+// Test publish notification conflicting with `notification()` (same sequence number but
+// different root).
+pub async fn forked_notification() -> PublishNotification {
+    // Make a conflicting proof of update.
+    let db = AsyncInMemoryDatabase::new();
+    let vrf = IdpVrf::new(&vrf_keypair().1);
+    let akd = Directory::new::<Blake3>(&db, &vrf, false).await.unwrap();
+    akd.publish::<Blake3>(vec![(AkdLabel(vec![1, 2, 3]), AkdValue(vec![3, 4, 6]))])
+        .await
+        .unwrap();
+    let current_azks = akd.retrieve_current_azks().await.unwrap();
+    let root = akd
+        .get_root_hash_at_epoch::<Blake3>(&current_azks, /* sequence number */ 1)
+        .await
+        .unwrap();
+
+    // Generate the audit proof.
+    let proof = akd.audit::<Blake3>(0, 1).await.unwrap();
+
+    // Make the conflicting notification.
+    let (_, identity_provider) = keys().pop().unwrap();
+    PublishNotification::new(
+        root,
+        proof,
+        /* sequence number */ 1,
+        /* keypair */ &identity_provider,
+    )
+}
+
 // The witnesses' votes over a test notification.
 pub async fn votes() -> Vec<PublishVote> {
     let notification = notification().await;
@@ -129,6 +253,39 @@ pub async fn votes() -> Vec<PublishVote> {
 pub async fn certificate() -> PublishCertificate {
     let notification = notification().await;
     PublishCertificate {
+        root: notification.root,
+        sequence_number: notification.sequence_number,
+        committee_change: None,
+        votes: Votes::Individual(
+            votes()
+                .await
+                .into_iter()
+                .map(|x| (x.author, x.signature))
+                .collect(),
+        ),
+    }
+}
+
+// A certificate over a notification (signed by all the test witnesses).
+pub fn certify(notification: &PublishNotification) -> PublishCertificate {
+    PublishCertificate {
+        root: notification.root,
+        sequence_number: notification.sequence_number,
+        committee_change: None,
+        votes: Votes::Individual(
+            keys()
+                .iter()
+                .map(|(_, keypair)| PublishVote::new(notification, keypair))
+                .map(|x| (x.author, x.signature))
+                .collect(),
+        ),
+    }
+}
+
+// A test certificate in the layout of the legacy databases.
+pub async fn legacy_certificate() -> LegacyPublishCertificate {
+    let notification = notification().await;
+    LegacyPublishCertificate {
         root: notification.root,
         sequence_number: notification.sequence_number,
         votes: votes()
@@ -139,94 +296,316 @@ pub async fn certificate() -> PublishCertificate {
     }
 }
 
+// Test votes for `notification()` carrying aggregatable signatures.
+pub async fn bls_votes() -> Vec<PublishVote> {
+    let notification = notification().await;
+    keys()
+        .iter()
+        .zip(bls_keys().iter())
+        .map(|((_, keypair), (_, bls_keypair))| {
+            PublishVote::with_bls_signature(&notification, keypair, Some(bls_keypair))
+        })
+        .collect()
+}
+
+// A test certificate holding a single aggregate signature (valid under `bls_committee()`).
+pub async fn aggregate_certificate() -> PublishCertificate {
+    let notification = notification().await;
+    let committee = bls_committee(0);
+    let votes = bls_votes().await;
+    let mut signers = SignerBitmap::default();
+    for vote in &votes {
+        signers.insert(committee.witness_index(&vote.author).unwrap());
+    }
+    let signatures: Vec<_> = votes.into_iter().filter_map(|x| x.bls_signature).collect();
+    PublishCertificate {
+        root: notification.root,
+        sequence_number: notification.sequence_number,
+        committee_change: None,
+        votes: Votes::Aggregate(signers, BlsSignature::aggregate(&signatures).unwrap()),
+    }
+}
+
+// A rogue BLS key such that the aggregate of `targets` and the rogue key is `attacker`. Without
+// proofs of possession, the attacker alone could forge aggregate signatures of all of them.
+pub fn rogue_bls_key(attacker: &BlsPublicKey, targets: &[BlsPublicKey]) -> BlsPublicKey {
+    use blst::{
+        blst_p1, blst_p1_add_or_double, blst_p1_affine, blst_p1_cneg, blst_p1_compress,
+        blst_p1_from_affine, blst_p1_uncompress,
+    };
+    let point = |key: &BlsPublicKey| {
+        let (mut affine, mut point) = (blst_p1_affine::default(), blst_p1::default());
+        unsafe {
+            blst_p1_uncompress(&mut affine, key.0.as_ptr());
+            blst_p1_from_affine(&mut point, &affine);
+        }
+        point
+    };
+    let mut rogue = point(attacker);
+    for target in targets {
+        let mut negated = point(target);
+        unsafe {
+            blst_p1_cneg(&mut negated, true);
+            let sum = rogue;
+            blst_p1_add_or_double(&mut rogue, &sum, &negated);
+        }
+    }
+    let mut bytes = [0u8; BLS_PUBLIC_KEY_LENGTH];
+    unsafe { blst_p1_compress(bytes.as_mut_ptr(), &rogue) };
+    BlsPublicKey(bytes)
+}
+
+// Test committee change handing over to the first witness only (right after `notification()`).
+pub fn committee_change(base_port: u16) -> CommitteeChange {
+    let mut committee = committee(base_port);
+    let (name, _) = keys().swap_remove(0);
+    committee.witnesses.retain(|x, _| *x == name);
+    CommitteeChange::new(committee, /* activation */ 2)
+}
+
+// A certificate over `notification()` that also certifies `committee_change()`.
+pub async fn committee_change_certificate(base_port: u16) -> PublishCertificate {
+    let (_, identity_provider) = keys().pop().unwrap();
+    let (_, root, proof) = proof().await;
+    let committee_change = committee_change(base_port);
+    let notification = PublishNotification::with_committee_change(
+        root,
+        proof,
+        /* sequence_number */ 1,
+        Some(committee_change.clone()),
+        /* keypair */ &identity_provider,
+    );
+    PublishCertificate {
+        root: notification.root,
+        sequence_number: notification.sequence_number,
+        committee_change: Some(committee_change),
+        votes: Votes::Individual(
+            keys()
+                .iter()
+                .map(|(_, keypair)| PublishVote::new(&notification, keypair))
+                .map(|x| (x.author, x.signature))
+                .collect(),
+        ),
+    }
+}
+
 // Spawn test witnesses.
 pub fn spawn_test_witnesses(test_id: &str, committee: &Committee) {
-    delete_storage(test_id);
-    for (i, (_, keypair)) in keys().into_iter().enumerate() {
-        let secure_storage_path = format!(".test_secure_storage_{}_{}", test_id, i);
-        let secure_storage = Storage::new(&secure_storage_path).unwrap();
-
-        let audit_storage_path = format!(".test_audit_storage_{}_{}", test_id, i);
-        let audit_storage = Storage::new(&audit_storage_path).unwrap();
+    spawn_test_witnesses_with_transport(test_id, committee, Arc::new(TcpTransport));
+}
 
-        spawn_witness(keypair, committee.clone(), secure_storage, audit_storage);
+// Spawn test witnesses communicating over a specific transport.
+pub fn spawn_test_witnesses_with_transport(
+    test_id: &str,
+    committee: &Committee,
+    transport: Arc<dyn Transport>,
+) {
+    for i in 0..keys().len() {
+        spawn_test_witness_with_transport(test_id, committee, i, transport.clone());
     }
 }
 
+// Spawn a single test witness (with a fresh storage).
+pub fn spawn_test_witness(test_id: &str, committee: &Committee, index: usize) {
+    spawn_test_witness_with_transport(test_id, committee, index, Arc::new(TcpTransport));
+}
+
+// Spawn a single test witness (with a fresh storage) communicating over a specific transport.
+pub fn spawn_test_witness_with_transport(
+    test_id: &str,
+    committee: &Committee,
+    index: usize,
+    transport: Arc<dyn Transport>,
+) {
+    spawn_test_joining_witness(test_id, committee, None, index, transport);
+}
+
+// Spawn a single test witness (with a fresh storage) joining the next committee (if any).
+pub fn spawn_test_joining_witness(
+    test_id: &str,
+    committee: &Committee,
+    next_committee: Option<Committee>,
+    index: usize,
+    transport: Arc<dyn Transport>,
+) {
+    let (_, keypair) = keys().swap_remove(index);
+    let (_, bls_keypair) = bls_keys().swap_remove(index);
+
+    let storage_path = format!(".test_storage_{}_{}", test_id, index);
+    let _ = std::fs::remove_dir_all(&storage_path);
+    let storage = Storage::new(&storage_path).unwrap();
+
+    spawn_witness_with_transport(
+        keypair,
+        bls_keypair,
+        committee.clone(),
+        next_committee,
+        storage,
+        PIPELINE_DEPTH,
+        DEFAULT_CHECKPOINT_INTERVAL,
+        transport,
+    );
+}
+
+// Spawn a single test full witness (with a fresh storage holding its replica).
+pub fn spawn_test_full_witness(test_id: &str, committee: &Committee, index: usize) {
+    spawn_test_full_witness_with_transport(test_id, committee, index, Arc::new(TcpTransport));
+}
+
+// Spawn a single test full witness (with a fresh storage holding its replica) communicating over
+// a specific transport.
+pub fn spawn_test_full_witness_with_transport(
+    test_id: &str,
+    committee: &Committee,
+    index: usize,
+    transport: Arc<dyn Transport>,
+) {
+    let (_, keypair) = keys().swap_remove(index);
+    let (_, bls_keypair) = bls_keys().swap_remove(index);
+
+    let storage_path = format!(".test_storage_{}_{}", test_id, index);
+    let _ = std::fs::remove_dir_all(&storage_path);
+    let storage = Storage::new(&storage_path).unwrap();
+
+    spawn_full_witness_with_transport(
+        keypair,
+        bls_keypair,
+        committee.clone(),
+        /* next_committee */ None,
+        storage,
+        PIPELINE_DEPTH,
+        DEFAULT_CHECKPOINT_INTERVAL,
+        transport,
+    );
+}
+
 // Spawn test idp.
 pub fn spawn_test_idp(test_id: &str, committee: Committee) {
-    delete_storage(test_id);
-    let (_, keypair) = keys().pop().unwrap();
+    spawn_test_idp_with_transport(test_id, committee, Arc::new(TcpTransport));
+}
 
-    let secure_storage_path = format!(".test_idp_secure_storage_{}", test_id);
-    let secure_storage = Storage::new(&secure_storage_path).unwrap();
+// Spawn test idp communicating over a specific transport.
+pub fn spawn_test_idp_with_transport(
+    test_id: &str,
+    committee: Committee,
+    transport: Arc<dyn Transport>,
+) {
+    spawn_test_reconfiguring_idp(
+        test_id, committee, /* next_committee */ None, transport,
+    );
+}
+
+// Spawn test idp handing over to the next committee (if any) with its first notification.
+pub fn spawn_test_reconfiguring_idp(
+    test_id: &str,
+    committee: Committee,
+    next_committee: Option<Committee>,
+    transport: Arc<dyn Transport>,
+) {
+    delete_idp_storage(test_id);
+    let (_, keypair) = keys().pop().unwrap();
+    let (_, vrf_keypair) = vrf_keypair();
 
-    let sync_storage_path = format!(".test_sync_storage_{}", test_id);
-    let sync_storage = Storage::new(&sync_storage_path).unwrap();
+    let storage_path = format!(".test_idp_storage_{}", test_id);
+    let storage = Storage::new(&storage_path).unwrap();
 
     let batch_size = serialized_updates().len();
     let max_batch_delay = 200;
 
     tokio::spawn(async move {
-        spawn_idp(
+        spawn_idp_with_transport(
             keypair,
+            vrf_keypair,
             committee.clone(),
-            secure_storage,
-            sync_storage,
+            next_committee,
+            storage,
             /* akd_storage */ AsyncInMemoryDatabase::new(),
             batch_size,
             max_batch_delay,
+            PIPELINE_DEPTH,
+            /* ship_batches */ true,
+            transport,
         )
         .await;
     });
 }
 
+// Make a (fresh) legacy database holding the specified records.
+pub fn legacy_database(path: &str, records: Vec<(Vec<u8>, Vec<u8>)>) {
+    let _ = std::fs::remove_dir_all(path);
+    let db = DB::open_default(path).unwrap();
+    for (key, value) in records {
+        db.put(key, value).unwrap();
+    }
+}
+
 // Helper function deleting a test storage.
 pub fn delete_storage(test_id: &str) {
+    delete_witnesses_storage(test_id);
+    delete_idp_storage(test_id);
+}
+
+// Helper function deleting the test storage of the witnesses.
+pub fn delete_witnesses_storage(test_id: &str) {
     for i in 0..keys().len() {
-        let secure_storage_path = format!(".test_secure_storage_{}_{}", test_id, i);
-        let _ = std::fs::remove_dir_all(&secure_storage_path);
-        let audit_storage_path = format!(".test_audit_storage_{}_{}", test_id, i);
-        let _ = std::fs::remove_dir_all(&audit_storage_path);
+        let storage_path = format!(".test_storage_{}_{}", test_id, i);
+        let _ = std::fs::remove_dir_all(&storage_path);
     }
-    let idp_secure_storage_path = format!(".test_idp_secure_storage_{}", test_id);
-    let _ = std::fs::remove_dir_all(&idp_secure_storage_path);
-    let sync_storage_path = format!(".test_sync_storage_{}", test_id);
-    let _ = std::fs::remove_dir_all(&sync_storage_path);
+}
+
+// Helper function deleting the test storage of the IdP.
+pub fn delete_idp_storage(test_id: &str) {
+    let storage_path = format!(".test_idp_storage_{}", test_id);
+    let _ = std::fs::remove_dir_all(&storage_path);
+}
+
+// A simulated network connecting the nodes of a test within the process (no TCP port is bound).
+pub fn memory_transport() -> Arc<dyn Transport> {
+    Arc::new(MemoryNetwork::default())
+}
+
+// Test network sender authenticated as the IdP.
+pub fn idp_sender() -> ReliableSender {
+    idp_sender_with_transport(Arc::new(TcpTransport))
+}
+
+// Test network sender authenticated as the IdP communicating over a specific transport.
+pub fn idp_sender_with_transport(transport: Arc<dyn Transport>) -> ReliableSender {
+    let (_, keypair) = keys().pop().unwrap();
+    ReliableSender::with_transport(keypair, BufferLimits::default(), transport)
+}
+
+// Test network sender authenticated as the client communicating over a specific transport.
+pub fn client_sender(transport: Arc<dyn Transport>) -> ReliableSender {
+    ReliableSender::with_transport(client_keypair(), BufferLimits::default(), transport)
 }
 
 // Broadcast a publish notification to the witnesses.
 pub async fn broadcast_notification(
     notification: PublishNotification,
     committee: &Committee,
+    transport: Arc<dyn Transport>,
 ) -> Vec<CancelHandler> {
-    let addresses = committee
-        .witnesses_addresses()
-        .into_iter()
-        .map(|(_, address)| address)
-        .collect();
+    let peers = committee.witnesses_addresses();
     let message = IdPToWitnessMessage::PublishNotification(notification);
     let serialized = bincode::serialize(&message).unwrap();
     let bytes = Bytes::from(serialized);
-    let mut sender = ReliableSender::new();
-    sender.broadcast(addresses, bytes).await
+    let mut sender = idp_sender_with_transport(transport);
+    sender.broadcast(peers, bytes).await
 }
 
 // Broadcast a publish certificate to the witnesses.
 pub async fn broadcast_certificate(
     certificate: PublishCertificate,
     committee: &Committee,
+    transport: Arc<dyn Transport>,
 ) -> Vec<CancelHandler> {
-    let addresses = committee
-        .witnesses_addresses()
-        .into_iter()
-        .map(|(_, address)| address)
-        .collect();
+    let peers = committee.witnesses_addresses();
     let message = IdPToWitnessMessage::PublishCertificate(certificate);
     let serialized = bincode::serialize(&message).unwrap();
     let bytes = Bytes::from(serialized);
-    let mut sender = ReliableSender::new();
-    sender.broadcast(addresses, bytes).await
+    let mut sender = idp_sender_with_transport(transport);
+    sender.broadcast(peers, bytes).await
 }
 
 // A test network listener emulating a witness. It replies to a publish notification
@@ -234,37 +613,80 @@ pub async fn broadcast_certificate(
 pub fn listener(
     address: SocketAddr,
     keypair: KeyPair,
+    transport: Arc<dyn Transport>,
 ) -> JoinHandle<(PublishNotification, PublishCertificate)> {
     tokio::spawn(async move {
-        let listener = TcpListener::bind(&address).await.unwrap();
-        let (socket, _) = listener.accept().await.unwrap();
-        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let mut listener = transport
+            .listen(address, Arc::new(keypair.copy()))
+            .await
+            .unwrap();
+        let (_, incoming) = listener.accept().await.unwrap();
+        let (mut writer, mut reader, _) = incoming.await.unwrap();
 
         // Wait for a publish notification and reply with a vote.
-        let notification = match transport.next().await {
-            Some(Ok(bytes)) => match bincode::deserialize(&bytes).unwrap() {
-                IdPToWitnessMessage::PublishNotification(n) => {
-                    let vote = PublishVote::new(&n, &keypair);
-                    let message = WitnessToIdPMessage::PublishVote(Ok(vote));
-                    let serialized = bincode::serialize(&message).unwrap();
-                    transport.send(Bytes::from(serialized)).await.unwrap();
-                    n
-                }
-                _ => panic!("Unexpected protocol message"),
-            },
+        let request = match reader.next().await {
+            Some(Ok(frame)) => Envelope::decode(frame).unwrap(),
             _ => panic!("Failed to receive network message"),
         };
+        let notification = match bincode::deserialize(&request.payload).unwrap() {
+            IdPToWitnessMessage::PublishNotification(n) => {
+                let vote = PublishVote::new(&n, &keypair);
+                let message = WitnessToIdPMessage::PublishVote(Ok(vote));
+                let serialized = bincode::serialize(&message).unwrap();
+                let reply = request.reply(Bytes::from(serialized));
+                writer.send(reply.encode()).await.unwrap();
+                n
+            }
+            _ => panic!("Unexpected protocol message"),
+        };
 
         // Wait for a publish certificate.
-        let certificate = match transport.next().await {
-            Some(Ok(bytes)) => match bincode::deserialize(&bytes).unwrap() {
-                IdPToWitnessMessage::PublishCertificate(c) => c,
-                _ => panic!("Unexpected protocol message"),
-            },
+        let request = match reader.next().await {
+            Some(Ok(frame)) => Envelope::decode(frame).unwrap(),
             _ => panic!("Failed to receive network message"),
         };
+        let certificate = match bincode::deserialize(&request.payload).unwrap() {
+            IdPToWitnessMessage::PublishCertificate(c) => c,
+            _ => panic!("Unexpected protocol message"),
+        };
 
         // Output both the notification and certificate.
         (notification, certificate)
     })
 }
+
+// A test network listener emulating the gossip endpoint of a witness. It acknowledges all
+// messages and outputs the first equivocation report it receives.
+pub fn gossip_listener(
+    address: SocketAddr,
+    keypair: KeyPair,
+    transport: Arc<dyn Transport>,
+) -> JoinHandle<EquivocationReport> {
+    tokio::spawn(async move {
+        let mut listener = transport.listen(address, Arc::new(keypair)).await.unwrap();
+        let (tx_report, mut rx_report) = channel(100);
+        tokio::spawn(async move {
+            loop {
+                let (_, incoming) = listener.accept().await.unwrap();
+                let tx_report = tx_report.clone();
+                tokio::spawn(async move {
+                    let (mut writer, mut reader, _) = match incoming.await {
+                        Ok(value) => value,
+                        Err(_) => return,
+                    };
+                    while let Some(Ok(frame)) = reader.next().await {
+                        let request = Envelope::decode(frame).unwrap();
+                        let reply = request.reply(Bytes::from("Ack"));
+                        let _ = writer.send(reply.encode()).await;
+                        if let WitnessToWitnessMessage::EquivocationReport(report) =
+                            bincode::deserialize(&request.payload).unwrap()
+                        {
+                            let _ = tx_report.send(report).await;
+                        }
+                    }
+                });
+            }
+        });
+        rx_report.recv().await.unwrap()
+    })
+}
@@ -0,0 +1,256 @@
+use bytes::Bytes;
+use crypto::{KeyPair, PublicKey};
+use log::{debug, warn};
+use messages::{
+    equivocation::EquivocationProof,
+    gossip::{EquivocationReport, Gossip, WitnessToWitnessMessage},
+    publish::{PublishCertificate, PublishMessage, PublishNotification},
+    reconfiguration::CommitteeHistory,
+    sync::State,
+    SequenceNumber,
+};
+use network::{
+    reliable_sender::{BufferLimits, CancelHandler, ReliableSender},
+    transport::Transport,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+use tokio::{
+    sync::mpsc::{Receiver, Sender},
+    time::{interval, Duration},
+};
+
+/// The delay between two rounds of gossip (in ms).
+const GOSSIP_INTERVAL: u64 = 1_000;
+
+/// The number of sequence numbers for which to remember notifications and certificates.
+const GOSSIP_WINDOW: u64 = 100;
+
+/// Messages observed by the publish handler and shared with the other witnesses.
+#[derive(Debug)]
+pub enum Observation {
+    /// The witness voted for a notification.
+    Vote(State, PublishNotification),
+    /// The witness committed a certificate.
+    Commit(State, PublishCertificate),
+}
+
+/// Exchanges the latest state of the witness with the other witnesses to detect whether the IdP
+/// showed different roots to different witnesses.
+pub struct Gossiper {
+    /// The public key of this witness.
+    name: PublicKey,
+    /// The committees in charge of certifying each range of sequence numbers.
+    committees: CommitteeHistory,
+    /// Receive notifications and certificates processed by the publish handler.
+    rx_observation: Receiver<Observation>,
+    /// Receive gossip from the other witnesses.
+    rx_gossip: Receiver<Gossip>,
+    /// Receive equivocation reports from the other witnesses.
+    rx_report: Receiver<EquivocationReport>,
+    /// Outputs evidence that the IdP equivocated (to persist it).
+    tx_equivocation: Sender<EquivocationProof>,
+    /// The latest state of the witness.
+    state: State,
+    /// The notifications voted by the witness (indexed by sequence number).
+    notifications: BTreeMap<SequenceNumber, PublishNotification>,
+    /// The certificates committed by the witness (indexed by sequence number).
+    certificates: BTreeMap<SequenceNumber, PublishCertificate>,
+    /// The sequence numbers for which we already hold an equivocation report.
+    reported: HashSet<SequenceNumber>,
+    /// A reliable network sender.
+    network: ReliableSender,
+    /// The handlers of the last gossip sent to each witness (dropping them cancels the messages).
+    pending: HashMap<PublicKey, CancelHandler>,
+}
+
+impl Gossiper {
+    /// Spawn a new gossiper task.
+    pub fn spawn(
+        keypair: KeyPair,
+        committees: CommitteeHistory,
+        rx_observation: Receiver<Observation>,
+        rx_gossip: Receiver<Gossip>,
+        rx_report: Receiver<EquivocationReport>,
+        tx_equivocation: Sender<EquivocationProof>,
+        transport: Arc<dyn Transport>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                name: keypair.public(),
+                committees,
+                rx_observation,
+                rx_gossip,
+                rx_report,
+                tx_equivocation,
+                state: State::default(),
+                notifications: BTreeMap::new(),
+                certificates: BTreeMap::new(),
+                reported: HashSet::new(),
+                network: ReliableSender::with_transport(
+                    keypair,
+                    BufferLimits::default(),
+                    transport,
+                ),
+                pending: HashMap::new(),
+            }
+            .run()
+            .await
+        });
+    }
+
+    /// Remember a message observed by the publish handler.
+    fn observe(&mut self, observation: Observation) {
+        match observation {
+            Observation::Vote(state, notification) => {
+                self.state = state;
+                self.notifications
+                    .insert(notification.sequence_number(), notification);
+            }
+            Observation::Commit(state, certificate) => {
+                self.state = state;
+                self.committees.update(&certificate);
+                self.certificates
+                    .insert(certificate.sequence_number(), certificate);
+            }
+        }
+
+        // Only remember the most recent messages.
+        let horizon = self.state.sequence_number.saturating_sub(GOSSIP_WINDOW);
+        self.notifications = self.notifications.split_off(&horizon);
+        self.certificates = self.certificates.split_off(&horizon);
+    }
+
+    /// Compare the gossip of another witness with our own view and return evidence of
+    /// equivocation (if any).
+    fn compare(&self, gossip: &Gossip) -> Vec<EquivocationReport> {
+        let mut reports = Vec::new();
+
+        if let Some(theirs) = &gossip.notification {
+            if let Some(ours) = self
+                .notifications
+                .get(&theirs.sequence_number())
+                .filter(|ours| ours.root() != theirs.root())
+            {
+                let proof = EquivocationProof::new(ours.clone(), theirs.clone());
+                reports.push(EquivocationReport::ConflictingNotifications(proof));
+            }
+        }
+
+        if let Some(theirs) = &gossip.certificate {
+            if let Some(ours) = self
+                .certificates
+                .get(&theirs.sequence_number())
+                .filter(|ours| ours.root() != theirs.root())
+            {
+                reports.push(EquivocationReport::ConflictingCertificates(
+                    ours.clone(),
+                    theirs.clone(),
+                ));
+            }
+        }
+
+        // Only keep the reports that are valid evidence of equivocation.
+        reports
+            .into_iter()
+            .filter(|report| {
+                let committee = self.committees.get(report.sequence_number());
+                report.verify(committee).is_ok()
+            })
+            .collect()
+    }
+
+    /// Raise an equivocation report and forward it to the other witnesses.
+    async fn raise(&mut self, report: EquivocationReport) {
+        if !self.reported.insert(report.sequence_number()) {
+            return;
+        }
+        warn!("The IdP equivocated: {:?}", report);
+
+        // Persist the evidence of equivocation.
+        if let EquivocationReport::ConflictingNotifications(proof) = &report {
+            self.tx_equivocation
+                .send(proof.clone())
+                .await
+                .expect("Failed to send equivocation proof to sync helper");
+        }
+
+        let peers = self
+            .committees
+            .current()
+            .others_gossip_addresses(&self.name);
+        let message = WitnessToWitnessMessage::EquivocationReport(report);
+        let serialized = bincode::serialize(&message).expect("Failed to serialize report");
+        let handles = self.network.broadcast(peers, Bytes::from(serialized)).await;
+
+        // Keep delivering the report even after we stop tracking the handlers.
+        for handle in handles {
+            tokio::spawn(async move {
+                let _ = handle.await;
+            });
+        }
+    }
+
+    /// Send our latest state to the other witnesses (only once our committee takes over).
+    async fn gossip(&mut self) {
+        if !self.committees.current().witnesses.contains_key(&self.name) {
+            return;
+        }
+
+        let gossip = Gossip {
+            author: self.name,
+            state: self.state.clone(),
+            certificate: self.certificates.values().next_back().cloned(),
+            notification: self.notifications.values().next_back().cloned(),
+        };
+        let message = WitnessToWitnessMessage::Gossip(gossip);
+        let serialized = bincode::serialize(&message).expect("Failed to serialize gossip");
+        let bytes = Bytes::from(serialized);
+
+        let peers = self
+            .committees
+            .current()
+            .others_gossip_addresses(&self.name);
+        for (name, address) in peers {
+            // Replacing the previous handler cancels the previous gossip (if not yet delivered).
+            let handle = self.network.send(name, address, bytes.clone()).await;
+            self.pending.insert(name, handle);
+        }
+    }
+
+    /// Main loop gossiping with the other witnesses.
+    async fn run(&mut self) {
+        let timer = interval(Duration::from_millis(GOSSIP_INTERVAL));
+        tokio::pin!(timer);
+
+        loop {
+            tokio::select! {
+                // Keep track of the latest messages processed by the publish handler.
+                Some(observation) = self.rx_observation.recv() => self.observe(observation),
+
+                // Compare the view of other witnesses with our own.
+                Some(gossip) = self.rx_gossip.recv() => {
+                    debug!("Received {:?}", gossip);
+                    for report in self.compare(&gossip) {
+                        self.raise(report).await;
+                    }
+                },
+
+                // Verify and propagate equivocation reports.
+                Some(report) = self.rx_report.recv() => {
+                    debug!("Received {:?}", report);
+                    let committee = self.committees.get(report.sequence_number());
+                    match report.verify(committee) {
+                        Ok(()) => self.raise(report).await,
+                        Err(e) => warn!("Invalid equivocation report: {}", e)
+                    }
+                },
+
+                // Periodically gossip our latest state.
+                _ = timer.tick() => self.gossip().await,
+            }
+        }
+    }
+}
@@ -1,21 +1,42 @@
+mod gossiper;
+mod migration;
 mod publish_handler;
+mod replica;
 mod sync_helper;
+mod synchronizer;
+mod verifier;
 
-use crate::{publish_handler::PublishHandler, sync_helper::SyncHelper};
+use crate::{
+    gossiper::Gossiper, publish_handler::PublishHandler, replica::Replica, sync_helper::SyncHelper,
+    synchronizer::Synchronizer, verifier::Verifier,
+};
 use async_trait::async_trait;
 use bytes::Bytes;
 use config::Committee;
-use crypto::KeyPair;
-use futures::sink::SinkExt;
-use log::info;
+use crypto::{BlsKeyPair, KeyPair, PublicKey};
+use log::{info, warn};
 use messages::{
-    error::MessageError,
+    equivocation::EquivocationProofQuery,
+    error::{MessageError, WitnessError},
+    gossip::{EquivocationReport, Gossip, WitnessToWitnessMessage},
+    lookup::LookupRequest,
     publish::{PublishCertificate, PublishNotification},
-    sync::PublishCertificateQuery,
-    IdPToWitnessMessage, SerializedPublishCertificateMessage, WitnessToIdPMessage,
+    reconfiguration::CommitteeHistory,
+    sync::PublishCertificateRangeQuery,
+    update::ReplicaBatch,
+    IdPToWitnessMessage, SequenceNumber, SerializedPublishCertificateMessage, WitnessToIdPMessage,
+};
+pub use migration::migrate_storage;
+use network::{
+    receiver::{MessageHandler, Ordering, Receiver as NetworkReceiver, Writer},
+    transport::{TcpTransport, Transport},
+};
+pub use publish_handler::DEFAULT_CHECKPOINT_INTERVAL;
+use std::{
+    collections::HashSet,
+    error::Error,
+    sync::{Arc, Mutex},
 };
-use network::receiver::{MessageHandler, Receiver as NetworkReceiver, Writer};
-use std::error::Error;
 use storage::Storage;
 use tokio::sync::{
     mpsc::{channel, Sender},
@@ -28,71 +49,342 @@ pub(crate) const DEFAULT_CHANNEL_SIZE: usize = 1_000;
 /// One-shot channel to reply to the IdP.
 pub(crate) type Replier = oneshot::Sender<WitnessToIdPMessage>;
 
+/// The witnesses allowed to connect to this witness: the members of the committees it knows about.
+/// The publish handler adds the committees it learns.
+#[derive(Clone)]
+pub(crate) struct Peers(Arc<Mutex<HashSet<PublicKey>>>);
+
+impl Peers {
+    /// Make the set of peers from the committees known by the witness (and the one it joins).
+    fn new(committees: &CommitteeHistory, next_committee: Option<&Committee>) -> Self {
+        let peers = Self(Arc::new(Mutex::new(HashSet::new())));
+        committees
+            .iter()
+            .chain(next_committee)
+            .for_each(|x| peers.extend(x));
+        peers
+    }
+
+    /// Allow the witnesses of a committee to connect.
+    pub(crate) fn extend(&self, committee: &Committee) {
+        self.0
+            .lock()
+            .unwrap()
+            .extend(committee.witnesses.keys().cloned());
+    }
+
+    /// Check whether a witness is allowed to connect.
+    fn contains(&self, name: &PublicKey) -> bool {
+        self.0.lock().unwrap().contains(name)
+    }
+}
+
 /// Spawn a new witness.
 pub fn spawn_witness(
     // The public and secret keypair of this witness.
     keypair: KeyPair,
-    // The committee information.
+    // The BLS keypair of this witness (to sign votes aggregated into compact certificates).
+    bls_keypair: BlsKeyPair,
+    // The genesis committee information (later committees are loaded from the storage).
+    committee: Committee,
+    // The committee the witness joins (if it is not in the genesis committee).
+    next_committee: Option<Committee>,
+    // The storage for safety-critical information, certificates, and evidence of equivocation.
+    storage: Storage,
+    // The maximum number of uncertified notifications to vote for (the IdP's pipeline depth).
+    pipeline_depth: usize,
+    // The number of sequence numbers between two checkpoints.
+    checkpoint_interval: SequenceNumber,
+) {
+    spawn_witness_with_transport(
+        keypair,
+        bls_keypair,
+        committee,
+        next_committee,
+        storage,
+        pipeline_depth,
+        checkpoint_interval,
+        Arc::new(TcpTransport),
+    );
+}
+
+/// Spawn a new witness talking to its peers over a specific transport (e.g., an in-memory network
+/// simulating a whole committee within a single process).
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_witness_with_transport(
+    // The public and secret keypair of this witness.
+    keypair: KeyPair,
+    // The BLS keypair of this witness (to sign votes aggregated into compact certificates).
+    bls_keypair: BlsKeyPair,
+    // The genesis committee information (later committees are loaded from the storage).
+    committee: Committee,
+    // The committee the witness joins (if it is not in the genesis committee).
+    next_committee: Option<Committee>,
+    // The storage for safety-critical information, certificates, and evidence of equivocation.
+    storage: Storage,
+    // The maximum number of uncertified notifications to vote for (the IdP's pipeline depth).
+    pipeline_depth: usize,
+    // The number of sequence numbers between two checkpoints.
+    checkpoint_interval: SequenceNumber,
+    // The transport carrying the messages of the witness.
+    transport: Arc<dyn Transport>,
+) {
+    spawn_witness_tasks(
+        keypair,
+        bls_keypair,
+        committee,
+        next_committee,
+        storage,
+        pipeline_depth,
+        checkpoint_interval,
+        /* tx_replica */ None,
+        /* tx_lookup */ None,
+        transport,
+    );
+}
+
+/// Spawn a new full witness. On top of the tasks of a witness, it replays the batches of updates
+/// certified by the committee into its own replica of the directory and serves lookups from it.
+/// The IdP must ship the batches of updates (along with the VRF proofs of their labels).
+pub fn spawn_full_witness(
+    // The public and secret keypair of this witness.
+    keypair: KeyPair,
+    // The BLS keypair of this witness (to sign votes aggregated into compact certificates).
+    bls_keypair: BlsKeyPair,
+    // The genesis committee information (later committees are loaded from the storage).
+    committee: Committee,
+    // The committee the witness joins (if it is not in the genesis committee).
+    next_committee: Option<Committee>,
+    // The storage for safety-critical information, certificates, evidence of equivocation, and
+    // the replica of the directory.
+    storage: Storage,
+    // The maximum number of uncertified notifications to vote for (the IdP's pipeline depth).
+    pipeline_depth: usize,
+    // The number of sequence numbers between two checkpoints.
+    checkpoint_interval: SequenceNumber,
+) {
+    spawn_full_witness_with_transport(
+        keypair,
+        bls_keypair,
+        committee,
+        next_committee,
+        storage,
+        pipeline_depth,
+        checkpoint_interval,
+        Arc::new(TcpTransport),
+    );
+}
+
+/// Spawn a new full witness talking to its peers (and the IdP) over a specific transport.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_full_witness_with_transport(
+    // The public and secret keypair of this witness.
+    keypair: KeyPair,
+    // The BLS keypair of this witness (to sign votes aggregated into compact certificates).
+    bls_keypair: BlsKeyPair,
+    // The genesis committee information (later committees are loaded from the storage).
+    committee: Committee,
+    // The committee the witness joins (if it is not in the genesis committee).
+    next_committee: Option<Committee>,
+    // The storage for safety-critical information, certificates, evidence of equivocation, and
+    // the replica of the directory.
+    storage: Storage,
+    // The maximum number of uncertified notifications to vote for (the IdP's pipeline depth).
+    pipeline_depth: usize,
+    // The number of sequence numbers between two checkpoints.
+    checkpoint_interval: SequenceNumber,
+    // The transport carrying the messages of the witness.
+    transport: Arc<dyn Transport>,
+) {
+    let (tx_replica, rx_replica) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_lookup, rx_lookup) = channel(DEFAULT_CHANNEL_SIZE);
+
+    // Spawn the replica. This task replays the certified batches of updates (pulling the ones it
+    // misses from the IdP) and serves lookups.
+    Replica::spawn(
+        keypair.copy(),
+        &committee,
+        storage.clone(),
+        rx_replica,
+        rx_lookup,
+        transport.clone(),
+    );
+
+    spawn_witness_tasks(
+        keypair,
+        bls_keypair,
+        committee,
+        next_committee,
+        storage,
+        pipeline_depth,
+        checkpoint_interval,
+        Some(tx_replica),
+        Some(tx_lookup),
+        transport,
+    );
+}
+
+/// Spawn the tasks common to all witnesses.
+#[allow(clippy::too_many_arguments)]
+fn spawn_witness_tasks(
+    keypair: KeyPair,
+    bls_keypair: BlsKeyPair,
     committee: Committee,
-    // The storage for safety-critical information.
-    secure_storage: Storage,
-    // The storage for certificates and other self-authenticated information.
-    audit_storage: Storage,
+    next_committee: Option<Committee>,
+    storage: Storage,
+    pipeline_depth: usize,
+    checkpoint_interval: SequenceNumber,
+    tx_replica: Option<Sender<(PublishCertificate, Option<ReplicaBatch>)>>,
+    tx_lookup: Option<Sender<(LookupRequest, Replier)>>,
+    transport: Arc<dyn Transport>,
 ) {
+    // Witnesses must vote for at least one notification at a time and checkpoint at a positive
+    // interval (otherwise they would never vote, or fail to decide when to checkpoint).
+    assert!(pipeline_depth > 0, "The pipeline depth must be positive");
+    assert!(
+        checkpoint_interval > 0,
+        "The checkpoint interval must be positive"
+    );
     let name = keypair.public();
 
+    // Load the committees the witness already knows about. A witness joining a later committee
+    // starts passively: it processes certificates (to learn the handover) but does not vote nor
+    // gossip until its committee takes over.
+    let committees = publish_handler::load_committees(&storage, committee);
+    let membership = committees
+        .latest_including(&name)
+        .or(next_committee.as_ref())
+        .expect("Our public key is not in the committee")
+        .clone();
+    let peers = Peers::new(&committees, next_committee.as_ref());
+
     let (tx_notification, rx_notification) = channel(DEFAULT_CHANNEL_SIZE);
     let (tx_certificate, rx_certificate) = channel(DEFAULT_CHANNEL_SIZE);
     let (tx_state_query, rx_state_query) = channel(DEFAULT_CHANNEL_SIZE);
     let (tx_certificate_request, rx_certificate_request) = channel(DEFAULT_CHANNEL_SIZE);
     let (tx_processed_certificate, rx_processed_certificate) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_observation, rx_observation) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_gossip, rx_gossip) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_report, rx_report) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_equivocation, rx_equivocation) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_equivocation_request, rx_equivocation_request) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_verification, rx_verification) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_verified, rx_verified) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_sync, rx_sync) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_adopt, rx_adopt) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_checkpoint, rx_checkpoint) = channel(DEFAULT_CHANNEL_SIZE);
+    let (tx_checkpoint_request, rx_checkpoint_request) = channel(DEFAULT_CHANNEL_SIZE);
+
+    // Spawn the verifier. This task checks the state-transition proofs of the notifications on a
+    // pool of workers.
+    Verifier::spawn(rx_verification, tx_verified);
 
     // Spawn the publish handler. This task handles all publish-related messages.
     PublishHandler::spawn(
-        keypair,
-        committee.clone(),
-        secure_storage,
+        keypair.copy(),
+        bls_keypair,
+        committees.clone(),
+        storage.clone(),
         rx_notification,
         rx_certificate,
         rx_state_query,
         tx_processed_certificate,
+        tx_observation,
+        tx_equivocation.clone(),
+        tx_verification,
+        rx_verified,
+        tx_replica,
+        tx_sync,
+        rx_adopt,
+        tx_checkpoint,
+        pipeline_depth,
+        checkpoint_interval,
+        peers.clone(),
+    );
+
+    // Spawn the synchronizer. This task pulls the certificates missed by the witness from the other
+    // witnesses (in case the IdP is unable to provide them).
+    Synchronizer::spawn(
+        keypair.copy(),
+        rx_sync,
+        tx_certificate.clone(),
+        tx_adopt,
+        transport.clone(),
     );
 
     // Spawn the sync helper. This task replies to sync request helping other witness to get up to speed.
+    // It also keeps the evidence of equivocation of the IdP.
     SyncHelper::spawn(
-        audit_storage,
+        storage,
         rx_processed_certificate,
         rx_certificate_request,
+        rx_equivocation,
+        rx_equivocation_request,
+        rx_checkpoint,
+        rx_checkpoint_request,
     );
 
     // Spawn a network receiver.
-    let mut address = committee
+    let mut address = membership
         .witness_address(&name)
         .expect("Our public key is not in the committee");
     address.set_ip("0.0.0.0".parse().unwrap());
     let handler = WitnessHandler {
+        idp: membership.idp.name,
+        peers: peers.clone(),
         tx_notification,
         tx_certificate,
         tx_state_query,
         tx_certificate_request,
+        tx_equivocation_request,
+        tx_checkpoint_request,
+        tx_lookup,
+    };
+    NetworkReceiver::spawn_with_transport(address, keypair.copy(), handler, transport.clone());
+
+    // Spawn the gossiper. This task exchanges the witness' view with the other witnesses to detect
+    // equivocations of the IdP.
+    Gossiper::spawn(
+        keypair.copy(),
+        committees,
+        rx_observation,
+        rx_gossip,
+        rx_report,
+        tx_equivocation,
+        transport.clone(),
+    );
+
+    // Spawn a network receiver for the gossip of the other witnesses.
+    let mut address = membership
+        .gossip_address(&name)
+        .expect("Our public key is not in the committee");
+    address.set_ip("0.0.0.0".parse().unwrap());
+    let handler = GossipHandler {
+        peers,
+        tx_gossip,
+        tx_report,
     };
-    NetworkReceiver::spawn(address, handler);
+    NetworkReceiver::spawn_with_transport(address, keypair, handler, transport);
 
     info!(
         "Witness {} successfully booted on {}",
         name,
-        committee
+        membership
             .witness_address(&name)
             .expect("Our public key is not in the committee")
             .ip()
     );
     #[cfg(features = "witness-only-benchmark")]
-    log::warn!("Witness booted in witness-benchmark mode (safety/consistency is not guaranteed)");
+    warn!("Witness booted in witness-benchmark mode (safety/consistency is not guaranteed)");
 }
 
 /// Defines how the network receiver handles incoming messages.
 #[derive(Clone)]
 struct WitnessHandler {
+    /// The identity of the IdP (the only peer allowed to publish new states).
+    idp: PublicKey,
+    /// The witnesses allowed to query this witness.
+    peers: Peers,
     tx_notification: Sender<(PublishNotification, Replier)>,
     tx_certificate: Sender<(
         SerializedPublishCertificateMessage,
@@ -100,16 +392,45 @@ struct WitnessHandler {
         Replier,
     )>,
     tx_state_query: Sender<Replier>,
-    tx_certificate_request: Sender<(PublishCertificateQuery, Replier)>,
+    tx_certificate_request: Sender<(PublishCertificateRangeQuery, Replier)>,
+    tx_equivocation_request: Sender<(EquivocationProofQuery, Replier)>,
+    tx_checkpoint_request: Sender<Replier>,
+    tx_lookup: Option<Sender<(LookupRequest, Replier)>>,
 }
 
 #[async_trait]
 impl MessageHandler for WitnessHandler {
-    async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        peer: &PublicKey,
+        serialized: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
         let (sender, receiver) = oneshot::channel();
 
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized).map_err(MessageError::from)? {
+        // Full witnesses serve the lookups of any client, but only the IdP and the witnesses of the
+        // known committees may send other messages.
+        let message = bincode::deserialize(&serialized).map_err(MessageError::from)?;
+        let member = peer == &self.idp || self.peers.contains(peer);
+        if !member && !matches!(message, IdPToWitnessMessage::Lookup(_)) {
+            return Err(Box::new(WitnessError::UnauthorizedPeer(*peer)));
+        }
+
+        // Parse the message. Only the IdP can publish notifications and certificates, other peers
+        // can only query the witness.
+        match message {
+            IdPToWitnessMessage::PublishNotification(_) if peer != &self.idp => {
+                let error = WitnessError::UnauthorizedSender(*peer);
+                sender
+                    .send(WitnessToIdPMessage::PublishVote(Err(error)))
+                    .expect("Failed to reply to publish notification");
+            }
+            IdPToWitnessMessage::PublishCertificate(_) if peer != &self.idp => {
+                let error = WitnessError::UnauthorizedSender(*peer);
+                sender
+                    .send(WitnessToIdPMessage::State(Err(error)))
+                    .expect("Failed to reply to publish certificate");
+            }
             IdPToWitnessMessage::PublishNotification(notification) => self
                 .tx_notification
                 .send((notification, sender))
@@ -125,17 +446,115 @@ impl MessageHandler for WitnessHandler {
                 .send(sender)
                 .await
                 .expect("Failed to send state query to publish handler"),
-            IdPToWitnessMessage::PublishCertificateQuery(query) => self
+            IdPToWitnessMessage::PublishCertificateRangeQuery(query) => self
                 .tx_certificate_request
                 .send((query, sender))
                 .await
-                .expect("Failed to certificate query query to sync helper"),
+                .expect("Failed to send certificate query to sync helper"),
+            IdPToWitnessMessage::EquivocationProofQuery(query) => self
+                .tx_equivocation_request
+                .send((query, sender))
+                .await
+                .expect("Failed to send equivocation proof query to sync helper"),
+            IdPToWitnessMessage::CheckpointQuery => self
+                .tx_checkpoint_request
+                .send(sender)
+                .await
+                .expect("Failed to send checkpoint query to sync helper"),
+            IdPToWitnessMessage::Lookup(request) => match &self.tx_lookup {
+                Some(tx_lookup) => tx_lookup
+                    .send((request, sender))
+                    .await
+                    .expect("Failed to send lookup request to replica"),
+                None => {
+                    let reply =
+                        WitnessToIdPMessage::LookupResponse(Err(WitnessError::NotFullWitness));
+                    sender
+                        .send(reply)
+                        .expect("Failed to reply to lookup request");
+                }
+            },
         }
 
-        // Reply to the IdP.
-        let reply = receiver.await.expect("Failed to receive message reply");
-        let bytes = bincode::serialize(&reply).expect("Failed to serialize reply");
-        writer.send(Bytes::from(bytes)).await?;
+        // Reply to the sender. The messages of the IdP are only ordered until they are handed over
+        // (so that the publish handler processes the pipelined notifications concurrently): their
+        // replies are sent by a separate task, which holds the writer (and thus the slot of the
+        // message in the task limit of the IdP) until the reply is sent. The other messages are
+        // already dispatched in their own (bounded) task.
+        let mut writer = writer.clone();
+        let reply = async move {
+            let reply = receiver.await.expect("Failed to receive message reply");
+            let bytes = bincode::serialize(&reply).expect("Failed to serialize reply");
+            writer.send(Bytes::from(bytes)).await
+        };
+        if peer == &self.idp {
+            tokio::spawn(async move {
+                if let Err(e) = reply.await {
+                    warn!("Failed to reply to the IdP: {}", e);
+                }
+            });
+        } else {
+            reply.await?;
+        }
         Ok(())
     }
+
+    fn ordering(&self, peer: &PublicKey, _message: &Bytes) -> Ordering {
+        // The publish handler must receive the notifications and certificates of the IdP in the
+        // order the IdP sent them. The queries of the other peers do not depend on one another.
+        if peer == &self.idp {
+            Ordering::Sequential
+        } else {
+            Ordering::Concurrent
+        }
+    }
+
+    fn authorize(&self, peer: &PublicKey) -> bool {
+        // Only full witnesses accept connections from clients (to serve their lookups).
+        peer == &self.idp || self.peers.contains(peer) || self.tx_lookup.is_some()
+    }
+}
+
+/// Defines how the network receiver handles gossip messages from other witnesses.
+#[derive(Clone)]
+struct GossipHandler {
+    /// The witnesses allowed to gossip with this witness.
+    peers: Peers,
+    tx_gossip: Sender<Gossip>,
+    tx_report: Sender<EquivocationReport>,
+}
+
+#[async_trait]
+impl MessageHandler for GossipHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        peer: &PublicKey,
+        serialized: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        // Reply with an ACK.
+        let _ = writer.send(Bytes::from("Ack")).await;
+
+        // Deserialize and parse the message. Witnesses only gossip their own view.
+        match bincode::deserialize(&serialized).map_err(MessageError::from)? {
+            WitnessToWitnessMessage::Gossip(gossip) if &gossip.author != peer => {
+                return Err(Box::new(WitnessError::UnauthorizedPeer(*peer)));
+            }
+            WitnessToWitnessMessage::Gossip(gossip) => self
+                .tx_gossip
+                .send(gossip)
+                .await
+                .expect("Failed to send gossip to gossiper"),
+            WitnessToWitnessMessage::EquivocationReport(report) => self
+                .tx_report
+                .send(report)
+                .await
+                .expect("Failed to send equivocation report to gossiper"),
+        }
+        Ok(())
+    }
+
+    fn authorize(&self, peer: &PublicKey) -> bool {
+        self.peers.contains(peer)
+    }
 }
@@ -1,8 +1,9 @@
 use anyhow::{Context, Result};
 use clap::{arg, crate_name, crate_version, Arg, ArgMatches, Command};
-use config::{Committee, Export, Import, PrivateConfig};
+use config::{Committee, Export, Import, WitnessPrivateConfig};
+use messages::DEFAULT_PIPELINE_DEPTH;
 use storage::Storage;
-use witness::spawn_witness;
+use witness::{migrate_storage, spawn_full_witness, spawn_witness, DEFAULT_CHECKPOINT_INTERVAL};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -18,10 +19,23 @@ async fn main() -> Result<()> {
         )
         .subcommand(Command::new("run").about("Run a witness").args(&[
             arg!(--committee <FILE> "The path to the committee file"),
+            arg!(--next_committee [FILE] "The path to the file of the committee the witness joins"),
             arg!(--keypair <FILE> "The path to the witness keypair"),
-            arg!(--secure_storage <FILE> "The directory to hold the secure storage"),
-            arg!(--audit_storage <FILE> "The directory to hold the audit storage"),
+            arg!(--storage <FILE> "The directory to hold the database of the witness"),
+            arg!(--pipeline_depth [INT] "The maximum number of uncertified notifications"),
+            arg!(--checkpoint_interval [INT] "The number of sequence numbers between checkpoints"),
+            arg!(--full "Replicate the directory and serve lookups (the IdP must ship batches)"),
         ]))
+        .subcommand(
+            Command::new("migrate")
+                .about("Move the legacy databases of a witness into a single database")
+                .args(&[
+                    arg!(--storage <FILE> "The directory to hold the database of the witness"),
+                    arg!(--secure_storage <FILE> "The directory of the legacy secure storage"),
+                    arg!(--audit_storage <FILE> "The directory of the legacy audit storage"),
+                    arg!(--akd_storage [FILE] "The directory of the legacy replica (if any)"),
+                ]),
+        )
         .arg_required_else_help(true)
         .get_matches();
 
@@ -41,12 +55,15 @@ async fn main() -> Result<()> {
 
     // Parse the input parameters.
     match matches.subcommand() {
-        Some(("generate", sub_matches)) => PrivateConfig::new()
+        Some(("generate", sub_matches)) => WitnessPrivateConfig::new()
             .export(sub_matches.value_of("filename").unwrap())
             .context("Failed to generate key pair")?,
         Some(("run", sub_matches)) => spawn(sub_matches)
             .await
             .context("Failed to spawn witness")?,
+        Some(("migrate", sub_matches)) => {
+            migrate(sub_matches).context("Failed to migrate storage")?
+        }
         _ => unreachable!(),
     }
     Ok(())
@@ -57,22 +74,86 @@ async fn spawn(matches: &ArgMatches) -> Result<()> {
     let committee_file = matches.value_of("committee").unwrap();
     let committee = Committee::import(committee_file).context("Failed to load committee")?;
 
+    // A witness that is not in the genesis committee joins the next committee.
+    let next_committee = match matches.value_of("next_committee") {
+        Some(file) => Some(Committee::import(file).context("Failed to load next committee")?),
+        None => None,
+    };
+
     let keypair_file = matches.value_of("keypair").unwrap();
-    let keypair = PrivateConfig::import(keypair_file).context("Failed to load keypair")?;
+    let private_config =
+        WitnessPrivateConfig::import(keypair_file).context("Failed to load keypair")?;
+
+    let storage_file = matches.value_of("storage").unwrap();
+    let storage = Storage::new(storage_file).context("Failed to create storage")?;
 
-    let secure_storage_file = matches.value_of("secure_storage").unwrap();
-    let secure_storage =
-        Storage::new(secure_storage_file).context("Failed to create secure storage")?;
+    // The pipeline depth must match the one of the IdP.
+    let pipeline_depth = match matches.value_of("pipeline_depth") {
+        Some(x) => x
+            .parse::<usize>()
+            .context("The pipeline depth must be a positive integer")?,
+        None => DEFAULT_PIPELINE_DEPTH,
+    };
+    anyhow::ensure!(
+        pipeline_depth > 0,
+        "The pipeline depth must be a positive integer"
+    );
 
-    let audit_storage_file = matches.value_of("audit_storage").unwrap();
-    let audit_storage =
-        Storage::new(audit_storage_file).context("Failed to create audit storage")?;
+    let checkpoint_interval = match matches.value_of("checkpoint_interval") {
+        Some(x) => x
+            .parse::<u64>()
+            .context("The checkpoint interval must be a positive integer")?,
+        None => DEFAULT_CHECKPOINT_INTERVAL,
+    };
+    anyhow::ensure!(
+        checkpoint_interval > 0,
+        "The checkpoint interval must be a positive integer"
+    );
 
-    // Spawn a witness.
-    spawn_witness(keypair.secret, committee, secure_storage, audit_storage);
+    // Spawn a full witness if requested (and a regular one otherwise).
+    if matches.is_present("full") {
+        spawn_full_witness(
+            /* keypair */ private_config.secret,
+            /* bls_keypair */ private_config.bls_secret,
+            committee,
+            next_committee,
+            storage,
+            pipeline_depth,
+            checkpoint_interval,
+        );
+    } else {
+        spawn_witness(
+            /* keypair */ private_config.secret,
+            /* bls_keypair */ private_config.bls_secret,
+            committee,
+            next_committee,
+            storage,
+            pipeline_depth,
+            checkpoint_interval,
+        );
+    }
 
     // TODO: better way to prevent the program from exiting....
     loop {
         tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
     }
 }
+
+/// Move the legacy databases of a witness into a single database (only once).
+fn migrate(matches: &ArgMatches) -> Result<()> {
+    let storage_file = matches.value_of("storage").unwrap();
+    let storage = Storage::new(storage_file).context("Failed to create storage")?;
+    anyhow::ensure!(
+        !storage.is_migrated()?,
+        "The legacy databases are already migrated"
+    );
+
+    migrate_storage(
+        &storage,
+        matches.value_of("secure_storage").unwrap(),
+        matches.value_of("audit_storage").unwrap(),
+        matches.value_of("akd_storage"),
+    )?;
+    storage.set_migrated()?;
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use crate::publish_handler::STORE_STATE_KEY;
+use log::info;
+use messages::{
+    legacy::{LegacyIdPToWitnessMessage, LegacyState},
+    publish::PublishCertificate,
+    sync::State,
+    IdPToWitnessMessage,
+};
+use std::convert::TryInto;
+use storage::{akd_storage::AkdStorage, Column, Key, Storage, StoreResult};
+
+/// Legacy address (in the secure storage) of the state. It is the only record of the secure
+/// storage: the records added since (e.g., the locks or the committees) are named keys of the
+/// state column.
+const LEGACY_STATE_ADDR: [u8; 32] = [255; 32];
+
+/// Move the records of the legacy layout of a witness (one database per directory) into the column
+/// families of a single database, translating them into their current format. Returns the number
+/// of migrated records.
+pub fn migrate_storage(
+    storage: &Storage,
+    secure_storage: &str,
+    audit_storage: &str,
+    akd_storage: Option<&str>,
+) -> StoreResult<usize> {
+    let mut migrated = 0;
+
+    // The secure storage holds the state of the witness at a fixed address. The lock of the
+    // legacy state is a vote: it is kept in the state (the witness does not hold the notification).
+    migrated += storage.migrate(secure_storage, |key, value| {
+        if key != LEGACY_STATE_ADDR {
+            return Ok(None);
+        }
+        let state: LegacyState = bincode::deserialize(value)?;
+        let serialized = bincode::serialize(&State::from(state))?;
+        Ok(Some((Column::State, STORE_STATE_KEY.encode(), serialized)))
+    })?;
+
+    // The audit storage indexes certificates by little-endian sequence numbers. They are persisted
+    // as serialized network messages.
+    migrated += storage.migrate(audit_storage, |key, value| {
+        let sequence_number = match key.try_into() {
+            Ok(bytes) => u64::from_le_bytes(bytes),
+            Err(_) => return Ok(None),
+        };
+        let certificate = match bincode::deserialize(value)? {
+            LegacyIdPToWitnessMessage::PublishCertificate(certificate) => certificate,
+            _ => return Ok(None),
+        };
+        let message =
+            IdPToWitnessMessage::PublishCertificate(PublishCertificate::from(certificate));
+        let serialized = bincode::serialize(&message)?;
+        Ok(Some((
+            Column::Certificates,
+            sequence_number.encode(),
+            serialized,
+        )))
+    })?;
+
+    // The keys of the records of the replica (if any) are unchanged, but value states are now
+    // indexed by label.
+    if let Some(akd_storage) = akd_storage {
+        migrated += storage.migrate(akd_storage, |key, value| {
+            let entries = AkdStorage::legacy_entries(key, value)?;
+            Ok(entries
+                .into_iter()
+                .map(|(key, value)| (Column::Akd, key, value)))
+        })?;
+    }
+
+    info!("Migrated {} records", migrated);
+    Ok(migrated)
+}
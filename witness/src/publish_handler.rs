@@ -1,26 +1,62 @@
-use crate::Replier;
-use config::Committee;
-use crypto::KeyPair;
+use crate::{
+    gossiper::Observation,
+    synchronizer::SyncRequest,
+    verifier::{VerificationOutcome, VerificationRequest},
+    Peers, Replier,
+};
+use config::{Committee, SignatureScheme};
+use crypto::{BlsKeyPair, KeyPair};
 use log::{debug, info, warn};
 use messages::{
+    checkpoint::Checkpoint,
     ensure,
+    equivocation::EquivocationProof,
     error::{WitnessError, WitnessResult},
     publish::{PublishCertificate, PublishMessage, PublishNotification, PublishVote},
+    reconfiguration::CommitteeHistory,
     sync::State,
-    SequenceNumber, SerializedPublishCertificateMessage, WitnessToIdPMessage,
+    update::ReplicaBatch,
+    IdPToWitnessMessage, Root, SequenceNumber, SerializedPublishCertificateMessage,
+    WitnessToIdPMessage,
 };
-use storage::Storage;
+use std::{collections::BTreeMap, iter};
+use storage::{Column, DecodeKey, Key, Storage};
 use tokio::sync::mpsc::{Receiver, Sender};
 
-/// Storage address of the state.
-pub const STORE_STATE_ADDR: [u8; 32] = [255; 32];
+/// Storage key (in the state column) of the state.
+pub(crate) const STORE_STATE_KEY: &str = "state";
+
+/// Prefix of the storage keys (in the state column) of the notifications on which the witness is
+/// locked. Each lock is stored under this prefix followed by its sequence number.
+const STORE_LOCK_KEY_PREFIX: &str = "lock/";
+
+/// Storage key (in the state column) of the committees in charge of each range of sequence numbers.
+const STORE_COMMITTEES_KEY: &str = "committees";
+
+/// The default number of sequence numbers between two checkpoints.
+pub const DEFAULT_CHECKPOINT_INTERVAL: SequenceNumber = 100;
+
+/// Return the storage key of the lock at the specified sequence number.
+fn lock_key(sequence_number: SequenceNumber) -> Vec<u8> {
+    [STORE_LOCK_KEY_PREFIX.encode(), sequence_number.encode()].concat()
+}
+
+/// Load from storage the committees known by the witness (starting with the genesis committee).
+pub fn load_committees(storage: &Storage, genesis: Committee) -> CommitteeHistory {
+    storage
+        .get(Column::State, STORE_COMMITTEES_KEY)
+        .expect("Failed to load committees from storage")
+        .unwrap_or_else(|| CommitteeHistory::new(genesis))
+}
 
 /// Core logic handing publish notifications and certificates.
 pub struct PublishHandler {
     /// The keypair of this authority.
     keypair: KeyPair,
-    /// The committee information.
-    committee: Committee,
+    /// The BLS keypair of this authority.
+    bls_keypair: BlsKeyPair,
+    /// The committees in charge of certifying each range of sequence numbers.
+    committees: CommitteeHistory,
     /// The persistent storage.
     storage: Storage,
     /// Receive publish notifications from the IdP.
@@ -35,15 +71,44 @@ pub struct PublishHandler {
     rx_state_query: Receiver<Replier>,
     /// Outputs processed (thus verified) publish certificates.
     tx_processed_certificate: Sender<(SerializedPublishCertificateMessage, SequenceNumber)>,
+    /// Outputs the notifications and certificates to gossip with the other witnesses.
+    tx_observation: Sender<Observation>,
+    /// Outputs evidence that the IdP equivocated.
+    tx_equivocation: Sender<EquivocationProof>,
+    /// Outputs notifications to verify.
+    tx_verification: Sender<VerificationRequest>,
+    /// Receive the result of the verification of notifications.
+    rx_verified: Receiver<VerificationOutcome>,
+    /// The roots of the notifications being verified (indexed by sequence number).
+    in_flight: BTreeMap<SequenceNumber, Root>,
+    /// Outputs the committed certificates (and the batch they certify) to the replica of the
+    /// directory (only for full witnesses).
+    tx_replica: Option<Sender<(PublishCertificate, Option<ReplicaBatch>)>>,
+    /// Outputs signals that the witness missed certificates (to pull them from other witnesses).
+    tx_sync: Sender<SyncRequest>,
+    /// Receive checkpoints pulled from the other witnesses.
+    rx_checkpoint: Receiver<(Checkpoint, Replier)>,
+    /// Outputs the checkpoints of the witness (to persist them and prune older certificates).
+    tx_checkpoint: Sender<Checkpoint>,
     /// The state of the witness.
     state: State,
+    /// The notifications on which the witness is locked (indexed by sequence number).
+    locks: BTreeMap<SequenceNumber, PublishNotification>,
+    /// The maximum number of uncertified notifications the witness votes for (ahead of its state).
+    pipeline_depth: SequenceNumber,
+    /// The number of sequence numbers between two checkpoints.
+    checkpoint_interval: SequenceNumber,
+    /// The witnesses allowed to connect (extended with the committees the witness learns).
+    peers: Peers,
 }
 
 impl PublishHandler {
     /// Spawn a new publish handler task.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         keypair: KeyPair,
-        committee: Committee,
+        bls_keypair: BlsKeyPair,
+        committees: CommitteeHistory,
         storage: Storage,
         rx_notification: Receiver<(PublishNotification, Replier)>,
         rx_certificate: Receiver<(
@@ -53,67 +118,222 @@ impl PublishHandler {
         )>,
         rx_state_query: Receiver<Replier>,
         tx_processed_certificate: Sender<(SerializedPublishCertificateMessage, SequenceNumber)>,
+        tx_observation: Sender<Observation>,
+        tx_equivocation: Sender<EquivocationProof>,
+        tx_verification: Sender<VerificationRequest>,
+        rx_verified: Receiver<VerificationOutcome>,
+        tx_replica: Option<Sender<(PublishCertificate, Option<ReplicaBatch>)>>,
+        tx_sync: Sender<SyncRequest>,
+        rx_checkpoint: Receiver<(Checkpoint, Replier)>,
+        tx_checkpoint: Sender<Checkpoint>,
+        pipeline_depth: usize,
+        checkpoint_interval: SequenceNumber,
+        peers: Peers,
     ) {
         tokio::spawn(async move {
             // Try to load the state from storage.
-            let state = storage
-                .read(&STORE_STATE_ADDR)
+            let state: State = storage
+                .get(Column::State, STORE_STATE_KEY)
                 .expect("Failed to load state from storage")
-                .map(|bytes| bincode::deserialize(&bytes).expect("Failed to deserialize state"))
                 .unwrap_or_default();
 
+            // Try to load the notifications on which we are locked from storage (the locks
+            // preceding the state are stale).
+            let prefix = STORE_LOCK_KEY_PREFIX.encode();
+            let locks = storage
+                .read_prefix(Column::State, &prefix)
+                .into_iter()
+                .filter_map(|(key, value)| {
+                    let sequence_number = SequenceNumber::decode(&key[prefix.len()..])?;
+                    let lock = bincode::deserialize(&value).expect("Failed to load lock");
+                    Some((sequence_number, lock))
+                })
+                .filter(|(sequence_number, _)| *sequence_number >= state.sequence_number)
+                .collect();
+
             // Run an instance of the handler.
             Self {
                 keypair,
-                committee,
+                bls_keypair,
+                committees,
                 storage,
                 rx_notification,
                 rx_certificate,
                 rx_state_query,
                 tx_processed_certificate,
+                tx_observation,
+                tx_equivocation,
+                tx_verification,
+                rx_verified,
+                in_flight: BTreeMap::new(),
+                tx_replica,
+                tx_sync,
+                rx_checkpoint,
+                tx_checkpoint,
                 state,
+                locks,
+                pipeline_depth: pipeline_depth as SequenceNumber,
+                checkpoint_interval,
+                peers,
             }
             .run()
             .await
         });
     }
 
-    /// Try to vote for a publish notification.
-    async fn make_vote(&self, notification: &PublishNotification) -> WitnessResult<PublishVote> {
-        // Verify the notification.
-        notification
-            .verify(&self.committee, &self.state.root)
-            .await?;
+    /// Make a vote for a notification (with an aggregatable signature if the committee aggregates
+    /// votes into compact certificates).
+    fn vote(&self, notification: &PublishNotification) -> PublishVote {
+        let bls_keypair = match self.committees.current().signature_scheme {
+            SignatureScheme::Ed25519 => None,
+            SignatureScheme::Bls => Some(&self.bls_keypair),
+        };
+        PublishVote::with_bls_signature(notification, &self.keypair, bls_keypair)
+    }
+
+    /// Check whether the notification with the specified sequence number is pipelined after the
+    /// current state (and not too far ahead).
+    fn is_pipelined(&self, sequence_number: SequenceNumber) -> bool {
+        sequence_number > self.state.sequence_number
+            && sequence_number < self.state.sequence_number + self.pipeline_depth
+    }
+
+    /// Return the root on top of which the notification with the specified sequence number
+    /// builds (if known). Notifications pipelined after the current state build on the root of
+    /// the notification we locked on at the previous sequence number.
+    fn previous_root(&self, sequence_number: SequenceNumber) -> Option<&Root> {
+        if sequence_number == self.state.sequence_number {
+            return Some(&self.state.root);
+        }
+        if !self.is_pipelined(sequence_number) {
+            return None;
+        }
+
+        // Do not pipeline notifications after a committee change: the next committee votes on them.
+        self.locks
+            .get(&(sequence_number - 1))
+            .filter(|lock| lock.committee_change.is_none())
+            .map(|lock| lock.root())
+    }
+
+    /// Return the root on top of which the notification with the specified sequence number is
+    /// expected to build. If we did not yet lock on a notification at the previous sequence number,
+    /// speculate that the one being verified (if any) is valid.
+    fn expected_previous_root(&self, sequence_number: SequenceNumber) -> Option<Root> {
+        match self.previous_root(sequence_number) {
+            Some(root) => Some(*root),
+            None if self.is_pipelined(sequence_number) => {
+                self.in_flight.get(&(sequence_number - 1)).copied()
+            }
+            None => None,
+        }
+    }
+
+    /// Hand over a notification to the verifier (or reply right away if it cannot be verified).
+    async fn dispatch(&mut self, notification: PublishNotification, replier: Replier) {
+        let sequence_number = notification.sequence_number();
+        match self.expected_previous_root(sequence_number) {
+            Some(previous_root) => {
+                self.in_flight.insert(sequence_number, *notification.root());
+                let request = VerificationRequest {
+                    notification,
+                    committee: self.committees.current().clone(),
+                    previous_root,
+                    replier,
+                };
+                self.tx_verification
+                    .send(request)
+                    .await
+                    .expect("Failed to send notification to verifier");
+            }
+            None => {
+                let e = WitnessError::UnexpectedSequenceNumber {
+                    expected: self.state.sequence_number,
+                    got: sequence_number,
+                };
+                warn!("{}", e);
+                let reply = WitnessToIdPMessage::PublishVote(Err(e));
+                replier
+                    .send(reply)
+                    .expect("Failed to reply to notification");
+            }
+        }
+    }
 
-        // Check the sequence number.
+    /// Try to vote for a publish notification (once its proof is verified against the specified
+    /// previous root).
+    fn make_vote(
+        &self,
+        notification: &PublishNotification,
+        previous_root: &Root,
+        verification: WitnessResult<()>,
+    ) -> WitnessResult<PublishVote> {
+        // Check the sequence number. The state may have changed during the verification.
         ensure!(
-            self.state.sequence_number == notification.sequence_number(),
+            self.previous_root(notification.sequence_number()) == Some(previous_root),
             WitnessError::UnexpectedSequenceNumber {
                 expected: self.state.sequence_number,
                 got: notification.sequence_number()
             }
         );
 
-        // Ensure there are no locks.
-        match self.state.lock.as_ref() {
-            Some(vote) => {
+        // Check the notification is valid.
+        verification?;
+
+        // Only vote once the committee including the witness takes over (a joining witness
+        // follows the certificates passively until then).
+        let sequence_number = notification.sequence_number();
+        ensure!(
+            self.committees
+                .get(sequence_number)
+                .witnesses
+                .contains_key(&self.keypair.public()),
+            WitnessError::NotInCommittee(sequence_number)
+        );
+
+        // Ensure there are no locks. A lock migrated from the legacy databases only holds the vote
+        // (not the notification).
+        if let Some(vote) = &self.state.lock {
+            ensure!(
+                vote.sequence_number != sequence_number || vote.root() == notification.root(),
+                WitnessError::ConflictingNotification {
+                    lock: *vote.root(),
+                    received: *notification.root()
+                }
+            );
+        }
+        match self.locks.get(&notification.sequence_number()) {
+            Some(lock) => {
                 ensure!(
-                    vote.root() == notification.root(),
+                    lock.root() == notification.root(),
                     WitnessError::ConflictingNotification {
-                        lock: *vote.root(),
+                        lock: *lock.root(),
                         received: *notification.root()
                     }
                 );
-                Ok(vote.clone())
+                Ok(self.vote(lock))
             }
-            None => Ok(PublishVote::new(notification, &self.keypair)),
+            None => Ok(self.vote(notification)),
         }
     }
 
+    /// Atomically persist the state along with other records of the state column (e.g., a new
+    /// lock or the committees).
+    fn persist_state(&self, records: Vec<(Vec<u8>, Vec<u8>)>) {
+        let state = bincode::serialize(&self.state).expect("Failed to serialize state");
+        let entries = records
+            .into_iter()
+            .chain(iter::once((STORE_STATE_KEY.encode(), state)));
+        self.storage
+            .write_batch(Column::State, entries)
+            .expect("Failed to persist state");
+    }
+
     /// Process a publish certificate.
     fn process_certificate(&self, certificate: &PublishCertificate) -> WitnessResult<()> {
-        // Verify the certificate's validity.
-        certificate.verify(&self.committee)?;
+        // Verify the certificate's validity (under the committee in charge of its sequence number).
+        let committee = self.committees.get(certificate.sequence_number());
+        certificate.verify(committee)?;
 
         // Ensure the witness is not missing previous certificates.
         ensure!(
@@ -123,14 +343,135 @@ impl PublishHandler {
         Ok(())
     }
 
+    /// Move the state of the witness past a (verified) certificate and persist it.
+    fn advance(&mut self, certificate: &PublishCertificate) {
+        #[cfg(not(feature = "witness-only-benchmark"))]
+        {
+            // Do not update the state root when running benchmarks. This allows the
+            // benchmark client to re-use the same proof (and thus not becoming the
+            // CPU bottleneck).
+            self.state.root = *certificate.root();
+        }
+        self.state.sequence_number = certificate.sequence_number() + 1;
+
+        // Move on to the lock of the next pipelined notification (if any).
+        self.locks = self.locks.split_off(&self.state.sequence_number);
+        self.in_flight = self.in_flight.split_off(&self.state.sequence_number);
+        self.state.lock = self
+            .locks
+            .get(&self.state.sequence_number)
+            .map(|lock| self.vote(lock));
+
+        // Hand over to the new committee (if the certificate changes it). The committees are
+        // persisted with the state so that the witness can always verify the certificates it
+        // is expecting.
+        let mut records = Vec::new();
+        if self.committees.update(certificate) {
+            let size = self.committees.current().size();
+            info!("Handing over to {} witnesses", size);
+            self.peers.extend(self.committees.current());
+            let committees =
+                bincode::serialize(&self.committees).expect("Failed to serialize committees");
+            records.push((STORE_COMMITTEES_KEY.encode(), committees));
+        }
+        self.persist_state(records);
+
+        // Clean up the stale locks (they are ignored when loading the locks anyway).
+        self.storage
+            .delete_range(
+                Column::State,
+                &lock_key(SequenceNumber::default()),
+                &lock_key(self.state.sequence_number),
+            )
+            .expect("Failed to delete stale locks");
+    }
+
+    /// Adopt a checkpoint pulled from the other witnesses, skipping all the certificates that
+    /// precede it.
+    async fn adopt(&mut self, checkpoint: Checkpoint) -> WitnessResult<()> {
+        // Only adopt checkpoints ahead of the state of the witness.
+        ensure!(
+            self.state.sequence_number <= checkpoint.sequence_number(),
+            WitnessError::UnexpectedSequenceNumber {
+                expected: self.state.sequence_number,
+                got: checkpoint.sequence_number()
+            }
+        );
+
+        // Verify the checkpoint (learning the committee changes that precede it) and persist the
+        // committees before moving the state past the checkpoint.
+        self.committees = checkpoint.verify(&self.committees)?;
+        self.committees.iter().for_each(|x| self.peers.extend(x));
+        let committees =
+            bincode::serialize(&self.committees).expect("Failed to serialize committees");
+        self.persist_state(vec![(STORE_COMMITTEES_KEY.encode(), committees)]);
+
+        self.advance(&checkpoint.certificate);
+        info!("Adopted {:?}", checkpoint);
+
+        // Let the replica (if any) catch up on the skipped batches.
+        if let Some(tx_replica) = &self.tx_replica {
+            tx_replica
+                .send((checkpoint.certificate.clone(), None))
+                .await
+                .expect("Failed to send certificate to replica");
+        }
+
+        // Keep the certificate and the checkpoint to help the other witnesses to catch up.
+        let message = IdPToWitnessMessage::PublishCertificate(checkpoint.certificate.clone());
+        let serialized = bincode::serialize(&message).expect("Failed to serialize certificate");
+        self.tx_processed_certificate
+            .send((serialized, checkpoint.sequence_number()))
+            .await
+            .expect("Failed to send certificate to sync helper");
+        self.tx_checkpoint
+            .send(checkpoint)
+            .await
+            .expect("Failed to send checkpoint to sync helper");
+        Ok(())
+    }
+
     /// Main loop listening to verified IdP's notification messages.
     async fn run(&mut self) {
         loop {
             tokio::select! {
-                // Receive publish notifications.
+                // Receive publish notifications and hand them over to the verifier.
                 Some((notification, replier)) = self.rx_notification.recv() => {
                     debug!("Received {:?}", notification);
-                    let reply = match self.make_vote(&notification).await {
+                    self.dispatch(notification, replier).await;
+                },
+
+                // Receive verified publish notifications.
+                Some(outcome) = self.rx_verified.recv() => {
+                    let VerificationOutcome {
+                        notification,
+                        previous_root,
+                        replier,
+                        result
+                    } = outcome;
+                    let sequence_number = notification.sequence_number();
+                    if self.in_flight.get(&sequence_number) == Some(notification.root()) {
+                        self.in_flight.remove(&sequence_number);
+                    }
+
+                    let reply = match self.make_vote(&notification, &previous_root, result) {
+                        Err(e @ WitnessError::ConflictingNotification { .. }) => {
+                            warn!("{}", e);
+
+                            // Keep evidence that the IdP equivocated.
+                            let lock = self.locks.get(&notification.sequence_number()).cloned();
+                            if let Some(lock) = lock {
+                                let proof = EquivocationProof::new(lock, notification);
+                                self
+                                    .tx_equivocation
+                                    .send(proof)
+                                    .await
+                                    .expect("Failed to send equivocation proof to sync helper");
+                            }
+
+                            // Reply with an error message.
+                            WitnessToIdPMessage::PublishVote(Err(e))
+                        },
                         Err(e) => {
                             warn!("{}", e);
 
@@ -141,11 +482,24 @@ impl PublishHandler {
                             debug!("Create {:?}", vote);
 
                             // Register the lock.
-                            self.state.lock = Some(vote.clone());
-                            let serialized_state = bincode::serialize(&self.state)
-                                .expect("Failed to serialize state");
-                            self.storage.write(&STORE_STATE_ADDR, &serialized_state)
-                                .expect("Failed to persist state");
+                            if self.state.sequence_number == notification.sequence_number() {
+                                self.state.lock = Some(vote.clone());
+                            }
+
+                            // Remember the notification to prove a possible equivocation (and to
+                            // verify the notifications pipelined after it).
+                            let sequence_number = notification.sequence_number();
+                            let lock = bincode::serialize(&notification)
+                                .expect("Failed to serialize lock");
+                            self.locks.insert(sequence_number, notification.clone());
+                            self.persist_state(vec![(lock_key(sequence_number), lock)]);
+
+                            // Share the notification with the other witnesses.
+                            self
+                                .tx_observation
+                                .send(Observation::Vote(self.state.clone(), notification))
+                                .await
+                                .expect("Failed to send notification to gossiper");
 
                             // Reply with a vote.
                             WitnessToIdPMessage::PublishVote(Ok(vote))
@@ -158,6 +512,22 @@ impl PublishHandler {
                 Some((serialized, certificate, replier)) = self.rx_certificate.recv() => {
                     debug!("Received {:?}", certificate);
                     let reply = match self.process_certificate(&certificate) {
+                        Err(e @ WitnessError::MissingEarlierCertificates(_)) => {
+                            warn!("{}", e);
+
+                            // Try to pull the missing certificates from the other witnesses. It is
+                            // fine to drop the signal if the synchronizer is busy: the next
+                            // certificate will trigger it again.
+                            let request = SyncRequest {
+                                from: self.state.sequence_number,
+                                to: certificate.sequence_number(),
+                                committee: self.committees.current().clone(),
+                            };
+                            let _ = self.tx_sync.try_send(request);
+
+                            // Reply with an error message.
+                            WitnessToIdPMessage::State(Err(e))
+                        },
                         Err(e) => {
                             warn!("{}", e);
 
@@ -166,21 +536,15 @@ impl PublishHandler {
                         },
                         Ok(()) => {
                             if self.state.sequence_number == certificate.sequence_number() {
-                                // Update the witness state.
-                                #[cfg(not(feature = "witness-only-benchmark"))]
-                                {
-                                    // Do not update the state root when running benchmarks. This allows the
-                                    // benchmark client to re-use the same proof (and thus not becoming the
-                                    // CPU bottleneck).
-                                    self.state.root = *certificate.root();
-                                }
-                                self.state.sequence_number += 1;
-                                self.state.lock = None;
+                                // Retrieve the certified batch (if we voted for it).
+                                let batch = self
+                                    .locks
+                                    .get(&certificate.sequence_number())
+                                    .filter(|lock| lock.root() == certificate.root())
+                                    .and_then(|lock| lock.batch.clone());
 
-                                let serialized_state = bincode::serialize(&self.state)
-                                    .expect("Failed to serialize state");
-                                self.storage.write(&STORE_STATE_ADDR, &serialized_state)
-                                    .expect("Failed to persist state");
+                                // Update the witness state.
+                                self.advance(&certificate);
 
                                 debug!("Commit {:?}", certificate);
                                 // NOTE: These log entries are used to compute performance.
@@ -192,6 +556,36 @@ impl PublishHandler {
                                     .send((serialized, certificate.sequence_number()))
                                     .await
                                     .expect("Failed to send certificate to sync helper");
+
+                                // Periodically checkpoint the state (to let fresh witnesses skip
+                                // the certificates preceding it).
+                                let interval = self.checkpoint_interval;
+                                if certificate.sequence_number() % interval == 0 {
+                                    let checkpoint = Checkpoint::new(
+                                        certificate.clone(),
+                                        &self.committees
+                                    );
+                                    self
+                                        .tx_checkpoint
+                                        .send(checkpoint)
+                                        .await
+                                        .expect("Failed to send checkpoint to sync helper");
+                                }
+
+                                // Replay the certified batch into the replica (if any).
+                                if let Some(tx_replica) = &self.tx_replica {
+                                    tx_replica
+                                        .send((certificate.clone(), batch))
+                                        .await
+                                        .expect("Failed to send certificate to replica");
+                                }
+
+                                // Share the certificate with the other witnesses.
+                                self
+                                    .tx_observation
+                                    .send(Observation::Commit(self.state.clone(), certificate))
+                                    .await
+                                    .expect("Failed to send certificate to gossiper");
                             } else {
                                 debug!("Already processed {:?}", certificate);
                             }
@@ -203,6 +597,19 @@ impl PublishHandler {
                     replier.send(reply).expect("Failed to reply to certificate");
                 }
 
+                // Receive checkpoints pulled from the other witnesses.
+                Some((checkpoint, replier)) = self.rx_checkpoint.recv() => {
+                    debug!("Received {:?}", checkpoint);
+                    let reply = match self.adopt(checkpoint).await {
+                        Ok(()) => WitnessToIdPMessage::State(Ok(self.state.clone())),
+                        Err(e) => {
+                            warn!("{}", e);
+                            WitnessToIdPMessage::State(Err(e))
+                        }
+                    };
+                    replier.send(reply).expect("Failed to reply to checkpoint");
+                },
+
                 // Receive state queries.
                 Some(replier) = self.rx_state_query.recv() => {
                     let reply =  WitnessToIdPMessage::State(Ok(self.state.clone()));
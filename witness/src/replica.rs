@@ -0,0 +1,322 @@
+use crate::Replier;
+use akd::{
+    directory::Directory,
+    ecvrf::{Proof, VRFKeyStorage, VRFPublicKey, VrfError},
+    storage::types::AkdLabel,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use config::Committee;
+use crypto::{KeyPair, PublicKey};
+use log::{debug, warn};
+use messages::{
+    ensure,
+    error::{WitnessError, WitnessResult},
+    lookup::{LookupRequest, LookupResponse},
+    publish::{PublishCertificate, PublishMessage},
+    update::ReplicaBatch,
+    Blake3, ClientToIdPMessage, IdPToClientMessage, SequenceNumber, WitnessToIdPMessage,
+};
+use network::{
+    reliable_sender::{BufferLimits, ReliableSender},
+    transport::Transport,
+};
+use std::{convert::TryFrom, net::SocketAddr, sync::Arc};
+use storage::{akd_storage::AkdStorage, Column, Storage};
+use tokio::{
+    sync::mpsc::Receiver,
+    time::{timeout, Duration},
+};
+use winter_crypto::Hasher;
+
+/// The delay (in ms) after which the replica gives up waiting for the IdP to provide a batch.
+const BATCH_QUERY_TIMEOUT: u64 = 5_000;
+
+/// Return the storage key of the VRF proof of a version of a label.
+fn vrf_proof_key(label: &AkdLabel, stale: bool, version: u64) -> Vec<u8> {
+    let mut key = (label.0.len() as u64).to_be_bytes().to_vec();
+    key.extend_from_slice(&label.0);
+    key.push(stale as u8);
+    key.extend_from_slice(&version.to_be_bytes());
+    key
+}
+
+/// The VRF of the replica. It does not hold the VRF secret key of the IdP: it serves the VRF proofs
+/// shipped by the IdP along with the batches of updates (and persisted in storage).
+#[derive(Clone)]
+struct ReplicaVrf {
+    /// The VRF public key of the IdP.
+    public_key: PublicKey,
+    /// The storage holding the VRF proofs.
+    storage: Storage,
+}
+
+impl ReplicaVrf {
+    /// Persist the VRF proofs shipped with a batch of updates.
+    fn persist(&self, batch: &ReplicaBatch) -> WitnessResult<()> {
+        let entries = batch.proofs.iter().map(|x| {
+            let key = vrf_proof_key(&x.label, x.stale, x.version);
+            (key, x.proof.clone())
+        });
+        self.storage
+            .write_batch(Column::VrfProofs, entries)
+            .map_err(|e| WitnessError::ReplicationFailed(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl VRFKeyStorage for ReplicaVrf {
+    async fn retrieve(&self) -> Result<Vec<u8>, VrfError> {
+        Err(VrfError::SigningKey(
+            "Full witnesses do not hold the VRF secret key".into(),
+        ))
+    }
+
+    async fn get_vrf_public_key(&self) -> Result<VRFPublicKey, VrfError> {
+        VRFPublicKey::try_from(self.public_key.as_ref())
+    }
+
+    async fn get_label_proof<H: Hasher>(
+        &self,
+        label: &AkdLabel,
+        stale: bool,
+        version: u64,
+    ) -> Result<Proof, VrfError> {
+        let bytes = self
+            .storage
+            .read(Column::VrfProofs, &vrf_proof_key(label, stale, version))
+            .map_err(|e| VrfError::SigningKey(e.to_string()))?
+            .ok_or_else(|| {
+                let error = format!("Missing VRF proof of {:?} (version {})", label, version);
+                VrfError::SigningKey(error)
+            })?;
+        Proof::try_from(&bytes[..])
+    }
+}
+
+/// Replays the batches of updates certified by the committee into an independent replica of the
+/// directory and serves read-only lookups from it (only run by full witnesses). The batches the
+/// replica misses (e.g., when the witness syncs certificates it did not vote for, adopts a
+/// checkpoint, or restarts) are pulled from the IdP. If the replica fails to reach a certified
+/// root, it is wiped and rebuilt from the first batch.
+pub struct Replica {
+    /// Receive the certificates committed by the publish handler (and the batch they certify).
+    rx_commit: Receiver<(PublishCertificate, Option<ReplicaBatch>)>,
+    /// Receive clients' lookup requests.
+    rx_lookup: Receiver<(LookupRequest, Replier)>,
+    /// The storage holding the replica (and the VRF proofs of its labels).
+    storage: Storage,
+    /// The VRF deriving the labels of the replica.
+    vrf: ReplicaVrf,
+    /// The replica of the `akd` key directory.
+    akd: Directory<AkdStorage, ReplicaVrf>,
+    /// The sequence number of the last batch replayed into the replica.
+    sequence_number: SequenceNumber,
+    /// The certificate over the current state of the replica (if the replica matches it).
+    certificate: Option<PublishCertificate>,
+    /// The name and address of the IdP (to pull the missed batches).
+    idp: (PublicKey, SocketAddr),
+    /// A reliable network sender.
+    network: ReliableSender,
+}
+
+impl Replica {
+    /// Spawn a new replica task.
+    pub fn spawn(
+        keypair: KeyPair,
+        committee: &Committee,
+        storage: Storage,
+        rx_commit: Receiver<(PublishCertificate, Option<ReplicaBatch>)>,
+        rx_lookup: Receiver<(LookupRequest, Replier)>,
+        transport: Arc<dyn Transport>,
+    ) {
+        let vrf = ReplicaVrf {
+            public_key: committee.idp.vrf_public_key,
+            storage: storage.clone(),
+        };
+        let idp = (committee.idp.name, committee.idp.address);
+        tokio::spawn(async move {
+            // Make or load the replica of the akd directory.
+            let (akd, sequence_number) = Self::open(&storage, &vrf).await;
+
+            Self {
+                rx_commit,
+                rx_lookup,
+                storage,
+                vrf,
+                akd,
+                sequence_number,
+                certificate: None,
+                idp,
+                network: ReliableSender::with_transport(
+                    keypair,
+                    BufferLimits::default(),
+                    transport,
+                ),
+            }
+            .run()
+            .await
+        });
+    }
+
+    /// Make (or load) the replica of the directory. Returns the sequence number of the last batch
+    /// replayed into it.
+    async fn open(
+        storage: &Storage,
+        vrf: &ReplicaVrf,
+    ) -> (Directory<AkdStorage, ReplicaVrf>, SequenceNumber) {
+        let db = AkdStorage::with_storage(storage.clone());
+        let akd = Directory::new::<Blake3>(&db, vrf, false)
+            .await
+            .expect("Failed to create akd replica");
+        let sequence_number = akd
+            .retrieve_current_azks()
+            .await
+            .expect("Failed to load akd replica")
+            .get_latest_epoch();
+        (akd, sequence_number)
+    }
+
+    /// Wipe the replica so that it is rebuilt from the first batch.
+    async fn reset(&mut self) {
+        warn!("Rebuilding the replica of the directory");
+        self.storage
+            .clear(Column::Akd)
+            .expect("Failed to wipe akd replica");
+        let (akd, sequence_number) = Self::open(&self.storage, &self.vrf).await;
+        self.akd = akd;
+        self.sequence_number = sequence_number;
+        self.certificate = None;
+    }
+
+    /// Pull from the IdP the batch of the specified sequence number.
+    async fn fetch(&mut self, sequence_number: SequenceNumber) -> WitnessResult<ReplicaBatch> {
+        debug!("Pulling batch {} from the IdP", sequence_number);
+        let message = ClientToIdPMessage::BatchQuery(sequence_number);
+        let serialized = bincode::serialize(&message).expect("Failed to serialize batch query");
+        let (name, address) = self.idp;
+        let handle = self
+            .network
+            .send(name, address, Bytes::from(serialized))
+            .await;
+
+        // Dropping the handle (on timeout) cancels the request.
+        let reply = timeout(Duration::from_millis(BATCH_QUERY_TIMEOUT), handle)
+            .await
+            .ok()
+            .and_then(|reply| reply.ok());
+        match reply.map(|x| bincode::deserialize(&x)) {
+            Some(Ok(IdPToClientMessage::BatchResponse(Some(batch)))) => Ok(batch),
+            _ => Err(WitnessError::MissingBatch(sequence_number)),
+        }
+    }
+
+    /// Replay the batches of updates up to a certificate (pulling from the IdP the ones the
+    /// replica missed) and check that the replica reaches the certified root.
+    async fn replay(
+        &mut self,
+        certificate: &PublishCertificate,
+        mut batch: Option<ReplicaBatch>,
+    ) -> WitnessResult<()> {
+        let target = certificate.sequence_number();
+        while self.sequence_number < target {
+            let next = self.sequence_number + 1;
+            let shipped = if next == target { batch.take() } else { None };
+            let batch = match shipped {
+                Some(batch) => batch,
+                None => self.fetch(next).await?,
+            };
+
+            // The replica moves to the next epoch even if it does not reach the certified root.
+            self.certificate = None;
+            self.vrf.persist(&batch)?;
+            self.akd
+                .publish::<Blake3>(batch.updates)
+                .await
+                .map_err(|e| WitnessError::ReplicationFailed(e.to_string()))?;
+            self.sequence_number = next;
+        }
+
+        // Ensure the replica reached the certified root.
+        let current_azks = self
+            .akd
+            .retrieve_current_azks()
+            .await
+            .map_err(|e| WitnessError::ReplicationFailed(e.to_string()))?;
+        let root = self
+            .akd
+            .get_root_hash_at_epoch::<Blake3>(&current_azks, self.sequence_number)
+            .await
+            .map_err(|e| WitnessError::ReplicationFailed(e.to_string()))?;
+        ensure!(
+            &root == certificate.root(),
+            WitnessError::ReplicaDiverged {
+                expected: *certificate.root(),
+                got: root
+            }
+        );
+        Ok(())
+    }
+
+    /// Bring the replica up to a certificate. If the replica cannot reach the certified root, it is
+    /// rebuilt from scratch. If the IdP cannot provide a missed batch, the next certificate will
+    /// trigger the catch-up again.
+    async fn commit(&mut self, certificate: PublishCertificate, batch: Option<ReplicaBatch>) {
+        let result = match self.replay(&certificate, batch).await {
+            Err(e @ WitnessError::MissingBatch(_)) => Err(e),
+            Err(e) => {
+                warn!("{}", e);
+                self.reset().await;
+                self.replay(&certificate, None).await
+            }
+            Ok(()) => Ok(()),
+        };
+        match result {
+            Ok(()) => {
+                debug!("Replayed {:?}", certificate);
+                self.certificate = Some(certificate);
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    /// Compute a lookup proof against the current (certified) state of the replica.
+    async fn lookup(&self, request: LookupRequest) -> WitnessResult<LookupResponse> {
+        let certificate = self
+            .certificate
+            .clone()
+            .ok_or(WitnessError::NoReplicatedState)?;
+        let proof = self
+            .akd
+            .lookup::<Blake3>(request.label)
+            .await
+            .map_err(|e| WitnessError::ReplicationFailed(e.to_string()))?;
+        Ok(LookupResponse { proof, certificate })
+    }
+
+    /// Main loop replaying certified batches and serving lookups.
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                // Replay the batches certified by the committee.
+                Some((certificate, batch)) = self.rx_commit.recv() => {
+                    // A restarted replica re-checks the certificate over its current state.
+                    let replayed = self.certificate.is_some()
+                        || certificate.sequence_number() < self.sequence_number;
+                    if replayed && certificate.sequence_number() <= self.sequence_number {
+                        debug!("Already replayed {:?}", certificate);
+                        continue;
+                    }
+                    self.commit(certificate, batch).await;
+                },
+
+                // Serve lookups against the replica.
+                Some((request, replier)) = self.rx_lookup.recv() => {
+                    debug!("Received {:?}", request);
+                    let reply = WitnessToIdPMessage::LookupResponse(self.lookup(request).await);
+                    replier.send(reply).expect("Failed to reply to lookup request");
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,20 @@
 use crate::Replier;
 use messages::{
-    sync::PublishCertificateQuery, SequenceNumber, SerializedPublishCertificateMessage,
-    WitnessToIdPMessage,
+    checkpoint::Checkpoint,
+    equivocation::{EquivocationProof, EquivocationProofQuery},
+    sync::{PublishCertificateRangeQuery, PublishCertificateRangeResponse},
+    SequenceNumber, SerializedPublishCertificateMessage, WitnessToIdPMessage,
 };
-use storage::Storage;
+use storage::{Column, Storage};
 use tokio::sync::mpsc::Receiver;
 
+/// The maximum size (in bytes) of the certificates sent in a single reply (regardless of the size
+/// requested). It keeps replies well below the maximum frame size of the network.
+const MAX_REPLY_BYTES: usize = 4 * 1024 * 1024;
+
+/// Storage key (in the state column) of the latest checkpoint.
+const STORE_CHECKPOINT_KEY: &str = "checkpoint";
+
 /// Task dedicated to help other witnesses to sync up by replying to certificate requests.
 pub struct SyncHelper {
     /// The persistent storage.
@@ -13,7 +22,15 @@ pub struct SyncHelper {
     /// Received serialized publish certificates once processed by the publish handler.
     rx_processed_certificate: Receiver<(SerializedPublishCertificateMessage, SequenceNumber)>,
     /// Receive the publish certificates requests.
-    rx_certificate_request: Receiver<(PublishCertificateQuery, Replier)>,
+    rx_certificate_request: Receiver<(PublishCertificateRangeQuery, Replier)>,
+    /// Receive evidence that the IdP equivocated.
+    rx_equivocation: Receiver<EquivocationProof>,
+    /// Receive the equivocation proofs requests.
+    rx_equivocation_request: Receiver<(EquivocationProofQuery, Replier)>,
+    /// Receive the checkpoints of the witness.
+    rx_checkpoint: Receiver<Checkpoint>,
+    /// Receive the checkpoint requests.
+    rx_checkpoint_request: Receiver<Replier>,
 }
 
 impl SyncHelper {
@@ -21,47 +38,140 @@ impl SyncHelper {
     pub fn spawn(
         storage: Storage,
         rx_processed_certificate: Receiver<(SerializedPublishCertificateMessage, SequenceNumber)>,
-        rx_certificate_request: Receiver<(PublishCertificateQuery, Replier)>,
+        rx_certificate_request: Receiver<(PublishCertificateRangeQuery, Replier)>,
+        rx_equivocation: Receiver<EquivocationProof>,
+        rx_equivocation_request: Receiver<(EquivocationProofQuery, Replier)>,
+        rx_checkpoint: Receiver<Checkpoint>,
+        rx_checkpoint_request: Receiver<Replier>,
     ) {
         tokio::spawn(async move {
             Self {
                 storage,
                 rx_processed_certificate,
                 rx_certificate_request,
+                rx_equivocation,
+                rx_equivocation_request,
+                rx_checkpoint,
+                rx_checkpoint_request,
             }
             .run()
             .await
         });
     }
 
+    /// Load a range of certificates from storage in a single scan.
+    fn load_certificates(
+        &self,
+        query: &PublishCertificateRangeQuery,
+    ) -> PublishCertificateRangeResponse {
+        let max_bytes = query.max_bytes.min(MAX_REPLY_BYTES);
+        let mut certificates = Vec::new();
+        let mut size = 0;
+        for (sequence_number, certificate) in self
+            .storage
+            .iter_from(Column::Certificates, &query.from)
+            .take_while(|(s, _)| *s <= query.to)
+        {
+            // Only serve contiguous certificates starting at the first requested one.
+            if sequence_number != query.from + certificates.len() as SequenceNumber {
+                break;
+            }
+            // Always include the first certificate (to ensure progress).
+            size += certificate.len();
+            if size > max_bytes && !certificates.is_empty() {
+                break;
+            }
+            certificates.push(certificate.into_vec());
+        }
+
+        if certificates.is_empty() {
+            PublishCertificateRangeResponse::NotFound(query.from)
+        } else {
+            PublishCertificateRangeResponse::Found(certificates)
+        }
+    }
+
+    /// Load the latest checkpoint from storage (if any).
+    fn load_checkpoint(&self) -> Option<Checkpoint> {
+        self.storage
+            .get(Column::State, STORE_CHECKPOINT_KEY)
+            .expect("Failed to load checkpoint from storage")
+    }
+
+    /// Persist a new checkpoint and prune the certificates preceding it (the checkpoint replaces
+    /// them to bootstrap fresh witnesses, and carries the certificates of the committee changes
+    /// fresh witnesses need to verify it).
+    fn store_checkpoint(&self, checkpoint: &Checkpoint) {
+        if let Some(latest) = self.load_checkpoint() {
+            if latest.sequence_number() >= checkpoint.sequence_number() {
+                return;
+            }
+        }
+        self.storage
+            .put(Column::State, STORE_CHECKPOINT_KEY, checkpoint)
+            .expect("Failed to persist checkpoint");
+        self.storage
+            .delete_before(Column::Certificates, &checkpoint.sequence_number())
+            .expect("Failed to prune certificates");
+    }
+
     /// Main loop answering certificate requests.
     async fn run(&mut self) {
         loop {
             tokio::select! {
                 // Store new certificates.
                 Some((serialized_certificate, sequence_number)) = self.rx_processed_certificate.recv() => {
-                    let key = sequence_number.to_le_bytes();
                     self
                         .storage
-                        .write(&key, &serialized_certificate)
+                        .write(Column::Certificates, &sequence_number, &serialized_certificate)
                         .expect("Failed to persist certificate");
                 },
 
                 // Serve certificates to whoever asks for them.
                 Some((request, replier)) = self.rx_certificate_request.recv() => {
-                    // Check whether the requested certificate is in storage.
-                    let key = request.sequence_number.to_le_bytes();
-                    if let Some(serialized_certificate) = self
+                    let response = self.load_certificates(&request);
+                    let reply = WitnessToIdPMessage::PublishCertificateRangeResponse(response);
+                    replier
+                        .send(reply)
+                        .expect("Failed to reply to certificate sync request");
+                },
+
+                // Store evidence of equivocation (one proof per sequence number is enough).
+                Some(proof) = self.rx_equivocation.recv() => {
+                    let sequence_number = proof.sequence_number();
+                    let stored = self
                         .storage
-                        .read(&key)
-                        .expect("Failed to load certificate from storage")
-                    {
-                        // Reply with the certificate (if we have it).
-                        let reply = WitnessToIdPMessage::PublishCertificateResponse(serialized_certificate);
-                        replier
-                            .send(reply)
-                            .expect("Failed to reply to certificate sync request");
+                        .read(Column::Evidence, &sequence_number)
+                        .expect("Failed to load equivocation proof from storage");
+                    if stored.is_none() {
+                        self
+                            .storage
+                            .put(Column::Evidence, &sequence_number, &proof)
+                            .expect("Failed to persist equivocation proof");
                     }
+                },
+
+                // Serve equivocation proofs to whoever asks for them.
+                Some((request, replier)) = self.rx_equivocation_request.recv() => {
+                    let proof = self
+                        .storage
+                        .get(Column::Evidence, &request.sequence_number)
+                        .expect("Failed to load equivocation proof from storage");
+                    let reply = WitnessToIdPMessage::EquivocationProofResponse(proof);
+                    replier
+                        .send(reply)
+                        .expect("Failed to reply to equivocation proof request");
+                },
+
+                // Store the checkpoints of the witness.
+                Some(checkpoint) = self.rx_checkpoint.recv() => self.store_checkpoint(&checkpoint),
+
+                // Serve the latest checkpoint to whoever asks for it.
+                Some(replier) = self.rx_checkpoint_request.recv() => {
+                    let reply = WitnessToIdPMessage::CheckpointResponse(self.load_checkpoint());
+                    replier
+                        .send(reply)
+                        .expect("Failed to reply to checkpoint request");
                 }
             }
         }
@@ -0,0 +1,271 @@
+use crate::Replier;
+use bytes::Bytes;
+use config::Committee;
+use crypto::{KeyPair, PublicKey};
+use log::{debug, warn};
+use messages::{
+    checkpoint::Checkpoint,
+    publish::PublishCertificate,
+    sync::{PublishCertificateRangeQuery, PublishCertificateRangeResponse},
+    IdPToWitnessMessage, SequenceNumber, SerializedPublishCertificateMessage, WitnessToIdPMessage,
+};
+use network::{
+    reliable_sender::{BufferLimits, ReliableSender},
+    transport::Transport,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    sync::{
+        mpsc::{Receiver, Sender},
+        oneshot,
+    },
+    time::{timeout, Duration},
+};
+
+/// The delay after which a peer is deemed unresponsive (in ms).
+const SYNC_TIMEOUT: u64 = 5_000;
+
+/// The maximum size (in bytes) of the certificates requested to a peer in a single message.
+const SYNC_MAX_BYTES: usize = 1024 * 1024;
+
+/// Signal to the synchronizer that the witness missed some certificates.
+#[derive(Debug)]
+pub struct SyncRequest {
+    /// The sequence number of the first missing certificate.
+    pub from: SequenceNumber,
+    /// The sequence number of the last missing certificate (inclusive).
+    pub to: SequenceNumber,
+    /// The committee whose witnesses may hold the missing certificates.
+    pub committee: Committee,
+}
+
+/// Pulls the certificates missed by the witness from the sync helpers of the other witnesses, so
+/// that the witness catches up even when the IdP is unable to update it. The certificates are
+/// handed to the publish handler that verifies and commits them as if they came from the IdP. If
+/// the other witnesses pruned the missing certificates, the witness adopts their latest checkpoint.
+pub struct Synchronizer {
+    /// The public key of this witness.
+    name: PublicKey,
+    /// Receive signals that the witness missed some certificates.
+    rx_request: Receiver<SyncRequest>,
+    /// Outputs the certificates received from the other witnesses to the publish handler.
+    tx_certificate: Sender<(
+        SerializedPublishCertificateMessage,
+        PublishCertificate,
+        Replier,
+    )>,
+    /// Outputs the checkpoints received from the other witnesses to the publish handler.
+    tx_checkpoint: Sender<(Checkpoint, Replier)>,
+    /// The sequence number of the next certificate expected by the publish handler.
+    sequence_number: SequenceNumber,
+    /// The index of the next peer to ask for certificates (to spread the load among the peers).
+    next_peer: usize,
+    /// A reliable network sender.
+    network: ReliableSender,
+}
+
+impl Synchronizer {
+    /// Spawn a new synchronizer task.
+    pub fn spawn(
+        keypair: KeyPair,
+        rx_request: Receiver<SyncRequest>,
+        tx_certificate: Sender<(
+            SerializedPublishCertificateMessage,
+            PublishCertificate,
+            Replier,
+        )>,
+        tx_checkpoint: Sender<(Checkpoint, Replier)>,
+        transport: Arc<dyn Transport>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                name: keypair.public(),
+                rx_request,
+                tx_certificate,
+                tx_checkpoint,
+                sequence_number: SequenceNumber::default(),
+                next_peer: 0,
+                network: ReliableSender::with_transport(
+                    keypair,
+                    BufferLimits::default(),
+                    transport,
+                ),
+            }
+            .run()
+            .await
+        });
+    }
+
+    /// Send a request to a peer. Returns `None` if the peer does not reply in time.
+    async fn request(
+        &mut self,
+        peer: PublicKey,
+        address: SocketAddr,
+        message: &IdPToWitnessMessage,
+    ) -> Option<WitnessToIdPMessage> {
+        debug!("Sending {:?} to {}", message, address);
+        let serialized = bincode::serialize(message).expect("Failed to serialize sync request");
+        let handle = self
+            .network
+            .send(peer, address, Bytes::from(serialized))
+            .await;
+
+        // Dropping the handle (on timeout) cancels the request.
+        let reply = timeout(Duration::from_millis(SYNC_TIMEOUT), handle)
+            .await
+            .ok()?
+            .ok()?;
+        match bincode::deserialize(&reply) {
+            Ok(reply) => Some(reply),
+            Err(e) => {
+                warn!("Received invalid sync reply from {}: {}", address, e);
+                None
+            }
+        }
+    }
+
+    /// Request a range of certificates to a peer. Returns `None` if the peer does not have the
+    /// first requested certificate or does not reply in time.
+    async fn fetch(
+        &mut self,
+        peer: PublicKey,
+        address: SocketAddr,
+        from: SequenceNumber,
+        to: SequenceNumber,
+    ) -> Option<Vec<SerializedPublishCertificateMessage>> {
+        let query = PublishCertificateRangeQuery {
+            from,
+            to,
+            max_bytes: SYNC_MAX_BYTES,
+        };
+        let message = IdPToWitnessMessage::PublishCertificateRangeQuery(query);
+        match self.request(peer, address, &message).await? {
+            WitnessToIdPMessage::PublishCertificateRangeResponse(response) => match response {
+                PublishCertificateRangeResponse::Found(certificates) => Some(certificates),
+                PublishCertificateRangeResponse::NotFound(_) => {
+                    debug!("{} does not have certificate {}", address, from);
+                    None
+                }
+            },
+            _ => {
+                warn!("Received unexpected sync reply from {}", address);
+                None
+            }
+        }
+    }
+
+    /// Adopt the first checkpoint of the peers that skips the missing certificates (the publish
+    /// handler verifies it before adopting it).
+    async fn bootstrap(&mut self, peers: &[(PublicKey, SocketAddr)]) {
+        for (peer, address) in peers {
+            let checkpoint = match self
+                .request(*peer, *address, &IdPToWitnessMessage::CheckpointQuery)
+                .await
+            {
+                Some(WitnessToIdPMessage::CheckpointResponse(Some(checkpoint))) => checkpoint,
+                _ => continue,
+            };
+            if checkpoint.sequence_number() < self.sequence_number {
+                continue;
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            self.tx_checkpoint
+                .send((checkpoint, sender))
+                .await
+                .expect("Failed to send checkpoint to publish handler");
+            match receiver.await.expect("Failed to receive checkpoint reply") {
+                WitnessToIdPMessage::State(Ok(state)) => {
+                    self.sequence_number = state.sequence_number;
+                    return;
+                }
+                WitnessToIdPMessage::State(Err(e)) => {
+                    warn!("Received invalid checkpoint from {}: {}", address, e);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Hand over certificates to the publish handler (that verifies them before committing them).
+    /// Stops at the first certificate that the publish handler rejects.
+    async fn deliver(&mut self, certificates: Vec<SerializedPublishCertificateMessage>) {
+        for serialized in certificates {
+            let certificate = match bincode::deserialize(&serialized) {
+                Ok(IdPToWitnessMessage::PublishCertificate(certificate)) => certificate,
+                _ => {
+                    warn!("Received invalid certificate from a peer");
+                    return;
+                }
+            };
+
+            let (sender, receiver) = oneshot::channel();
+            self.tx_certificate
+                .send((serialized, certificate, sender))
+                .await
+                .expect("Failed to send certificate to publish handler");
+            match receiver.await.expect("Failed to receive certificate reply") {
+                WitnessToIdPMessage::State(Ok(state)) => {
+                    self.sequence_number = state.sequence_number;
+                }
+                WitnessToIdPMessage::State(Err(e)) => {
+                    warn!("Received invalid certificate from a peer: {}", e);
+                    return;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Pull missing certificates from the peers (one at the time) until the witness catches up or
+    /// no peer can help.
+    async fn synchronize(&mut self, request: SyncRequest) {
+        let peers: Vec<_> = request
+            .committee
+            .witnesses_addresses()
+            .into_iter()
+            .filter(|(name, _)| name != &self.name)
+            .collect();
+
+        let mut failures = 0;
+        let mut bootstrapped = false;
+        while self.sequence_number <= request.to && failures < peers.len() {
+            let (peer, address) = peers[self.next_peer % peers.len()];
+            let from = self.sequence_number;
+            if let Some(certificates) = self.fetch(peer, address, from, request.to).await {
+                self.deliver(certificates).await;
+            }
+
+            // The peers may have pruned the missing certificates: try (once) to adopt a checkpoint.
+            if self.sequence_number == from && failures + 1 == peers.len() && !bootstrapped {
+                bootstrapped = true;
+                self.bootstrap(&peers).await;
+            }
+
+            // Move on to another peer if this one did not help.
+            if self.sequence_number > from {
+                failures = 0;
+            } else {
+                failures += 1;
+                self.next_peer += 1;
+            }
+        }
+    }
+
+    /// Main loop receiving signals that the witness missed some certificates.
+    async fn run(&mut self) {
+        while let Some(request) = self.rx_request.recv().await {
+            // Skip requests for certificates we already caught up with.
+            if request.to < self.sequence_number {
+                continue;
+            }
+            self.sequence_number = self.sequence_number.max(request.from);
+
+            debug!(
+                "Synchronizing certificates {}..={}",
+                request.from, request.to
+            );
+            self.synchronize(request).await;
+        }
+    }
+}
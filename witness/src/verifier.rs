@@ -0,0 +1,107 @@
+use crate::Replier;
+use config::Committee;
+use futures::stream::{FuturesOrdered, StreamExt};
+use messages::{error::WitnessResult, publish::PublishNotification, Root};
+use tokio::{
+    runtime::Handle,
+    sync::mpsc::{Receiver, Sender},
+    task::spawn_blocking,
+};
+
+/// A notification to verify against the root on top of which it is expected to build.
+pub struct VerificationRequest {
+    /// The notification to verify.
+    pub notification: PublishNotification,
+    /// The committee in charge of the notification.
+    pub committee: Committee,
+    /// The root on top of which the notification is expected to build.
+    pub previous_root: Root,
+    /// The channel to reply to the IdP.
+    pub replier: Replier,
+}
+
+/// The result of the verification of a notification.
+pub struct VerificationOutcome {
+    /// The verified notification.
+    pub notification: PublishNotification,
+    /// The root against which the notification was verified.
+    pub previous_root: Root,
+    /// The channel to reply to the IdP.
+    pub replier: Replier,
+    /// The result of the verification.
+    pub result: WitnessResult<()>,
+}
+
+/// Verifies the state-transition proofs of publish notifications (very CPU-intensive) on a bounded
+/// pool of blocking threads, so that neither the publish handler nor the other tasks of the runtime
+/// are starved meanwhile. Outcomes are delivered in the order of the requests.
+pub struct Verifier {
+    /// Receive notifications to verify.
+    rx_request: Receiver<VerificationRequest>,
+    /// Outputs the result of the verifications.
+    tx_outcome: Sender<VerificationOutcome>,
+    /// The maximum number of notifications verified in parallel.
+    workers: usize,
+}
+
+impl Verifier {
+    /// Spawn a new verifier task.
+    pub fn spawn(
+        rx_request: Receiver<VerificationRequest>,
+        tx_outcome: Sender<VerificationOutcome>,
+    ) {
+        let workers = std::thread::available_parallelism().map_or(1, |x| x.get());
+        tokio::spawn(async move {
+            Self {
+                rx_request,
+                tx_outcome,
+                workers,
+            }
+            .run()
+            .await
+        });
+    }
+
+    /// Verify a notification against the expected previous root. This function blocks the calling
+    /// thread until the proof is verified.
+    fn verify(request: VerificationRequest) -> VerificationOutcome {
+        let VerificationRequest {
+            notification,
+            committee,
+            previous_root,
+            replier,
+        } = request;
+        let result = Handle::current()
+            .block_on(notification.verify(&committee, &previous_root))
+            .map_err(Into::into);
+        VerificationOutcome {
+            notification,
+            previous_root,
+            replier,
+            result,
+        }
+    }
+
+    /// Main loop dispatching notifications to the workers.
+    async fn run(&mut self) {
+        let mut pending = FuturesOrdered::new();
+        loop {
+            tokio::select! {
+                // Hand over notifications to the workers (as long as one of them is available). The
+                // number of blocking threads in use is thus bounded by the number of workers.
+                Some(request) = self.rx_request.recv(), if pending.len() < self.workers => {
+                    pending.push_back(spawn_blocking(move || Self::verify(request)));
+                },
+
+                // Deliver the outcomes in the order of the requests.
+                Some(outcome) = pending.next() => {
+                    let outcome = outcome.expect("Failed to verify notification");
+                    self.tx_outcome
+                        .send(outcome)
+                        .await
+                        .expect("Failed to deliver verified notification");
+                }
+            }
+        }
+    }
+}
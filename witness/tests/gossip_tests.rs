@@ -0,0 +1,51 @@
+use bytes::Bytes;
+use function_name::named;
+use messages::{gossip::EquivocationReport, IdPToWitnessMessage};
+use test_utils::{
+    committee, delete_storage, forked_notification, gossip_listener, idp_sender_with_transport,
+    keys, memory_transport, notification, spawn_test_witness_with_transport,
+};
+
+#[tokio::test]
+#[named]
+async fn split_view() {
+    let base_port = 7_500;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 3 witnesses and a listener acting as the gossip endpoint of the last witness.
+    let (names, mut keypairs): (Vec<_>, Vec<_>) = keys().into_iter().unzip();
+    for i in 0..3 {
+        spawn_test_witness_with_transport(&test_id, &committee, i, transport.clone());
+    }
+    let address = committee.gossip_address(&names[3]).unwrap();
+    let handle = gossip_listener(address, keypairs.pop().unwrap(), transport.clone());
+    tokio::task::yield_now().await;
+
+    // Show a different root to the first two witnesses.
+    let mut network = idp_sender_with_transport(transport.clone());
+    let mut handles = Vec::new();
+    let notifications = vec![notification().await, forked_notification().await];
+    for (name, notification) in names.iter().zip(notifications.into_iter()) {
+        let address = committee.witness_address(name).unwrap();
+        let message = IdPToWitnessMessage::PublishNotification(notification);
+        let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+        handles.push(network.send(*name, address, bytes).await);
+    }
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    // Ensure the witnesses detect the equivocation and report it.
+    match handle.await.unwrap() {
+        report @ EquivocationReport::ConflictingNotifications(..) => {
+            assert_eq!(report.sequence_number(), 1);
+            assert!(report.verify(&committee).is_ok());
+        }
+        x => panic!("Unexpected report: {:?}", x),
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
@@ -0,0 +1,107 @@
+use bytes::Bytes;
+use function_name::named;
+use messages::{
+    legacy::{LegacyIdPToWitnessMessage, LegacyPublishVote, LegacyState},
+    publish::{PublishMessage, PublishVote},
+    sync::State,
+    IdPToWitnessMessage, WitnessToIdPMessage,
+};
+use storage::{Column, Storage};
+use test_utils::{
+    bls_keys, certificate, committee, delete_storage, idp_sender_with_transport, keys,
+    legacy_certificate, legacy_database, memory_transport, notification, pipelined_notification,
+    PIPELINE_DEPTH,
+};
+use witness::{migrate_storage, spawn_witness_with_transport, DEFAULT_CHECKPOINT_INTERVAL};
+
+#[tokio::test]
+#[named]
+async fn migrate_legacy_storage() {
+    let base_port = 8_600;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+    let secure_path = format!(".test_legacy_secure_storage_{}", test_id);
+    let audit_path = format!(".test_legacy_audit_storage_{}", test_id);
+
+    // Make the legacy databases of a witness that processed the first certificate and voted for
+    // the next notification.
+    let (name, keypair) = keys().swap_remove(0);
+    let vote = PublishVote::new(&pipelined_notification().await, &keypair);
+    let state = LegacyState {
+        root: notification().await.root,
+        sequence_number: 2,
+        lock: Some(LegacyPublishVote {
+            root: vote.root,
+            sequence_number: vote.sequence_number,
+            author: vote.author,
+            signature: vote.signature.clone(),
+        }),
+    };
+    legacy_database(
+        &secure_path,
+        vec![(vec![255; 32], bincode::serialize(&state).unwrap())],
+    );
+    let message = LegacyIdPToWitnessMessage::PublishCertificate(legacy_certificate().await);
+    legacy_database(
+        &audit_path,
+        vec![(
+            1u64.to_le_bytes().to_vec(),
+            bincode::serialize(&message).unwrap(),
+        )],
+    );
+
+    // Migrate the legacy databases.
+    let storage_path = format!(".test_storage_{}_0", test_id);
+    let _ = std::fs::remove_dir_all(&storage_path);
+    let storage = Storage::new(&storage_path).unwrap();
+    let migrated = migrate_storage(&storage, &secure_path, &audit_path, None).unwrap();
+    assert_eq!(migrated, 2);
+
+    // The certificate is translated into the current format (and remains valid).
+    let bytes = storage.read(Column::Certificates, &1u64).unwrap().unwrap();
+    match bincode::deserialize(&bytes).unwrap() {
+        IdPToWitnessMessage::PublishCertificate(received) => {
+            assert!(received.verify(&committee).is_ok());
+            assert_eq!(received, certificate().await);
+        }
+        _ => panic!("Unexpected message"),
+    }
+
+    // The witness boots from the migrated state (including its lock).
+    let (_, bls_keypair) = bls_keys().swap_remove(0);
+    spawn_witness_with_transport(
+        keypair,
+        bls_keypair,
+        committee.clone(),
+        /* next_committee */ None,
+        storage,
+        PIPELINE_DEPTH,
+        DEFAULT_CHECKPOINT_INTERVAL,
+        transport.clone(),
+    );
+    tokio::task::yield_now().await;
+
+    let address = committee.witness_address(&name).unwrap();
+    let message = IdPToWitnessMessage::StateQuery;
+    let serialized = bincode::serialize(&message).unwrap();
+    let reply = idp_sender_with_transport(transport)
+        .send(name, address, Bytes::from(serialized))
+        .await
+        .await
+        .unwrap();
+    let expected = State {
+        root: *notification().await.root(),
+        sequence_number: 2,
+        lock: Some(vote),
+    };
+    match bincode::deserialize(&reply).unwrap() {
+        WitnessToIdPMessage::State(Ok(state)) => assert_eq!(state, expected),
+        _ => panic!("Unexpected protocol message"),
+    }
+
+    // Delete the storage.
+    let _ = std::fs::remove_dir_all(&secure_path);
+    let _ = std::fs::remove_dir_all(&audit_path);
+    delete_storage(&test_id);
+}
@@ -1,23 +1,20 @@
-use akd::{
-    directory::Directory,
-    ecvrf::HardCodedAkdVRF,
-    storage::{
-        memory::AsyncInMemoryDatabase,
-        types::{AkdLabel, AkdValue},
-    },
-};
+use bytes::Bytes;
 use function_name::named;
 use futures::future::try_join_all;
 use messages::{
     error::WitnessError,
-    publish::{PublishCertificate, PublishNotification, PublishVote},
+    publish::{PublishCertificate, PublishNotification, PublishVote, Votes},
     sync::State,
-    Blake3, WitnessToIdPMessage,
+    IdPToWitnessMessage, WitnessToIdPMessage,
 };
+use network::reliable_sender::{BufferLimits, ReliableSender};
 use test_utils::{
-    broadcast_certificate, broadcast_notification, certificate, committee, delete_storage, keys,
-    notification, proof, spawn_test_witnesses, votes,
+    bls_committee, broadcast_certificate, broadcast_notification, certificate, client_sender,
+    committee, committee_change_certificate, delete_storage, forked_notification, keys,
+    memory_transport, notification, pipelined_notification, proof,
+    spawn_test_witnesses_with_transport, votes,
 };
+use tokio::time::{timeout, Duration};
 
 #[tokio::test]
 #[named]
@@ -25,14 +22,15 @@ async fn correct_notification() {
     let base_port = 7_000;
     let committee = committee(base_port);
     let test_id = function_name!();
+    let transport = memory_transport();
 
     // Spawn 4 witnesses.
-    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
     tokio::task::yield_now().await;
 
     // Broadcast a publish notification.
     let notification = notification().await;
-    let handles = broadcast_notification(notification, &committee).await;
+    let handles = broadcast_notification(notification, &committee, transport.clone()).await;
 
     // Wait for the witnesses' replies.
     let mut replies: Vec<_> = try_join_all(handles)
@@ -61,9 +59,10 @@ async fn unexpected_sequence_number() {
     let base_port = 7_100;
     let committee = committee(base_port);
     let test_id = function_name!();
+    let transport = memory_transport();
 
     // Spawn 4 witnesses.
-    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
     tokio::task::yield_now().await;
 
     // Make a publish notification with a bad sequence number.
@@ -78,7 +77,7 @@ async fn unexpected_sequence_number() {
     );
 
     // Broadcast the notification.
-    let handles = broadcast_notification(notification, &committee).await;
+    let handles = broadcast_notification(notification, &committee, transport.clone()).await;
 
     // Ensure the witnesses' replies are as expected.
     for reply in try_join_all(handles).await.unwrap() {
@@ -104,43 +103,22 @@ async fn conflicting_notification() {
     let base_port = 7_200;
     let committee = committee(base_port);
     let test_id = function_name!();
+    let transport = memory_transport();
 
     // Spawn 4 witnesses.
-    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
     tokio::task::yield_now().await;
 
     // Broadcast a first notification.
     let notification = notification().await;
     let notification_root = notification.root.clone();
-    let handles = broadcast_notification(notification, &committee).await;
+    let handles = broadcast_notification(notification, &committee, transport.clone()).await;
     let _ = try_join_all(handles).await.unwrap();
 
-    // Make a conflicting proof of update.
-    let db = AsyncInMemoryDatabase::new();
-    let vrf = HardCodedAkdVRF {};
-    let akd = Directory::new::<Blake3>(&db, &vrf, false).await.unwrap();
-    akd.publish::<Blake3>(vec![(AkdLabel(vec![1, 2, 3]), AkdValue(vec![3, 4, 6]))])
-        .await
-        .unwrap();
-    let current_azks = akd.retrieve_current_azks().await.unwrap();
-    let root = akd
-        .get_root_hash_at_epoch::<Blake3>(&current_azks, /* sequence number */ 1)
-        .await
-        .unwrap();
-
-    // Generate the audit proof.
-    let proof = akd.audit::<Blake3>(0, 1).await.unwrap();
-
     // Broadcast a conflicting notification.
-    let (_, identity_provider) = keys().pop().unwrap();
-    let conflict = PublishNotification::new(
-        root,
-        proof,
-        /* sequence number */ 1,
-        /* keypair */ &identity_provider,
-    );
+    let conflict = forked_notification().await;
     let conflict_root = conflict.root.clone();
-    let handles = broadcast_notification(conflict, &committee).await;
+    let handles = broadcast_notification(conflict, &committee, transport.clone()).await;
 
     // Ensure the witnesses' replies are as expected.
     for reply in try_join_all(handles).await.unwrap() {
@@ -166,14 +144,15 @@ async fn expected_certificate() {
     let base_port = 7_300;
     let committee = committee(base_port);
     let test_id = function_name!();
+    let transport = memory_transport();
 
     // Spawn 4 witnesses.
-    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
     tokio::task::yield_now().await;
 
     // Broadcast a certificate.
     let certificate = certificate().await;
-    let handles = broadcast_certificate(certificate, &committee).await;
+    let handles = broadcast_certificate(certificate, &committee, transport.clone()).await;
 
     // Make the expected state.
     let (_, root, _) = proof().await;
@@ -202,9 +181,10 @@ async fn unexpected_certificate() {
     let base_port = 7_400;
     let committee = committee(base_port);
     let test_id = function_name!();
+    let transport = memory_transport();
 
     // Spawn 4 witnesses.
-    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
     tokio::task::yield_now().await;
 
     // Make a publish certificate for a future sequence number.
@@ -226,11 +206,12 @@ async fn unexpected_certificate() {
     let certificate = PublishCertificate {
         root: notification.root.clone(),
         sequence_number: notification.sequence_number,
-        votes: votes.into_iter().map(|x| (x.author, x.signature)).collect(),
+        committee_change: None,
+        votes: Votes::Individual(votes.into_iter().map(|x| (x.author, x.signature)).collect()),
     };
 
     // Broadcast the certificate.
-    let handles = broadcast_certificate(certificate, &committee).await;
+    let handles = broadcast_certificate(certificate, &committee, transport.clone()).await;
 
     // Ensure the witnesses' replies are as expected.
     for reply in try_join_all(handles).await.unwrap() {
@@ -245,3 +226,257 @@ async fn unexpected_certificate() {
     // Delete the storage.
     delete_storage(&test_id);
 }
+
+#[tokio::test]
+#[named]
+async fn committee_change() {
+    let base_port = 7_600;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    tokio::task::yield_now().await;
+
+    // Broadcast a certificate handing over to the first witness only.
+    let certificate = committee_change_certificate(base_port).await;
+    let handles = broadcast_certificate(certificate, &committee, transport.clone()).await;
+    let _ = try_join_all(handles).await.unwrap();
+
+    // Make a certificate for the next sequence number signed by the new committee only.
+    let (_, identity_provider) = keys().pop().unwrap();
+    let (_, root, proof) = proof().await;
+    let notification = PublishNotification::new(
+        root,
+        proof,
+        /* sequence_number */ 2,
+        /* keypair */ &identity_provider,
+    );
+
+    let (_, keypair) = keys().swap_remove(0);
+    let vote = PublishVote::new(&notification, &keypair);
+    let certificate = PublishCertificate {
+        root: notification.root.clone(),
+        sequence_number: notification.sequence_number,
+        committee_change: None,
+        votes: Votes::Individual(vec![(vote.author, vote.signature)]),
+    };
+
+    // Broadcast the certificate (it would not form a quorum under the old committee).
+    let handles = broadcast_certificate(certificate, &committee, transport.clone()).await;
+
+    // Ensure the witnesses' replies are as expected.
+    for reply in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::State(Ok(state)) => assert_eq!(state.sequence_number, 3),
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn pipelined_notifications() {
+    let base_port = 7_700;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    tokio::task::yield_now().await;
+
+    // Broadcast a first notification.
+    let handles = broadcast_notification(notification().await, &committee, transport.clone()).await;
+    let _ = try_join_all(handles).await.unwrap();
+
+    // Broadcast the next notification before the first one is certified.
+    let notification = pipelined_notification().await;
+    let root = notification.root.clone();
+    let handles = broadcast_notification(notification, &committee, transport.clone()).await;
+
+    // Ensure the witnesses vote for it.
+    for reply in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::PublishVote(Ok(vote)) => {
+                assert_eq!(vote.root, root);
+                assert_eq!(vote.sequence_number, 2);
+            }
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+
+    // Broadcast the certificate of the first notification.
+    let handles = broadcast_certificate(certificate().await, &committee, transport.clone()).await;
+
+    // Ensure the witnesses are now locked on the pipelined notification.
+    for reply in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::State(Ok(state)) => {
+                assert_eq!(state.sequence_number, 2);
+                assert_eq!(state.lock.unwrap().root, root);
+            }
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn aggregatable_votes() {
+    let base_port = 7_800;
+    let committee = bls_committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    tokio::task::yield_now().await;
+
+    // Broadcast a publish notification.
+    let handles = broadcast_notification(notification().await, &committee, transport.clone()).await;
+
+    // Ensure the votes can be aggregated into a compact certificate.
+    for reply in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::PublishVote(Ok(vote)) => {
+                assert!(vote.bls_signature.is_some());
+                assert!(vote.verify(&committee).is_ok());
+            }
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn unauthorized_notification() {
+    let base_port = 7_900;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    tokio::task::yield_now().await;
+
+    // Broadcast a publish notification from a peer that is not the IdP.
+    let (name, keypair) = keys().swap_remove(0);
+    let message = IdPToWitnessMessage::PublishNotification(notification().await);
+    let serialized = bincode::serialize(&message).unwrap();
+    let mut sender = ReliableSender::with_transport(keypair, BufferLimits::default(), transport);
+    let handles = sender
+        .broadcast(committee.witnesses_addresses(), Bytes::from(serialized))
+        .await;
+
+    // Ensure the witnesses refuse to vote.
+    for reply in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::PublishVote(Err(WitnessError::UnauthorizedSender(peer))) => {
+                assert_eq!(peer, name)
+            }
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn unauthorized_peer() {
+    let base_port = 8_700;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    tokio::task::yield_now().await;
+
+    // Query the state of a witness from a peer that is not in the committee.
+    let (name, _) = keys().swap_remove(0);
+    let address = committee.witness_address(&name).unwrap();
+    let message = IdPToWitnessMessage::StateQuery;
+    let serialized = bincode::serialize(&message).unwrap();
+    let mut sender = client_sender(transport);
+    let handle = sender.send(name, address, Bytes::from(serialized)).await;
+
+    // Ensure the witness drops the connection without replying.
+    let reply = timeout(Duration::from_millis(1_000), handle).await;
+    assert!(!matches!(reply, Ok(Ok(_))));
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn speculative_verification() {
+    let base_port = 8_500;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    tokio::task::yield_now().await;
+
+    // Broadcast an invalid notification (its proof does not lead to its root) followed right away
+    // by a notification building on it. The latter is verified while the former is in flight.
+    let (_, identity_provider) = keys().pop().unwrap();
+    let notification = notification().await;
+    let invalid = PublishNotification::new(
+        notification.root,
+        forked_notification().await.proof,
+        /* sequence_number */ 1,
+        /* keypair */ &identity_provider,
+    );
+    let invalid_handles = broadcast_notification(invalid, &committee, transport.clone()).await;
+    let pipelined_handles = broadcast_notification(
+        pipelined_notification().await,
+        &committee,
+        transport.clone(),
+    )
+    .await;
+
+    // Ensure the witnesses vote for neither of them.
+    for handles in [invalid_handles, pipelined_handles] {
+        for reply in try_join_all(handles).await.unwrap() {
+            match bincode::deserialize(&reply).unwrap() {
+                WitnessToIdPMessage::PublishVote(Err(_)) => (),
+                _ => panic!("Unexpected protocol message"),
+            }
+        }
+    }
+
+    // Ensure the witnesses still vote for the valid notifications afterwards.
+    let handles = broadcast_notification(notification, &committee, transport.clone()).await;
+    let _ = try_join_all(handles).await.unwrap();
+    let handles = broadcast_notification(
+        pipelined_notification().await,
+        &committee,
+        transport.clone(),
+    )
+    .await;
+    for reply in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::PublishVote(Ok(vote)) => assert_eq!(vote.sequence_number, 2),
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
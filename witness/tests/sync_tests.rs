@@ -1,15 +1,72 @@
 use bytes::Bytes;
+use config::Committee;
+use crypto::PublicKey;
 use function_name::named;
 use futures::future::try_join_all;
 use messages::{
-    publish::PublishCertificate,
-    sync::{PublishCertificateQuery, State},
+    equivocation::{EquivocationProof, EquivocationProofQuery},
+    error::WitnessError,
+    publish::{PublishCertificate, PublishVote, Votes},
+    sync::{PublishCertificateRangeQuery, PublishCertificateRangeResponse, State},
     IdPToWitnessMessage, WitnessToIdPMessage,
 };
-use network::reliable_sender::ReliableSender;
+use network::{reliable_sender::ReliableSender, transport::Transport};
+use std::{net::SocketAddr, sync::Arc};
+use storage::Storage;
 use test_utils::{
-    broadcast_certificate, committee, delete_storage, notification, spawn_test_witnesses, votes,
+    bls_keys, broadcast_certificate, broadcast_notification, certificate, certify,
+    chained_notification, committee, delete_storage, forked_notification,
+    idp_sender_with_transport, keys, memory_transport, notification, pipelined_notification,
+    spawn_test_witnesses_with_transport, votes, PIPELINE_DEPTH,
 };
+use tokio::time::{sleep, Duration};
+use witness::spawn_witness_with_transport;
+
+// The number of sequence numbers between two checkpoints of `spawn_checkpointing_witness`.
+const CHECKPOINT_INTERVAL: u64 = 2;
+
+// Spawn a single test witness (with a fresh storage) checkpointing every `CHECKPOINT_INTERVAL`
+// sequence numbers.
+fn spawn_checkpointing_witness(
+    test_id: &str,
+    committee: &Committee,
+    index: usize,
+    transport: Arc<dyn Transport>,
+) {
+    let (_, keypair) = keys().swap_remove(index);
+    let (_, bls_keypair) = bls_keys().swap_remove(index);
+
+    let storage_path = format!(".test_storage_{}_{}", test_id, index);
+    let _ = std::fs::remove_dir_all(&storage_path);
+    let storage = Storage::new(&storage_path).unwrap();
+
+    spawn_witness_with_transport(
+        keypair,
+        bls_keypair,
+        committee.clone(),
+        /* next_committee */ None,
+        storage,
+        PIPELINE_DEPTH,
+        CHECKPOINT_INTERVAL,
+        transport,
+    );
+}
+
+// Send a message to some witnesses and return their replies.
+async fn send_to(
+    sender: &mut ReliableSender,
+    peers: Vec<(PublicKey, SocketAddr)>,
+    message: &IdPToWitnessMessage,
+) -> Vec<WitnessToIdPMessage> {
+    let bytes = Bytes::from(bincode::serialize(message).unwrap());
+    let handles = sender.broadcast(peers, bytes).await;
+    try_join_all(handles)
+        .await
+        .unwrap()
+        .iter()
+        .map(|reply| bincode::deserialize(reply).unwrap())
+        .collect()
+}
 
 #[tokio::test]
 #[named]
@@ -17,22 +74,19 @@ async fn state_query() {
     let base_port = 8_000;
     let committee = committee(base_port);
     let test_id = function_name!();
+    let transport = memory_transport();
 
     // Spawn 4 witnesses.
-    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
     tokio::task::yield_now().await;
 
     // Broadcast a state query.
-    let addresses = committee
-        .witnesses_addresses()
-        .into_iter()
-        .map(|(_, address)| address)
-        .collect();
+    let peers = committee.witnesses_addresses();
     let message = IdPToWitnessMessage::StateQuery;
     let serialized = bincode::serialize(&message).unwrap();
     let bytes = Bytes::from(serialized);
-    let mut sender = ReliableSender::new();
-    let handles = sender.broadcast(addresses, bytes).await;
+    let mut sender = idp_sender_with_transport(transport.clone());
+    let handles = sender.broadcast(peers, bytes).await;
 
     // Make the expected state.
     let expected = State::default();
@@ -55,9 +109,10 @@ async fn sync_request() {
     let base_port = 8_100;
     let committee = committee(base_port);
     let test_id = function_name!();
+    let transport = memory_transport();
 
     // Spawn 4 witnesses.
-    spawn_test_witnesses(&test_id, &committee);
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
     tokio::task::yield_now().await;
 
     // Broadcast a certificate.
@@ -65,36 +120,40 @@ async fn sync_request() {
     let certificate = PublishCertificate {
         root: notification.root,
         sequence_number: notification.sequence_number,
-        votes: votes()
-            .await
-            .into_iter()
-            .map(|x| (x.author, x.signature))
-            .collect(),
+        committee_change: None,
+        votes: Votes::Individual(
+            votes()
+                .await
+                .into_iter()
+                .map(|x| (x.author, x.signature))
+                .collect(),
+        ),
     };
-    let handles = broadcast_certificate(certificate.clone(), &committee).await;
+    let handles = broadcast_certificate(certificate.clone(), &committee, transport.clone()).await;
     let _ = try_join_all(handles).await.unwrap();
 
     // Broadcast a sync request.
-    let request = PublishCertificateQuery {
-        sequence_number: notification.sequence_number,
+    let request = PublishCertificateRangeQuery {
+        from: notification.sequence_number,
+        to: notification.sequence_number,
+        max_bytes: usize::MAX,
     };
 
-    let addresses = committee
-        .witnesses_addresses()
-        .into_iter()
-        .map(|(_, address)| address)
-        .collect();
-    let message = IdPToWitnessMessage::PublishCertificateQuery(request);
+    let peers = committee.witnesses_addresses();
+    let message = IdPToWitnessMessage::PublishCertificateRangeQuery(request);
     let serialized = bincode::serialize(&message).unwrap();
     let bytes = Bytes::from(serialized);
-    let mut sender = ReliableSender::new();
-    let handles = sender.broadcast(addresses, bytes).await;
+    let mut sender = idp_sender_with_transport(transport.clone());
+    let handles = sender.broadcast(peers, bytes).await;
 
     // Ensure the witnesses' replies are as expected.
     for reply in try_join_all(handles).await.unwrap() {
         match bincode::deserialize(&reply).unwrap() {
-            WitnessToIdPMessage::PublishCertificateResponse(received) => {
-                match bincode::deserialize(&received).unwrap() {
+            WitnessToIdPMessage::PublishCertificateRangeResponse(
+                PublishCertificateRangeResponse::Found(received),
+            ) => {
+                assert_eq!(received.len(), 1);
+                match bincode::deserialize(&received[0]).unwrap() {
                     IdPToWitnessMessage::PublishCertificate(cert) => {
                         assert_eq!(cert, certificate);
                     }
@@ -108,3 +167,263 @@ async fn sync_request() {
     // Delete the storage.
     delete_storage(&test_id);
 }
+
+#[tokio::test]
+#[named]
+async fn sync_request_not_found() {
+    let base_port = 8_300;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    tokio::task::yield_now().await;
+
+    // Broadcast a sync request for certificates the witnesses do not have.
+    let request = PublishCertificateRangeQuery {
+        from: 1,
+        to: 10,
+        max_bytes: usize::MAX,
+    };
+
+    let peers = committee.witnesses_addresses();
+    let message = IdPToWitnessMessage::PublishCertificateRangeQuery(request);
+    let serialized = bincode::serialize(&message).unwrap();
+    let bytes = Bytes::from(serialized);
+    let mut sender = idp_sender_with_transport(transport.clone());
+    let handles = sender.broadcast(peers, bytes).await;
+
+    // Ensure the witnesses reply (rather than leaving the request unanswered).
+    for reply in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::PublishCertificateRangeResponse(
+                PublishCertificateRangeResponse::NotFound(sequence_number),
+            ) => assert_eq!(sequence_number, 1),
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn equivocation_proof_request() {
+    let base_port = 8_200;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    tokio::task::yield_now().await;
+
+    // Broadcast two conflicting notifications.
+    let notification = notification().await;
+    let handles = broadcast_notification(notification.clone(), &committee, transport.clone()).await;
+    let _ = try_join_all(handles).await.unwrap();
+
+    let conflict = forked_notification().await;
+    let handles = broadcast_notification(conflict.clone(), &committee, transport.clone()).await;
+    let _ = try_join_all(handles).await.unwrap();
+
+    // Broadcast an equivocation proof request.
+    let request = EquivocationProofQuery {
+        sequence_number: notification.sequence_number,
+    };
+
+    let peers = committee.witnesses_addresses();
+    let message = IdPToWitnessMessage::EquivocationProofQuery(request);
+    let serialized = bincode::serialize(&message).unwrap();
+    let bytes = Bytes::from(serialized);
+    let mut sender = idp_sender_with_transport(transport.clone());
+    let handles = sender.broadcast(peers, bytes).await;
+
+    // Ensure the witnesses' replies are as expected.
+    let expected = EquivocationProof::new(notification, conflict);
+    for reply in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::EquivocationProofResponse(Some(proof)) => {
+                assert!(proof.verify(&committee).is_ok());
+                assert_eq!(proof, expected);
+            }
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn sync_from_other_witnesses() {
+    let base_port = 8_400;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 4 witnesses.
+    spawn_test_witnesses_with_transport(&test_id, &committee, transport.clone());
+    tokio::task::yield_now().await;
+
+    // Send the first certificate to all witnesses but one.
+    let lagging = keys()[0].0;
+    let lagging_address = committee.witness_address(&lagging).unwrap();
+    let peers: Vec<_> = committee
+        .witnesses_addresses()
+        .into_iter()
+        .filter(|(name, _)| name != &lagging)
+        .collect();
+    let message = IdPToWitnessMessage::PublishCertificate(certificate().await);
+    let serialized = bincode::serialize(&message).unwrap();
+    let mut sender = idp_sender_with_transport(transport.clone());
+    let handles = sender.broadcast(peers, Bytes::from(serialized)).await;
+    let _ = try_join_all(handles).await.unwrap();
+
+    // Send the next certificate to the lagging witness.
+    let notification = pipelined_notification().await;
+    let votes = keys()
+        .iter()
+        .map(|(_, keypair)| PublishVote::new(&notification, keypair))
+        .map(|x| (x.author, x.signature))
+        .collect();
+    let next_certificate = PublishCertificate {
+        root: notification.root,
+        sequence_number: notification.sequence_number,
+        committee_change: None,
+        votes: Votes::Individual(votes),
+    };
+    let message = IdPToWitnessMessage::PublishCertificate(next_certificate);
+    let serialized = bincode::serialize(&message).unwrap();
+    let reply = sender
+        .send(lagging, lagging_address, Bytes::from(serialized))
+        .await
+        .await
+        .unwrap();
+    match bincode::deserialize(&reply).unwrap() {
+        WitnessToIdPMessage::State(Err(WitnessError::MissingEarlierCertificates(seq))) => {
+            assert_eq!(seq, 1);
+        }
+        _ => panic!("Unexpected protocol message"),
+    }
+
+    // Ensure the lagging witness pulls the missing certificate from the other witnesses.
+    let mut synced = false;
+    for _ in 0..50 {
+        let serialized = bincode::serialize(&IdPToWitnessMessage::StateQuery).unwrap();
+        let reply = sender
+            .send(lagging, lagging_address, Bytes::from(serialized))
+            .await
+            .await
+            .unwrap();
+        match bincode::deserialize(&reply).unwrap() {
+            WitnessToIdPMessage::State(Ok(state)) if state.sequence_number == 2 => {
+                synced = true;
+                break;
+            }
+            WitnessToIdPMessage::State(Ok(_)) => sleep(Duration::from_millis(100)).await,
+            _ => panic!("Unexpected protocol message"),
+        }
+    }
+    assert!(synced);
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}
+
+#[tokio::test]
+#[named]
+async fn bootstrap_from_checkpoint() {
+    let base_port = 8_800;
+    let committee = committee(base_port);
+    let test_id = function_name!();
+    let transport = memory_transport();
+
+    // Spawn 3 witnesses (enough to certify notifications without the first one).
+    let (fresh, _) = keys().swap_remove(0);
+    for i in 1..keys().len() {
+        spawn_checkpointing_witness(&test_id, &committee, i, transport.clone());
+    }
+    tokio::task::yield_now().await;
+
+    // Certify the notifications up to the first checkpoint.
+    let mut sender = idp_sender_with_transport(transport.clone());
+    let peers: Vec<_> = committee
+        .witnesses_addresses()
+        .into_iter()
+        .filter(|(name, _)| name != &fresh)
+        .collect();
+    for sequence_number in 1..=CHECKPOINT_INTERVAL {
+        let notification = chained_notification(sequence_number).await;
+        let message = IdPToWitnessMessage::PublishNotification(notification.clone());
+        for reply in send_to(&mut sender, peers.clone(), &message).await {
+            assert!(matches!(reply, WitnessToIdPMessage::PublishVote(Ok(_))));
+        }
+        let message = IdPToWitnessMessage::PublishCertificate(certify(&notification));
+        for reply in send_to(&mut sender, peers.clone(), &message).await {
+            assert!(matches!(reply, WitnessToIdPMessage::State(Ok(_))));
+        }
+    }
+
+    // Ensure the witnesses prune the certificates preceding the checkpoint.
+    let query = PublishCertificateRangeQuery {
+        from: 1,
+        to: CHECKPOINT_INTERVAL,
+        max_bytes: usize::MAX,
+    };
+    let message = IdPToWitnessMessage::PublishCertificateRangeQuery(query);
+    let mut pruned = false;
+    for _ in 0..50 {
+        pruned = send_to(&mut sender, peers.clone(), &message)
+            .await
+            .into_iter()
+            .all(|reply| {
+                matches!(
+                    reply,
+                    WitnessToIdPMessage::PublishCertificateRangeResponse(
+                        PublishCertificateRangeResponse::NotFound(1)
+                    )
+                )
+            });
+        if pruned {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert!(pruned);
+
+    // Spawn the last witness and show it the certificate of the checkpoint. It cannot pull the
+    // pruned certificates from its peers and adopts their checkpoint instead.
+    spawn_checkpointing_witness(&test_id, &committee, 0, transport.clone());
+    tokio::task::yield_now().await;
+    let address = committee.witness_address(&fresh).unwrap();
+    let checkpoint = certify(&chained_notification(CHECKPOINT_INTERVAL).await);
+    let message = IdPToWitnessMessage::PublishCertificate(checkpoint);
+    let _ = send_to(&mut sender, vec![(fresh, address)], &message).await;
+
+    // Ensure the witness votes for the notification following the checkpoint.
+    let notification = chained_notification(CHECKPOINT_INTERVAL + 1).await;
+    let message = IdPToWitnessMessage::PublishNotification(notification);
+    let mut vote = None;
+    for _ in 0..50 {
+        match send_to(&mut sender, vec![(fresh, address)], &message)
+            .await
+            .pop()
+        {
+            Some(WitnessToIdPMessage::PublishVote(Ok(x))) => {
+                vote = Some(x);
+                break;
+            }
+            _ => sleep(Duration::from_millis(100)).await,
+        }
+    }
+    let vote = vote.unwrap();
+    assert_eq!(vote.sequence_number, CHECKPOINT_INTERVAL + 1);
+    assert!(vote.verify(&committee).is_ok());
+
+    // Delete the storage.
+    delete_storage(&test_id);
+}